@@ -1,4 +1,5 @@
 import { OAuth2Client } from 'google-auth-library';
+import { createHash } from 'crypto';
 
 const client = new OAuth2Client(process.env.GOOGLE_CLIENT_ID);
 
@@ -43,3 +44,12 @@ export async function verifyGoogleToken(token: string): Promise<VerifiedUser> {
   }
 }
 
+
+/**
+ * Account id for a desktop signing in anonymously (ANONYMOUS_AUTH)
+ * @param secret - 32 random bytes the desktop keeps to itself
+ * @returns "anon:" and the first 128 bits of the secret's SHA-256, in hex
+ */
+export function anonymousUserId(secret: Buffer): string {
+  return 'anon:' + createHash('sha256').update(secret).digest('hex').slice(0, 32);
+}
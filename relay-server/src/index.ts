@@ -5,13 +5,32 @@ import { WebSocketServer, WebSocket } from 'ws';
 import * as os from 'os';
 import * as path from 'path';
 import * as http from 'http';
+import * as fs from 'fs';
 import express from 'express';
-import { verifyGoogleToken } from './auth';
+import { randomInt } from 'crypto';
+import { verifyGoogleToken, anonymousUserId } from './auth';
 import { signJWT, verifyJWT, refreshJWT, getExpirationSeconds } from './jwt';
 
 const PORT = process.env.PORT ? parseInt(process.env.PORT) : 8080;
+// Listen on a Unix socket instead of PORT, e.g. behind a reverse proxy on the
+// same host; a socket passed by systemd (LISTEN_FDS) takes precedence
+const SOCKET_PATH = process.env.SOCKET_PATH;
+const SYSTEMD_SOCKET = Number(process.env.LISTEN_FDS) > 0 && Number(process.env.LISTEN_PID) === process.pid;
 const MAX_MESSAGE_LENGTH = process.env.MAX_MESSAGE_LENGTH ? parseInt(process.env.MAX_MESSAGE_LENGTH) : 5000;
 
+// Reported to clients via get_capabilities so they can detect version mismatches
+const RELAY_VERSION: string = require('../package.json').version;
+const PROTOCOL_VERSION = 1;
+const FEATURES = ['jwt-auth', 'e2e-relay', 'devices', 'ping', 'capabilities', 'message-ids', 'corrections', 'keys', 'pairing', 'config-sync', 'sequence-numbers', 'acks', 'device-updates', 'clipboard', 'partials', 'chunks', 'session-keys', 'bound-aad', 'cipher-negotiation', 'broadcast', 'sender-auth'];
+
+// Self-hosted relays can do without Google: desktops sign in with a secret of
+// their own (POST /auth/anonymous) and phones join them with a one-time code
+const ANONYMOUS_AUTH = process.env.ANONYMOUS_AUTH === 'true';
+if (ANONYMOUS_AUTH) FEATURES.push('anonymous-auth');
+
+// Largest encrypted config bundle a user can store (base64 characters)
+const MAX_CONFIG_BLOB_LENGTH = 64 * 1024;
+
 // Debug mode - enabled with --debug flag
 const DEBUG = process.argv.includes('--debug');
 
@@ -43,11 +62,15 @@ interface Client {
   deviceName?: string;
   userId?: string;
   publicKey?: string;
+  // AEADs the device decrypts, most preferred first
+  ciphers?: string[];
   status: 'online' | 'offline';
   connectedAt: Date;
   version?: string;
   platform?: string;
   arch?: string;
+  // JWT from the upgrade request's Authorization header, used when register carries none
+  bearerJwt?: string;
 }
 
 const clients = new Map<string, Client>();
@@ -58,10 +81,36 @@ interface Device {
   deviceType: 'android' | 'target' | 'controller';
   userId: string;
   publicKey?: string;
+  ciphers?: string[];
   status: 'online' | 'offline';
   lastConnected: Date;
+  version?: string;
+  platform?: string;
+}
+
+// End-to-end encrypted config bundle per user, shared by their desktops.
+// The relay only sees ciphertext; version is bumped on every upload.
+interface SyncedConfig {
+  version: number;
+  blob: string;
+  nonce: string;
+  updatedBy: string;
+  updatedAt: number;
 }
 
+const syncedConfigs = new Map<string, SyncedConfig>();
+
+// One-time codes a phone registers with to join an anonymous desktop's
+// account; one per account, replaced when used or after PAIRING_CODE_TTL
+interface PairingCode {
+  userId: string;
+  timer: NodeJS.Timeout;
+}
+
+const PAIRING_CODE_TTL = 10 * 60;
+const PAIRING_CODE_ALPHABET = '23456789ABCDEFGHJKLMNPQRSTUVWXYZ';
+const pairingCodes = new Map<string, PairingCode>();
+
 // Create Express app for HTTP endpoints
 const app = express();
 app.use(express.json());
@@ -113,6 +162,30 @@ app.post('/auth', async (req, res) => {
   }
 });
 
+// Anonymous sign-in - exchange a desktop's own secret for a JWT (ANONYMOUS_AUTH)
+app.post('/auth/anonymous', (req, res) => {
+  if (!ANONYMOUS_AUTH) {
+    return res.status(403).json({
+      error: 'Anonymous sign-in is disabled on this relay (ANONYMOUS_AUTH)'
+    });
+  }
+
+  const { secret } = req.body;
+  const secretBytes = typeof secret === 'string' ? Buffer.from(secret, 'base64') : Buffer.alloc(0);
+  if (secretBytes.length !== 32) {
+    return res.status(400).json({
+      error: 'secret must be 32 bytes, base64-encoded'
+    });
+  }
+
+  const userId = anonymousUserId(secretBytes);
+  res.json({
+    jwt: signJWT(userId),
+    expiresIn: getExpirationSeconds(),
+    userId
+  });
+});
+
 // Refresh endpoint - refresh JWT before expiration
 app.post('/auth/refresh', async (req, res) => {
   try {
@@ -178,11 +251,25 @@ function getNetworkAddresses(): string[] {
   return addresses;
 }
 
-// Start HTTP server (which includes WebSocket)
-httpServer.listen(PORT, () => {
+// A socket left behind by an earlier run would make listen fail
+if (SOCKET_PATH && !SYSTEMD_SOCKET && fs.existsSync(SOCKET_PATH)) {
+  fs.unlinkSync(SOCKET_PATH);
+}
+
+// Start HTTP server (which includes WebSocket); systemd passes its socket as fd 3
+httpServer.listen(SYSTEMD_SOCKET ? { fd: 3 } : SOCKET_PATH || PORT, () => {
   console.log('');
   console.log(`${colors.bright}${colors.cyan}Utter${colors.reset} ${colors.dim}Relay Server${colors.reset}`);
   console.log(`${colors.gray}${'─'.repeat(60)}${colors.reset}`);
+  if (SYSTEMD_SOCKET || SOCKET_PATH) {
+    const where = SYSTEMD_SOCKET ? 'the socket from systemd' : SOCKET_PATH;
+    console.log(`${colors.green}●${colors.reset} Listening on ${colors.bright}${where}${colors.reset}`);
+    if (SOCKET_PATH) {
+      console.log(`  ${colors.cyan}utterd --server unix://${SOCKET_PATH}${colors.reset}`);
+    }
+    console.log('');
+    return;
+  }
   console.log(`${colors.green}●${colors.reset} Listening on ${colors.bright}*:${PORT}${colors.reset}`);
   if (DEBUG) {
     console.log(`${colors.yellow}⚠${colors.reset}  Debug mode ${colors.bright}ENABLED${colors.reset}`);
@@ -190,6 +277,9 @@ httpServer.listen(PORT, () => {
   console.log('');
   console.log(`${colors.dim}HTTP Endpoints:${colors.reset}`);
   console.log(`  ${colors.cyan}POST http://localhost:${PORT}/auth${colors.reset} ${colors.dim}(obtain JWT)${colors.reset}`);
+  if (ANONYMOUS_AUTH) {
+    console.log(`  ${colors.cyan}POST http://localhost:${PORT}/auth/anonymous${colors.reset} ${colors.dim}(obtain JWT without Google)${colors.reset}`);
+  }
   console.log(`  ${colors.cyan}GET  http://localhost:${PORT}/health${colors.reset} ${colors.dim}(health check)${colors.reset}`);
   console.log('');
   console.log(`${colors.dim}WebSocket Endpoints:${colors.reset}`);
@@ -211,15 +301,17 @@ httpServer.listen(PORT, () => {
   console.log('');
 });
 
-wss.on('connection', (ws: WebSocket) => {
+wss.on('connection', (ws: WebSocket, req: http.IncomingMessage) => {
   const clientId = generateId();
+  const authorization = req.headers.authorization;
 
   const client: Client = {
     ws,
     id: clientId,
     type: 'unknown',
     status: 'online',
-    connectedAt: new Date()
+    connectedAt: new Date(),
+    bearerJwt: authorization?.startsWith('Bearer ') ? authorization.slice('Bearer '.length).trim() : undefined
   };
 
   clients.set(clientId, client);
@@ -247,6 +339,31 @@ wss.on('connection', (ws: WebSocket) => {
           handleText(client, message);
           break;
 
+        case 'get_capabilities':
+          handleGetCapabilities(client);
+          break;
+
+        case 'get_config':
+          handleGetConfig(client);
+          break;
+
+        case 'put_config':
+          handlePutConfig(client, message);
+          break;
+
+        case 'pair_response':
+          handlePairResponse(client, message);
+          break;
+
+        case 'key_rotated':
+        case 'session_key':
+          handleKeyNotice(client, message);
+          break;
+
+        case 'ack':
+          handleAck(client, message);
+          break;
+
         case 'ping':
           const pong = { type: 'pong', timestamp: Date.now() };
           debug(`${colors.magenta}→ OUT${colors.reset} [${clientId}] ${JSON.stringify(pong)}`);
@@ -265,6 +382,7 @@ wss.on('connection', (ws: WebSocket) => {
     const name = client.deviceName || client.deviceId || clientId;
     console.log(`${colors.dim}[${clientId}]${colors.reset} ${colors.red}●${colors.reset} ${colors.red}DOWN${colors.reset} ${colors.dim}${name}${colors.reset}`);
     clients.delete(clientId);
+    if (client.userId) pushDevices(client.userId);
   });
 
   ws.on('error', (error) => {
@@ -283,8 +401,25 @@ wss.on('connection', (ws: WebSocket) => {
 });
 
 function handleRegister(client: Client, message: any) {
-  // JWT Authentication - REQUIRED
-  if (!message.jwt) {
+  // A phone joining an anonymous account with its pairing code gets a JWT
+  // for later sessions
+  let issuedJwt: string | undefined;
+  if (!message.jwt && typeof message.pairingCode === 'string') {
+    const userId = redeemPairingCode(message.pairingCode, message.clientType);
+    if (!userId) {
+      client.ws.send(JSON.stringify({
+        type: 'error',
+        message: 'Invalid or expired pairing code',
+        timestamp: Date.now()
+      }));
+      return;
+    }
+    issuedJwt = signJWT(userId);
+  }
+
+  // JWT Authentication - REQUIRED, in the message or the upgrade request's Authorization header
+  const jwt = message.jwt || issuedJwt || client.bearerJwt;
+  if (!jwt) {
     console.error(`${colors.dim}[${client.id}]${colors.reset} ${colors.red}✗${colors.reset} JWT required but not provided`);
     client.ws.send(JSON.stringify({
       type: 'error',
@@ -297,7 +432,7 @@ function handleRegister(client: Client, message: any) {
   // Verify JWT
   let authenticatedUserId: string;
   try {
-    const payload = verifyJWT(message.jwt);
+    const payload = verifyJWT(jwt);
     authenticatedUserId = payload.userId;
   } catch (error: any) {
     console.error(`${colors.dim}[${client.id}]${colors.reset} ${colors.red}✗${colors.reset} JWT verification failed:`, error.message);
@@ -329,6 +464,18 @@ function handleRegister(client: Client, message: any) {
     }
   }
 
+  if (message.ciphers !== undefined) {
+    if (!Array.isArray(message.ciphers) || message.ciphers.length > 8 || !message.ciphers.every((c: unknown) => typeof c === 'string' && c.length <= 32)) {
+      client.ws.send(JSON.stringify({
+        type: 'error',
+        message: 'ciphers must be an array of cipher names',
+        timestamp: Date.now()
+      }));
+      return;
+    }
+    client.ciphers = message.ciphers;
+  }
+
   client.type = message.clientType || 'unknown';
   client.deviceId = message.deviceId || client.id;
   client.deviceName = message.deviceName || `${client.type}-${client.id}`;
@@ -357,10 +504,58 @@ function handleRegister(client: Client, message: any) {
     deviceId: client.deviceId,
     clientType: client.type,
     userId: client.userId,
+    ...(issuedJwt && { jwt: issuedJwt, expiresIn: getExpirationSeconds() }),
     timestamp: Date.now()
   };
   debug(`${colors.magenta}→ OUT${colors.reset} [${client.id}] ${JSON.stringify(registeredMsg)}`);
   client.ws.send(JSON.stringify(registeredMsg));
+
+  // Targets show which phones can reach them; tell them about this one
+  pushDevices(client.userId);
+
+  // An anonymous desktop shows a code for its phones to join with
+  if (ANONYMOUS_AUTH && client.type === 'target' && client.userId.startsWith('anon:')) {
+    issuePairingCode(client.userId);
+  }
+}
+
+// Replace the account's pairing code and send it to its targets; a new one
+// follows when it expires, while a target is still connected
+function issuePairingCode(userId: string) {
+  for (const [code, pending] of pairingCodes) {
+    if (pending.userId === userId) {
+      clearTimeout(pending.timer);
+      pairingCodes.delete(code);
+    }
+  }
+
+  const targets = Array.from(clients.values()).filter(c => c.userId === userId && c.type === 'target' && c.ws.readyState === WebSocket.OPEN);
+  if (targets.length === 0) return;
+
+  let code: string;
+  do {
+    code = Array.from({ length: 8 }, () => PAIRING_CODE_ALPHABET[randomInt(PAIRING_CODE_ALPHABET.length)]).join('');
+  } while (pairingCodes.has(code));
+  const timer = setTimeout(() => issuePairingCode(userId), PAIRING_CODE_TTL * 1000);
+  timer.unref();
+  pairingCodes.set(code, { userId, timer });
+
+  const codeMsg = { type: 'pairing_code', code, expiresIn: PAIRING_CODE_TTL, timestamp: Date.now() };
+  for (const target of targets) {
+    debug(`${colors.magenta}→ OUT${colors.reset} [${target.id}] ${JSON.stringify(codeMsg)}`);
+    target.ws.send(JSON.stringify(codeMsg));
+  }
+}
+
+// The account a pairing code lets a phone join, using up the code; only
+// phones and controllers may join this way
+function redeemPairingCode(code: string, clientType: string): string | undefined {
+  if (!ANONYMOUS_AUTH || clientType === 'target') return undefined;
+  const pending = pairingCodes.get(code.toUpperCase().replace(/[\s-]/g, ''));
+  if (!pending) return undefined;
+
+  issuePairingCode(pending.userId);
+  return pending.userId;
 }
 
 function handleGetDevices(client: Client) {
@@ -394,8 +589,11 @@ function handleGetDevices(client: Client) {
         deviceType: c.type as 'controller' | 'target',
         userId: c.userId || 'test-user',
         publicKey: c.publicKey,
+        ciphers: c.ciphers,
         status: c.status,
-        lastConnected: c.connectedAt
+        lastConnected: c.connectedAt,
+        version: c.version,
+        platform: c.platform
       });
     } else {
       if (c.userId !== client.userId) {
@@ -418,7 +616,209 @@ function handleGetDevices(client: Client) {
   client.ws.send(JSON.stringify(response));
 }
 
-function handleMessage(sender: Client, message: any) {
+// Send each of the user's targets the current device list, as if it had asked
+function pushDevices(userId: string) {
+  clients.forEach((c) => {
+    if (c.userId === userId && c.type === 'target' && c.ws.readyState === WebSocket.OPEN) {
+      handleGetDevices(c);
+    }
+  });
+}
+
+function handleGetCapabilities(client: Client) {
+  const response = {
+    type: 'capabilities',
+    version: RELAY_VERSION,
+    protocol: PROTOCOL_VERSION,
+    features: FEATURES,
+    maxMessageLength: MAX_MESSAGE_LENGTH,
+    timestamp: Date.now()
+  };
+  debug(`${colors.magenta}→ OUT${colors.reset} [${client.id}] ${JSON.stringify(response)}`);
+  client.ws.send(JSON.stringify(response));
+}
+
+function handleGetConfig(client: Client) {
+  if (!client.userId) {
+    client.ws.send(JSON.stringify({ type: 'error', message: 'Register before syncing config', timestamp: Date.now() }));
+    return;
+  }
+
+  const stored = syncedConfigs.get(client.userId);
+  const response = {
+    type: 'config',
+    version: stored?.version ?? 0,
+    blob: stored?.blob,
+    nonce: stored?.nonce,
+    updatedBy: stored?.updatedBy,
+    updatedAt: stored?.updatedAt,
+    timestamp: Date.now()
+  };
+  debug(`${colors.magenta}→ OUT${colors.reset} [${client.id}] config v${response.version}`);
+  client.ws.send(JSON.stringify(response));
+}
+
+function handlePutConfig(client: Client, message: any) {
+  if (!client.userId) {
+    client.ws.send(JSON.stringify({ type: 'error', message: 'Register before syncing config', timestamp: Date.now() }));
+    return;
+  }
+
+  if (typeof message.blob !== 'string' || typeof message.nonce !== 'string' || typeof message.baseVersion !== 'number') {
+    client.ws.send(JSON.stringify({ type: 'error', message: 'put_config requires blob, nonce and baseVersion', timestamp: Date.now() }));
+    return;
+  }
+
+  if (message.blob.length > MAX_CONFIG_BLOB_LENGTH) {
+    client.ws.send(JSON.stringify({
+      type: 'error',
+      message: `Config too large (${message.blob.length}/${MAX_CONFIG_BLOB_LENGTH} characters)`,
+      timestamp: Date.now()
+    }));
+    return;
+  }
+
+  // Only accept uploads based on the latest version, so two desktops
+  // editing at once can't silently overwrite each other
+  const current = syncedConfigs.get(client.userId)?.version ?? 0;
+  if (message.baseVersion !== current) {
+    console.log(`${colors.dim}[${client.id}]${colors.reset} ${colors.yellow}⚠${colors.reset} Config conflict: based on v${message.baseVersion}, relay has v${current}`);
+    client.ws.send(JSON.stringify({ type: 'config_conflict', version: current, timestamp: Date.now() }));
+    return;
+  }
+
+  const version = current + 1;
+  syncedConfigs.set(client.userId, {
+    version,
+    blob: message.blob,
+    nonce: message.nonce,
+    updatedBy: client.deviceName || client.deviceId || client.id,
+    updatedAt: Date.now()
+  });
+  console.log(`${colors.dim}[${client.id}]${colors.reset} ${colors.green}✓${colors.reset} Config synced (v${version})`);
+  client.ws.send(JSON.stringify({ type: 'config_saved', version, timestamp: Date.now() }));
+}
+
+// The desktop's answer to a pairing request, passed back to the phone
+function handlePairResponse(sender: Client, message: any) {
+  let target: Client | undefined;
+  clients.forEach((client) => {
+    if (client.deviceId === message.to && client.userId === sender.userId) {
+      target = client;
+    }
+  });
+
+  if (!target || target.ws.readyState !== WebSocket.OPEN) {
+    debug(`Pair response for offline device ${message.to} dropped`);
+    return;
+  }
+
+  console.log(`${colors.dim}[${sender.id}]${colors.reset} ${message.accepted ? colors.green + '✓' : colors.yellow + '✗'}${colors.reset} Pairing ${message.accepted ? 'accepted' : 'rejected'} by ${sender.deviceName}`);
+  target.ws.send(JSON.stringify({
+    type: 'pair_response',
+    from: sender.deviceId || sender.id,
+    deviceName: sender.deviceName,
+    accepted: message.accepted === true,
+    timestamp: Date.now()
+  }));
+}
+
+// A target's new public key after a rotation, or its current session key,
+// passed to a paired phone with the proof the phone checks against its pinned key
+function handleKeyNotice(sender: Client, message: any) {
+  if (typeof message.publicKey !== 'string' || typeof message.proof !== 'string') {
+    debug(`Malformed ${message.type} from ${sender.id} dropped`);
+    return;
+  }
+
+  let target: Client | undefined;
+  clients.forEach((client) => {
+    if (client.deviceId === message.to && client.userId === sender.userId) {
+      target = client;
+    }
+  });
+
+  if (!target || target.ws.readyState !== WebSocket.OPEN) {
+    debug(`${message.type} for offline device ${message.to} dropped`);
+    return;
+  }
+
+  target.ws.send(JSON.stringify({
+    type: message.type,
+    from: sender.deviceId || sender.id,
+    deviceName: sender.deviceName,
+    publicKey: message.publicKey,
+    proof: message.proof,
+    timestamp: Date.now()
+  }));
+}
+
+// A target's report on a message it received (typed, held, failed...), passed back to the phone
+function handleAck(sender: Client, message: any) {
+  if (typeof message.id !== 'string' || typeof message.status !== 'string') {
+    debug(`Malformed ack from ${sender.id} dropped`);
+    return;
+  }
+
+  let target: Client | undefined;
+  clients.forEach((client) => {
+    if (client.deviceId === message.to && client.userId === sender.userId) {
+      target = client;
+    }
+  });
+
+  if (!target || target.ws.readyState !== WebSocket.OPEN) {
+    debug(`Ack for offline device ${message.to} dropped`);
+    return;
+  }
+
+  target.ws.send(JSON.stringify({
+    type: 'ack',
+    from: sender.deviceId || sender.id,
+    id: message.id,
+    status: message.status,
+    error: typeof message.error === 'string' ? message.error : undefined,
+    timestamp: Date.now()
+  }));
+}
+
+// A broadcast's content key wrapped for one target
+interface KeySlot {
+  to: string;
+  ephemeralPublicKey: string;
+  nonce: string;
+  key: string;
+}
+
+const MAX_RECIPIENTS = 8;
+
+function isKeySlot(slot: any): slot is KeySlot {
+  return typeof slot === 'object' && slot !== null
+    && ['to', 'ephemeralPublicKey', 'nonce', 'key'].every((field) => typeof slot[field] === 'string');
+}
+
+function handleMessage(sender: Client, message: any, slot?: KeySlot) {
+  // Broadcast: one ciphertext for several targets, each sent only its own key slot
+  if (message.recipients !== undefined && slot === undefined) {
+    const recipients = message.recipients;
+    const broadcastable = message.kind === undefined || message.kind === 'correction' || message.kind === 'partial';
+    if (!Array.isArray(recipients) || recipients.length < 1 || recipients.length > MAX_RECIPIENTS
+        || !recipients.every(isKeySlot) || !broadcastable) {
+      sender.ws.send(JSON.stringify({
+        type: 'error',
+        message: `Broadcasts need 1 to ${MAX_RECIPIENTS} key slots with to, ephemeralPublicKey, nonce and key, and only text can be broadcast`,
+        timestamp: Date.now()
+      }));
+      return;
+    }
+    // The same id for every target, so the acks can be matched up
+    const id = message.id || generateId();
+    for (const recipient of recipients) {
+      handleMessage(sender, { ...message, to: recipient.to, id }, recipient);
+    }
+    return;
+  }
+
   const targetDeviceId = message.to;
   const content = message.content;
 
@@ -453,6 +853,51 @@ function handleMessage(sender: Client, message: any) {
     return;
   }
 
+  // Corrections replace an earlier message and must say which one
+  const isCorrection = message.kind === 'correction';
+  if (isCorrection && !message.id) {
+    sender.ws.send(JSON.stringify({
+      type: 'error',
+      message: 'Correction requires the id of the message it replaces',
+      timestamp: Date.now()
+    }));
+    return;
+  }
+
+  // Interim speech-recognition results, superseded by the final text with the same id
+  const isPartial = message.kind === 'partial';
+  if (isPartial && !message.id) {
+    sender.ws.send(JSON.stringify({
+      type: 'error',
+      message: 'Partial result requires the id of its utterance',
+      timestamp: Date.now()
+    }));
+    return;
+  }
+
+  // One part of a message too long for MAX_MESSAGE_LENGTH; the target joins
+  // the parts sharing an id before decrypting
+  const isChunk = message.part !== undefined || message.parts !== undefined;
+  if (isChunk && (!message.id || message.kind || !Number.isSafeInteger(message.part) || !Number.isSafeInteger(message.parts)
+      || message.part < 1 || message.part > message.parts)) {
+    sender.ws.send(JSON.stringify({
+      type: 'error',
+      message: 'Message parts require an id and a part between 1 and parts, and only text can be split',
+      timestamp: Date.now()
+    }));
+    return;
+  }
+
+  // Special keys (Enter, Tab, arrows) carry the encrypted key name as content
+  const isKey = message.kind === 'key';
+  // Content for the target's clipboard rather than its keyboard
+  const isClipboard = message.kind === 'clipboard';
+  // Pairing handoff: content is the phone's encrypted pairing bundle
+  const isPair = message.kind === 'pair';
+
+  // Stable id so the sender can reference this message in a later correction
+  const messageId: string = message.id || generateId();
+
   // Find target client by device ID
   let targetClient: Client | undefined;
   clients.forEach((client) => {
@@ -481,8 +926,19 @@ function handleMessage(sender: Client, message: any) {
 
   // Forward message to target
   console.log(`${colors.dim}[${sender.id}]${colors.reset} ${colors.cyan}→${colors.reset} ${colors.dim}[${targetClient.id}]${colors.reset}`);
-  const forwardedMessage: any = {
-    type: 'text',
+  const forwardedMessage: any = isPair ? {
+    type: 'pair_request',
+    content: content,
+    from: sender.deviceId || sender.id,
+    timestamp: Date.now()
+  } : isKey ? {
+    type: 'key',
+    key: content,
+    from: sender.deviceId || sender.id,
+    timestamp: message.timestamp || Date.now()
+  } : {
+    type: isCorrection ? 'correction' : isClipboard ? 'clipboard' : isPartial ? 'text_partial' : 'text',
+    id: messageId,
     content: content,
     from: sender.deviceId || sender.id,
     timestamp: message.timestamp || Date.now()
@@ -495,6 +951,32 @@ function handleMessage(sender: Client, message: any) {
     forwardedMessage.ephemeralPublicKey = message.ephemeralPublicKey;
     // Include sender's public key for authenticity verification
     forwardedMessage.senderPublicKey = sender.publicKey;
+    // The ciphertext is bound to both device ids and seq as associated data
+    if (Number.isSafeInteger(message.aad)) {
+      forwardedMessage.aad = message.aad;
+    }
+    // The message key mixes in the sender's static key (senderPublicKey)
+    if (Number.isSafeInteger(message.senderAuth)) {
+      forwardedMessage.senderAuth = message.senderAuth;
+    }
+    // AEAD picked from the recipient's advertised ciphers (AES-256-GCM if absent)
+    if (typeof message.cipher === 'string') {
+      forwardedMessage.cipher = message.cipher;
+    }
+    if (slot) {
+      forwardedMessage.recipients = [{ to: slot.to, ephemeralPublicKey: slot.ephemeralPublicKey, nonce: slot.nonce, key: slot.key }];
+    }
+  }
+
+  // Per-phone sequence number, so the target can restore the dictation order
+  // when a queued message overtakes a live one after a reconnect
+  if (!isPair && !isPartial && Number.isSafeInteger(message.seq) && message.seq >= 0) {
+    forwardedMessage.seq = message.seq;
+  }
+
+  if (isChunk) {
+    forwardedMessage.part = message.part;
+    forwardedMessage.parts = message.parts;
   }
 
   targetClient.ws.send(JSON.stringify(forwardedMessage));
@@ -503,6 +985,7 @@ function handleMessage(sender: Client, message: any) {
   sender.ws.send(JSON.stringify({
     type: 'message_sent',
     to: targetDeviceId,
+    id: messageId,
     timestamp: Date.now()
   }));
 }
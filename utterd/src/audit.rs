@@ -0,0 +1,139 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+/// Entries kept when the log is compacted
+const MAX_ENTRIES: usize = 1000;
+
+/// File size that triggers compaction (down to half of it), checked on
+/// every append instead of parsing the file
+const COMPACT_BYTES: u64 = 512 * 1024;
+
+/// A message refused for security reasons
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Rejection {
+    pub timestamp: DateTime<Utc>,
+    /// Device id the relay gave as the sender; anyone on the relay can claim any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    /// Fingerprint of the public key the sender presented
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<String>,
+    /// "Rejected plaintext message", "Decryption failed: ...", ...
+    pub reason: String,
+}
+
+/// Persistent log of refused messages: plaintext, failed decryption,
+/// unpaired senders, changed keys and replays
+///
+/// A steady trickle of these means someone is probing this desktop. Stored
+/// as JSON lines in ~/.local/share/utterd/rejected.jsonl (owner-only).
+pub struct AuditLog {
+    path: PathBuf,
+}
+
+impl AuditLog {
+    pub fn new() -> Result<Self, String> {
+        let data_dir = dirs::data_dir()
+            .ok_or("Cannot determine data directory")?
+            .join("utterd");
+        fs::create_dir_all(&data_dir).map_err(|e| format!("Failed to create data directory: {}", e))?;
+        Ok(Self { path: data_dir.join("rejected.jsonl") })
+    }
+
+    /// Append an entry, compacting the file once it grows past `COMPACT_BYTES`
+    pub fn append(&self, rejection: &Rejection) -> Result<(), String> {
+        let error = |e: std::io::Error| format!("Failed to write {}: {}", self.path.display(), e);
+        let line = serde_json::to_string(rejection).map_err(|e| format!("Failed to serialize rejection: {}", e))?;
+
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path).map_err(error)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&self.path, fs::Permissions::from_mode(0o600)).map_err(error)?;
+        }
+        writeln!(file, "{}", line).map_err(error)?;
+
+        if file.metadata().map_err(error)?.len() > COMPACT_BYTES {
+            self.compact(COMPACT_BYTES / 2)?;
+        }
+        Ok(())
+    }
+
+    /// Keep the newest entries, at most `MAX_ENTRIES` and `budget` bytes
+    /// (the newest one whatever its size)
+    fn compact(&self, budget: u64) -> Result<(), String> {
+        let entries = self.load()?;
+        let mut size = 0;
+        let lines: Vec<String> = entries
+            .iter()
+            .rev()
+            .take(MAX_ENTRIES)
+            .filter_map(|entry| serde_json::to_string(entry).ok())
+            .enumerate()
+            .take_while(|(index, line)| {
+                size += line.len() as u64 + 1;
+                *index == 0 || size <= budget
+            })
+            .map(|(_, line)| line + "\n")
+            .collect();
+        let contents: String = lines.into_iter().rev().collect();
+        fs::write(&self.path, contents).map_err(|e| format!("Failed to write {}: {}", self.path.display(), e))
+    }
+
+    /// All entries, oldest first (unparseable lines are skipped)
+    pub fn load(&self) -> Result<Vec<Rejection>, String> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        let contents = fs::read_to_string(&self.path)
+            .map_err(|e| format!("Failed to read {}: {}", self.path.display(), e))?;
+        Ok(contents.lines().filter_map(|line| serde_json::from_str(line).ok()).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_audit_log() {
+        let path = std::env::temp_dir().join(format!("utterd-rejected-test-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let log = AuditLog { path: path.clone() };
+
+        let now = Utc::now();
+        let rejection = |reason: &str| Rejection {
+            timestamp: now,
+            from: Some("pixel".to_string()),
+            fingerprint: None,
+            reason: reason.to_string(),
+        };
+        assert!(log.load().unwrap().is_empty());
+        log.append(&rejection("Rejected plaintext message")).unwrap();
+        log.append(&rejection("Decryption failed: aead::Error")).unwrap();
+
+        let entries = log.load().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].from.as_deref(), Some("pixel"));
+        assert_eq!(entries[1].reason, "Decryption failed: aead::Error");
+        assert!(fs::read_to_string(&path).unwrap().contains("\"from\":\"pixel\""));
+
+        // Compacted to the newest entries that fit
+        for i in 0..5 {
+            log.append(&rejection(&format!("Replayed message {}", i))).unwrap();
+        }
+        let line = fs::read_to_string(&path).unwrap().lines().last().unwrap().len() as u64 + 1;
+        log.compact(line * 3).unwrap();
+        let entries = log.load().unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[2].reason, "Replayed message 4");
+        log.compact(0).unwrap();
+        assert_eq!(log.load().unwrap().len(), 1);
+
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -18,51 +18,106 @@ pub struct AuthResponse {
     pub user_id: String,
 }
 
+/// POST to the relay's HTTP API at `auth_url`, an http(s):// URL or a
+/// unix:// socket; returns whether it succeeded and the JSON reply
+async fn post(
+    client: &reqwest::Client,
+    auth_url: &str,
+    endpoint: &str,
+    body: serde_json::Value,
+) -> Result<(bool, serde_json::Value), Box<dyn std::error::Error>> {
+    #[cfg(unix)]
+    if let Some(path) = crate::unix::socket_path(auth_url) {
+        return Ok(crate::unix::post_json(path, endpoint, &body).await?);
+    }
+
+    let response = client.post(format!("{}{}", auth_url, endpoint)).json(&body).send().await?;
+    let success = response.status().is_success();
+    Ok((success, response.json().await?))
+}
+
 pub async fn exchange_for_jwt(
+    client: &reqwest::Client,
     auth_url: &str,
     oauth_token: &str,
 ) -> Result<AuthResponse, Box<dyn std::error::Error>> {
-    let client = reqwest::Client::new();
-    let response = client
-        .post(format!("{}/auth", auth_url))
-        .json(&serde_json::json!({ "token": oauth_token }))
-        .send()
-        .await?;
-
-    if !response.status().is_success() {
-        let error: serde_json::Value = response.json().await?;
+    let (success, reply) = post(client, auth_url, "/auth", serde_json::json!({ "token": oauth_token })).await?;
+
+    if !success {
         return Err(format!(
             "JWT exchange failed: {}",
-            error["error"].as_str().unwrap_or("unknown error")
+            reply["error"].as_str().unwrap_or("unknown error")
         )
         .into());
     }
 
-    let auth_resp: AuthResponse = response.json().await?;
+    let auth_resp: AuthResponse = serde_json::from_value(reply)?;
     Ok(auth_resp)
 }
 
+/// Sign in to a relay with ANONYMOUS_AUTH using this desktop's `secret`
+/// (`anonymous_secret`) instead of a Google token
+pub async fn exchange_anonymous(
+    client: &reqwest::Client,
+    auth_url: &str,
+    secret: &str,
+) -> Result<AuthResponse, Box<dyn std::error::Error>> {
+    let (success, reply) = post(client, auth_url, "/auth/anonymous", serde_json::json!({ "secret": secret })).await?;
+
+    if !success {
+        return Err(format!(
+            "Anonymous sign-in failed: {}",
+            reply["error"].as_str().unwrap_or("unknown error")
+        )
+        .into());
+    }
+
+    let auth_resp: AuthResponse = serde_json::from_value(reply)?;
+    Ok(auth_resp)
+}
+
+/// The secret this desktop signs in with under `--anonymous`, base64; made
+/// on first use and kept in anonymous.secret (owner-only) next to the keys
+///
+/// The relay account is derived from it, so whoever has it can sign in as
+/// this desktop's account.
+pub fn anonymous_secret(profile: Option<&str>) -> Result<String, String> {
+    let dir = crate::config::identity_dir(profile).ok_or("Cannot determine config directory")?;
+    let path = dir.join("anonymous.secret");
+    if let Ok(secret) = std::fs::read_to_string(&path) {
+        return Ok(secret.trim().to_string());
+    }
+
+    let mut bytes = zeroize::Zeroizing::new([0u8; 32]);
+    rand::RngCore::fill_bytes(&mut rand::rngs::OsRng, bytes.as_mut_slice());
+    let secret = base64::engine::general_purpose::STANDARD.encode(bytes.as_slice());
+    let error = |e: std::io::Error| format!("Failed to write {}: {}", path.display(), e);
+    std::fs::create_dir_all(&dir).map_err(error)?;
+    std::fs::write(&path, format!("{}\n", secret)).map_err(error)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).map_err(error)?;
+    }
+    Ok(secret)
+}
+
 pub async fn refresh_jwt(
+    client: &reqwest::Client,
     auth_url: &str,
     current_jwt: &str,
 ) -> Result<AuthResponse, Box<dyn std::error::Error>> {
-    let client = reqwest::Client::new();
-    let response = client
-        .post(format!("{}/auth/refresh", auth_url))
-        .json(&serde_json::json!({ "jwt": current_jwt }))
-        .send()
-        .await?;
-
-    if !response.status().is_success() {
-        let error: serde_json::Value = response.json().await?;
+    let (success, reply) = post(client, auth_url, "/auth/refresh", serde_json::json!({ "jwt": current_jwt })).await?;
+
+    if !success {
         return Err(format!(
             "JWT refresh failed: {}",
-            error["error"].as_str().unwrap_or("unknown error")
+            reply["error"].as_str().unwrap_or("unknown error")
         )
         .into());
     }
 
-    let auth_resp: AuthResponse = response.json().await?;
+    let auth_resp: AuthResponse = serde_json::from_value(reply)?;
     Ok(auth_resp)
 }
 
@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Parts of a dictation that haven't all arrived by now won't
+const ABANDONED: Duration = Duration::from_secs(60);
+
+/// Most parts one dictation may be split into
+const MAX_PARTS: usize = 256;
+
+/// Puts long dictations back together
+///
+/// To stay under the relay's message size limit the phone splits a Text's
+/// ciphertext into numbered parts (`part` 1 to `parts`) sharing the
+/// message id. They're joined here, before decryption.
+pub struct Chunks {
+    /// Longest content accepted, whole or in parts (`--max-length`)
+    max_length: usize,
+    pending: HashMap<String, Pending>,
+}
+
+struct Pending {
+    parts: Vec<Option<String>>,
+    length: usize,
+    started: Instant,
+}
+
+impl Chunks {
+    pub fn new(max_length: usize) -> Self {
+        Self {
+            max_length,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Check an unsplit message's length
+    pub fn check(&self, content: &str) -> Result<(), String> {
+        if content.len() > self.max_length {
+            return Err(format!("longer than {} characters", self.max_length));
+        }
+        Ok(())
+    }
+
+    /// Take part `part` of `parts` of message `key`; returns the whole
+    /// content once every part is in
+    pub fn add(&mut self, key: &str, part: usize, parts: usize, content: String, now: Instant) -> Result<Option<String>, String> {
+        self.pending.retain(|_, pending| now.duration_since(pending.started) < ABANDONED);
+
+        if parts > MAX_PARTS {
+            return Err(format!("split into {} parts, more than {}", parts, MAX_PARTS));
+        }
+        if part == 0 || part > parts {
+            return Err(format!("part {} of {}", part, parts));
+        }
+
+        let pending = self.pending.entry(key.to_string()).or_insert_with(|| Pending {
+            parts: vec![None; parts],
+            length: 0,
+            started: now,
+        });
+        if pending.parts.len() != parts {
+            self.pending.remove(key);
+            return Err("parts disagree on how many there are".to_string());
+        }
+
+        // A resent part replaces the earlier copy
+        pending.length += content.len();
+        if let Some(previous) = pending.parts[part - 1].replace(content) {
+            pending.length -= previous.len();
+        }
+        if pending.length > self.max_length {
+            self.pending.remove(key);
+            return Err(format!("longer than {} characters", self.max_length));
+        }
+
+        if pending.parts.iter().any(Option::is_none) {
+            return Ok(None);
+        }
+        let whole = self.pending.remove(key).map(|pending| pending.parts.into_iter().flatten().collect());
+        Ok(whole)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunks() {
+        let now = Instant::now();
+        let mut chunks = Chunks::new(10);
+
+        // Out of order and with a resend, joined in part order
+        assert_eq!(chunks.add("pixel/1", 2, 3, "def".to_string(), now), Ok(None));
+        assert_eq!(chunks.add("pixel/1", 1, 3, "abc".to_string(), now), Ok(None));
+        assert_eq!(chunks.add("pixel/1", 1, 3, "abc".to_string(), now), Ok(None));
+        assert_eq!(chunks.add("pixel/1", 3, 3, "g".to_string(), now), Ok(Some("abcdefg".to_string())));
+
+        assert!(chunks.add("pixel/2", 1, 2, "abcdef".to_string(), now).is_ok());
+        assert_eq!(chunks.add("pixel/2", 2, 2, "ghijk".to_string(), now), Err("longer than 10 characters".to_string()));
+        assert!(chunks.add("pixel/3", 3, 2, "a".to_string(), now).is_err());
+        assert!(chunks.add("pixel/4", 1, MAX_PARTS + 1, "a".to_string(), now).is_err());
+        assert!(chunks.check("abcdefghijk").is_err());
+
+        // Parts that stop coming are forgotten
+        assert_eq!(chunks.add("pixel/5", 1, 2, "ab".to_string(), now), Ok(None));
+        assert_eq!(chunks.add("pixel/5", 2, 2, "cd".to_string(), now + ABANDONED), Ok(None));
+    }
+}
@@ -0,0 +1,51 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Clipboard tools to try, in order, for the current session
+fn candidates() -> Vec<(&'static str, &'static [&'static str])> {
+    if cfg!(target_os = "macos") {
+        vec![("pbcopy", &[])]
+    } else if cfg!(windows) {
+        vec![("clip", &[])]
+    } else if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        vec![("wl-copy", &[]), ("xclip", &["-selection", "clipboard"]), ("xsel", &["--clipboard", "--input"])]
+    } else {
+        vec![("xclip", &["-selection", "clipboard"]), ("xsel", &["--clipboard", "--input"])]
+    }
+}
+
+/// Copy text to the system clipboard
+pub fn copy(text: &str) -> Result<(), String> {
+    copy_via(text, |program| Command::new(program))
+}
+
+/// Copy text, building each clipboard tool's command with `command`
+/// (e.g. to point it at the typing target's display)
+pub fn copy_via(text: &str, command: impl Fn(&str) -> Command) -> Result<(), String> {
+    for (program, args) in candidates() {
+        let Ok(mut child) = command(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+        else {
+            continue;
+        };
+
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin
+                .write_all(text.as_bytes())
+                .map_err(|e| format!("Failed to write to {}: {}", program, e))?;
+        }
+
+        let status = child
+            .wait()
+            .map_err(|e| format!("{} failed: {}", program, e))?;
+        if status.success() {
+            return Ok(());
+        }
+    }
+
+    Err("No clipboard tool found (install wl-clipboard or xclip)".to_string())
+}
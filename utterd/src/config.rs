@@ -0,0 +1,242 @@
+use crate::typing::FocusedWindow;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::PathBuf;
+
+/// Optional settings from ~/.config/utterd/config.toml
+///
+/// Every key may be omitted; a missing file means all defaults.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Shell command run (and waited for) before each dictation is typed
+    pub pre_type_command: Option<String>,
+    /// Shell command run after each dictation is typed
+    pub post_type_command: Option<String>,
+    /// Extra options passed to the typing tool's type command, keyed by tool
+    pub tool_args: HashMap<String, Vec<String>>,
+    /// Window classes never typed into, as `|`-separated case-insensitive
+    /// substrings (e.g. "polkit|pinentry|lock")
+    pub do_not_type_classes: Option<String>,
+    /// Workspaces (virtual desktop numbers, from 0) never typed into
+    pub do_not_type_workspaces: Vec<u32>,
+    /// Typing settings per window class; the first match applies
+    pub profiles: Vec<Profile>,
+    /// Relays to fail over between, used when `--server` isn't given
+    pub servers: Vec<String>,
+    /// Extra headers for the relay handshake (`[headers]`), under `--header`'s
+    pub headers: BTreeMap<String, String>,
+    /// Colors of the status view (`[theme]`)
+    pub theme: ThemeConfig,
+    /// Leave the mouse to the terminal, for selecting text, instead of
+    /// scrolling the status view's log with the wheel
+    pub no_mouse: bool,
+    /// Let the status view signal each dictation that arrives
+    pub alert: Option<Alert>,
+    /// Show only the length of dictated text in the status view, plain
+    /// output and `history pick`, e.g. while sharing the screen
+    pub redact: bool,
+}
+
+/// How the status view signals an incoming dictation (`alert`)
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Alert {
+    /// The terminal's bell
+    Bell,
+    /// Invert the screen for a moment
+    Flash,
+}
+
+/// The TUI's colors (`[theme]`): names such as "cyan" or "light-blue",
+/// "#rrggbb", or a 0-255 palette index; unset ones keep their default
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThemeConfig {
+    /// No colors, only bold, reverse video and dimming, e.g. for light
+    /// terminals or high contrast; NO_COLOR in the environment does the same
+    pub monochrome: bool,
+    pub accent: Option<String>,
+    pub ok: Option<String>,
+    pub warning: Option<String>,
+    pub error: Option<String>,
+    pub muted: Option<String>,
+}
+
+/// How to type into windows of some classes (`[[profiles]]`)
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    /// `|`-separated case-insensitive substrings, as for do_not_type_classes
+    pub classes: String,
+    /// Typing tool to use instead of the default
+    pub tool: Option<String>,
+    /// Pause between keystrokes (xdotool and ydotool)
+    pub delay_ms: Option<u64>,
+    /// Press Enter after the text, e.g. to run a command in a terminal
+    pub trailing_newline: bool,
+    /// Paste through the clipboard instead of typing
+    pub clipboard: bool,
+}
+
+/// Whether `class` contains one of the `|`-separated `patterns`, ignoring case
+fn class_matches(patterns: &str, class: &str) -> bool {
+    let class = class.to_lowercase();
+    patterns
+        .split('|')
+        .map(|pattern| pattern.trim().to_lowercase())
+        .any(|pattern| !pattern.is_empty() && class.contains(pattern.as_str()))
+}
+
+/// ~/.config/utterd, where config.toml and other synced files live
+pub fn dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("utterd"))
+}
+
+/// Where a `--profile`'s keypair and trusted phones live: ~/.config/utterd/<profile>,
+/// or ~/.config/utterd itself without one
+pub fn identity_dir(profile: Option<&str>) -> Option<PathBuf> {
+    dir().map(|dir| match profile {
+        Some(profile) => dir.join(profile),
+        None => dir,
+    })
+}
+
+/// Parse `--profile`: letters, digits, `-` and `_`, so it's a safe directory name
+pub fn parse_profile(name: &str) -> Result<String, String> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err("use letters, digits, - and _".to_string());
+    }
+    Ok(name.to_string())
+}
+
+impl Config {
+    pub fn load() -> Result<Self, String> {
+        let Some(config_dir) = dir() else {
+            return Ok(Self::default());
+        };
+
+        let path = config_dir.join("config.toml");
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let contents = fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+
+        let config: Self = toml::from_str(&contents).map_err(|e| format!("Invalid {}: {}", path.display(), e))?;
+        config.validate().map_err(|e| format!("Invalid {}: {}", path.display(), e))?;
+        Ok(config)
+    }
+
+    /// Catch tool_args mistakes at startup rather than on the first dictation
+    ///
+    /// Arguments go before the text, so the first one must be an option;
+    /// anything else would be typed or taken as the text itself.
+    fn validate(&self) -> Result<(), String> {
+        for (tool, args) in &self.tool_args {
+            if !crate::typing::TOOLS.contains(&tool.as_str()) {
+                return Err(format!("tool_args: unknown tool {}", tool));
+            }
+            if !crate::typing::COMMAND_TOOLS.contains(&tool.as_str()) {
+                return Err(format!("tool_args: {} is not a command-line tool and takes no arguments", tool));
+            }
+            if args.first().is_some_and(|arg| !arg.starts_with('-')) {
+                return Err(format!("tool_args.{}: first argument must be an option, got {:?}", tool, args[0]));
+            }
+            if let Some(arg) = args.iter().find(|arg| arg.is_empty() || *arg == "--") {
+                return Err(format!("tool_args.{}: {:?} is not allowed", tool, arg));
+            }
+        }
+
+        crate::theme::Theme::from_config(&self.theme)?;
+
+        for profile in &self.profiles {
+            if profile.classes.split('|').all(|pattern| pattern.trim().is_empty()) {
+                return Err("profiles: every profile needs classes".to_string());
+            }
+            if let Some(ref tool) = profile.tool {
+                if !crate::typing::TOOLS.contains(&tool.as_str()) {
+                    return Err(format!("profiles ({}): unknown tool {}", profile.classes, tool));
+                }
+                if profile.delay_ms.is_some_and(|ms| crate::typing::delay_args(tool, ms).is_none()) {
+                    return Err(format!("profiles ({}): {} has no keystroke delay", profile.classes, tool));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The profile for windows of `class`, if one matches
+    pub fn profile(&self, class: &str) -> Option<&Profile> {
+        self.profiles.iter().find(|profile| class_matches(&profile.classes, class))
+    }
+
+    /// Why typing into `window` is off-limits, if it is
+    pub fn do_not_type(&self, window: &FocusedWindow) -> Option<String> {
+        if self.do_not_type_classes.as_deref().is_some_and(|patterns| class_matches(patterns, &window.class)) {
+            return Some(format!("{} is focused", window.class));
+        }
+
+        window
+            .workspace
+            .filter(|workspace| self.do_not_type_workspaces.contains(workspace))
+            .map(|workspace| format!("workspace {} is active", workspace))
+    }
+
+    /// Extra type-command arguments configured for `tool`
+    pub fn tool_args(&self, tool: &str) -> Vec<String> {
+        self.tool_args.get(tool).cloned().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_tool_args() {
+        let parse = |s: &str| toml::from_str::<Config>(s).unwrap().validate();
+
+        assert!(parse("[tool_args]\nxdotool = [\"--clearmodifiers\", \"--delay\", \"20\"]").is_ok());
+        assert!(parse("[tool_args]\nxdotool = [\"20\"]").is_err());
+        assert!(parse("[tool_args]\nxdotool = [\"--\"]").is_err());
+        assert!(parse("[tool_args]\natspi = [\"--fast\"]").is_err());
+        assert!(parse("[tool_args]\nnotatool = []").is_err());
+    }
+
+    #[test]
+    fn test_do_not_type() {
+        let config: Config = toml::from_str("do_not_type_classes = \"polkit|Pinentry|lock\"\ndo_not_type_workspaces = [3]").unwrap();
+        let window = |class: &str, workspace| FocusedWindow { class: class.to_string(), title: None, workspace };
+
+        assert!(config.do_not_type(&window("pinentry-gtk-2", None)).is_some());
+        assert!(config.do_not_type(&window("xfce4-screensaver-lock", Some(0))).is_some());
+        assert!(config.do_not_type(&window("firefox", Some(3))).is_some());
+        assert!(config.do_not_type(&window("firefox", Some(1))).is_none());
+        assert!(Config::default().do_not_type(&window("polkit-agent", None)).is_none());
+    }
+
+    #[test]
+    fn test_profiles() {
+        let parse = |s: &str| toml::from_str::<Config>(s).unwrap();
+
+        let config = parse("[[profiles]]\nclasses = \"kitty|Terminal\"\ntrailing_newline = true\n[[profiles]]\nclasses = \"firefox\"\nclipboard = true");
+        assert!(config.validate().is_ok());
+        assert!(config.profile("gnome-terminal-server").is_some_and(|p| p.trailing_newline));
+        assert!(config.profile("Firefox").is_some_and(|p| p.clipboard));
+        assert!(config.profile("obsidian").is_none());
+
+        assert!(parse("[[profiles]]\nclasses = \"\"").validate().is_err());
+        assert!(parse("[[profiles]]\nclasses = \"kitty\"\ntool = \"keyd\"\ndelay_ms = 5").validate().is_err());
+    }
+
+    #[test]
+    fn test_parse_profile() {
+        assert_eq!(parse_profile("work-relay_2"), Ok("work-relay_2".to_string()));
+        assert!(parse_profile("").is_err());
+        assert!(parse_profile("../home").is_err());
+        assert!(parse_profile("trusted_devices.json").is_err());
+    }
+}
@@ -0,0 +1,52 @@
+/// The last dictation as typed, kept so a correction can be diffed against it
+#[derive(Debug, Clone)]
+pub struct Typed {
+    pub id: String,
+    pub text: String,
+}
+
+/// Keystrokes that turn already-typed text into its corrected version
+#[derive(Debug, PartialEq)]
+pub struct Edit<'a> {
+    /// Characters to erase from the end of what was typed
+    pub backspaces: usize,
+    /// Text to type afterwards
+    pub insert: &'a str,
+}
+
+/// Diff typed text against its correction
+///
+/// The common prefix is kept; everything after it is erased with Backspace
+/// and retyped. Counts are in characters since each Backspace removes one.
+pub fn edit<'a>(typed: &str, corrected: &'a str) -> Edit<'a> {
+    let prefix_bytes = typed
+        .char_indices()
+        .zip(corrected.chars())
+        .find(|((_, a), b)| a != b)
+        .map(|((i, _), _)| i)
+        // One is a prefix of the other
+        .unwrap_or_else(|| typed.len().min(corrected.len()));
+
+    Edit {
+        backspaces: typed[prefix_bytes..].chars().count(),
+        insert: &corrected[prefix_bytes..],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edit() {
+        assert_eq!(
+            edit("meet at five", "meet at nine"),
+            Edit { backspaces: 4, insert: "nine" }
+        );
+        assert_eq!(edit("hello", "hello world"), Edit { backspaces: 0, insert: " world" });
+        assert_eq!(edit("hello world", "hello"), Edit { backspaces: 6, insert: "" });
+        assert_eq!(edit("same", "same"), Edit { backspaces: 0, insert: "" });
+        // Counts characters, not bytes
+        assert_eq!(edit("café noir", "café crème"), Edit { backspaces: 4, insert: "crème" });
+    }
+}
@@ -1,50 +1,227 @@
 use aes_gcm::{
-    aead::{Aead, KeyInit, OsRng},
+    aead::{Aead, KeyInit, OsRng, Payload},
     Aes256Gcm, Nonce,
 };
 use base64::{Engine as _, engine::general_purpose};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
 use hkdf::Hkdf;
 use rand::RngCore;
-use sha2::Sha256;
-use x25519_dalek::{PublicKey as X25519PublicKey, StaticSecret};
+use secrecy::{ExposeSecret, SecretBox};
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use x25519_dalek::{PublicKey as X25519PublicKey, SharedSecret, StaticSecret};
 use serde::{Deserialize, Serialize};
+use zeroize::{Zeroize, Zeroizing};
+
+use super::keys::secret;
+
+/// AEADs a payload can be encrypted with
+///
+/// The sender picks the first of those we advertise (`SUPPORTED`) that it
+/// supports too and names it in the message's `cipher`; a message without
+/// one is AES-256-GCM.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum Cipher {
+    #[default]
+    #[serde(rename = "aes-256-gcm")]
+    Aes256Gcm,
+    /// Fast without AES instructions, and with 24-byte nonces random ones
+    /// never realistically repeat
+    #[serde(rename = "xchacha20-poly1305")]
+    XChaCha20Poly1305,
+}
+
+impl Cipher {
+    /// What utterd supports, most preferred first, as advertised when registering
+    pub const SUPPORTED: [Cipher; 2] = [Cipher::XChaCha20Poly1305, Cipher::Aes256Gcm];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Cipher::Aes256Gcm => "aes-256-gcm",
+            Cipher::XChaCha20Poly1305 => "xchacha20-poly1305",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::SUPPORTED.into_iter().find(|cipher| cipher.name() == name)
+    }
+
+    pub fn nonce_length(self) -> usize {
+        match self {
+            Cipher::Aes256Gcm => 12,
+            Cipher::XChaCha20Poly1305 => 24,
+        }
+    }
+
+    /// `nonce` must be `nonce_length` bytes
+    #[allow(deprecated)]
+    fn seal(self, key: &[u8; 32], nonce: &[u8], payload: Payload) -> Result<Vec<u8>, String> {
+        match self {
+            Cipher::Aes256Gcm => Aes256Gcm::new(key.into()).encrypt(Nonce::from_slice(nonce), payload),
+            Cipher::XChaCha20Poly1305 => XChaCha20Poly1305::new(key.into()).encrypt(XNonce::from_slice(nonce), payload),
+        }
+        .map_err(|e| format!("Encryption failed: {:?}", e))
+    }
+
+    #[allow(deprecated)]
+    fn open(self, key: &[u8; 32], nonce: &[u8], payload: Payload) -> Result<Vec<u8>, String> {
+        if nonce.len() != self.nonce_length() {
+            return Err("Invalid nonce length".to_string());
+        }
+        match self {
+            Cipher::Aes256Gcm => Aes256Gcm::new(key.into()).decrypt(Nonce::from_slice(nonce), payload),
+            Cipher::XChaCha20Poly1305 => XChaCha20Poly1305::new(key.into()).decrypt(XNonce::from_slice(nonce), payload),
+        }
+        .map_err(|e| format!("Decryption failed: {:?}", e))
+    }
+}
 
 /// Data structure for encrypted messages
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EncryptedMessage {
     pub ciphertext: String,           // base64-encoded ciphertext
-    pub nonce: String,                 // base64-encoded nonce (12 bytes for AES-GCM)
-    pub ephemeral_public_key: String, // base64-encoded X25519 ephemeral public key
+    pub nonce: String,                 // base64-encoded nonce (12 bytes for AES-GCM, 24 for XChaCha20)
+    pub ephemeral_public_key: String, // base64-encoded X25519 ephemeral public key (empty for broadcasts)
+    #[serde(default)]
+    pub cipher: Cipher,
+    /// For a broadcast: the content key wrapped for each recipient
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub recipients: Vec<KeySlot>,
+}
+
+/// A broadcast's content key, wrapped for one recipient
+///
+/// The key is encrypted with the message's cipher under a key agreed from
+/// `ephemeral_public_key` and the recipient's, bound to the sender, the
+/// recipient and the counter like a `Binding`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KeySlot {
+    /// Recipient device id
+    pub to: String,
+    pub ephemeral_public_key: String,
+    pub nonce: String,
+    /// The wrapped content key, base64
+    pub key: String,
+}
+
+/// What a ciphertext is bound to: the sender's and recipient's device ids
+/// and the sender's message counter
+///
+/// Used as the AES-GCM associated data, so a ciphertext only decrypts as
+/// the message it was sent as, not relayed to another device, reflected
+/// back the other way or passed off under another counter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Binding<'a> {
+    pub from: &'a str,
+    pub to: &'a str,
+    pub seq: Option<u64>,
+}
+
+impl Binding<'_> {
+    /// "utter-aad-v1", each id prefixed with its length (u32, big-endian),
+    /// then 0 without a counter or 1 and the counter (u64, big-endian)
+    fn associated_data(&self) -> Vec<u8> {
+        let mut aad = AAD_CONTEXT.to_vec();
+        for id in [self.from, self.to] {
+            aad.extend_from_slice(&(id.len() as u32).to_be_bytes());
+            aad.extend_from_slice(id.as_bytes());
+        }
+        match self.seq {
+            Some(seq) => {
+                aad.push(1);
+                aad.extend_from_slice(&seq.to_be_bytes());
+            }
+            None => aad.push(0),
+        }
+        aad
+    }
 }
 
 /// Handles E2E encryption/decryption using hybrid cryptography:
-/// - X25519 ECDH for key exchange
+/// - X25519 ECDH for key exchange, of the sender's ephemeral key and, if the
+///   sender authenticates (`senderAuth`), of both static keys, so only the
+///   holder of the sender's key can encrypt to us
 /// - HKDF-SHA256 for key derivation
 /// - AES-256-GCM for symmetric encryption
+///
+/// Private keys and the AES keys derived for each message are wiped from
+/// memory when dropped.
 pub struct MessageEncryption {
-    private_key: [u8; 32],
-    #[allow(dead_code)]
+    private_key: SecretBox<[u8; 32]>,
     public_key: [u8; 32],
+    /// The key replaced by a rotation, still accepted during its grace window
+    previous_private_key: Option<SecretBox<[u8; 32]>>,
+    derived: Mutex<DerivedKeys>,
+}
+
+/// Keys derived from recently seen ephemeral keys
+const DERIVED_KEPT: usize = 32;
+const DERIVED_TTL: Duration = Duration::from_secs(60);
+
+/// HKDF outputs by our static key, the sender's ephemeral key (and static
+/// key, if it authenticates) and the HKDF info, so a ciphertext delivered
+/// again (a resend after a lost ack, a replay) or tried against each of our
+/// keys doesn't redo the ECDH
+///
+/// Entries are identified by a SHA-256 of their inputs, kept for a minute
+/// at most and wiped when evicted, so they don't outlive the session keys
+/// they came from by much.
+#[derive(Default)]
+struct DerivedKeys {
+    /// Oldest first
+    entries: VecDeque<([u8; 32], Instant, Zeroizing<[u8; 32]>)>,
+}
+
+impl DerivedKeys {
+    fn get(&mut self, id: &[u8; 32], now: Instant) -> Option<Zeroizing<[u8; 32]>> {
+        self.entries.retain(|(_, derived_at, _)| now.duration_since(*derived_at) < DERIVED_TTL);
+        self.entries.iter().find(|(known, _, _)| known == id).map(|(_, _, key)| key.clone())
+    }
+
+    fn insert(&mut self, id: [u8; 32], now: Instant, key: Zeroizing<[u8; 32]>) {
+        if self.entries.len() >= DERIVED_KEPT {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((id, now, key));
+    }
 }
 
 // HKDF parameters (must match Android and relay server)
 const HKDF_SALT: &[u8] = b"utter-relay-e2e-2024";
 const HKDF_INFO: &[u8] = b"message-encryption-v1";
+const ROTATION_INFO: &[u8] = b"utter-key-rotation-v1";
+const SESSION_INFO: &[u8] = b"utter-session-key-v1";
+const AAD_CONTEXT: &[u8] = b"utter-aad-v1";
+const KEY_SLOT_INFO: &[u8] = b"utter-key-slot-v1";
 
 impl MessageEncryption {
     /// Create a new MessageEncryption with the device's keypair
     pub fn new(private_key: &[u8; 32], public_key: &[u8; 32]) -> Self {
         Self {
-            private_key: *private_key,
+            private_key: secret(private_key),
             public_key: *public_key,
+            previous_private_key: None,
+            derived: Mutex::default(),
         }
     }
 
+    /// Also decrypt messages sent to the key this one replaced
+    pub fn with_previous_key(mut self, previous_private_key: SecretBox<[u8; 32]>) -> Self {
+        self.previous_private_key = Some(previous_private_key);
+        self
+    }
+
     /// Encrypt a plaintext message for a specific recipient
     ///
     /// # Arguments
     /// * `plaintext` - The message to encrypt
     /// * `recipient_public_key_base64` - The recipient's Ed25519 public key (base64)
+    /// * `cipher` - The AEAD to encrypt with, as negotiated with the recipient
+    /// * `binding` - Sender, recipient and counter to bind the ciphertext to
+    /// * `sender_auth` - Whether to mix in our static key, proving who sent it
     ///
     /// # Returns
     /// Result containing EncryptedMessage with ciphertext, nonce, and ephemeral public key
@@ -53,6 +230,9 @@ impl MessageEncryption {
         &self,
         plaintext: &str,
         recipient_public_key_base64: &str,
+        cipher: Cipher,
+        binding: Option<&Binding>,
+        sender_auth: bool,
     ) -> Result<EncryptedMessage, Box<dyn std::error::Error>> {
         // 1. Generate ephemeral X25519 keypair
         let ephemeral_secret = StaticSecret::random_from_rng(OsRng);
@@ -69,28 +249,81 @@ impl MessageEncryption {
             <[u8; 32]>::try_from(recipient_bytes.as_slice())?
         );
 
-        // 3. Perform ECDH to get shared secret
+        // 3. Perform ECDH to get shared secret, from the ephemeral key and,
+        // to prove who sent it, from our static key
         let shared_secret = ephemeral_secret.diffie_hellman(&recipient_x25519);
+        let static_secret = sender_auth.then(|| self.static_secret(&recipient_x25519));
 
         // 4. Derive AES key using HKDF
-        let aes_key = self.derive_aes_key(shared_secret.as_bytes())?;
+        let aes_key = self.derive_aes_key(&key_material(&shared_secret, static_secret.as_ref()), HKDF_INFO)?;
 
-        // 5. Generate random nonce (12 bytes for AES-GCM)
-        let mut nonce_bytes = [0u8; 12];
+        // 5. Generate random nonce (12 bytes for AES-GCM, 24 for XChaCha20)
+        let mut nonce_bytes = vec![0u8; cipher.nonce_length()];
         OsRng.fill_bytes(&mut nonce_bytes);
-        #[allow(deprecated)]
-        let nonce = Nonce::from_slice(&nonce_bytes);
 
-        // 6. Encrypt with AES-256-GCM
-        let cipher = Aes256Gcm::new_from_slice(&aes_key)?;
-        let ciphertext = cipher
-            .encrypt(nonce, plaintext.as_bytes())
-            .map_err(|e| format!("Encryption failed: {:?}", e))?;
+        // 6. Encrypt with the AEAD
+        let aad = binding.map(Binding::associated_data).unwrap_or_default();
+        let ciphertext = cipher.seal(&aes_key, &nonce_bytes, Payload { msg: plaintext.as_bytes(), aad: &aad })?;
 
         Ok(EncryptedMessage {
             ciphertext: general_purpose::STANDARD.encode(&ciphertext),
-            nonce: general_purpose::STANDARD.encode(&nonce_bytes),
+            nonce: general_purpose::STANDARD.encode(nonce_bytes),
             ephemeral_public_key: general_purpose::STANDARD.encode(ephemeral_public.as_bytes()),
+            cipher,
+            recipients: Vec::new(),
+        })
+    }
+
+    /// Encrypt one message for several recipients (`(device id, public key)`)
+    ///
+    /// The plaintext is encrypted once under a random content key, bound to
+    /// the sender and counter only; each recipient gets a `KeySlot` with the
+    /// content key, bound to them as well. Phones send broadcasts; this is
+    /// how the tests make them.
+    #[cfg(test)]
+    fn encrypt_broadcast(
+        &self,
+        plaintext: &str,
+        recipients: &[(&str, &str)],
+        cipher: Cipher,
+        from: &str,
+        seq: Option<u64>,
+        sender_auth: bool,
+    ) -> Result<EncryptedMessage, Box<dyn std::error::Error>> {
+        let mut content_key = Zeroizing::new([0u8; 32]);
+        OsRng.fill_bytes(content_key.as_mut_slice());
+        let mut nonce_bytes = vec![0u8; cipher.nonce_length()];
+        OsRng.fill_bytes(&mut nonce_bytes);
+        let aad = Binding { from, to: "", seq }.associated_data();
+        let ciphertext = cipher.seal(&content_key, &nonce_bytes, Payload { msg: plaintext.as_bytes(), aad: &aad })?;
+
+        let recipients = recipients
+            .iter()
+            .map(|&(to, public_key)| {
+                let ephemeral_secret = StaticSecret::random_from_rng(OsRng);
+                let recipient = decode_public_key(public_key)?;
+                let shared_secret = ephemeral_secret.diffie_hellman(&recipient);
+                let static_secret = sender_auth.then(|| self.static_secret(&recipient));
+                let slot_key = self.derive_aes_key(&key_material(&shared_secret, static_secret.as_ref()), KEY_SLOT_INFO)?;
+                let mut slot_nonce = vec![0u8; cipher.nonce_length()];
+                OsRng.fill_bytes(&mut slot_nonce);
+                let aad = Binding { from, to, seq }.associated_data();
+                let key = cipher.seal(&slot_key, &slot_nonce, Payload { msg: content_key.as_slice(), aad: &aad })?;
+                Ok(KeySlot {
+                    to: to.to_string(),
+                    ephemeral_public_key: general_purpose::STANDARD.encode(X25519PublicKey::from(&ephemeral_secret).as_bytes()),
+                    nonce: general_purpose::STANDARD.encode(slot_nonce),
+                    key: general_purpose::STANDARD.encode(key),
+                })
+            })
+            .collect::<Result<Vec<_>, Box<dyn std::error::Error>>>()?;
+
+        Ok(EncryptedMessage {
+            ciphertext: general_purpose::STANDARD.encode(&ciphertext),
+            nonce: general_purpose::STANDARD.encode(nonce_bytes),
+            ephemeral_public_key: String::new(),
+            cipher,
+            recipients,
         })
     }
 
@@ -98,71 +331,209 @@ impl MessageEncryption {
     ///
     /// # Arguments
     /// * `encrypted` - The encrypted message
-    /// * `_sender_public_key_base64` - The sender's Ed25519 public key (currently unused)
+    /// * `sender_public_key_base64` - The sender's X25519 public key (base64)
+    ///   if it authenticated the message (`senderAuth`), which then only
+    ///   decrypts if it was encrypted with the matching private key
+    /// * `binding` - What the sender bound the ciphertext to, if it did
     ///
     /// # Returns
     /// Result containing the decrypted plaintext message
     pub fn decrypt(
         &self,
         encrypted: &EncryptedMessage,
-        _sender_public_key_base64: &str,
+        sender_public_key_base64: Option<&str>,
+        binding: Option<&Binding>,
     ) -> Result<String, Box<dyn std::error::Error>> {
-        // 1. Decode sender's ephemeral public key
-        let sender_ephemeral_bytes = general_purpose::STANDARD.decode(&encrypted.ephemeral_public_key)?;
-        if sender_ephemeral_bytes.len() != 32 {
-            return Err("Invalid ephemeral public key length".into());
+        let result = self.decrypt_with(self.private_key.expose_secret(), encrypted, sender_public_key_base64, binding);
+        match self.previous_private_key {
+            // A phone that hasn't picked up a rotation yet still uses the old key
+            Some(ref previous) if result.is_err() => {
+                self.decrypt_with(previous.expose_secret(), encrypted, sender_public_key_base64, binding).or(result)
+            }
+            _ => result,
         }
+    }
 
-        let sender_ephemeral = X25519PublicKey::from(
-            <[u8; 32]>::try_from(sender_ephemeral_bytes.as_slice())?
-        );
-
-        // 2. Use my private key for ECDH
-        let my_secret = StaticSecret::from(self.private_key);
+    /// Vouch for the current key to a phone that pinned the previous one
+    ///
+    /// HKDF-SHA256 over the ECDH secret of the previous key and the phone's,
+    /// bound to the new public key; the phone derives the same from its own
+    /// private key and the key it pinned. None when there was no rotation.
+    pub fn rotation_proof(&self, recipient_public_key_base64: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        let Some(ref previous) = self.previous_private_key else {
+            return Ok(None);
+        };
+        vouch(previous.expose_secret(), recipient_public_key_base64, ROTATION_INFO, &self.public_key).map(Some)
+    }
 
-        // 3. Perform ECDH to get shared secret (same as sender)
-        let shared_secret = my_secret.diffie_hellman(&sender_ephemeral);
+    /// Vouch for a session key to a paired phone: the same derivation as
+    /// `rotation_proof`, from the current key, bound to the session key
+    pub fn session_key_proof(
+        &self,
+        recipient_public_key_base64: &str,
+        session_public_key: &[u8; 32],
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        vouch(self.private_key.expose_secret(), recipient_public_key_base64, SESSION_INFO, session_public_key)
+    }
 
-        // 4. Derive AES key (same derivation as sender)
-        let aes_key = self.derive_aes_key(shared_secret.as_bytes())?;
+    /// Decrypt a message with the first of the session keys (newest first)
+    /// that opens it, else as `decrypt`; also returns the index of the
+    /// session key used
+    pub fn decrypt_any(
+        &self,
+        encrypted: &EncryptedMessage,
+        session_private_keys: &[[u8; 32]],
+        sender_public_key_base64: Option<&str>,
+        binding: Option<&Binding>,
+    ) -> Result<(Option<usize>, String), Box<dyn std::error::Error>> {
+        let session = session_private_keys.iter().enumerate().find_map(|(index, key)| {
+            self.decrypt_with(key, encrypted, sender_public_key_base64, binding).ok().map(|plaintext| (Some(index), plaintext))
+        });
+        match session {
+            Some(decrypted) => Ok(decrypted),
+            None => self.decrypt(encrypted, sender_public_key_base64, binding).map(|plaintext| (None, plaintext)),
+        }
+    }
 
-        // 5. Decode ciphertext and nonce
+    fn decrypt_with(
+        &self,
+        private_key: &[u8; 32],
+        encrypted: &EncryptedMessage,
+        sender_public_key_base64: Option<&str>,
+        binding: Option<&Binding>,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        // 1-2. ECDH with my private key, then derive the key (same derivation
+        // as sender): from the sender's ephemeral key (and static key, if it
+        // authenticated), or for a broadcast unwrap it from this device's slot
+        let (key, aad) = if encrypted.recipients.is_empty() {
+            let aad = binding.map(Binding::associated_data).unwrap_or_default();
+            (self.agree(private_key, &encrypted.ephemeral_public_key, sender_public_key_base64, HKDF_INFO)?, aad)
+        } else {
+            let binding = binding.ok_or("Broadcast is not bound to its recipients")?;
+            let slot = encrypted
+                .recipients
+                .iter()
+                .find(|slot| slot.to == binding.to)
+                .ok_or("Broadcast has no key slot for this device")?;
+            let slot_key = self.agree(private_key, &slot.ephemeral_public_key, sender_public_key_base64, KEY_SLOT_INFO)?;
+            let wrapped = general_purpose::STANDARD.decode(&slot.key)?;
+            let slot_nonce = general_purpose::STANDARD.decode(&slot.nonce)?;
+            let payload = Payload { msg: &wrapped, aad: &binding.associated_data() };
+            let content_key = Zeroizing::new(encrypted.cipher.open(&slot_key, &slot_nonce, payload)?);
+            if content_key.len() != 32 {
+                return Err("Invalid content key length".into());
+            }
+            let mut key = Zeroizing::new([0u8; 32]);
+            key.copy_from_slice(&content_key);
+            (key, Binding { to: "", ..*binding }.associated_data())
+        };
+
+        // 3. Decode ciphertext and nonce
         let ciphertext = general_purpose::STANDARD.decode(&encrypted.ciphertext)?;
         let nonce_bytes = general_purpose::STANDARD.decode(&encrypted.nonce)?;
 
-        if nonce_bytes.len() != 12 {
-            return Err("Invalid nonce length".into());
-        }
+        // 4. Decrypt with the AEAD the sender picked
+        let plaintext = encrypted.cipher.open(&key, &nonce_bytes, Payload { msg: &ciphertext, aad: &aad })?;
 
-        #[allow(deprecated)]
-        let nonce = Nonce::from_slice(&nonce_bytes);
+        String::from_utf8(plaintext).map_err(|e| {
+            e.into_bytes().zeroize();
+            "Decrypted message is not valid UTF-8".into()
+        })
+    }
 
-        // 6. Decrypt with AES-256-GCM
-        let cipher = Aes256Gcm::new_from_slice(&aes_key)?;
-        let plaintext = cipher
-            .decrypt(nonce, ciphertext.as_ref())
-            .map_err(|e| format!("Decryption failed: {:?}", e))?;
+    /// The key derived from the ECDH of `private_key` and a sender's
+    /// ephemeral key (and static key, if given), from the cache if it was
+    /// derived recently
+    fn agree(
+        &self,
+        private_key: &[u8; 32],
+        ephemeral_public_key_base64: &str,
+        sender_public_key_base64: Option<&str>,
+        info: &[u8],
+    ) -> Result<Zeroizing<[u8; 32]>, Box<dyn std::error::Error>> {
+        let mut id = Sha256::new().chain_update(private_key).chain_update(ephemeral_public_key_base64);
+        if let Some(sender) = sender_public_key_base64 {
+            id.update([0]);
+            id.update(sender);
+        }
+        let id: [u8; 32] = id.chain_update(info).finalize().into();
+        let now = Instant::now();
+        if let Some(key) = self.derived.lock().map_err(|_| "Derived key cache poisoned")?.get(&id, now) {
+            return Ok(key);
+        }
 
-        Ok(String::from_utf8(plaintext)?)
+        let private_key = StaticSecret::from(*private_key);
+        let shared_secret = private_key.diffie_hellman(&decode_public_key(ephemeral_public_key_base64)?);
+        let static_secret = match sender_public_key_base64 {
+            Some(sender) => Some(private_key.diffie_hellman(&decode_public_key(sender)?)),
+            None => None,
+        };
+        let key = self.derive_aes_key(&key_material(&shared_secret, static_secret.as_ref()), info)?;
+        self.derived.lock().map_err(|_| "Derived key cache poisoned")?.insert(id, now, key.clone());
+        Ok(key)
     }
 
     /// Derive AES-256 key from shared secret using HKDF-SHA256
     ///
     /// # Arguments
-    /// * `shared_secret` - The ECDH shared secret
+    /// * `shared_secret` - The ECDH shared secret(s), as `key_material`
+    /// * `info` - What the key is for: a message, or a broadcast's key slot
     ///
     /// # Returns
     /// Result containing the AES-256 key (32 bytes)
-    fn derive_aes_key(&self, shared_secret: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    fn derive_aes_key(&self, shared_secret: &[u8], info: &[u8]) -> Result<Zeroizing<[u8; 32]>, Box<dyn std::error::Error>> {
         // HKDF-Extract + HKDF-Expand
         let hkdf = Hkdf::<Sha256>::new(Some(HKDF_SALT), shared_secret);
 
-        let mut okm = vec![0u8; 32]; // 32 bytes for AES-256
-        hkdf.expand(HKDF_INFO, &mut okm)
+        let mut okm = Zeroizing::new([0u8; 32]); // 32 bytes for AES-256
+        hkdf.expand(info, okm.as_mut_slice())
             .map_err(|e| format!("HKDF failed: {:?}", e))?;
 
         Ok(okm)
     }
+
+    /// The ECDH secret of our static key and `recipient`'s
+    fn static_secret(&self, recipient: &X25519PublicKey) -> SharedSecret {
+        StaticSecret::from(*self.private_key.expose_secret()).diffie_hellman(recipient)
+    }
+}
+
+/// HKDF input for a message key: the ephemeral-static ECDH secret, then for
+/// an authenticated sender the static-static one that proves it holds its key
+fn key_material(shared_secret: &SharedSecret, static_secret: Option<&SharedSecret>) -> Zeroizing<Vec<u8>> {
+    let mut material = Zeroizing::new(shared_secret.as_bytes().to_vec());
+    if let Some(static_secret) = static_secret {
+        material.extend_from_slice(static_secret.as_bytes());
+    }
+    material
+}
+
+/// An X25519 public key from base64
+fn decode_public_key(base64: &str) -> Result<X25519PublicKey, Box<dyn std::error::Error>> {
+    let bytes = general_purpose::STANDARD.decode(base64)?;
+    let bytes = <[u8; 32]>::try_from(bytes.as_slice()).map_err(|_| "Invalid public key length")?;
+    Ok(X25519PublicKey::from(bytes))
+}
+
+/// HKDF-SHA256 over the ECDH secret of `private_key` and the recipient's
+/// key, with `info` and then `vouched_key` as the info
+fn vouch(
+    private_key: &[u8; 32],
+    recipient_public_key_base64: &str,
+    info: &[u8],
+    vouched_key: &[u8; 32],
+) -> Result<String, Box<dyn std::error::Error>> {
+    let recipient_bytes = general_purpose::STANDARD.decode(recipient_public_key_base64)?;
+    let recipient = X25519PublicKey::from(
+        <[u8; 32]>::try_from(recipient_bytes.as_slice()).map_err(|_| "Invalid recipient public key length")?
+    );
+    let shared_secret = StaticSecret::from(*private_key).diffie_hellman(&recipient);
+
+    let hkdf = Hkdf::<Sha256>::new(Some(HKDF_SALT), shared_secret.as_bytes());
+    let mut proof = [0u8; 32];
+    hkdf.expand_multi_info(&[info, vouched_key], &mut proof)
+        .map_err(|e| format!("HKDF failed: {:?}", e))?;
+    Ok(general_purpose::STANDARD.encode(proof))
 }
 
 #[cfg(test)]
@@ -173,27 +544,254 @@ mod tests {
     fn test_encrypt_decrypt_roundtrip() {
         // Generate two keypairs (sender and receiver)
         let sender_private = [1u8; 32];
-        let sender_public = [2u8; 32];
+        let sender_public = *X25519PublicKey::from(&StaticSecret::from(sender_private)).as_bytes();
         let receiver_private = [3u8; 32];
-        let receiver_public = [4u8; 32];
+        let receiver_public = *X25519PublicKey::from(&StaticSecret::from(receiver_private)).as_bytes();
 
         let sender_encryption = MessageEncryption::new(&sender_private, &sender_public);
         let receiver_encryption = MessageEncryption::new(&receiver_private, &receiver_public);
 
         let plaintext = "Hello, World!";
-        let receiver_public_b64 = base64::encode(&receiver_public);
+        let receiver_public_b64 = general_purpose::STANDARD.encode(receiver_public);
 
         // Encrypt
         let encrypted = sender_encryption
-            .encrypt(plaintext, &receiver_public_b64)
+            .encrypt(plaintext, &receiver_public_b64, Cipher::Aes256Gcm, None, true)
             .expect("Encryption failed");
 
         // Decrypt
-        let sender_public_b64 = base64::encode(&sender_public);
+        let sender_public_b64 = general_purpose::STANDARD.encode(sender_public);
         let decrypted = receiver_encryption
-            .decrypt(&encrypted, &sender_public_b64)
+            .decrypt(&encrypted, Some(&sender_public_b64), None)
             .expect("Decryption failed");
 
         assert_eq!(plaintext, decrypted);
     }
+
+    #[test]
+    fn test_ciphers() {
+        let public = |private: [u8; 32]| *X25519PublicKey::from(&StaticSecret::from(private)).as_bytes();
+        let (desktop_private, phone_private) = ([3u8; 32], [7u8; 32]);
+        let desktop = MessageEncryption::new(&desktop_private, &public(desktop_private));
+        let phone = MessageEncryption::new(&phone_private, &public(phone_private));
+        let phone_key = general_purpose::STANDARD.encode(public(phone_private));
+        let desktop_key = general_purpose::STANDARD.encode(public(desktop_private));
+
+        for cipher in Cipher::SUPPORTED {
+            let encrypted = phone.encrypt("hello", &desktop_key, cipher, None, true).unwrap();
+            assert_eq!(general_purpose::STANDARD.decode(&encrypted.nonce).unwrap().len(), cipher.nonce_length());
+            assert_eq!(desktop.decrypt(&encrypted, Some(&phone_key), None).unwrap(), "hello");
+
+            // Opened as the other cipher it fails
+            let other = Cipher::SUPPORTED.into_iter().find(|&c| c != cipher).unwrap();
+            assert!(desktop.decrypt(&EncryptedMessage { cipher: other, ..encrypted }, Some(&phone_key), None).is_err());
+        }
+
+        // Messages from before negotiation name no cipher
+        let legacy: EncryptedMessage = serde_json::from_str(r#"{"ciphertext": "", "nonce": "", "ephemeral_public_key": ""}"#).unwrap();
+        assert_eq!(legacy.cipher, Cipher::Aes256Gcm);
+
+        assert_eq!(Cipher::from_name("xchacha20-poly1305"), Some(Cipher::XChaCha20Poly1305));
+        assert_eq!(Cipher::from_name("rot13"), None);
+    }
+
+    #[test]
+    fn test_binding() {
+        let public = |private: [u8; 32]| *X25519PublicKey::from(&StaticSecret::from(private)).as_bytes();
+        let (desktop_private, phone_private) = ([3u8; 32], [7u8; 32]);
+        let desktop = MessageEncryption::new(&desktop_private, &public(desktop_private));
+        let phone = MessageEncryption::new(&phone_private, &public(phone_private));
+        let phone_key = general_purpose::STANDARD.encode(public(phone_private));
+
+        let sent = Binding { from: "pixel", to: "desk", seq: Some(7) };
+        let encrypted = phone.encrypt("hello", &general_purpose::STANDARD.encode(public(desktop_private)), Cipher::Aes256Gcm, Some(&sent), true).unwrap();
+        assert_eq!(desktop.decrypt(&encrypted, Some(&phone_key), Some(&sent)).unwrap(), "hello");
+
+        // Not as another sender's or recipient's, the other direction, another counter, or unbound
+        for binding in [
+            Binding { from: "tablet", ..sent },
+            Binding { to: "laptop", ..sent },
+            Binding { from: "desk", to: "pixel", ..sent },
+            Binding { seq: Some(8), ..sent },
+            Binding { seq: None, ..sent },
+        ] {
+            assert!(desktop.decrypt(&encrypted, Some(&phone_key), Some(&binding)).is_err());
+        }
+        assert!(desktop.decrypt(&encrypted, Some(&phone_key), None).is_err());
+
+        // Length prefixes keep ids from running into each other
+        let shifted = Binding { from: "pix", to: "eldesk", seq: Some(7) };
+        assert_ne!(shifted.associated_data(), Binding { from: "pixel", to: "desk", seq: Some(7) }.associated_data());
+    }
+
+    #[test]
+    fn test_spoofed_sender() {
+        let public = |private: [u8; 32]| *X25519PublicKey::from(&StaticSecret::from(private)).as_bytes();
+        let (desktop_private, phone_private, relay_private) = ([3u8; 32], [7u8; 32], [11u8; 32]);
+        let desktop = MessageEncryption::new(&desktop_private, &public(desktop_private));
+        let phone = MessageEncryption::new(&phone_private, &public(phone_private));
+        let relay = MessageEncryption::new(&relay_private, &public(relay_private));
+        let phone_key = general_purpose::STANDARD.encode(public(phone_private));
+        let desktop_key = general_purpose::STANDARD.encode(public(desktop_private));
+
+        // Only the phone's private key makes a message that decrypts as the phone's
+        let genuine = phone.encrypt("hello", &desktop_key, Cipher::Aes256Gcm, None, true).unwrap();
+        assert_eq!(desktop.decrypt(&genuine, Some(&phone_key), None).unwrap(), "hello");
+        let forged = relay.encrypt("rm -rf ~\n", &desktop_key, Cipher::Aes256Gcm, None, true).unwrap();
+        assert!(desktop.decrypt(&forged, Some(&phone_key), None).is_err());
+        assert!(desktop.decrypt_any(&forged, &[[9u8; 32]], Some(&phone_key), None).is_err());
+
+        // Nor is the phone's message passed off as another device's, or as unauthenticated
+        assert!(desktop.decrypt(&genuine, Some(&general_purpose::STANDARD.encode(public(relay_private))), None).is_err());
+        assert!(desktop.decrypt(&genuine, None, None).is_err());
+
+        // A forged broadcast slot doesn't open either
+        let forged = relay.encrypt_broadcast("hello", &[("desk", &desktop_key)], Cipher::Aes256Gcm, "pixel", None, true).unwrap();
+        assert!(desktop.decrypt(&forged, Some(&phone_key), Some(&Binding { from: "pixel", to: "desk", seq: None })).is_err());
+    }
+
+    #[test]
+    fn test_legacy_derivation() {
+        let public = |private: [u8; 32]| *X25519PublicKey::from(&StaticSecret::from(private)).as_bytes();
+        let (desktop_private, ephemeral_private, phone_private) = ([3u8; 32], [5u8; 32], [7u8; 32]);
+        let desktop = MessageEncryption::new(&desktop_private, &public(desktop_private));
+        let phone_key = general_purpose::STANDARD.encode(public(phone_private));
+
+        // As the phone apps encrypt without senderAuth: HKDF over the
+        // ephemeral ECDH secret alone, AES-256-GCM, no associated data
+        let shared = StaticSecret::from(ephemeral_private).diffie_hellman(&X25519PublicKey::from(public(desktop_private)));
+        let mut key = [0u8; 32];
+        Hkdf::<Sha256>::new(Some(HKDF_SALT), shared.as_bytes()).expand(HKDF_INFO, &mut key).unwrap();
+        let nonce = [1u8; 12];
+        let ciphertext = Aes256Gcm::new((&key).into()).encrypt(&Nonce::from(nonce), b"hello".as_ref()).unwrap();
+        let legacy = EncryptedMessage {
+            ciphertext: general_purpose::STANDARD.encode(ciphertext),
+            nonce: general_purpose::STANDARD.encode(nonce),
+            ephemeral_public_key: general_purpose::STANDARD.encode(public(ephemeral_private)),
+            cipher: Cipher::Aes256Gcm,
+            recipients: Vec::new(),
+        };
+        assert_eq!(desktop.decrypt(&legacy, None, None).unwrap(), "hello");
+
+        // It doesn't pass for an authenticated one, nor the other way round
+        assert!(desktop.decrypt(&legacy, Some(&phone_key), None).is_err());
+        let phone = MessageEncryption::new(&phone_private, &public(phone_private));
+        let desktop_key = general_purpose::STANDARD.encode(public(desktop_private));
+        let unauthenticated = phone.encrypt("hello", &desktop_key, Cipher::Aes256Gcm, None, false).unwrap();
+        assert_eq!(desktop.decrypt(&unauthenticated, None, None).unwrap(), "hello");
+        assert!(desktop.decrypt(&unauthenticated, Some(&phone_key), None).is_err());
+    }
+
+    #[test]
+    fn test_broadcast() {
+        let public = |private: [u8; 32]| *X25519PublicKey::from(&StaticSecret::from(private)).as_bytes();
+        let (desk_private, laptop_private, phone_private) = ([3u8; 32], [5u8; 32], [7u8; 32]);
+        let desk = MessageEncryption::new(&desk_private, &public(desk_private));
+        let laptop = MessageEncryption::new(&laptop_private, &public(laptop_private));
+        let phone = MessageEncryption::new(&phone_private, &public(phone_private));
+        let phone_key = general_purpose::STANDARD.encode(public(phone_private));
+
+        let recipients = [
+            ("desk", general_purpose::STANDARD.encode(public(desk_private))),
+            ("laptop", general_purpose::STANDARD.encode(public(laptop_private))),
+        ];
+        let recipients: Vec<(&str, &str)> = recipients.iter().map(|(to, key)| (*to, key.as_str())).collect();
+        let encrypted = phone.encrypt_broadcast("hello", &recipients, Cipher::XChaCha20Poly1305, "pixel", Some(3), true).unwrap();
+        assert_eq!(encrypted.recipients.len(), 2);
+
+        // Each recipient opens its own slot
+        let to_desk = Binding { from: "pixel", to: "desk", seq: Some(3) };
+        assert_eq!(desk.decrypt(&encrypted, Some(&phone_key), Some(&to_desk)).unwrap(), "hello");
+        assert_eq!(laptop.decrypt(&encrypted, Some(&phone_key), Some(&Binding { to: "laptop", ..to_desk })).unwrap(), "hello");
+
+        // Not the other's slot, under another counter, unbound or without a slot
+        assert!(laptop.decrypt(&encrypted, Some(&phone_key), Some(&to_desk)).is_err());
+        assert!(desk.decrypt(&encrypted, Some(&phone_key), Some(&Binding { seq: Some(4), ..to_desk })).is_err());
+        assert!(desk.decrypt(&encrypted, Some(&phone_key), None).is_err());
+        let mut swapped = encrypted.clone();
+        swapped.recipients.retain(|slot| slot.to == "laptop");
+        swapped.recipients[0].to = "desk".to_string();
+        assert!(desk.decrypt(&swapped, Some(&phone_key), Some(&to_desk)).is_err());
+        assert!(desk.decrypt(&EncryptedMessage { recipients: Vec::new(), ..encrypted }, Some(&phone_key), Some(&to_desk)).is_err());
+    }
+
+    #[test]
+    fn test_derived_key_cache() {
+        let public = |private: [u8; 32]| *X25519PublicKey::from(&StaticSecret::from(private)).as_bytes();
+        let (desktop_private, phone_private) = ([3u8; 32], [7u8; 32]);
+        let desktop = MessageEncryption::new(&desktop_private, &public(desktop_private));
+        let phone = MessageEncryption::new(&phone_private, &public(phone_private));
+        let phone_key = general_purpose::STANDARD.encode(public(phone_private));
+        let encrypted = phone.encrypt("hello", &general_purpose::STANDARD.encode(public(desktop_private)), Cipher::Aes256Gcm, None, true).unwrap();
+
+        // Delivered twice, derived once; tried with another key, derived apart
+        assert_eq!(desktop.decrypt(&encrypted, Some(&phone_key), None).unwrap(), "hello");
+        assert_eq!(desktop.decrypt(&encrypted, Some(&phone_key), None).unwrap(), "hello");
+        assert_eq!(desktop.derived.lock().unwrap().entries.len(), 1);
+        assert!(desktop.decrypt_any(&encrypted, &[[9u8; 32]], Some(&phone_key), None).is_ok());
+        assert_eq!(desktop.derived.lock().unwrap().entries.len(), 2);
+
+        // Bounded, and forgotten after a minute
+        let mut cache = DerivedKeys::default();
+        let now = Instant::now();
+        for i in 0..DERIVED_KEPT as u8 + 1 {
+            cache.insert([i; 32], now, Zeroizing::new([i; 32]));
+        }
+        assert!(cache.get(&[0; 32], now).is_none());
+        assert!(cache.get(&[1; 32], now).is_some());
+        assert!(cache.get(&[1; 32], now + DERIVED_TTL).is_none());
+    }
+
+    #[test]
+    fn test_rotation() {
+        let public = |private: [u8; 32]| *X25519PublicKey::from(&StaticSecret::from(private)).as_bytes();
+        let (old_private, new_private, phone_private) = ([3u8; 32], [5u8; 32], [7u8; 32]);
+        let phone = MessageEncryption::new(&phone_private, &public(phone_private));
+        let phone_key = general_purpose::STANDARD.encode(public(phone_private));
+        let rotated = MessageEncryption::new(&new_private, &public(new_private)).with_previous_key(secret(&old_private));
+
+        // Sent to the old key and the new one, both decrypt during the grace window
+        for key in [public(old_private), public(new_private)] {
+            let encrypted = phone.encrypt("hello", &general_purpose::STANDARD.encode(key), Cipher::Aes256Gcm, None, true).unwrap();
+            assert_eq!(rotated.decrypt(&encrypted, Some(&phone_key), None).unwrap(), "hello");
+        }
+        let unrotated = MessageEncryption::new(&new_private, &public(new_private));
+        let encrypted = phone.encrypt("hello", &general_purpose::STANDARD.encode(public(old_private)), Cipher::Aes256Gcm, None, true).unwrap();
+        assert!(unrotated.decrypt(&encrypted, Some(&phone_key), None).is_err());
+        assert_eq!(unrotated.rotation_proof(&general_purpose::STANDARD.encode(public(phone_private))).unwrap(), None);
+
+        // The phone checks the proof with its key and the old one it pinned
+        let proof = rotated.rotation_proof(&general_purpose::STANDARD.encode(public(phone_private))).unwrap().unwrap();
+        let shared = StaticSecret::from(phone_private).diffie_hellman(&X25519PublicKey::from(public(old_private)));
+        let mut expected = [0u8; 32];
+        Hkdf::<Sha256>::new(Some(HKDF_SALT), shared.as_bytes())
+            .expand_multi_info(&[ROTATION_INFO, &public(new_private)], &mut expected)
+            .unwrap();
+        assert_eq!(proof, general_purpose::STANDARD.encode(expected));
+    }
+
+    #[test]
+    fn test_session_key() {
+        let public = |private: [u8; 32]| *X25519PublicKey::from(&StaticSecret::from(private)).as_bytes();
+        let (desktop_private, session_private, phone_private) = ([3u8; 32], [5u8; 32], [7u8; 32]);
+        let desktop = MessageEncryption::new(&desktop_private, &public(desktop_private));
+        let phone = MessageEncryption::new(&phone_private, &public(phone_private));
+        let phone_key = general_purpose::STANDARD.encode(public(phone_private));
+
+        // Only the session key decrypts what was sent to it
+        let encrypted = phone.encrypt("hello", &general_purpose::STANDARD.encode(public(session_private)), Cipher::XChaCha20Poly1305, None, true).unwrap();
+        assert_eq!(desktop.decrypt_any(&encrypted, &[[9u8; 32], session_private], Some(&phone_key), None).unwrap(), (Some(1), "hello".to_string()));
+        assert!(desktop.decrypt(&encrypted, Some(&phone_key), None).is_err());
+        let direct = phone.encrypt("hi", &general_purpose::STANDARD.encode(public(desktop_private)), Cipher::Aes256Gcm, None, true).unwrap();
+        assert_eq!(desktop.decrypt_any(&direct, &[session_private], Some(&phone_key), None).unwrap(), (None, "hi".to_string()));
+
+        // The phone checks the session key against the desktop key it pinned
+        let proof = desktop.session_key_proof(&general_purpose::STANDARD.encode(public(phone_private)), &public(session_private)).unwrap();
+        let shared = StaticSecret::from(phone_private).diffie_hellman(&X25519PublicKey::from(public(desktop_private)));
+        let mut expected = [0u8; 32];
+        Hkdf::<Sha256>::new(Some(HKDF_SALT), shared.as_bytes())
+            .expand_multi_info(&[SESSION_INFO, &public(session_private)], &mut expected)
+            .unwrap();
+        assert_eq!(proof, general_purpose::STANDARD.encode(expected));
+    }
 }
@@ -1,88 +1,278 @@
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use argon2::Argon2;
 use base64::{Engine as _, engine::general_purpose};
+use hkdf::Hkdf;
+use rand::RngCore;
 use rand::rngs::OsRng;
+use secrecy::{ExposeSecret, ExposeSecretMut, SecretBox, SecretString};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::{Zeroize, Zeroizing};
+
+/// How long the key replaced by `utterd keys rotate` still decrypts, so
+/// phones that haven't heard of the new one yet keep working
+pub const ROTATION_GRACE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+
+/// What a key file holds instead of the key when the key is in the keyring
+const IN_KEYRING: &[u8] = b"keyring";
+
+/// Start of a key file encrypted with a passphrase, followed by the Argon2id
+/// salt, the AES-GCM nonce and the encrypted key
+const ENCRYPTED: &[u8] = b"utterd-key-v1\n";
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 12;
+
+/// Start of a key file sealed to the TPM, followed by the systemd-creds
+/// credential holding the key
+const SEALED: &[u8] = b"utterd-tpm-v1\n";
+
+/// How a key file holds its key
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Storage {
+    Plain,
+    Keyring,
+    Passphrase,
+    Tpm,
+}
 
 /// Manages X25519 keypairs for E2E encryption
 ///
-/// Keys are stored in ~/.config/utterd/keypair.key
+/// Private keys are kept in the OS keyring (Secret Service, macOS Keychain,
+/// Windows Credential Manager), with ~/.config/utterd/keypair.key saying so.
+/// Without a keyring, e.g. on a headless system, the file holds the key
+/// itself, or with a passphrase the key encrypted with it (Argon2id and
+/// AES-256-GCM), or with `--tpm` the key sealed to this machine's TPM2.
+/// After a rotation the old key is kept as keypair.previous
+/// until its grace window ends. Each `--profile` has its own keys, in
+/// ~/.config/utterd/<profile>.
+///
+/// Keys and passphrases in memory are wiped when dropped and print as
+/// `[REDACTED]`.
 pub struct KeyManager {
     config_dir: PathBuf,
+    /// Keyring service the private keys are stored under
+    keyring_service: String,
+    /// Whether to try the OS keyring when saving a key
+    keyring: bool,
+    /// Encrypt key files with this instead of using the keyring
+    passphrase: Option<SecretString>,
+    /// Seal keys to the TPM instead of using the keyring or a passphrase
+    tpm: bool,
     private_key: Option<StaticSecret>,
     public_key: Option<PublicKey>,
 }
 
 impl KeyManager {
-    /// Create a new KeyManager with default config directory
-    pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
-        let config_dir = dirs::config_dir()
-            .ok_or("Could not find config directory")?
-            .join("utterd");
+    /// Create a new KeyManager for `profile`'s keys, or the default ones
+    pub fn new(profile: Option<&str>) -> Result<Self, Box<dyn std::error::Error>> {
+        let config_dir = crate::config::identity_dir(profile)
+            .ok_or("Could not find config directory")?;
 
         // Create config directory if it doesn't exist
         fs::create_dir_all(&config_dir)?;
 
-        Ok(Self {
+        let mut key_manager = Self::in_dir(config_dir, true);
+        if let Some(profile) = profile {
+            key_manager.keyring_service = format!("utterd-{}", profile);
+        }
+        Ok(key_manager)
+    }
+
+    fn in_dir(config_dir: PathBuf, keyring: bool) -> Self {
+        Self {
             config_dir,
+            keyring_service: "utterd".to_string(),
+            keyring,
+            passphrase: None,
+            tpm: false,
             private_key: None,
             public_key: None,
-        })
+        }
+    }
+
+    /// Protect key files with `passphrase` from now on
+    pub fn set_passphrase(&mut self, passphrase: Option<String>) {
+        self.passphrase = passphrase.filter(|passphrase| !passphrase.is_empty()).map(SecretString::from);
+    }
+
+    /// Seal key files to the TPM from now on (`--tpm`)
+    pub fn set_tpm(&mut self, tpm: bool) {
+        self.tpm = tpm;
+    }
+
+    /// Whether the key file is encrypted, so a passphrase is needed to load it
+    pub fn is_passphrase_protected(&self) -> Result<bool, Box<dyn std::error::Error>> {
+        Ok(self.storage("keypair.key")? == Some(Storage::Passphrase))
+    }
+
+    /// Whether a keypair was saved, so loading won't generate one
+    pub fn has_keypair(&self) -> Result<bool, Box<dyn std::error::Error>> {
+        Ok(self.storage("keypair.key")?.is_some())
     }
 
     /// Get or generate X25519 keypair
+    ///
+    /// A plain key file is moved to the keyring if there is one now, or
+    /// sealed or encrypted if the TPM or a passphrase was asked for.
     pub fn get_or_generate_keypair(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        let key_path = self.config_dir.join("keypair.key");
-
-        if key_path.exists() {
-            self.load_keypair(&key_path)?;
-        } else {
-            self.generate_and_save_keypair(&key_path)?;
+        match self.read_secret("keypair.key")? {
+            Some(key_bytes) => {
+                let key_array = key_array(&key_bytes)?;
+                let storage = self.storage("keypair.key")?;
+                let protect = (self.tpm && storage != Some(Storage::Tpm))
+                    || (!self.tpm && self.passphrase.is_some() && storage != Some(Storage::Passphrase));
+                if protect || (self.keyring && storage == Some(Storage::Plain)) {
+                    // Still in the file if the keyring can't take it
+                    self.save_secret("keypair.key", key_array.expose_secret())?;
+                }
+                self.set_keypair(StaticSecret::from(*key_array.expose_secret()));
+            }
+            None => self.generate_and_save_keypair()?,
         }
 
         Ok(())
     }
 
-    /// Generate new X25519 keypair and save to file
-    fn generate_and_save_keypair(&mut self, path: &PathBuf) -> Result<(), Box<dyn std::error::Error>> {
-        let private_key = StaticSecret::random_from_rng(OsRng);
-        let public_key = PublicKey::from(&private_key);
-
-        // Save private key to file
-        fs::write(path, private_key.to_bytes())?;
-
-        // Set restrictive permissions (Unix only)
-        #[cfg(unix)]
-        {
-            use std::os::unix::fs::PermissionsExt;
-            let mut perms = fs::metadata(path)?.permissions();
-            perms.set_mode(0o600); // rw------- (owner only)
-            fs::set_permissions(path, perms)?;
+    /// Replace the keypair with a fresh one, keeping the old private key
+    /// as keypair.previous for `ROTATION_GRACE`
+    pub fn rotate(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.get_or_generate_keypair()?;
+        let old_key = self.get_private_key_bytes()?;
+        self.save_secret("keypair.previous", old_key.expose_secret())?;
+        self.generate_and_save_keypair()
+    }
+
+    /// Replace the keypair with one brought from another machine; a key
+    /// left from a rotation here goes too
+    pub fn import(&mut self, private_key: &[u8; 32]) -> Result<(), Box<dyn std::error::Error>> {
+        self.save_secret("keypair.key", private_key)?;
+        self.delete_secret("keypair.previous")?;
+        self.set_keypair(StaticSecret::from(*private_key));
+        Ok(())
+    }
+
+    /// The private key replaced by the last rotation, while it's still in
+    /// its grace window; removed once the window has passed
+    pub fn previous_private_key(&self) -> Result<Option<SecretBox<[u8; 32]>>, Box<dyn std::error::Error>> {
+        let path = self.config_dir.join("keypair.previous");
+        if !path.exists() {
+            return Ok(None);
+        }
+        let age = fs::metadata(&path)?.modified()?.elapsed().unwrap_or_default();
+        if age >= ROTATION_GRACE {
+            self.delete_secret("keypair.previous")?;
+            return Ok(None);
         }
 
-        self.private_key = Some(private_key);
-        self.public_key = Some(public_key);
+        match self.read_secret("keypair.previous")? {
+            Some(key_bytes) => Ok(Some(key_array(&key_bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Generate new X25519 keypair and save it
+    fn generate_and_save_keypair(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let private_key = StaticSecret::random_from_rng(OsRng);
+        self.save_secret("keypair.key", &Zeroizing::new(private_key.to_bytes()))?;
+        self.set_keypair(private_key);
 
         Ok(())
     }
 
-    /// Load keypair from file
-    fn load_keypair(&mut self, path: &PathBuf) -> Result<(), Box<dyn std::error::Error>> {
-        let key_bytes = fs::read(path)?;
+    fn set_keypair(&mut self, private_key: StaticSecret) {
+        self.public_key = Some(PublicKey::from(&private_key));
+        self.private_key = Some(private_key);
+    }
 
-        if key_bytes.len() != 32 {
-            return Err(format!("Invalid key length: {} bytes (expected 32)", key_bytes.len()).into());
+    /// How key `name`'s file holds it; None if there's no such key
+    fn storage(&self, name: &str) -> Result<Option<Storage>, Box<dyn std::error::Error>> {
+        let path = self.config_dir.join(name);
+        if !path.exists() {
+            return Ok(None);
         }
+        let contents = fs::read(path)?;
+        Ok(Some(if contents == IN_KEYRING {
+            Storage::Keyring
+        } else if contents.starts_with(ENCRYPTED) {
+            Storage::Passphrase
+        } else if contents.starts_with(SEALED) {
+            Storage::Tpm
+        } else {
+            Storage::Plain
+        }))
+    }
 
-        let key_array: [u8; 32] = key_bytes.try_into()
-            .map_err(|_| "Failed to convert key bytes to array")?;
+    /// Read key `name` from the keyring or its file
+    fn read_secret(&self, name: &str) -> Result<Option<Zeroizing<Vec<u8>>>, Box<dyn std::error::Error>> {
+        let path = self.config_dir.join(name);
+        let key = match self.storage(name)? {
+            None => return Ok(None),
+            Some(Storage::Plain) => Zeroizing::new(fs::read(&path)?),
+            Some(Storage::Passphrase) => {
+                let passphrase = self.passphrase.as_ref()
+                    .ok_or_else(|| format!("{} is protected by a passphrase (UTTER_KEY_PASSPHRASE)", name))?
+                    .expose_secret();
+                decrypt_key(&fs::read(&path)?, passphrase).map_err(|e| format!("Cannot decrypt {}: {}", name, e))?
+            }
+            Some(Storage::Tpm) => {
+                let contents = fs::read(&path)?;
+                tpm_unseal(&contents[SEALED.len()..]).map_err(|e| format!("Cannot unseal {}: {}", name, e))?
+            }
+            // Never replace a key that's only out of reach, or every paired
+            // phone would have to pair again
+            Some(Storage::Keyring) => keyring::Entry::new(&self.keyring_service, name)
+                .and_then(|entry| entry.get_secret())
+                .map(Zeroizing::new)
+                .map_err(|e| format!("{} is in the OS keyring, which can't be read: {}", name, e))?,
+        };
+        Ok(Some(key))
+    }
 
-        let private_key = StaticSecret::from(key_array);
-        let public_key = PublicKey::from(&private_key);
+    /// Save key `name` sealed to the TPM or encrypted with the passphrase,
+    /// or to the keyring leaving a note in its file, or to the file itself if
+    /// there's no keyring
+    fn save_secret(&self, name: &str, key: &[u8; 32]) -> Result<(), Box<dyn std::error::Error>> {
+        let path = self.config_dir.join(name);
+        let was_in_keyring = self.storage(name)? == Some(Storage::Keyring);
 
-        self.private_key = Some(private_key);
-        self.public_key = Some(public_key);
+        let protected = if self.tpm {
+            Some([SEALED, &tpm_seal(key)?].concat())
+        } else if let Some(ref passphrase) = self.passphrase {
+            Some(encrypt_key(key, passphrase.expose_secret())?)
+        } else {
+            None
+        };
+        if let Some(protected) = protected {
+            write_owner_only(&path, &protected)?;
+            if was_in_keyring {
+                let _ = keyring::Entry::new(&self.keyring_service, name).and_then(|entry| entry.delete_credential());
+            }
+            return Ok(());
+        }
 
+        let in_keyring = self.keyring
+            && keyring::Entry::new(&self.keyring_service, name)
+                .and_then(|entry| entry.set_secret(key))
+                .is_ok();
+        write_owner_only(&path, if in_keyring { IN_KEYRING } else { key })
+    }
+
+    fn delete_secret(&self, name: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let path = self.config_dir.join(name);
+        if !path.exists() {
+            return Ok(());
+        }
+        if self.storage(name)? == Some(Storage::Keyring) {
+            match keyring::Entry::new(&self.keyring_service, name).and_then(|entry| entry.delete_credential()) {
+                Ok(()) | Err(keyring::Error::NoEntry) => {}
+                Err(e) => return Err(format!("Failed to remove {} from the OS keyring: {}", name, e).into()),
+            }
+        }
+        fs::remove_file(path)?;
         Ok(())
     }
 
@@ -96,12 +286,23 @@ impl KeyManager {
     }
 
     /// Get the private key bytes
-    pub fn get_private_key_bytes(&self) -> Result<[u8; 32], Box<dyn std::error::Error>> {
+    pub fn get_private_key_bytes(&self) -> Result<SecretBox<[u8; 32]>, Box<dyn std::error::Error>> {
         let private_key = self.private_key
             .as_ref()
             .ok_or("No keypair loaded")?;
 
-        Ok(private_key.to_bytes())
+        Ok(secret(&Zeroizing::new(private_key.to_bytes())))
+    }
+
+    /// A key for encrypting other files at rest (e.g. the OAuth token
+    /// cache), derived from the private key so it's protected the same way
+    pub fn derive_key(&self, purpose: &str) -> Result<SecretBox<[u8; 32]>, Box<dyn std::error::Error>> {
+        let private_key = self.get_private_key_bytes()?;
+        let mut key = SecretBox::new(Box::new([0u8; 32]));
+        Hkdf::<sha2::Sha256>::new(None, private_key.expose_secret())
+            .expand_multi_info(&[b"utterd-storage-v1:", purpose.as_bytes()], key.expose_secret_mut())
+            .map_err(|e| format!("HKDF failed: {:?}", e))?;
+        Ok(key)
     }
 
     /// Get the public key bytes
@@ -113,19 +314,175 @@ impl KeyManager {
         Ok(*public_key.as_bytes())
     }
 
-    /// Clear all stored keys (delete key file)
+    /// Clear all stored keys (keyring entries and key files)
     #[allow(dead_code)]
     pub fn clear_keys(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let key_path = self.config_dir.join("keypair.key");
-        if key_path.exists() {
-            fs::remove_file(key_path)?;
-        }
-        Ok(())
+        self.delete_secret("keypair.key")?;
+        self.delete_secret("keypair.previous")
+    }
+}
+
+/// A copy of `key` that's wiped when dropped
+pub fn secret(key: &[u8; 32]) -> SecretBox<[u8; 32]> {
+    SecretBox::new(Box::new(*key))
+}
+
+fn key_array(key_bytes: &[u8]) -> Result<SecretBox<[u8; 32]>, Box<dyn std::error::Error>> {
+    let key: &[u8; 32] = key_bytes
+        .try_into()
+        .map_err(|_| format!("Invalid key length: {} bytes (expected 32)", key_bytes.len()))?;
+    Ok(secret(key))
+}
+
+/// Stretch a passphrase into an AES-256 key
+fn passphrase_key(passphrase: &str, salt: &[u8]) -> Result<Zeroizing<[u8; 32]>, String> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, key.as_mut_slice())
+        .map_err(|e| format!("Key derivation failed: {}", e))?;
+    Ok(key)
+}
+
+/// Encrypt `plaintext` with a passphrase: the Argon2id salt, the AES-GCM
+/// nonce, then the ciphertext
+pub fn encrypt_with_passphrase(plaintext: &[u8], passphrase: &str) -> Result<Vec<u8>, String> {
+    let mut salt = [0u8; SALT_LENGTH];
+    let mut nonce = [0u8; NONCE_LENGTH];
+    OsRng.fill_bytes(&mut salt);
+    OsRng.fill_bytes(&mut nonce);
+
+    let cipher = Aes256Gcm::new_from_slice(passphrase_key(passphrase, &salt)?.as_slice()).map_err(|e| e.to_string())?;
+    let ciphertext = cipher
+        .encrypt(&Nonce::from(nonce), plaintext)
+        .map_err(|e| format!("Encryption failed: {}", e))?;
+    Ok([&salt[..], &nonce, &ciphertext].concat())
+}
+
+pub fn decrypt_with_passphrase(sealed: &[u8], passphrase: &str) -> Result<Zeroizing<Vec<u8>>, String> {
+    if sealed.len() < SALT_LENGTH + NONCE_LENGTH {
+        return Err("truncated".to_string());
     }
+    let (salt, sealed) = sealed.split_at(SALT_LENGTH);
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LENGTH);
+
+    let cipher = Aes256Gcm::new_from_slice(passphrase_key(passphrase, salt)?.as_slice()).map_err(|e| e.to_string())?;
+    #[allow(deprecated)]
+    let nonce = Nonce::from_slice(nonce);
+    cipher.decrypt(nonce, ciphertext).map(Zeroizing::new).map_err(|_| "wrong passphrase".to_string())
+}
+
+/// Encrypt `key` with the TPM2 through systemd-creds, so only this machine
+/// can decrypt it
+fn tpm_seal(key: &[u8; 32]) -> Result<Vec<u8>, String> {
+    systemd_creds(&["encrypt", "--with-key=tpm2", "--name=utterd-key", "-", "-"], key)
+}
+
+fn tpm_unseal(sealed: &[u8]) -> Result<Zeroizing<Vec<u8>>, String> {
+    systemd_creds(&["decrypt", "--name=utterd-key", "-", "-"], sealed).map(Zeroizing::new)
+}
+
+fn systemd_creds(args: &[&str], input: &[u8]) -> Result<Vec<u8>, String> {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    let mut child = Command::new("systemd-creds")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Cannot run systemd-creds (needed for --tpm): {}", e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(input).map_err(|e| format!("Cannot write to systemd-creds: {}", e))?;
+    }
+    let mut output = child.wait_with_output().map_err(|e| format!("systemd-creds failed: {}", e))?;
+    if !output.status.success() {
+        output.stdout.zeroize();
+        return Err(format!("systemd-creds {}: {}", args[0], String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(output.stdout)
+}
+
+fn encrypt_key(key: &[u8; 32], passphrase: &str) -> Result<Vec<u8>, String> {
+    Ok([ENCRYPTED, &encrypt_with_passphrase(key, passphrase)?].concat())
+}
+
+fn decrypt_key(contents: &[u8], passphrase: &str) -> Result<Zeroizing<Vec<u8>>, String> {
+    decrypt_with_passphrase(&contents[ENCRYPTED.len()..], passphrase)
+}
+
+/// Write a key file readable only by its owner
+fn write_owner_only(path: &Path, contents: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+    fs::write(path, contents)?;
+
+    // Set restrictive permissions (Unix only)
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mut perms = fs::metadata(path)?.permissions();
+        perms.set_mode(0o600); // rw------- (owner only)
+        fs::set_permissions(path, perms)?;
+    }
+
+    Ok(())
 }
 
 impl Default for KeyManager {
     fn default() -> Self {
-        Self::new().expect("Failed to create KeyManager")
+        Self::new(None).expect("Failed to create KeyManager")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotate_without_keyring() {
+        let dir = std::env::temp_dir().join(format!("utterd-keys-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut keys = KeyManager::in_dir(dir.clone(), false);
+        keys.get_or_generate_keypair().unwrap();
+        let old_private = *keys.get_private_key_bytes().unwrap().expose_secret();
+        assert_eq!(fs::read(dir.join("keypair.key")).unwrap(), old_private);
+
+        keys.rotate().unwrap();
+        assert_ne!(keys.get_private_key_bytes().unwrap().expose_secret(), &old_private);
+        assert_eq!(keys.previous_private_key().unwrap().unwrap().expose_secret(), &old_private);
+
+        // The new key is the one loaded next time
+        let mut reloaded = KeyManager::in_dir(dir.clone(), false);
+        reloaded.get_or_generate_keypair().unwrap();
+        assert_eq!(reloaded.get_public_key_base64().unwrap(), keys.get_public_key_base64().unwrap());
+
+        reloaded.clear_keys().unwrap();
+        assert!(reloaded.previous_private_key().unwrap().is_none());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_passphrase() {
+        let dir = std::env::temp_dir().join(format!("utterd-keys-passphrase-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        // A plain key file is encrypted once a passphrase is set
+        let mut keys = KeyManager::in_dir(dir.clone(), false);
+        keys.get_or_generate_keypair().unwrap();
+        let private = *keys.get_private_key_bytes().unwrap().expose_secret();
+        keys.set_passphrase(Some("correct horse".to_string()));
+        keys.get_or_generate_keypair().unwrap();
+        assert!(keys.is_passphrase_protected().unwrap());
+        assert!(!fs::read(dir.join("keypair.key")).unwrap().windows(32).any(|window| window == private));
+
+        let mut reloaded = KeyManager::in_dir(dir.clone(), false);
+        assert!(reloaded.get_or_generate_keypair().is_err());
+        reloaded.set_passphrase(Some("wrong".to_string()));
+        assert!(reloaded.get_or_generate_keypair().is_err());
+        reloaded.set_passphrase(Some("correct horse".to_string()));
+        reloaded.get_or_generate_keypair().unwrap();
+        assert_eq!(reloaded.get_private_key_bytes().unwrap().expose_secret(), &private);
+
+        let _ = fs::remove_dir_all(&dir);
     }
 }
@@ -2,4 +2,4 @@ pub mod keys;
 pub mod encryption;
 
 pub use keys::KeyManager;
-pub use encryption::{MessageEncryption, EncryptedMessage};
+pub use encryption::{Binding, Cipher, KeySlot, MessageEncryption, EncryptedMessage};
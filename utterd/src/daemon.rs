@@ -0,0 +1,87 @@
+//! `--daemon`: detach from the terminal so utterd can be started from
+//! session autostart
+//!
+//! The process forks, leaves the terminal's session and sends everything it
+//! would print to a log file. Its pid goes into a file next to the lock,
+//! removed again when utterd exits.
+
+use std::fs::{self, File, OpenOptions};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+/// Default log file: ~/.local/share/utterd/utterd.log, or <profile>.log
+pub fn log_path(profile: Option<&str>) -> Result<PathBuf, String> {
+    let dir = dirs::data_dir().ok_or("Cannot determine data directory")?.join("utterd");
+    Ok(dir.join(format!("{}.log", profile.unwrap_or("utterd"))))
+}
+
+/// PID file: ~/.utterd/utterd.pid, or <profile>.pid
+pub fn pid_path(profile: Option<&str>) -> Result<PathBuf, String> {
+    let dir = dirs::home_dir().ok_or("Cannot determine home directory")?.join(".utterd");
+    Ok(dir.join(format!("{}.pid", profile.unwrap_or("utterd"))))
+}
+
+/// Removes the PID file when dropped
+pub struct PidFile(PathBuf);
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// Fork into the background, appending output to `log`
+///
+/// Returns in the background process only; the one started from the
+/// terminal reports where the daemon logs and exits. Must be called before
+/// any threads are started (no tokio runtime yet), as only the calling
+/// thread survives a fork.
+pub fn detach(log: &Path, pid_file: &Path) -> Result<PidFile, String> {
+    if let Some(parent) = log.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("Cannot create {}: {}", parent.display(), e))?;
+    }
+    let log_file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(0o600)
+        .open(log)
+        .map_err(|e| format!("Cannot open {}: {}", log.display(), e))?;
+    let null = File::open("/dev/null").map_err(|e| format!("Cannot open /dev/null: {}", e))?;
+
+    match unsafe { libc::fork() } {
+        -1 => return Err(format!("Cannot fork: {}", std::io::Error::last_os_error())),
+        0 => {}
+        pid => {
+            println!("✓ utterd is running in the background (pid {}), logging to {}", pid, log.display());
+            std::process::exit(0);
+        }
+    }
+
+    // A session of its own: closing the terminal doesn't hang it up
+    unsafe {
+        libc::setsid();
+        libc::dup2(null.as_raw_fd(), libc::STDIN_FILENO);
+        libc::dup2(log_file.as_raw_fd(), libc::STDOUT_FILENO);
+        libc::dup2(log_file.as_raw_fd(), libc::STDERR_FILENO);
+    }
+
+    if let Some(parent) = pid_file.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("Cannot create {}: {}", parent.display(), e))?;
+    }
+    fs::write(pid_file, format!("{}\n", std::process::id()))
+        .map_err(|e| format!("Cannot write {}: {}", pid_file.display(), e))?;
+    Ok(PidFile(pid_file.to_path_buf()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paths() {
+        let log = log_path(Some("work")).unwrap();
+        assert!(log.ends_with("utterd/work.log"));
+        assert!(pid_path(None).unwrap().ends_with(".utterd/utterd.pid"));
+    }
+}
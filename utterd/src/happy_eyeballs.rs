@@ -0,0 +1,89 @@
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::task::JoinSet;
+
+/// Head start each address gets before the next one is tried alongside it
+const ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Longest a single connection attempt may take
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Open a TCP connection to `host:port`, racing its addresses (RFC 8305)
+///
+/// Resolves every A and AAAA record and tries them IPv6 first, alternating
+/// families, starting the next attempt whenever one fails or has gone
+/// unanswered for 250 ms. The first to connect wins, so a broken IPv6 route
+/// costs a quarter second instead of a TCP timeout.
+pub async fn connect(host: &str, port: u16) -> io::Result<TcpStream> {
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|e| io::Error::new(e.kind(), format!("Cannot resolve {}: {}", host, e)))?
+        .collect();
+    if addrs.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("Cannot resolve {}", host)));
+    }
+
+    let mut addrs = interleave(addrs).into_iter();
+    let mut attempts = JoinSet::new();
+    let mut last_error = None;
+    loop {
+        match addrs.next() {
+            Some(addr) => {
+                attempts.spawn(async move {
+                    tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(addr))
+                        .await
+                        .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::TimedOut, format!("Timed out connecting to {}", addr))))
+                });
+            }
+            None if attempts.is_empty() => {
+                return Err(last_error.unwrap_or_else(|| io::Error::other(format!("Cannot connect to {}", host))));
+            }
+            None => {}
+        }
+
+        // Dropping the set aborts the attempts still running
+        let more = addrs.len() > 0;
+        tokio::select! {
+            Some(done) = attempts.join_next() => match done {
+                Ok(Ok(stream)) => return Ok(stream),
+                Ok(Err(e)) => last_error = Some(e),
+                Err(e) => last_error = Some(io::Error::other(e)),
+            },
+            _ = tokio::time::sleep(ATTEMPT_DELAY), if more => {}
+        }
+    }
+}
+
+/// Order addresses for the race: IPv6 first, then alternating families,
+/// keeping the resolver's order within each
+fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let (v6, v4): (Vec<_>, Vec<_>) = addrs.into_iter().partition(SocketAddr::is_ipv6);
+    let mut v6 = v6.into_iter();
+    let mut v4 = v4.into_iter();
+    let mut ordered = Vec::new();
+    loop {
+        let (a, b) = (v6.next(), v4.next());
+        if a.is_none() && b.is_none() {
+            return ordered;
+        }
+        ordered.extend(a);
+        ordered.extend(b);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interleave() {
+        let addr = |s: &str| s.parse::<SocketAddr>().unwrap();
+        let resolved = vec![addr("192.0.2.1:443"), addr("192.0.2.2:443"), addr("192.0.2.3:443"), addr("[2001:db8::1]:443")];
+        assert_eq!(
+            interleave(resolved),
+            vec![addr("[2001:db8::1]:443"), addr("192.0.2.1:443"), addr("192.0.2.2:443"), addr("192.0.2.3:443")]
+        );
+    }
+}
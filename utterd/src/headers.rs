@@ -0,0 +1,83 @@
+use std::collections::BTreeMap;
+use tokio_tungstenite::tungstenite::http::header::{HeaderMap, HeaderName, HeaderValue};
+
+/// Extra HTTP headers for the relay (`--header`, `[headers]` in config.toml),
+/// e.g. an API key or basic auth for a reverse proxy in front of it
+///
+/// Sent with the WebSocket handshake and the JWT exchange. Values are
+/// secrets as often as not, so they're never printed.
+#[derive(Clone, Default)]
+pub struct Headers(Vec<(HeaderName, HeaderValue)>);
+
+impl Headers {
+    /// config.toml's headers, then `--header "Name: value"` arguments,
+    /// which replace a config header of the same name
+    pub fn new(config: &BTreeMap<String, String>, args: &[String]) -> Result<Self, String> {
+        let mut headers = Vec::new();
+        let lines = args.iter().map(|arg| {
+            arg.split_once(':')
+                .ok_or_else(|| format!("--header {:?}: expected \"Name: value\"", arg))
+        });
+        for line in config.iter().map(|(name, value)| Ok((name.as_str(), value.as_str()))).chain(lines) {
+            let (name, value) = line?;
+            let name = HeaderName::from_bytes(name.trim().as_bytes())
+                .map_err(|_| format!("Invalid header name {:?}", name.trim()))?;
+            let value = HeaderValue::from_str(value.trim()).map_err(|_| format!("Invalid value for header {}", name))?;
+            headers.retain(|(known, _)| *known != name);
+            headers.push((name, value));
+        }
+        Ok(Self(headers))
+    }
+
+    /// Add them to a handshake request's headers
+    ///
+    /// A user-supplied Authorization (basic auth for a proxy) replaces the
+    /// JWT bearer; the relay then reads the JWT from the register message.
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        for (name, value) in &self.0 {
+            headers.insert(name.clone(), value.clone());
+        }
+    }
+
+    /// The same headers for reqwest, which has its own `http` types
+    pub fn for_http(&self) -> Result<reqwest::header::HeaderMap, String> {
+        let mut headers = reqwest::header::HeaderMap::new();
+        for (name, value) in &self.0 {
+            let name = reqwest::header::HeaderName::from_bytes(name.as_str().as_bytes())
+                .map_err(|_| format!("Invalid header name {:?}", name.as_str()))?;
+            let value = reqwest::header::HeaderValue::from_bytes(value.as_bytes())
+                .map_err(|_| format!("Invalid value for header {}", name))?;
+            headers.insert(name, value);
+        }
+        Ok(headers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_headers() {
+        let config = BTreeMap::from([
+            ("X-Api-Key".to_string(), "from-config".to_string()),
+            ("X-Team".to_string(), "desk".to_string()),
+        ]);
+        let headers = Headers::new(&config, &["x-api-key: from-cli".to_string()]).unwrap();
+        let mut map = HeaderMap::new();
+        headers.apply(&mut map);
+        assert_eq!(map.len(), 2);
+        assert_eq!(map["x-api-key"], "from-cli");
+
+        // Replaces a bearer already in the request
+        let basic = Headers::new(&BTreeMap::new(), &["Authorization: Basic dTpw".to_string()]).unwrap();
+        map.insert("authorization", HeaderValue::from_static("Bearer jwt"));
+        basic.apply(&mut map);
+        assert_eq!(map["authorization"], "Basic dTpw");
+        assert_eq!(basic.for_http().unwrap()["authorization"], "Basic dTpw");
+
+        assert!(Headers::new(&BTreeMap::new(), &["X-Api-Key".to_string()]).is_err());
+        assert!(Headers::new(&BTreeMap::new(), &["Bad Name: x".to_string()]).is_err());
+        assert!(Headers::new(&BTreeMap::new(), &["X-Key: line\nbreak".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,259 @@
+use chrono::{DateTime, Local, NaiveDate, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+/// Entries kept when the history file is compacted
+const MAX_ENTRIES: usize = 1000;
+
+/// Size past which the history file is compacted, to the newest entries
+/// that fit in half of it; checked from the file's length, so appending
+/// doesn't read the whole history back
+const COMPACT_BYTES: u64 = 2 * 1024 * 1024;
+
+/// One received dictation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// Message id from the relay; entries written before ids existed have none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub timestamp: DateTime<Utc>,
+    pub sender: String,
+    pub text: String,
+}
+
+/// Document formats for `utterd history export`
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum ExportFormat {
+    /// Markdown, one section per day
+    Md,
+    /// timestamp,sender,text with a header row
+    Csv,
+    /// Array of entries as stored
+    Json,
+}
+
+/// Parse `--since` (a local date, YYYY-MM-DD) into the start of that day
+pub fn parse_since(date: &str) -> Result<DateTime<Utc>, String> {
+    let day = NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|_| format!("expected YYYY-MM-DD, got {:?}", date))?;
+    Local
+        .from_local_datetime(&day.and_hms_opt(0, 0, 0).unwrap_or_default())
+        .earliest()
+        .map(|start| start.with_timezone(&Utc))
+        .ok_or_else(|| format!("{} has no midnight in the local time zone", date))
+}
+
+/// Entries to export: corrected dictations appear once, with their final
+/// text, at the time they were first received
+pub fn final_versions(entries: &[HistoryEntry]) -> Vec<HistoryEntry> {
+    let mut merged: Vec<HistoryEntry> = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let earlier = entry
+            .id
+            .as_ref()
+            .and_then(|id| merged.iter_mut().find(|e| e.id.as_ref() == Some(id)));
+        match earlier {
+            Some(earlier) => earlier.text = entry.text.clone(),
+            None => merged.push(entry.clone()),
+        }
+    }
+    merged
+}
+
+/// Render entries (oldest first) as a document
+pub fn export(entries: &[HistoryEntry], format: ExportFormat) -> Result<String, String> {
+    match format {
+        ExportFormat::Md => {
+            let mut out = String::from("# Dictations\n");
+            let mut day = None;
+            for entry in entries {
+                let time = entry.timestamp.with_timezone(&Local);
+                if day != Some(time.date_naive()) {
+                    day = Some(time.date_naive());
+                    out.push_str(&format!("\n## {}\n\n", time.format("%Y-%m-%d")));
+                }
+                // Indent continuation lines so they stay inside the list item
+                out.push_str(&format!("- **{}** {}\n", time.format("%H:%M"), entry.text.trim().replace('\n', "\n  ")));
+            }
+            Ok(out)
+        }
+        ExportFormat::Csv => {
+            let mut out = String::from("timestamp,sender,text\n");
+            for entry in entries {
+                let time = entry.timestamp.with_timezone(&Local).to_rfc3339();
+                out.push_str(&format!("{},{},{}\n", csv_field(&time), csv_field(&entry.sender), csv_field(&entry.text)));
+            }
+            Ok(out)
+        }
+        ExportFormat::Json => serde_json::to_string_pretty(entries)
+            .map(|json| json + "\n")
+            .map_err(|e| format!("Failed to serialize history: {}", e)),
+    }
+}
+
+/// Quote a CSV field when it needs it (RFC 4180)
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Persistent log of received dictations
+///
+/// Stored as JSON lines in ~/.local/share/utterd/history.jsonl (owner-only,
+/// since it holds everything the user has dictated).
+pub struct History {
+    path: PathBuf,
+}
+
+impl History {
+    pub fn new() -> Result<Self, String> {
+        let data_dir = dirs::data_dir()
+            .ok_or("Cannot determine data directory")?
+            .join("utterd");
+
+        fs::create_dir_all(&data_dir)
+            .map_err(|e| format!("Failed to create data directory: {}", e))?;
+
+        Ok(Self {
+            path: data_dir.join("history.jsonl"),
+        })
+    }
+
+    /// Append an entry, compacting the file once it grows past `COMPACT_BYTES`
+    pub fn append(&self, entry: &HistoryEntry) -> Result<(), String> {
+        let line = serde_json::to_string(entry)
+            .map_err(|e| format!("Failed to serialize history entry: {}", e))?;
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| format!("Failed to open history file: {}", e))?;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&self.path, fs::Permissions::from_mode(0o600))
+                .map_err(|e| format!("Failed to set history file permissions: {}", e))?;
+        }
+
+        writeln!(file, "{}", line)
+            .map_err(|e| format!("Failed to write history file: {}", e))?;
+
+        let size = file.metadata().map_err(|e| format!("Failed to read history file: {}", e))?.len();
+        if size > COMPACT_BYTES {
+            self.compact(COMPACT_BYTES / 2)?;
+        }
+
+        Ok(())
+    }
+
+    /// Keep the newest entries, at most `MAX_ENTRIES` and `budget` bytes
+    /// (the newest one whatever its size)
+    fn compact(&self, budget: u64) -> Result<(), String> {
+        let entries = self.load()?;
+        let mut size = 0;
+        let kept = entries
+            .iter()
+            .rev()
+            .take(MAX_ENTRIES)
+            .take_while(|entry| {
+                size += serde_json::to_string(entry).map_or(0, |line| line.len() as u64 + 1);
+                size <= budget
+            })
+            .count()
+            .max(1)
+            .min(entries.len());
+        self.rewrite(&entries[entries.len() - kept..])
+    }
+
+    /// Load all entries, oldest first (unparseable lines are skipped)
+    pub fn load(&self) -> Result<Vec<HistoryEntry>, String> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let contents = fs::read_to_string(&self.path)
+            .map_err(|e| format!("Failed to read history file: {}", e))?;
+
+        Ok(contents
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect())
+    }
+
+    fn rewrite(&self, entries: &[HistoryEntry]) -> Result<(), String> {
+        let mut contents = String::new();
+        for entry in entries {
+            let line = serde_json::to_string(entry)
+                .map_err(|e| format!("Failed to serialize history entry: {}", e))?;
+            contents.push_str(&line);
+            contents.push('\n');
+        }
+
+        fs::write(&self.path, contents)
+            .map_err(|e| format!("Failed to write history file: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(id: &str, text: &str) -> HistoryEntry {
+        HistoryEntry {
+            id: Some(id.to_string()),
+            timestamp: Utc::now(),
+            sender: "pixel".to_string(),
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_export() {
+        // A correction is stored as a second entry with the same id
+        let entries = final_versions(&[entry("a", "Buy milk"), entry("b", "Call Bob, \"urgent\""), entry("a", "Buy oat milk")]);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].text, "Buy oat milk");
+
+        let csv = export(&entries, ExportFormat::Csv).unwrap();
+        assert!(csv.starts_with("timestamp,sender,text\n"));
+        assert!(csv.ends_with(",pixel,\"Call Bob, \"\"urgent\"\"\"\n"));
+
+        let md = export(&entries, ExportFormat::Md).unwrap();
+        assert_eq!(md.matches("\n## ").count(), 1);
+        assert!(md.contains("** Buy oat milk\n"));
+
+        assert!(parse_since("2024-03-01").is_ok());
+        assert!(parse_since("March 1").is_err());
+    }
+
+    #[test]
+    fn test_compact() {
+        let history = History { path: std::env::temp_dir().join(format!("utterd-test-history-{}.jsonl", std::process::id())) };
+        let now = Utc::now();
+        let entries: Vec<HistoryEntry> = (0..MAX_ENTRIES + 10)
+            .map(|i| HistoryEntry { timestamp: now, ..entry(&i.to_string(), "hello") })
+            .collect();
+        history.rewrite(&entries).unwrap();
+
+        // The newest entries, up to the cap
+        history.compact(u64::MAX).unwrap();
+        let kept = history.load().unwrap();
+        assert_eq!(kept.len(), MAX_ENTRIES);
+        assert_eq!(kept.last().unwrap().id.as_deref(), Some((MAX_ENTRIES + 9).to_string().as_str()));
+
+        // Or what fits in the budget
+        let line = serde_json::to_string(kept.last().unwrap()).unwrap().len() as u64 + 1;
+        history.compact(line * 3).unwrap();
+        assert_eq!(history.load().unwrap().len(), 3);
+        history.compact(0).unwrap();
+        assert_eq!(history.load().unwrap().len(), 1);
+
+        fs::remove_file(&history.path).unwrap();
+    }
+}
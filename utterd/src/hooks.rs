@@ -0,0 +1,62 @@
+use std::process::Command;
+use std::time::Duration;
+
+/// How long a hook may run before it's killed, so a stuck one doesn't hold
+/// up typing
+const HOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Message metadata handed to typing hooks
+pub struct TypeEvent<'a> {
+    pub sender: &'a str,
+    pub text: &'a str,
+    pub language: Option<&'a str>,
+    pub timestamp: Option<i64>,
+    pub tool: &'a str,
+}
+
+/// Run a user hook through `sh -c` and wait for it, up to `HOOK_TIMEOUT`
+///
+/// The message is exposed as UTTER_SENDER, UTTER_TEXT, UTTER_LANGUAGE,
+/// UTTER_TIMESTAMP (ms since epoch) and UTTER_TOOL. `result` is set to
+/// "ok" or "error" for post-type hooks. `hardened` gives the hook the
+/// environment and network restrictions of typing tools (`--harden`).
+pub async fn run(command: &str, event: &TypeEvent<'_>, result: Option<&str>, hardened: bool) -> Result<(), String> {
+    let mut cmd = if cfg!(windows) {
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C").arg(command);
+        cmd
+    } else {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(command);
+        cmd
+    };
+    if hardened {
+        crate::sandbox::restrict_command(&mut cmd);
+    }
+
+    cmd.env("UTTER_SENDER", event.sender)
+        .env("UTTER_TEXT", event.text)
+        .env("UTTER_LANGUAGE", event.language.unwrap_or(""))
+        .env("UTTER_TIMESTAMP", event.timestamp.map(|t| t.to_string()).unwrap_or_default())
+        .env("UTTER_TOOL", event.tool);
+    if let Some(result) = result {
+        cmd.env("UTTER_RESULT", result);
+    }
+
+    let mut child = tokio::process::Command::from(cmd)
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("Failed to run hook: {}", e))?;
+    let status = match tokio::time::timeout(HOOK_TIMEOUT, child.wait()).await {
+        Ok(status) => status.map_err(|e| format!("Failed to run hook: {}", e))?,
+        Err(_) => {
+            let _ = child.kill().await;
+            return Err(format!("Hook killed after {} seconds", HOOK_TIMEOUT.as_secs()));
+        }
+    };
+
+    if !status.success() {
+        return Err(format!("Hook exited with {}", status));
+    }
+    Ok(())
+}
@@ -0,0 +1,87 @@
+//! `utterd keys export` / `utterd keys import`: move this desktop's identity
+//! to another machine
+//!
+//! The bundle holds the private key and the trusted phones with their pinned
+//! keys, so phones keep typing on the new machine without pairing again. It's
+//! encrypted with a passphrase (Argon2id, AES-256-GCM) and written as two
+//! lines of text that survive being pasted into a chat or a password manager.
+
+use crate::crypto::keys::{decrypt_with_passphrase, encrypt_with_passphrase, secret};
+use crate::pairing::PairedDevice;
+use base64::{engine::general_purpose, Engine as _};
+use secrecy::SecretBox;
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+const HEADER: &str = "utterd-identity-v1";
+
+/// Deliberately not `Debug`, so the private key can't end up in a log
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Identity {
+    /// X25519 private key, base64
+    pub private_key: String,
+    pub trusted_devices: Vec<PairedDevice>,
+}
+
+pub fn seal(identity: &Identity, passphrase: &str) -> Result<String, String> {
+    let json = Zeroizing::new(serde_json::to_vec(identity).map_err(|e| e.to_string())?);
+    let sealed = encrypt_with_passphrase(&json, passphrase)?;
+    Ok(format!("{}\n{}\n", HEADER, general_purpose::STANDARD.encode(sealed)))
+}
+
+pub fn open(bundle: &str, passphrase: &str) -> Result<Identity, String> {
+    let mut lines = bundle.lines().map(str::trim).filter(|line| !line.is_empty());
+    if lines.next() != Some(HEADER) {
+        return Err("Not an utterd identity bundle".to_string());
+    }
+    let sealed = general_purpose::STANDARD
+        .decode(lines.collect::<String>())
+        .map_err(|e| format!("Damaged identity bundle: {}", e))?;
+    let json = decrypt_with_passphrase(&sealed, passphrase).map_err(|e| format!("Cannot open the identity bundle: {}", e))?;
+    serde_json::from_slice(&json).map_err(|e| format!("Invalid identity bundle: {}", e))
+}
+
+impl Identity {
+    pub fn new(private_key: &[u8; 32], trusted_devices: Vec<PairedDevice>) -> Self {
+        Self {
+            private_key: general_purpose::STANDARD.encode(private_key),
+            trusted_devices,
+        }
+    }
+
+    pub fn private_key_bytes(&self) -> Result<SecretBox<[u8; 32]>, String> {
+        let bytes = Zeroizing::new(general_purpose::STANDARD.decode(&self.private_key).unwrap_or_default());
+        let key: &[u8; 32] = bytes
+            .as_slice()
+            .try_into()
+            .map_err(|_| "Invalid private key in the identity bundle".to_string())?;
+        Ok(secret(key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use secrecy::ExposeSecret;
+
+    #[test]
+    fn test_seal_open() {
+        let phone = PairedDevice {
+            device_id: "pixel".to_string(),
+            device_name: "Pixel 8".to_string(),
+            public_key: general_purpose::STANDARD.encode([9u8; 32]),
+            paired_at: chrono::Utc::now(),
+        };
+        let identity = Identity::new(&[7u8; 32], vec![phone]);
+        let bundle = seal(&identity, "correct horse").unwrap();
+        assert!(bundle.starts_with("utterd-identity-v1\n"));
+
+        let opened = open(&bundle, "correct horse").unwrap();
+        assert_eq!(opened.private_key_bytes().unwrap().expose_secret(), &[7u8; 32]);
+        assert_eq!(opened.trusted_devices[0].device_name, "Pixel 8");
+
+        assert!(open(&bundle, "wrong").is_err());
+        assert!(open("hello\nworld", "correct horse").is_err());
+    }
+}
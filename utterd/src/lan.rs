@@ -0,0 +1,566 @@
+use crate::strict;
+use base64::{engine::general_purpose, Engine as _};
+use futures_channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::net::{TcpListener, TcpStream};
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::{CloseFrame, WebSocketConfig};
+use tokio_tungstenite::tungstenite::Message;
+
+/// The address phones on the LAN can reach `listen` at: an unspecified
+/// (0.0.0.0) listen address becomes the one of the default route's
+/// interface
+pub fn reachable_address(listen: SocketAddr) -> SocketAddr {
+    if !listen.ip().is_unspecified() {
+        return listen;
+    }
+    // Connecting a UDP socket only picks the route; nothing is sent
+    let probe = if listen.is_ipv6() { "[2001:db8::1]:80" } else { "192.0.2.1:80" };
+    std::net::UdpSocket::bind((listen.ip(), 0))
+        .and_then(|socket| socket.connect(probe).and_then(|()| socket.local_addr()))
+        .map_or(listen, |local| SocketAddr::new(local.ip(), listen.port()))
+}
+
+/// Longest message content a phone may send, as on the relay
+const MAX_MESSAGE_LENGTH: usize = 5000;
+
+/// Relay features the built-in relay offers (no accounts, so no config sync)
+const FEATURES: &[&str] = &["e2e-relay", "capabilities", "message-ids", "corrections", "keys", "pairing", "sequence-numbers", "acks", "device-updates", "clipboard", "partials", "chunks", "session-keys", "bound-aad", "cipher-negotiation", "broadcast", "sender-auth"];
+
+/// How long a quitting daemon waits for phones to acknowledge the close
+const CLOSE_GRACE: Duration = Duration::from_secs(1);
+
+/// Client id the daemon's own session gets
+const TARGET_CLIENT_ID: &str = "local";
+
+/// Where a message goes next
+#[derive(Debug, PartialEq)]
+pub enum Route {
+    /// To the phone on this connection
+    Phone(u64, Value),
+    /// To the daemon's own session
+    Target(Value),
+}
+
+/// A registered device, as listed in `devices`
+struct Device {
+    id: String,
+    name: String,
+    public_key: Option<String>,
+    /// AEADs the device decrypts, most preferred first
+    ciphers: Option<Vec<String>>,
+    version: Option<String>,
+    platform: Option<String>,
+}
+
+/// The cipher names a register message advertises, ignoring anything else
+fn ciphers(message: &Value) -> Option<Vec<String>> {
+    message["ciphers"]
+        .as_array()
+        .map(|names| names.iter().filter_map(Value::as_str).map(str::to_string).collect())
+}
+
+impl Device {
+    fn to_json(&self, device_type: &str) -> Value {
+        json!({
+            "deviceId": self.id,
+            "deviceName": self.name,
+            "deviceType": device_type,
+            "publicKey": self.public_key,
+            "ciphers": self.ciphers,
+            "platform": self.platform,
+            "status": "online",
+            "version": self.version,
+        })
+    }
+}
+
+/// The relay's bookkeeping for `--listen`, reduced to one target: this daemon
+///
+/// Phones speak the relay protocol unchanged and the session sees the same
+/// messages a relay would forward, so E2E encryption and pairing work as
+/// usual. There are no accounts: the JWT is ignored, and only phones paired
+/// with the daemon get their messages acted on.
+#[derive(Default)]
+pub struct Hub {
+    target: Option<Device>,
+    /// Phones by connection; None until they register
+    phones: HashMap<u64, Option<Device>>,
+}
+
+fn now() -> i64 {
+    chrono::Utc::now().timestamp_millis()
+}
+
+fn error(message: &str) -> Value {
+    json!({ "type": "error", "message": message, "timestamp": now() })
+}
+
+impl Hub {
+    /// A phone connected; greets it like the relay does
+    pub fn connect(&mut self, conn: u64) -> Route {
+        self.phones.insert(conn, None);
+        Route::Phone(conn, json!({
+            "type": "connected",
+            "clientId": format!("lan-{}", conn),
+            "timestamp": now(),
+            "message": "Connected to utterd",
+        }))
+    }
+
+    /// A phone hung up; the daemon gets the shorter device list
+    pub fn disconnect(&mut self, conn: u64) -> Vec<Route> {
+        match self.phones.remove(&conn) {
+            Some(Some(_)) => self.devices_update(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The registered phones, pushed to the daemon like the relay's `device-updates`
+    fn devices_update(&self) -> Vec<Route> {
+        if self.target.is_none() {
+            return Vec::new();
+        }
+        let devices: Vec<Value> = self.phones.values().flatten().map(|phone| phone.to_json("android")).collect();
+        vec![Route::Target(json!({ "type": "devices", "devices": devices, "timestamp": now() }))]
+    }
+
+    /// Handle a message from the phone on `conn`
+    pub fn on_phone(&mut self, conn: u64, message: &Value) -> Vec<Route> {
+        let reply = |message: Value| vec![Route::Phone(conn, message)];
+        match message["type"].as_str() {
+            Some("register") => self.register_phone(conn, message),
+            Some("get_devices") => {
+                let devices: Vec<Value> = self.target.iter().map(|target| target.to_json("target")).collect();
+                reply(json!({ "type": "devices", "devices": devices, "timestamp": now() }))
+            }
+            Some("get_capabilities") => reply(json!({
+                "type": "capabilities",
+                "version": format!("utterd v{}", crate::VERSION),
+                "protocol": crate::probe::PROTOCOL_VERSION,
+                "features": FEATURES,
+                "maxMessageLength": MAX_MESSAGE_LENGTH,
+                "timestamp": now(),
+            })),
+            Some("message") => self.forward(conn, message),
+            Some("text") => reply(error("REJECTED: Plaintext messages not allowed. E2E encryption is REQUIRED.")),
+            Some("ping") => reply(json!({ "type": "pong", "timestamp": now() })),
+            _ => Vec::new(),
+        }
+    }
+
+    fn register_phone(&mut self, conn: u64, message: &Value) -> Vec<Route> {
+        let public_key = message["publicKey"].as_str();
+        if public_key.is_some_and(|key| general_purpose::STANDARD.decode(key).map_or(true, |bytes| bytes.len() != 32)) {
+            return vec![Route::Phone(conn, error("Invalid public key format. Must be base64-encoded Ed25519 key (32 bytes)"))];
+        }
+
+        let id = message["deviceId"].as_str().map_or_else(|| format!("lan-{}", conn), str::to_string);
+        let phone = Device {
+            name: message["deviceName"].as_str().unwrap_or(&id).to_string(),
+            id,
+            public_key: public_key.map(str::to_string),
+            ciphers: ciphers(message),
+            version: message["version"].as_str().map(str::to_string),
+            platform: message["platform"].as_str().map(str::to_string),
+        };
+        let registered = json!({
+            "type": "registered",
+            "clientId": format!("lan-{}", conn),
+            "deviceId": phone.id,
+            "clientType": message["clientType"].as_str().unwrap_or("unknown"),
+            "timestamp": now(),
+        });
+        self.phones.insert(conn, Some(phone));
+        let mut routes = vec![Route::Phone(conn, registered)];
+        routes.extend(self.devices_update());
+        routes
+    }
+
+    /// Pass a phone's `message` on to the daemon, as the relay's handleMessage does
+    fn forward(&self, conn: u64, message: &Value) -> Vec<Route> {
+        let reject = |text: &str| vec![Route::Phone(conn, error(text))];
+        let Some(Some(phone)) = self.phones.get(&conn) else {
+            return reject("Register before sending messages");
+        };
+
+        let content = message["content"].as_str().unwrap_or_default();
+        let length = content.chars().count();
+        if length > MAX_MESSAGE_LENGTH {
+            return reject(&format!("Message too long ({}/{} characters)", length, MAX_MESSAGE_LENGTH));
+        }
+        // A broadcast names its targets in its key slots; only this daemon's
+        // is passed on, the relay would send the others to their own targets
+        let broadcast = message.get("recipients").is_some();
+        let slot = message["recipients"]
+            .as_array()
+            .and_then(|slots| slots.iter().find(|slot| self.target.as_ref().is_some_and(|target| slot["to"] == target.id.as_str())));
+        let to = match (slot, message["to"].as_str()) {
+            (Some(slot), _) => slot["to"].as_str().unwrap_or_default(),
+            (None, _) if broadcast => return reject("Target device not found or offline: none of the recipients"),
+            (None, Some(to)) => to,
+            (None, None) => return reject("No target device specified"),
+        };
+        if message["encrypted"] != true {
+            return reject("REJECTED: Plaintext messages not allowed. E2E encryption is REQUIRED.");
+        }
+        let kind = message["kind"].as_str();
+        if kind == Some("correction") && !message["id"].is_string() {
+            return reject("Correction requires the id of the message it replaces");
+        }
+        if kind == Some("partial") && !message["id"].is_string() {
+            return reject("Partial result requires the id of its utterance");
+        }
+        // One part of a text too long for one message, joined by the daemon
+        let chunk = (!message["part"].is_null() || !message["parts"].is_null())
+            .then(|| (message["part"].as_u64(), message["parts"].as_u64()));
+        if broadcast && !matches!(kind, None | Some("correction" | "partial")) {
+            return reject("Only text can be broadcast");
+        }
+        if let Some(chunk) = chunk {
+            let valid = matches!(chunk, (Some(part), Some(parts)) if (1..=parts).contains(&part));
+            if !valid || kind.is_some() || !message["id"].is_string() {
+                return reject("Message parts require an id and a part between 1 and parts, and only text can be split");
+            }
+        }
+        if self.target.as_ref().is_none_or(|target| target.id != to) {
+            return reject(&format!("Target device not found or offline: {}", to));
+        }
+
+        let id = message["id"].as_str().map_or_else(|| format!("{:016x}", rand::random::<u64>()), str::to_string);
+        let timestamp = message["timestamp"].as_i64().unwrap_or_else(now);
+        let mut forwarded = match kind {
+            Some("pair") => json!({ "type": "pair_request", "content": content, "from": phone.id, "timestamp": now() }),
+            Some("key") => json!({ "type": "key", "key": content, "from": phone.id, "timestamp": timestamp }),
+            Some("correction") => json!({ "type": "correction", "id": id, "content": content, "from": phone.id, "timestamp": timestamp }),
+            Some("clipboard") => json!({ "type": "clipboard", "id": id, "content": content, "from": phone.id, "timestamp": timestamp }),
+            Some("partial") => json!({ "type": "text_partial", "id": id, "content": content, "from": phone.id, "timestamp": timestamp }),
+            _ => json!({ "type": "text", "id": id, "content": content, "from": phone.id, "timestamp": timestamp }),
+        };
+
+        forwarded["encrypted"] = json!(true);
+        for field in ["nonce", "ephemeralPublicKey"] {
+            if let Some(value) = message.get(field).filter(|value| !value.is_null()) {
+                forwarded[field] = value.clone();
+            }
+        }
+        // The key the phone registered with, so only paired phones get
+        // through; with senderAuth its messages only decrypt if the phone
+        // holds that key
+        if let Some(ref key) = phone.public_key {
+            forwarded["senderPublicKey"] = json!(key);
+        }
+        if let Some(aad) = message["aad"].as_u64() {
+            forwarded["aad"] = json!(aad);
+        }
+        if let Some(sender_auth) = message["senderAuth"].as_u64() {
+            forwarded["senderAuth"] = json!(sender_auth);
+        }
+        if let Some(cipher) = message["cipher"].as_str() {
+            forwarded["cipher"] = json!(cipher);
+        }
+        if let Some(slot) = slot {
+            forwarded["recipients"] = json!([{
+                "to": slot["to"],
+                "ephemeralPublicKey": slot["ephemeralPublicKey"],
+                "nonce": slot["nonce"],
+                "key": slot["key"],
+            }]);
+        }
+        if kind != Some("pair") && kind != Some("partial") {
+            if let Some(seq) = message["seq"].as_u64() {
+                forwarded["seq"] = json!(seq);
+            }
+        }
+        if let Some((part, parts)) = chunk {
+            forwarded["part"] = json!(part);
+            forwarded["parts"] = json!(parts);
+        }
+
+        vec![
+            Route::Target(forwarded),
+            Route::Phone(conn, json!({ "type": "message_sent", "to": to, "id": id, "timestamp": now() })),
+        ]
+    }
+
+    /// Handle a message from the daemon's session
+    pub fn on_target(&mut self, message: &Value) -> Vec<Route> {
+        match message["type"].as_str() {
+            Some("register") => {
+                let target = Device {
+                    id: message["deviceId"].as_str().unwrap_or(TARGET_CLIENT_ID).to_string(),
+                    name: message["deviceName"].as_str().unwrap_or(TARGET_CLIENT_ID).to_string(),
+                    public_key: message["publicKey"].as_str().map(str::to_string),
+                    ciphers: ciphers(message),
+                    version: message["version"].as_str().map(str::to_string),
+                    platform: message["platform"].as_str().map(str::to_string),
+                };
+                let registered = json!({
+                    "type": "registered",
+                    "clientId": TARGET_CLIENT_ID,
+                    "deviceId": target.id,
+                    "clientType": "target",
+                    "timestamp": now(),
+                });
+                self.target = Some(target);
+                let mut routes = vec![Route::Target(registered)];
+                routes.extend(self.devices_update());
+                routes
+            }
+            Some("get_devices") => self.devices_update(),
+            Some("ack") => self.to_phone(message, |from, _| {
+                let mut ack = json!({
+                    "type": "ack",
+                    "from": from,
+                    "id": message["id"],
+                    "status": message["status"],
+                    "timestamp": now(),
+                });
+                if let Some(error) = message["error"].as_str() {
+                    ack["error"] = json!(error);
+                }
+                ack
+            }),
+            Some("pair_response") => self.to_phone(message, |from, name| json!({
+                "type": "pair_response",
+                "from": from,
+                "deviceName": name,
+                "accepted": message["accepted"] == true,
+                "timestamp": now(),
+            })),
+            Some(kind @ ("key_rotated" | "session_key")) => self.to_phone(message, |from, name| json!({
+                "type": kind,
+                "from": from,
+                "deviceName": name,
+                "publicKey": message["publicKey"],
+                "proof": message["proof"],
+                "timestamp": now(),
+            })),
+            Some("get_config" | "put_config") => vec![Route::Target(error("Config sync needs a relay"))],
+            Some("ping") => vec![Route::Target(json!({ "type": "pong", "timestamp": now() }))],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Route a reply to the phone named in its `to`, from the target's id and name;
+    /// dropped if that phone is offline
+    fn to_phone(&self, message: &Value, build: impl FnOnce(&str, &str) -> Value) -> Vec<Route> {
+        let Some(target) = self.target.as_ref() else {
+            return Vec::new();
+        };
+        let to = message["to"].as_str();
+        self.phones
+            .iter()
+            .find(|(_, phone)| phone.as_ref().is_some_and(|phone| Some(phone.id.as_str()) == to))
+            .map(|(&conn, _)| Route::Phone(conn, build(&target.id, &target.name)))
+            .into_iter()
+            .collect()
+    }
+}
+
+/// The hub plus a way to reach each side
+struct Switchboard {
+    hub: Mutex<Hub>,
+    phones: Mutex<HashMap<u64, UnboundedSender<Message>>>,
+    target: UnboundedSender<Message>,
+}
+
+impl Switchboard {
+    /// Close every phone's connection, like a relay going away, and wait a
+    /// little for them to hang up
+    async fn close_phones(&self) {
+        for phone in self.phones.lock().unwrap().values() {
+            let _ = phone.unbounded_send(Message::Close(Some(CloseFrame {
+                code: CloseCode::Away,
+                reason: "utterd shutting down".into(),
+            })));
+        }
+
+        let deadline = tokio::time::Instant::now() + CLOSE_GRACE;
+        while !self.phones.lock().unwrap().is_empty() && tokio::time::Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+
+    fn deliver(&self, routes: Vec<Route>) {
+        for route in routes {
+            // A closed channel means that side is going away; nothing to tell it
+            let _ = match route {
+                Route::Target(message) => self.target.unbounded_send(Message::Text(message.to_string())),
+                Route::Phone(conn, message) => match self.phones.lock().unwrap().get(&conn) {
+                    Some(phone) => phone.unbounded_send(Message::Text(message.to_string())),
+                    None => Ok(()),
+                },
+            };
+        }
+    }
+}
+
+/// Run the built-in relay: accept phones on `listener` and pass messages
+/// between them and the session at the other end of `target`/`from_target`
+///
+/// Returns once the session hangs up.
+pub async fn serve(listener: TcpListener, target: UnboundedSender<Message>, mut from_target: UnboundedReceiver<Message>) {
+    let switchboard = Arc::new(Switchboard {
+        hub: Mutex::new(Hub::default()),
+        phones: Mutex::new(HashMap::new()),
+        target,
+    });
+    // The session expects the relay's greeting before registering
+    switchboard.deliver(vec![Route::Target(json!({ "type": "connected", "clientId": TARGET_CLIENT_ID, "timestamp": now() }))]);
+
+    let mut next_conn = 0;
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                // Out of file descriptors and the like; the next phone may fare better
+                let Ok((stream, _)) = accepted else {
+                    continue;
+                };
+                next_conn += 1;
+                tokio::spawn(serve_phone(switchboard.clone(), next_conn, stream));
+            }
+            message = from_target.next() => match message {
+                Some(Message::Text(text)) => {
+                    if let Ok(message) = serde_json::from_str::<Value>(&text) {
+                        let routes = switchboard.hub.lock().unwrap().on_target(&message);
+                        switchboard.deliver(routes);
+                    }
+                }
+                // Keepalive pings only check that we're still here
+                Some(Message::Ping(data)) => {
+                    let _ = switchboard.target.unbounded_send(Message::Pong(data));
+                }
+                // utterd is quitting
+                Some(Message::Close(_)) => {
+                    let _ = switchboard.target.unbounded_send(Message::Close(None));
+                    switchboard.close_phones().await;
+                    return;
+                }
+                Some(_) => {}
+                None => return,
+            },
+        }
+    }
+}
+
+async fn serve_phone(switchboard: Arc<Switchboard>, conn: u64, stream: TcpStream) {
+    // Anyone on the network can connect, so cap frames as in --strict
+    let config = WebSocketConfig {
+        max_message_size: Some(strict::MAX_MESSAGE_SIZE),
+        max_frame_size: Some(strict::MAX_MESSAGE_SIZE),
+        ..Default::default()
+    };
+    let Ok(ws_stream) = tokio_tungstenite::accept_async_with_config(stream, Some(config)).await else {
+        return;
+    };
+    let (mut write, mut read) = ws_stream.split();
+
+    let (outbox, mut outgoing) = unbounded();
+    switchboard.phones.lock().unwrap().insert(conn, outbox);
+    let writer = tokio::spawn(async move {
+        while let Some(message) = outgoing.next().await {
+            if write.send(message).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let connected = switchboard.hub.lock().unwrap().connect(conn);
+    switchboard.deliver(vec![connected]);
+
+    while let Some(Ok(message)) = read.next().await {
+        match message {
+            Message::Text(text) => {
+                let Ok(message) = serde_json::from_str::<Value>(&text) else {
+                    continue;
+                };
+                let routes = switchboard.hub.lock().unwrap().on_phone(conn, &message);
+                switchboard.deliver(routes);
+            }
+            Message::Close(_) => break,
+            _ => {}
+        }
+    }
+
+    let routes = switchboard.hub.lock().unwrap().disconnect(conn);
+    switchboard.deliver(routes);
+    switchboard.phones.lock().unwrap().remove(&conn);
+    writer.abort();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hub_routes_like_the_relay() {
+        let mut hub = Hub::default();
+        let key = general_purpose::STANDARD.encode([7u8; 32]);
+
+        let registered = hub.on_target(&json!({ "type": "register", "clientType": "target", "deviceId": "desk", "deviceName": "desk" }));
+        assert!(matches!(&registered[..], [Route::Target(m), Route::Target(d)] if m["type"] == "registered" && d["devices"] == json!([])));
+
+        hub.connect(1);
+        let message = json!({ "type": "message", "to": "desk", "content": "abc", "encrypted": true, "nonce": "n", "seq": 4, "aad": 1, "senderAuth": 1, "cipher": "xchacha20-poly1305" });
+        // Unregistered phones can't send
+        assert!(matches!(&hub.on_phone(1, &message)[..], [Route::Phone(1, m)] if m["type"] == "error"));
+
+        // The daemon hears about phones as they register
+        let register = json!({ "type": "register", "clientType": "android", "deviceId": "pixel", "publicKey": key, "platform": "Android 14", "ciphers": ["aes-256-gcm"] });
+        let routes = hub.on_phone(1, &register);
+        let [Route::Phone(1, _), Route::Target(devices)] = &routes[..] else {
+            panic!("unexpected routes {:?}", routes);
+        };
+        assert_eq!(devices["devices"][0]["deviceId"], "pixel");
+        assert_eq!(devices["devices"][0]["platform"], "Android 14");
+        assert_eq!(devices["devices"][0]["ciphers"], json!(["aes-256-gcm"]));
+
+        let routes = hub.on_phone(1, &message);
+        let [Route::Target(forwarded), Route::Phone(1, sent)] = &routes[..] else {
+            panic!("unexpected routes {:?}", routes);
+        };
+        assert_eq!(forwarded["type"], "text");
+        assert_eq!(forwarded["from"], "pixel");
+        assert_eq!(forwarded["senderPublicKey"], key.as_str());
+        assert_eq!(forwarded["seq"], 4);
+        assert_eq!(forwarded["aad"], 1);
+        assert_eq!(forwarded["senderAuth"], 1);
+        assert_eq!(forwarded["cipher"], "xchacha20-poly1305");
+        assert_eq!(forwarded["id"], sent["id"]);
+
+        // A broadcast reaches the daemon with only its own key slot
+        let slot = |to: &str| json!({ "to": to, "ephemeralPublicKey": "e", "nonce": "n", "key": "k" });
+        let broadcast = json!({ "type": "message", "content": "abc", "encrypted": true, "aad": 1, "recipients": [slot("laptop"), slot("desk")] });
+        let routes = hub.on_phone(1, &broadcast);
+        let [Route::Target(forwarded), Route::Phone(1, sent)] = &routes[..] else {
+            panic!("unexpected routes {:?}", routes);
+        };
+        assert_eq!(forwarded["recipients"], json!([slot("desk")]));
+        assert_eq!(sent["to"], "desk");
+        let elsewhere = json!({ "type": "message", "content": "abc", "encrypted": true, "recipients": [slot("laptop")] });
+        assert!(matches!(&hub.on_phone(1, &elsewhere)[..], [Route::Phone(1, m)] if m["type"] == "error"));
+
+        let part = json!({ "type": "message", "to": "desk", "content": "abc", "encrypted": true, "id": "long", "part": 2, "parts": 3 });
+        assert!(matches!(&hub.on_phone(1, &part)[..], [Route::Target(m), _] if m["part"] == 2 && m["parts"] == 3));
+        let stray = json!({ "type": "message", "to": "desk", "content": "abc", "encrypted": true, "part": 1, "parts": 2 });
+        assert!(matches!(&hub.on_phone(1, &stray)[..], [Route::Phone(1, m)] if m["type"] == "error"));
+
+        // Plaintext and unknown targets are refused
+        let plaintext = json!({ "type": "message", "to": "desk", "content": "abc" });
+        assert!(matches!(&hub.on_phone(1, &plaintext)[..], [Route::Phone(1, m)] if m["type"] == "error"));
+        let elsewhere = json!({ "type": "message", "to": "laptop", "content": "abc", "encrypted": true });
+        assert!(matches!(&hub.on_phone(1, &elsewhere)[..], [Route::Phone(1, m)] if m["type"] == "error"));
+
+        // Acks find their way back to the phone, and are dropped once it's gone
+        let ack = json!({ "type": "ack", "to": "pixel", "id": "x", "status": "typed" });
+        assert!(matches!(&hub.on_target(&ack)[..], [Route::Phone(1, m)] if m["from"] == "desk" && m["status"] == "typed"));
+        assert!(matches!(&hub.disconnect(1)[..], [Route::Target(m)] if m["devices"] == json!([])));
+        assert!(hub.on_target(&ack).is_empty());
+    }
+}
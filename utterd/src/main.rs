@@ -1,33 +1,102 @@
+mod audit;
 mod auth;
+mod chunks;
+mod clipboard;
+mod config;
+mod correction;
 mod crypto;
+#[cfg(unix)]
+mod daemon;
+mod happy_eyeballs;
+mod headers;
+mod history;
+mod hooks;
+mod identity;
+mod lan;
+mod mdns;
+mod netwatch;
+mod normalize;
 mod oauth;
-
-use clap::Parser;
-use crypto::{KeyManager, MessageEncryption, EncryptedMessage};
-use futures_util::{SinkExt, StreamExt};
+mod output;
+mod pairing;
+mod picker;
+mod probe;
+mod proxy;
+mod ratelimit;
+mod reconnect;
+mod reorder;
+mod replay;
+mod sandbox;
+mod session_keys;
+mod speech;
+mod state;
+mod strict;
+mod sync;
+mod tls;
+mod transcript;
+mod theme;
+mod tui;
+mod typing;
+#[cfg(unix)]
+mod unix;
+use base64::{engine::general_purpose, Engine as _};
+
+use clap::{Parser, Subcommand};
+use config::{Config, Profile};
+use headers::Headers;
+use crypto::{Binding, Cipher, KeyManager, KeySlot, MessageEncryption, EncryptedMessage};
+use futures_util::{SinkExt, Stream, StreamExt};
+use secrecy::{ExposeSecret, SecretBox};
 use serde::{Deserialize, Serialize};
 use std::fs::{File, OpenOptions};
-use std::path::PathBuf;
-use std::process::Command;
+use std::io::{IsTerminal, Write};
+use std::collections::{HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::Mutex;
+use tokio::sync::{mpsc, Mutex, Notify};
 use tokio::time::sleep;
-use tokio_tungstenite::{connect_async, tungstenite::Message};
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::{CloseFrame, WebSocketConfig};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::header::{HeaderValue, AUTHORIZATION};
+use tokio_tungstenite::tungstenite::Message;
 use fs2::FileExt;
+use history::{History, HistoryEntry};
+use output::Output;
+use proxy::Proxy;
+use reconnect::{CloseStatus, ConnectError};
+use tokio_tungstenite::tungstenite::Error as WsError;
+use state::{AppState, Command, Focus, LogLevel, PairingPrompt, Phone, Warning};
+use transcript::Progress;
+use tui::Tui;
+use typing::{CancelToken, Injector, Key, TOOLS};
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// How often the typing tool's dependencies are re-checked while idle
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How often the TUI's view of the focused window is refreshed
+const FOCUS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a release waits for focus to leave utterd's terminal, and how
+/// often it checks
+const RELEASE_WAIT: Duration = Duration::from_secs(10);
+const RELEASE_POLL: Duration = Duration::from_millis(200);
+
+/// How long quitting waits for the relay to acknowledge our close frame
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(2);
+
 // ANSI color codes
 mod colors {
     pub const RESET: &str = "\x1b[0m";
-    pub const BRIGHT: &str = "\x1b[1m";
     pub const DIM: &str = "\x1b[2m";
     pub const RED: &str = "\x1b[31m";
     pub const GREEN: &str = "\x1b[32m";
     pub const YELLOW: &str = "\x1b[33m";
     pub const CYAN: &str = "\x1b[36m";
-    pub const GRAY: &str = "\x1b[90m";
 }
 
 fn get_hostname() -> String {
@@ -62,8 +131,13 @@ fn strip_ws_prefix(url: &str) -> &str {
         .unwrap_or(url)
 }
 
+/// Where the relay takes the OAuth token exchange and JWT refreshes
+fn http_url_for(server_url: &str) -> String {
+    server_url.replace("ws://", "http://").replace("wss://", "https://")
+}
+
 fn normalize_server_url(url: &str) -> String {
-    if url.starts_with("ws://") || url.starts_with("wss://") {
+    if url.starts_with("ws://") || url.starts_with("wss://") || url.starts_with("unix://") {
         url.to_string()
     } else {
         format!("ws://{}", url)
@@ -71,15 +145,16 @@ fn normalize_server_url(url: &str) -> String {
 }
 
 /// Acquire an exclusive lock to ensure only one instance of utterd runs
-fn acquire_singleton_lock(lock_file_path: Option<String>) -> Result<File, String> {
+fn acquire_singleton_lock(lock_file_path: Option<String>, profile: Option<&str>) -> Result<File, String> {
     let lock_path: PathBuf = if let Some(path) = lock_file_path {
         PathBuf::from(path)
     } else {
-        // Default: ~/.utterd/lock
+        // Default: ~/.utterd/lock, or ~/.utterd/<profile>.lock so each
+        // profile can run alongside the others
         dirs::home_dir()
             .ok_or("Cannot determine home directory")?
             .join(".utterd")
-            .join("lock")
+            .join(profile.map_or_else(|| "lock".to_string(), |profile| format!("{}.lock", profile)))
     };
 
     // Create parent directory if it doesn't exist
@@ -91,6 +166,7 @@ fn acquire_singleton_lock(lock_file_path: Option<String>) -> Result<File, String
     let lock_file = OpenOptions::new()
         .write(true)
         .create(true)
+        .truncate(false)
         .open(&lock_path)
         .map_err(|e| format!("Cannot create lock file at {}: {}", lock_path.display(), e))?;
 
@@ -105,18 +181,238 @@ fn acquire_singleton_lock(lock_file_path: Option<String>) -> Result<File, String
 #[derive(Parser)]
 #[command(name = "utterd")]
 #[command(about = "utterd - Voice dictation from Android to Linux", long_about = None)]
+#[command(after_help = "Passphrases are read from the environment, never the command line: UTTER_SYNC_PASSPHRASE syncs config.toml with your other desktops, UTTER_KEY_PASSPHRASE unlocks an encrypted key file.")]
 struct Args {
-    /// Relay server URL (default: localhost:8080)
-    #[arg(long, env = "UTTER_RELAY_SERVER", default_value = "ws://localhost:8080", hide_default_value = true)]
-    server: String,
+    /// Relay server URL (default: localhost:8080); repeat it, or separate URLs with commas, to fail over between relays
+    #[arg(long, env = "UTTER_RELAY_SERVER", value_delimiter = ',')]
+    server: Vec<String>,
+
+    /// Let the phone connect straight to utterd on this address (e.g. 0.0.0.0:8080) instead of going through a relay; for phones on the same network, no sign-in needed
+    #[arg(long, env = "UTTER_LISTEN", conflicts_with = "strict")]
+    listen: Option<SocketAddr>,
 
-    /// Tool for simulating keyboard input (default: xdotool)
-    #[arg(long, default_value = "xdotool", hide_default_value = true)]
-    tool: String,
+    /// Sign in to a self-hosted relay (started with ANONYMOUS_AUTH=true) with a secret of this desktop's own instead of a Google account; phones join with the one-time code in the pairing QR code
+    #[arg(long, env = "UTTER_ANONYMOUS", conflicts_with = "listen")]
+    anonymous: bool,
 
-    /// Lock file path to prevent multiple instances (default: ~/.utterd/lock)
+    /// With --listen, don't announce this desktop to phones on the network (mDNS, _utter._tcp)
+    #[arg(long, env = "UTTER_NO_ADVERTISE", requires = "listen")]
+    no_advertise: bool,
+
+    /// Tool for simulating keyboard input: xdotool, ydotool, keyd, atspi, portal; osascript on macOS; sendinput on Windows (default: detected from the session)
+    #[arg(long)]
+    tool: Option<String>,
+
+    /// Lock file path to prevent multiple instances (default: ~/.utterd/lock, or ~/.utterd/<profile>.lock)
     #[arg(long)]
     lock_file: Option<String>,
+
+    /// Use a separate identity, its own keypair and trusted phones in ~/.config/utterd/<profile>, e.g. one for a work relay and one for home
+    #[arg(long, env = "UTTER_PROFILE", value_parser = config::parse_profile)]
+    profile: Option<String>,
+
+    /// X11 display to type into, overriding DISPLAY (e.g. :0 from inside a container)
+    #[arg(long, env = "UTTER_DISPLAY")]
+    display: Option<String>,
+
+    /// Wayland display to type into, overriding WAYLAND_DISPLAY
+    #[arg(long, env = "UTTER_WAYLAND_DISPLAY")]
+    wayland_display: Option<String>,
+
+    /// Rewrite spoken numbers and dates as numerals, using each message's language tag
+    #[arg(long)]
+    normalize: bool,
+
+    /// Run in the background, detached from the terminal (e.g. from session autostart), logging to --log-file; Linux and macOS
+    #[arg(long, env = "UTTER_DAEMON")]
+    daemon: bool,
+
+    /// Log file for --daemon (default: ~/.local/share/utterd/utterd.log, or <profile>.log)
+    #[arg(long, requires = "daemon")]
+    log_file: Option<String>,
+
+    /// Screen-reader mode: plain line output and spoken announcements via speech-dispatcher
+    #[arg(long, env = "UTTER_SCREEN_READER")]
+    screen_reader: bool,
+
+    /// Focus the first window of this class before typing, then give focus back; dictations are skipped while none is open (e.g. obsidian)
+    #[arg(long, visible_alias = "target-window-class", env = "UTTER_TARGET_CLASS")]
+    target_class: Option<String>,
+
+    /// Sync config.toml with your other desktops through the relay, end-to-end encrypted with this passphrase (use the same one everywhere); from UTTER_SYNC_PASSPHRASE only, as arguments show up in the process list
+    #[arg(skip = std::env::var("UTTER_SYNC_PASSPHRASE").ok().filter(|passphrase| !passphrase.is_empty()))]
+    sync_passphrase: Option<String>,
+
+    /// Encrypt the private key file with a passphrase, asked for now, instead of keeping the key in the OS keyring; UTTER_KEY_PASSPHRASE gives it without asking, and it's asked for at startup once the file is encrypted
+    #[arg(long, env = "UTTER_ENCRYPT_KEY", conflicts_with = "tpm")]
+    encrypt_key: bool,
+
+    /// The key file's passphrase, from UTTER_KEY_PASSPHRASE only: arguments show up in the process list
+    #[arg(skip = std::env::var("UTTER_KEY_PASSPHRASE").ok().filter(|passphrase| !passphrase.is_empty()))]
+    key_passphrase: Option<String>,
+
+    /// Refuse to start if the keypair can't be loaded; --require-encryption=false connects anyway, though no message can be decrypted
+    #[arg(
+        long,
+        env = "UTTER_REQUIRE_ENCRYPTION",
+        default_value_t = true,
+        num_args = 0..=1,
+        default_missing_value = "true",
+        action = clap::ArgAction::Set,
+        value_parser = clap::builder::BoolishValueParser::new()
+    )]
+    require_encryption: bool,
+
+    /// INSECURE, for development: type unencrypted text messages from any sender, e.g. a test client on a dev relay
+    #[arg(long, env = "UTTER_ALLOW_PLAINTEXT")]
+    allow_plaintext: bool,
+
+    /// Seal the private key to this machine's TPM2 (with systemd-creds) instead of the OS keyring, so the key file is useless anywhere else
+    #[arg(long, env = "UTTER_TPM")]
+    tpm: bool,
+
+    /// ydotoold socket for --tool ydotool (default: YDOTOOL_SOCKET, then ydotool's own default)
+    #[arg(long, env = "UTTER_YDOTOOL_SOCKET")]
+    ydotool_socket: Option<PathBuf>,
+
+    /// Run typing tools with a minimal environment and no network, and make the filesystem read-only (except utterd's data) after startup (Linux)
+    #[arg(long, env = "UTTER_HARDEN")]
+    harden: bool,
+
+    /// Hang up on any protocol anomaly from the relay (unexpected fields or message order, invalid base64, oversize frames), for relays you don't operate
+    #[arg(long, env = "UTTER_STRICT")]
+    strict: bool,
+
+    /// Dictations older than this many seconds (e.g. queued during a long disconnect) aren't typed straight away; 0 types them regardless of age
+    #[arg(long, env = "UTTER_MAX_AGE", default_value_t = 600)]
+    max_age: u64,
+
+    /// What to do with dictations older than --max-age: hold them until released with r, or drop them (both keep them in history)
+    #[arg(long, env = "UTTER_STALE", value_enum, default_value_t = Defer::Hold)]
+    stale: Defer,
+
+    /// Most dictations and keys typed in any one second; more are held or dropped (see --over-limit); 0 for no cap
+    #[arg(long, env = "UTTER_MAX_PER_SECOND", default_value_t = 5)]
+    max_per_second: usize,
+
+    /// Most dictations and keys typed in any one minute; 0 for no cap
+    #[arg(long, env = "UTTER_MAX_PER_MINUTE", default_value_t = 30)]
+    max_per_minute: usize,
+
+    /// What to do with dictations over --max-per-second or --max-per-minute: hold them until released with r, or drop them (keys are always dropped)
+    #[arg(long, env = "UTTER_OVER_LIMIT", value_enum, default_value_t = Defer::Hold)]
+    over_limit: Defer,
+
+    /// Longest dictation accepted, in characters of its encrypted content (about 4/3 of the text); longer ones, whole or sent in parts, are refused
+    #[arg(long, env = "UTTER_MAX_LENGTH", default_value_t = 200_000)]
+    max_length: usize,
+
+    /// Type interim results of a dictation as the phone streams them, fixing them up as recognition settles, instead of waiting for the final text
+    #[arg(long, env = "UTTER_LIVE")]
+    live: bool,
+
+    /// Hold each dictation in an editor in the status view to fix recognition errors before it's typed (Enter types it, Esc discards it)
+    #[arg(long, env = "UTTER_EDIT", conflicts_with = "live")]
+    edit: bool,
+
+    /// Seconds between WebSocket pings to the relay, to notice dead connections (NAT timeouts, network changes); 0 turns them off
+    #[arg(long, env = "UTTER_PING_INTERVAL", default_value_t = 20)]
+    ping_interval: u64,
+
+    /// Seconds to wait for anything from the relay after a ping before reconnecting
+    #[arg(long, env = "UTTER_PING_TIMEOUT", default_value_t = 10)]
+    ping_timeout: u64,
+
+    /// Also trust this PEM certificate for wss:// relays: a private CA, or the relay's self-signed certificate
+    #[arg(long, env = "UTTER_TLS_CA")]
+    tls_ca: Option<PathBuf>,
+
+    /// Reach the relay through a proxy: http://, socks5:// or socks5h:// (default: HTTPS_PROXY for wss://, HTTP_PROXY for ws://, then ALL_PROXY; NO_PROXY is honored)
+    #[arg(long, env = "UTTER_PROXY")]
+    proxy: Option<Proxy>,
+
+    /// Extra header for the relay handshake and JWT exchange, e.g. "X-Api-Key: ..." for a reverse proxy; repeatable
+    #[arg(long = "header", value_name = "NAME: VALUE")]
+    headers: Vec<String>,
+
+    /// Client certificate (PEM) to present to relays behind mutual TLS; needs --tls-key
+    #[arg(long, env = "UTTER_TLS_CERT", requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// Private key for --tls-cert (unencrypted PKCS#8 PEM)
+    #[arg(long, env = "UTTER_TLS_KEY", requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// Don't verify the relay's TLS certificate at all (testing only: anyone on the network path can impersonate the relay)
+    #[arg(long, env = "UTTER_TLS_INSECURE")]
+    tls_insecure: bool,
+
+    /// Where dictations go: type (default); stdout or fifo:PATH for one line per dictation; file:PATH to append them to a notes file with timestamps
+    #[arg(long, env = "UTTER_OUTPUT", default_value = "type", hide_default_value = true)]
+    output: Output,
+
+    #[command(subcommand)]
+    command: Option<Commands>,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Browse previously received dictations
+    History {
+        #[command(subcommand)]
+        action: HistoryCommand,
+    },
+    /// Connect to the relay and print daemon/relay versions and features side by side
+    Probe,
+    /// Sign in with Google now (reusing a valid sign-in), e.g. before starting utterd as a service
+    Login {
+        /// Sign in as a further named account (e.g. work) and use it from now on; without it, the profile's default one
+        #[arg(long, value_parser = config::parse_profile)]
+        account: Option<String>,
+    },
+    /// Forget the cached Google sign-in
+    Logout {
+        /// The named account to forget instead of the default one
+        #[arg(long, value_parser = config::parse_profile)]
+        account: Option<String>,
+    },
+    /// Manage this desktop's encryption keypair
+    Keys {
+        #[command(subcommand)]
+        action: KeysCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum KeysCommand {
+    /// Print the fingerprints of this desktop's key and of every trusted phone, to compare with what the phones show
+    Show,
+    /// Replace the keypair; paired phones are sent the new key, and the old
+    /// one keeps working for a week
+    Rotate,
+    /// Write the private key and trusted phones to FILE, encrypted with a passphrase, to move them to another machine
+    Export { file: PathBuf },
+    /// Take over the identity exported on another machine, so its phones keep working here
+    Import {
+        file: PathBuf,
+        /// Replace this desktop's own, different key; phones paired with it must pair again
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum HistoryCommand {
+    /// Fuzzy-find a recent dictation and type it (Enter) or copy it (Tab)
+    Pick,
+    /// Print past dictations as a document, e.g. to archive meeting notes
+    Export {
+        #[arg(long, value_enum, default_value = "md")]
+        format: history::ExportFormat,
+        /// Only dictations from this local date on (YYYY-MM-DD)
+        #[arg(long, value_parser = history::parse_since)]
+        since: Option<chrono::DateTime<chrono::Utc>>,
+    },
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -143,6 +439,9 @@ enum WsMessage {
         arch: Option<String>,
         #[serde(skip_serializing_if = "Option::is_none")]
         jwt: Option<String>,
+        /// AEADs this desktop decrypts, most preferred first
+        #[serde(skip_serializing_if = "Option::is_none")]
+        ciphers: Option<Vec<String>>,
     },
     Registered,
     Text {
@@ -159,51 +458,491 @@ enum WsMessage {
         ephemeral_public_key: Option<String>,
         #[serde(rename = "senderPublicKey", skip_serializing_if = "Option::is_none")]
         sender_public_key: Option<String>,
+        /// 1: the ciphertext is bound to both device ids and `seq`, as
+        /// AES-GCM associated data (`crypto::Binding`)
+        #[serde(skip_serializing_if = "Option::is_none")]
+        aad: Option<u32>,
+        /// 1: the message key mixes in the sender's static key, so it only
+        /// decrypts if the sender holds `senderPublicKey`
+        #[serde(rename = "senderAuth", skip_serializing_if = "Option::is_none")]
+        sender_auth: Option<u32>,
+        /// AEAD the sender picked from those this desktop advertised
+        /// (`crypto::Cipher`); AES-256-GCM if absent
+        #[serde(skip_serializing_if = "Option::is_none")]
+        cipher: Option<String>,
+        /// For a broadcast to several desktops: the content key wrapped for
+        /// each (`crypto::KeySlot`); the relay passes on only this one's
+        #[serde(skip_serializing_if = "Option::is_none")]
+        recipients: Option<Vec<KeySlot>>,
+        /// BCP 47 language of the dictation (e.g. "de-DE")
+        #[serde(skip_serializing_if = "Option::is_none")]
+        language: Option<String>,
+        /// Stable id assigned by the relay, referenced by later corrections
+        #[serde(skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+        /// Sender's message counter, for restoring dictation order
+        #[serde(skip_serializing_if = "Option::is_none")]
+        seq: Option<u64>,
+        /// Which piece of a dictation too long for one relay message this
+        /// is, from 1 to `parts`; the pieces share the id
+        #[serde(skip_serializing_if = "Option::is_none")]
+        part: Option<u32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        parts: Option<u32>,
+    },
+    /// Interim speech-recognition result of an utterance still being
+    /// dictated: its whole text so far. The final is a Text with the same id
+    #[serde(rename = "text_partial")]
+    TextPartial {
+        id: String,
+        content: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        from: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        encrypted: Option<bool>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        nonce: Option<String>,
+        #[serde(rename = "ephemeralPublicKey", skip_serializing_if = "Option::is_none")]
+        ephemeral_public_key: Option<String>,
+        #[serde(rename = "senderPublicKey", skip_serializing_if = "Option::is_none")]
+        sender_public_key: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        aad: Option<u32>,
+        #[serde(rename = "senderAuth", skip_serializing_if = "Option::is_none")]
+        sender_auth: Option<u32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        cipher: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        recipients: Option<Vec<KeySlot>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        language: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        timestamp: Option<i64>,
+    },
+    /// Replacement text for an earlier Text, e.g. the phone's "fix last sentence"
+    Correction {
+        id: String,
+        content: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        from: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        encrypted: Option<bool>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        nonce: Option<String>,
+        #[serde(rename = "ephemeralPublicKey", skip_serializing_if = "Option::is_none")]
+        ephemeral_public_key: Option<String>,
+        #[serde(rename = "senderPublicKey", skip_serializing_if = "Option::is_none")]
+        sender_public_key: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        aad: Option<u32>,
+        #[serde(rename = "senderAuth", skip_serializing_if = "Option::is_none")]
+        sender_auth: Option<u32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        cipher: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        recipients: Option<Vec<KeySlot>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        language: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        seq: Option<u64>,
+    },
+    /// A non-text key such as Enter, Tab or an arrow; the name is
+    /// E2E-encrypted like Text content
+    Key {
+        key: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        from: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        encrypted: Option<bool>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        nonce: Option<String>,
+        #[serde(rename = "ephemeralPublicKey", skip_serializing_if = "Option::is_none")]
+        ephemeral_public_key: Option<String>,
+        #[serde(rename = "senderPublicKey", skip_serializing_if = "Option::is_none")]
+        sender_public_key: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        aad: Option<u32>,
+        #[serde(rename = "senderAuth", skip_serializing_if = "Option::is_none")]
+        sender_auth: Option<u32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        cipher: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        timestamp: Option<i64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        seq: Option<u64>,
+    },
+    /// Content for the clipboard instead of the keyboard (a password, a
+    /// URL), E2E-encrypted like Text
+    Clipboard {
+        content: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        from: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        encrypted: Option<bool>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        nonce: Option<String>,
+        #[serde(rename = "ephemeralPublicKey", skip_serializing_if = "Option::is_none")]
+        ephemeral_public_key: Option<String>,
+        #[serde(rename = "senderPublicKey", skip_serializing_if = "Option::is_none")]
+        sender_public_key: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        aad: Option<u32>,
+        #[serde(rename = "senderAuth", skip_serializing_if = "Option::is_none")]
+        sender_auth: Option<u32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        cipher: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        timestamp: Option<i64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        seq: Option<u64>,
+    },
+    /// A phone asking to pair; content is an encrypted `pairing::PairingBundle`
+    #[serde(rename = "pair_request")]
+    PairRequest {
+        content: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        from: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        encrypted: Option<bool>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        nonce: Option<String>,
+        #[serde(rename = "ephemeralPublicKey", skip_serializing_if = "Option::is_none")]
+        ephemeral_public_key: Option<String>,
+        #[serde(rename = "senderPublicKey", skip_serializing_if = "Option::is_none")]
+        sender_public_key: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        aad: Option<u32>,
+        #[serde(rename = "senderAuth", skip_serializing_if = "Option::is_none")]
+        sender_auth: Option<u32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        cipher: Option<String>,
     },
+    /// The user's answer, relayed back to the phone
+    #[serde(rename = "pair_response")]
+    PairResponse { to: String, accepted: bool },
+    /// Tell a paired phone this desktop's new key after `utterd keys rotate`,
+    /// with proof from the key it pinned
+    #[serde(rename = "key_rotated")]
+    KeyRotated {
+        to: String,
+        #[serde(rename = "publicKey")]
+        public_key: String,
+        proof: String,
+    },
+    /// The short-lived key a paired phone should encrypt to, vouched for by
+    /// this desktop's long-term key
+    #[serde(rename = "session_key")]
+    SessionKey {
+        to: String,
+        #[serde(rename = "publicKey")]
+        public_key: String,
+        proof: String,
+    },
+    /// Ask the relay for the account's synced config
+    #[serde(rename = "get_config")]
+    GetConfig,
+    /// The relay's copy of the synced config; no blob if nothing was uploaded yet
+    Config {
+        version: u64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        blob: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        nonce: Option<String>,
+        #[serde(rename = "updatedBy", skip_serializing_if = "Option::is_none")]
+        updated_by: Option<String>,
+    },
+    /// Upload an encrypted config bundle, accepted only if the relay is still at `base_version`
+    #[serde(rename = "put_config")]
+    PutConfig {
+        blob: String,
+        nonce: String,
+        #[serde(rename = "baseVersion")]
+        base_version: u64,
+    },
+    #[serde(rename = "config_saved")]
+    ConfigSaved { version: u64 },
+    /// Another desktop uploaded first
+    #[serde(rename = "config_conflict")]
+    ConfigConflict { version: u64 },
     Pong,
+    /// The relay refused something we sent
+    Error { message: String },
+    /// What became of a Text, relayed back to the phone that sent it
+    Ack {
+        to: String,
+        id: String,
+        status: Delivery,
+        /// What went wrong, for `failed`
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    /// The account's connected devices, pushed whenever one comes or goes
+    Devices { devices: Vec<RelayDevice> },
+    /// One-time code a phone joins this anonymous account with, replaced
+    /// by the relay once used or expired (`--anonymous`)
+    #[serde(rename = "pairing_code")]
+    PairingCode { code: String },
 }
 
-#[derive(Clone)]
-struct AppState {
-    client_id: Option<String>,
-    last_message_timestamp: Option<i64>,
-    last_message_sender: Option<String>,
-    last_message_text: Option<String>,
+/// An entry of the relay's device list
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct RelayDevice {
+    device_id: String,
+    device_name: String,
+    device_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    public_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    platform: Option<String>,
 }
 
-impl AppState {
-    fn new() -> Self {
-        Self {
-            client_id: None,
-            last_message_timestamp: None,
-            last_message_sender: None,
-            last_message_text: None,
+/// What to do with a dictation that mustn't be typed right away
+/// (`--stale`, `--over-limit`)
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+enum Defer {
+    /// Hold for review, like a do-not-type zone
+    Hold,
+    /// Don't type at all
+    Drop,
+}
+
+/// Outcome of a dictation, as reported to the phone in an Ack
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Delivery {
+    /// Typed into a window
+    Typed,
+    /// Written to `--output`
+    Written,
+    /// Typing is unavailable; copied to the clipboard instead
+    Clipboard,
+    /// Typing and the clipboard are unavailable; only in history
+    Saved,
+    /// Waiting in a do-not-type zone for the user to release it
+    Held,
+    /// The `--target-class` window isn't open
+    Skipped,
+    /// Older than `--max-age` and dropped
+    Expired,
+    /// Over `--max-per-second` or `--max-per-minute` and dropped
+    RateLimited,
+    /// Not from a paired phone
+    Unpaired,
+    /// Plaintext, or didn't decrypt with this desktop's key
+    DecryptionFailed,
+    /// The same encrypted message was already received: a relay replaying it
+    Replayed,
+    Cancelled,
+    /// The typing tool or `--output` failed
+    Failed,
+}
+
+impl Delivery {
+    /// Whether the text reached the user; a retransmission of it is a duplicate
+    fn is_final(self) -> bool {
+        matches!(self, Delivery::Typed | Delivery::Written | Delivery::Clipboard | Delivery::Saved | Delivery::Held)
+    }
+}
+
+impl WsMessage {
+    /// Sender and sequence number of a message that must keep its order
+    fn sequence(&self) -> Option<(&str, u64)> {
+        match self {
+            WsMessage::Text { from: Some(from), seq: Some(seq), .. }
+            | WsMessage::Correction { from: Some(from), seq: Some(seq), .. }
+            | WsMessage::Key { from: Some(from), seq: Some(seq), .. }
+            | WsMessage::Clipboard { from: Some(from), seq: Some(seq), .. } => Some((from, *seq)),
+            _ => None,
         }
     }
 }
 
+/// The E2E-encrypted payload of a phone's message, with what's needed to
+/// open it
+struct Sealed {
+    content: String,
+    encrypted: Option<bool>,
+    nonce: Option<String>,
+    ephemeral_public_key: Option<String>,
+    sender_public_key: Option<String>,
+    /// Associated data version the phone bound the ciphertext with
+    aad: Option<u32>,
+    /// Sender authentication version the phone derived the key with
+    sender_auth: Option<u32>,
+    cipher: Option<String>,
+    /// Key slots of a broadcast
+    recipients: Option<Vec<KeySlot>>,
+    seq: Option<u64>,
+}
+
+impl Sealed {
+    /// The sender's ephemeral key, for a broadcast the one in this desktop's
+    /// key slot; with the nonce it identifies the ciphertext
+    fn ephemeral_key(&self) -> Option<String> {
+        let to = get_hostname();
+        let slot = |slots: &Vec<KeySlot>| slots.iter().find(|slot| slot.to == to).map(|slot| slot.ephemeral_public_key.clone());
+        self.ephemeral_public_key.clone().or_else(|| self.recipients.as_ref().and_then(slot))
+    }
+}
+
+/// A received dictation on its way to being typed
+struct Dictation {
+    id: String,
+    sender: String,
+    /// The relay assigned `id` and named the sender, so the phone can get an Ack
+    ackable: bool,
+    text: String,
+    language: Option<String>,
+    timestamp: Option<i64>,
+}
+
+/// A device waiting for the user to trust it
+struct PendingPair {
+    /// The phone's device id, where a PairResponse goes
+    to: String,
+    bundle: pairing::PairingBundle,
+    /// It sent a pairing request and expects an answer; otherwise it just
+    /// sent a message (trust on first use)
+    requested: bool,
+    /// Its dictations so far, held until the user decides
+    dictations: Vec<Dictation>,
+}
+
+/// Whether a sender's messages may be acted on
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Trust {
+    Trusted,
+    /// Not yet: the user is being asked
+    Asked,
+    Refused,
+}
+
+/// How long a message that overtook an earlier one waits for it
+const REORDER_WINDOW: Duration = Duration::from_millis(750);
+
+/// Dictations that took longer (ms) were queued, not slow, and aren't
+/// counted in the delivery latency
+const DELIVERY_SAMPLE_LIMIT: i64 = 60_000;
+
+/// Recent dictation ids remembered to recognize a phone's retransmission
+const RECENT_DELIVERIES: usize = 256;
+
 struct UtterClient {
+    /// Relay in use, one of `servers`
     server_url: String,
-    tool: String,
+    servers: Vec<String>,
+    /// Serve phones directly instead of connecting to `server_url` (`--listen`)
+    listen: Option<SocketAddr>,
+    /// Announce `listen` over mDNS
+    advertise: bool,
+    /// Sign in without Google (`--anonymous`)
+    anonymous: bool,
+    injector: Injector,
     state: Arc<Mutex<AppState>>,
     key_manager: Option<Arc<KeyManager>>,
     message_encryption: Option<Arc<MessageEncryption>>,
     jwt: Option<String>,
+    normalize: bool,
+    screen_reader: bool,
+    target_class: Option<String>,
+    output: Output,
+    /// Protocol anomalies end the connection (`--strict`)
+    strict: bool,
+    /// Unencrypted text messages are typed, unpaired senders included
+    /// (`--allow-plaintext`)
+    allow_plaintext: bool,
+    /// Dictations older than this aren't typed directly (`--max-age`)
+    max_age: Option<Duration>,
+    stale: Defer,
+    /// Caps typing speed so a flooding sender can't take over the keyboard
+    rate_limit: Arc<Mutex<ratelimit::RateLimit>>,
+    over_limit: Defer,
+    /// How often to ping the relay (None: never) and how long to wait for an answer
+    ping_interval: Option<Duration>,
+    ping_timeout: Duration,
+    tls: tls::Tls,
+    proxy: Option<Proxy>,
+    /// Extra handshake headers (`--header`)
+    headers: Headers,
+    config: Config,
+    /// Set by the TUI's Esc key to abort the dictation being typed
+    cancel: CancelToken,
+    /// Output goes to the TUI rather than plain lines
+    tui: bool,
+    /// What the last dictation left in the focused window, for corrections
+    last_typed: Arc<Mutex<Option<correction::Typed>>>,
+    sync_passphrase: Option<String>,
+    /// Config sync, set up once the account id is known
+    sync: Option<Arc<sync::ConfigSync>>,
+    pairings: Arc<Mutex<pairing::Pairings>>,
+    /// Pairing request or first contact waiting for the user
+    pending_pair: Arc<Mutex<Option<PendingPair>>>,
+    /// Keys the user declined to trust on first contact, not asked about
+    /// again until restart
+    refused: Arc<Mutex<HashSet<String>>>,
+    /// Pairing answers and releases from the TUI or stdin
+    commands_tx: mpsc::UnboundedSender<Command>,
+    commands: Arc<Mutex<mpsc::UnboundedReceiver<Command>>>,
+    /// Dictations that arrived while a do-not-type window was focused
+    held: Arc<Mutex<Vec<Dictation>>>,
+    /// Puts messages back in the order they were dictated; kept across
+    /// reconnects, which is when messages get overtaken
+    reorder: Arc<Mutex<reorder::Reorderer<WsMessage>>>,
+    /// Outcome of recent dictations by id, newest last
+    deliveries: Arc<Mutex<VecDeque<(String, Delivery)>>>,
+    /// Utterances the phone is streaming interim results for
+    transcripts: Arc<Mutex<transcript::Transcripts>>,
+    /// Type interim results as they come (`--live`)
+    live: bool,
+    /// Hold dictations for the user to edit first (`--edit`)
+    edit: bool,
+    /// Dictations waiting in the editor, the one shown first
+    editing: Arc<Mutex<VecDeque<Dictation>>>,
+    /// Parts of long dictations still coming in
+    chunks: Arc<Mutex<chunks::Chunks>>,
+    /// Encrypted messages already received, so none is acted on twice
+    replay: Arc<Mutex<replay::ReplayGuard>>,
+    /// Short-lived keys the phones encrypt to, for forward secrecy
+    session_keys: Arc<Mutex<session_keys::SessionKeys>>,
+    /// `--profile`: whose keys, trusted phones and sign-in to use
+    profile: Option<String>,
 }
 
 impl UtterClient {
-    fn new(server_url: String, tool: String) -> Self {
-        let state = Arc::new(Mutex::new(AppState::new()));
+    fn new(servers: Vec<String>, args: &Args, config: Config, tls: tls::Tls, proxy: Option<Proxy>, headers: Headers) -> Self {
+        let server_url = servers[0].clone();
+        let injector = injector_from_args(args, &config);
+        let destination = match args.output {
+            Output::Type => injector.tool().to_string(),
+            ref output => format!("output: {}", output),
+        };
+        let pairings = pairing::Pairings::load(args.profile.as_deref()).unwrap_or_else(|e| {
+            eprintln!("{}✗ {}{}", colors::RED, e, colors::RESET);
+            std::process::exit(1);
+        });
+        let replay = replay::ReplayGuard::load().unwrap_or_else(|e| {
+            eprintln!("{}⚠ {} (replays are only caught until utterd restarts){}", colors::YELLOW, e, colors::RESET);
+            replay::ReplayGuard::default()
+        });
+        let (commands_tx, commands) = mpsc::unbounded_channel();
 
         // Initialize crypto
-        let (key_manager, message_encryption) = match KeyManager::new() {
+        let (key_manager, message_encryption) = match open_keys(args) {
             Ok(mut km) => {
                 match km.get_or_generate_keypair() {
                     Ok(_) => {
                         // Create MessageEncryption
                         match (km.get_private_key_bytes(), km.get_public_key_bytes()) {
                             (Ok(priv_key), Ok(pub_key)) => {
-                                let enc = MessageEncryption::new(&priv_key, &pub_key);
+                                let mut enc = MessageEncryption::new(priv_key.expose_secret(), &pub_key);
+                                match km.previous_private_key() {
+                                    Ok(Some(previous)) => enc = enc.with_previous_key(previous),
+                                    Ok(None) => {}
+                                    Err(e) => eprintln!("{}⚠ Failed to load the rotated-out key: {}{}", colors::YELLOW, e, colors::RESET),
+                                }
                                 (Some(Arc::new(km)), Some(Arc::new(enc)))
                             }
                             _ => {
@@ -219,291 +958,1876 @@ impl UtterClient {
                 }
             }
             Err(e) => {
-                eprintln!("{}✗ Failed to create KeyManager: {}{}", colors::RED, e, colors::RESET);
+                eprintln!("{}✗ Failed to open the keypair: {}{}", colors::RED, e, colors::RESET);
                 (None, None)
             }
         };
+        // Registering without a key would only get every message rejected
+        if message_encryption.is_none() {
+            if args.require_encryption {
+                eprintln!(
+                    "{}✗ Cannot start without a keypair (--require-encryption=false connects anyway){}",
+                    colors::RED,
+                    colors::RESET
+                );
+                std::process::exit(1);
+            }
+            eprintln!("{}⚠ No keypair: connecting anyway, but no message can be decrypted{}", colors::YELLOW, colors::RESET);
+        }
+
+        let shown_url = args.listen.map_or_else(|| server_url.clone(), |addr| format!("ws://{}", addr));
+        let mut app_state = AppState::new(shown_url, get_hostname(), destination);
+        if args.allow_plaintext {
+            eprintln!(
+                "{}⚠ INSECURE: typing unencrypted messages from any sender (--allow-plaintext), for development only{}",
+                colors::RED,
+                colors::RESET
+            );
+            app_state.insecure = true;
+        }
+        app_state.encryption = message_encryption.is_some();
+        if let Some(public_key) = key_manager.as_ref().and_then(|km| km.get_public_key_base64().ok()) {
+            let hostname = get_hostname();
+            let lan = args.listen.map(|addr| format!("ws://{}", lan::reachable_address(addr)));
+            app_state.pairing_code = Some(pairing::pairing_uri(&hostname, &hostname, &public_key, lan.as_deref()));
+            app_state.fingerprint = pairing::fingerprint(&public_key).ok();
+        }
+        let state = Arc::new(Mutex::new(app_state));
 
         Self {
             server_url,
-            tool,
+            servers,
+            listen: args.listen,
+            advertise: !args.no_advertise,
+            anonymous: args.anonymous,
+            injector,
             state,
             key_manager,
             message_encryption,
             jwt: None,
+            normalize: args.normalize,
+            screen_reader: args.screen_reader,
+            target_class: args.target_class.clone(),
+            output: args.output.clone(),
+            strict: args.strict,
+            allow_plaintext: args.allow_plaintext,
+            max_age: (args.max_age > 0).then(|| Duration::from_secs(args.max_age)),
+            stale: args.stale,
+            rate_limit: Arc::new(Mutex::new(ratelimit::RateLimit::new(args.max_per_second, args.max_per_minute))),
+            over_limit: args.over_limit,
+            ping_interval: (args.ping_interval > 0).then(|| Duration::from_secs(args.ping_interval)),
+            ping_timeout: Duration::from_secs(args.ping_timeout.max(1)),
+            tls,
+            proxy,
+            headers,
+            config,
+            cancel: CancelToken::default(),
+            tui: false,
+            last_typed: Arc::new(Mutex::new(None)),
+            sync_passphrase: args.sync_passphrase.clone(),
+            sync: None,
+            pairings: Arc::new(Mutex::new(pairings)),
+            pending_pair: Arc::new(Mutex::new(None)),
+            refused: Arc::new(Mutex::new(HashSet::new())),
+            commands_tx,
+            commands: Arc::new(Mutex::new(commands)),
+            held: Arc::new(Mutex::new(Vec::new())),
+            reorder: Arc::new(Mutex::new(reorder::Reorderer::new(REORDER_WINDOW))),
+            deliveries: Arc::new(Mutex::new(VecDeque::new())),
+            transcripts: Arc::new(Mutex::new(transcript::Transcripts::default())),
+            live: args.live,
+            edit: args.edit,
+            editing: Arc::new(Mutex::new(VecDeque::new())),
+            chunks: Arc::new(Mutex::new(chunks::Chunks::new(args.max_length))),
+            replay: Arc::new(Mutex::new(replay)),
+            session_keys: Arc::new(Mutex::new(session_keys::SessionKeys::default())),
+            profile: args.profile.clone(),
         }
     }
 
-    fn check_tool_available(tool: &str) -> bool {
-        Command::new(tool)
-            .arg("--version")
-            .output()
-            .map(|output| output.status.success())
-            .unwrap_or(false)
-    }
-
-    fn check_dependencies(&self) -> bool {
-        Self::check_tool_available(&self.tool)
+    /// Why the typing tool can't be used, if it can't
+    fn injection_problem(&self) -> Option<Warning> {
+        // Nothing is typed when dictations go elsewhere
+        if self.output != Output::Type {
+            return None;
+        }
+        let tool = self.injector.tool();
+        if tool == "atspi" && !self.injector.is_available() {
+            return Some(Warning {
+                problem: "Accessibility bus not reachable".to_string(),
+                hint: "Install at-spi2-core and enable accessibility for your desktop session".to_string(),
+            });
+        }
+        if tool == "portal" && !self.injector.is_available() {
+            return Some(Warning {
+                problem: "RemoteDesktop portal not available".to_string(),
+                hint: "Install xdg-desktop-portal with a backend that offers remote desktop (GNOME or KDE)".to_string(),
+            });
+        }
+        if !self.injector.is_available() {
+            return Some(Warning {
+                problem: format!("{} not found", tool),
+                hint: format!("Install it with: sudo apt install {}", tool),
+            });
+        }
+        if let Some((problem, hint)) = self.injector.backend_problem() {
+            return Some(Warning { problem, hint });
+        }
+        if tool == "xdotool" && self.injector.effective_display().is_none() {
+            return Some(Warning {
+                problem: "No X11 display available for xdotool".to_string(),
+                hint: "Pass --display :0 (with /tmp/.X11-unix mounted), or use --tool ydotool with /dev/uinput for headless setups".to_string(),
+            });
+        }
+        None
     }
 
-    fn simulate_typing(&self, text: &str) -> Result<(), String> {
-        let result = if self.tool == "ydotool" {
-            Command::new("ydotool")
-                .arg("type")
-                .arg(text)
-                .status()
-        } else {
-            Command::new("xdotool")
-                .arg("type")
-                .arg("--")
-                .arg(text)
-                .status()
+    /// Re-check the typing tool so installing it takes effect without a restart
+    async fn can_inject(&self) -> bool {
+        let problem = self.injection_problem();
+        let available = problem.is_none();
+        let (recovered, failed) = {
+            let mut state = self.state.lock().await;
+            let recovered = available && state.warning.is_some();
+            let failed = problem.as_ref().filter(|_| state.warning.is_none()).map(|w| w.problem.clone());
+            state.warning = problem;
+            (recovered, failed)
         };
-
-        result.map_err(|e| format!("Typing error: {}", e))?;
-        Ok(())
+        if recovered {
+            self.notify(&format!("✓ {} is available, typing again", self.injector.tool())).await;
+        }
+        // The TUI shows the warning itself; plain output needs a line
+        if let Some(problem) = failed.filter(|_| !self.tui) {
+            self.announce(&format!("⚠ {}: clipboard-only mode", problem));
+        }
+        available
     }
 
-    async fn handle_message(&self, msg: WsMessage) -> Option<WsMessage> {
-        match msg {
-            WsMessage::Connected { client_id } => {
-                let mut state = self.state.lock().await;
-                state.client_id = Some(client_id.clone());
-                drop(state);
-
-                let hostname = get_hostname();
-
-                // Get public key if crypto is enabled
-                let public_key = if let Some(ref km) = self.key_manager {
-                    km.get_public_key_base64().ok()
-                } else {
-                    None
-                };
-
-                Some(WsMessage::Register {
-                    client_type: "target".to_string(),
-                    device_id: hostname.clone(),
-                    device_name: hostname,
-                    public_key,
-                    version: Some(format!("utterd v{}", VERSION)),
-                    platform: Some(get_platform_info()),
-                    arch: Some(std::env::consts::ARCH.to_string()),
-                    jwt: self.jwt.clone(),
-                })
+    /// Clipboard-only fallback while the typing tool is missing
+    async fn deliver_without_typing(&self, text: &str) -> Delivery {
+        let copy_text = text.to_string();
+        let copied = tokio::task::spawn_blocking(move || clipboard::copy(&copy_text))
+            .await
+            .unwrap_or_else(|e| Err(e.to_string()));
+        match copied {
+            Ok(()) => {
+                self.notify("Copied to clipboard (typing unavailable)").await;
+                Delivery::Clipboard
             }
-            WsMessage::Registered => {
-                // Print connection status with placeholder for last message
-                print!("{}●{} Connected\n\nLast: -\n↓\n", colors::GREEN, colors::RESET);
-                use std::io::Write;
-                std::io::stdout().flush().unwrap();
-                None
+            Err(_) => {
+                self.notify("Saved to history only; use `utterd history pick` once typing works").await;
+                Delivery::Saved
             }
-            WsMessage::Text { content, from, timestamp, encrypted, nonce, ephemeral_public_key, sender_public_key } => {
-                // ENFORCE ENCRYPTION: Reject plaintext messages
-                if !encrypted.unwrap_or(false) {
-                    println!("\r\x1b[K{}✗ Rejected plaintext message{}", colors::RED, colors::RESET);
-                    return None;
-                }
+        }
+    }
 
-                // Decrypt encrypted message
-                let plaintext = if let (Some(ref enc), Some(nonce_str), Some(eph_key)) =
-                    (&self.message_encryption, nonce, ephemeral_public_key) {
+    /// Join the parts of a long dictation, checking its length: None while
+    /// parts are missing, Err with the Ack refusing one that's too long
+    async fn reassemble(&self, mut ws_msg: WsMessage) -> Result<Option<WsMessage>, Option<WsMessage>> {
+        let WsMessage::Text { content, from, id, part, parts, .. } = &mut ws_msg else {
+            return Ok(Some(ws_msg));
+        };
+        let checked = match (part.take(), parts.take()) {
+            (None, None) => self.chunks.lock().await.check(content),
+            (Some(part), Some(parts)) => {
+                let key = format!("{}/{}", from.as_deref().unwrap_or_default(), id.as_deref().unwrap_or_default());
+                let piece = std::mem::take(content);
+                match self.chunks.lock().await.add(&key, part as usize, parts as usize, piece, std::time::Instant::now()) {
+                    Ok(Some(whole)) => {
+                        *content = whole;
+                        Ok(())
+                    }
+                    Ok(None) => return Ok(None),
+                    Err(e) => Err(e),
+                }
+            }
+            (part, parts) => Err(format!("part {:?} of {:?}", part, parts)),
+        };
 
-                    let encrypted_msg = EncryptedMessage {
-                        ciphertext: content,
-                        nonce: nonce_str,
-                        ephemeral_public_key: eph_key,
-                    };
+        match checked {
+            Ok(()) => Ok(Some(ws_msg)),
+            Err(error) => {
+                self.report_error(&format!("Dictation refused: {}", error)).await;
+                Err(from.clone().zip(id.clone()).map(|(to, id)| WsMessage::Ack {
+                    to,
+                    id,
+                    status: Delivery::Failed,
+                    error: Some(error),
+                }))
+            }
+        }
+    }
 
-                    // Use sender's public key for authenticity verification
-                    let sender_key = sender_public_key.as_deref().unwrap_or("");
-                    if sender_key.is_empty() {
-                        eprintln!("{}⚠ Warning: No sender public key provided. Message authenticity cannot be verified.{}", colors::YELLOW, colors::RESET);
-                    }
+    /// Remember what became of a dictation and build the phone's Ack
+    async fn ack(&self, dictation: &Dictation, status: Delivery) -> Option<WsMessage> {
+        self.ack_with(dictation, status, None).await
+    }
 
-                    match enc.decrypt(&encrypted_msg, sender_key) {
-                        Ok(plaintext) => plaintext,
-                        Err(e) => {
-                            println!("\r\x1b[K{}✗ Decryption failed: {}{}", colors::RED, e, colors::RESET);
-                            return None;
-                        }
-                    }
-                } else {
-                    println!("\r\x1b[K{}✗ Crypto not initialized{}", colors::RED, colors::RESET);
-                    return None;
-                };
+    /// An Ack for a dictation that couldn't be typed or written, and why
+    async fn ack_failed(&self, dictation: &Dictation, error: String) -> Option<WsMessage> {
+        self.ack_with(dictation, Delivery::Failed, Some(error)).await
+    }
 
-                // Calculate time ago
-                let time_ago = if let Some(ts) = timestamp {
-                    use std::time::{SystemTime, UNIX_EPOCH, Duration};
-                    let msg_time = UNIX_EPOCH + Duration::from_millis(ts as u64);
-                    let now = SystemTime::now();
-
-                    if let Ok(elapsed) = now.duration_since(msg_time) {
-                        let secs = elapsed.as_secs();
-                        if secs < 60 {
-                            format!("{}s ago", secs)
-                        } else if secs < 3600 {
-                            format!("{}m ago", secs / 60)
-                        } else {
-                            format!("{}h ago", secs / 3600)
-                        }
-                    } else {
-                        "just now".to_string()
-                    }
-                } else {
-                    "just now".to_string()
-                };
+    async fn ack_with(&self, dictation: &Dictation, status: Delivery, error: Option<String>) -> Option<WsMessage> {
+        if !dictation.ackable {
+            return None;
+        }
 
-                // Get sender name
-                let sender = from.unwrap_or_else(|| "unknown".to_string());
+        let mut deliveries = self.deliveries.lock().await;
+        deliveries.retain(|(id, _)| *id != dictation.id);
+        if deliveries.len() >= RECENT_DELIVERIES {
+            deliveries.pop_front();
+        }
+        deliveries.push_back((dictation.id.clone(), status));
 
-                // Format display text
-                let display_text = if plaintext.len() > 60 {
-                    format!("{}...", &plaintext[..60])
-                } else {
-                    plaintext.clone()
-                };
+        Some(WsMessage::Ack {
+            to: dictation.sender.clone(),
+            id: dictation.id.clone(),
+            status,
+            error,
+        })
+    }
 
-                // Update state with message info
-                let mut state = self.state.lock().await;
-                state.last_message_timestamp = timestamp;
-                state.last_message_sender = Some(sender.clone());
-                state.last_message_text = Some(display_text.clone());
-                drop(state);
+    /// Print a plain status line, and speak it in screen-reader mode
+    ///
+    /// Only used when the TUI is not running (screen reader, or no terminal).
+    /// With `--output stdout` status goes to stderr, leaving stdout to dictations.
+    fn announce(&self, text: &str) {
+        if self.output == Output::Stdout {
+            eprintln!("{}", text);
+        } else {
+            println!("{}", text);
+        }
+        if self.screen_reader {
+            speech::speak(text);
+        }
+    }
 
-                // Print message status (two lines)
-                // Move up two lines and clear both before printing
-                use std::io::Write;
-                print!("\x1b[2A\r\x1b[K{}Last:{} {} {}from {}{}\n\x1b[K↓ {}\n",
-                    colors::DIM, colors::RESET,
-                    time_ago,
-                    colors::DIM, colors::RESET, sender,
-                    display_text);
-                std::io::stdout().flush().unwrap();
+    /// Update the connection line (TUI) or print it (plain output)
+    async fn set_connection(&self, connected: bool, text: String) {
+        let mut state = self.state.lock().await;
+        state.connected = connected;
+        state.connection = text.clone();
+        state.log.push(LogLevel::Info, text.as_str());
+        if connected {
+            state.notice = None;
+        } else {
+            // Refreshed by the relay once back
+            state.phones.clear();
+            state.round_trip.clear();
+        }
+        drop(state);
 
-                // Simulate typing
-                if let Err(e) = self.simulate_typing(&plaintext) {
-                    println!("\n{}✗ Typing error: {}{}", colors::RED, e, colors::RESET);
-                }
-                None
-            }
-            WsMessage::Pong => None,
-            _ => None,
+        if !self.tui {
+            self.announce(&text);
         }
     }
 
-    async fn update_message_display(&self) {
-        let state = self.state.lock().await;
+    async fn set_notice(&self, text: &str, is_error: bool) {
+        self.state.lock().await.set_notice(text, is_error);
+    }
 
-        if let (Some(timestamp), Some(sender), Some(text)) = (
-            state.last_message_timestamp,
-            state.last_message_sender.clone(),
-            state.last_message_text.clone(),
-        ) {
-            drop(state);
+    /// Show a corrected text as the last message
+    async fn show_last_text(&self, text: &str) {
+        let mut state = self.state.lock().await;
+        state.last_message_text = Some(preview(text));
+        state.last_message_full = Some(text.to_string());
+    }
 
-            // Calculate time ago
-            use std::time::{SystemTime, UNIX_EPOCH, Duration};
-            let msg_time = UNIX_EPOCH + Duration::from_millis(timestamp as u64);
-            let now = SystemTime::now();
+    /// Record a connection or crypto detail for the TUI's log pane (shown at
+    /// debug verbosity); plain output leaves these out
+    async fn debug(&self, text: &str) {
+        self.state.lock().await.log.push(LogLevel::Debug, text);
+    }
 
-            let time_ago = if let Ok(elapsed) = now.duration_since(msg_time) {
-                let secs = elapsed.as_secs();
-                if secs < 60 {
-                    format!("{}s ago", secs)
-                } else if secs < 3600 {
-                    format!("{}m ago", secs / 60)
-                } else {
-                    format!("{}h ago", secs / 3600)
+    async fn notify(&self, message: &str) {
+        self.set_notice(message, false).await;
+        if !self.tui {
+            self.announce(message);
+        }
+    }
+
+    async fn report_error(&self, message: &str) {
+        self.set_notice(message, true).await;
+        if !self.tui {
+            self.announce(&format!("Error: {}", message));
+        }
+    }
+
+    /// Type a dictation into the focused window, running the hooks around it
+    ///
+    /// Fails with the typing tool's error.
+    async fn type_dictation(&self, dictation: &Dictation) -> Result<Delivery, String> {
+        // Typing into whatever has focus instead would defeat --target-class
+        if let Some(ref class) = self.target_class {
+            let injector = self.injector.clone();
+            let target = class.clone();
+            if !tokio::task::spawn_blocking(move || injector.has_class(&target)).await.unwrap_or(false) {
+                *self.last_typed.lock().await = None;
+                self.report_error(&format!("Skipped: no {} window is open (kept in history)", class)).await;
+                return Ok(Delivery::Skipped);
+            }
+        }
+
+        let profile = self.profile().await.unwrap_or_default();
+        let injector = self.profile_injector(&profile).await;
+
+        let event = hooks::TypeEvent {
+            sender: &dictation.sender,
+            text: &dictation.text,
+            language: dictation.language.as_deref(),
+            timestamp: dictation.timestamp,
+            tool: injector.tool(),
+        };
+
+        if let Some(ref command) = self.config.pre_type_command {
+            if let Err(e) = hooks::run(command, &event, None, injector.is_hardened()).await {
+                self.report_error(&format!("pre_type_command: {}", e)).await;
+            }
+        }
+
+        let started = std::time::Instant::now();
+        let typed = self.simulate_typing(&injector, &profile, 0, &dictation.text).await;
+        if typed.is_ok() {
+            let mut state = self.state.lock().await;
+            state.type_time.record(started.elapsed());
+            state.stats.typed(&dictation.text);
+            state.transcript.push(&dictation.id, &dictation.text);
+        }
+        // After Enter the text may be gone (a terminal ran it), so it can't be corrected
+        *self.last_typed.lock().await = typed.as_ref().ok().filter(|_| !profile.trailing_newline).map(|_| correction::Typed {
+            id: dictation.id.clone(),
+            text: dictation.text.clone(),
+        });
+        let (result, status) = match typed {
+            Ok(()) => ("ok", Ok(Delivery::Typed)),
+            Err(_) if self.cancel.is_cancelled() => {
+                self.notify(typing::CANCELLED).await;
+                ("cancelled", Ok(Delivery::Cancelled))
+            }
+            Err(ref e) => {
+                self.report_error(&format!("Typing error: {}", e)).await;
+                ("error", Err(e.to_string()))
+            }
+        };
+
+        if let Some(ref command) = self.config.post_type_command {
+            if let Err(e) = hooks::run(command, &event, Some(result), injector.is_hardened()).await {
+                self.report_error(&format!("post_type_command: {}", e)).await;
+            }
+        }
+        status
+    }
+
+    /// Show the interim text of the utterance that changed last
+    async fn show_interim(&self) {
+        let interim = self.transcripts.lock().await.latest().map(|(_, text)| preview(text));
+        self.state.lock().await.interim = interim;
+    }
+
+    /// Type an interim result live (`--live`), fixing up what the previous one left
+    async fn type_partial(&self, id: &str, progress: Progress, text: &str) {
+        let shown = match progress {
+            Progress::Interrupted => return,
+            // Starting on screen takes what a dictation would; until then
+            // the final goes the usual way (held, rate limited, ...)
+            Progress::Buffered => {
+                if self.output != Output::Type
+                    || !self.can_inject().await
+                    || self.do_not_type_reason().await.is_some()
+                    || self.no_text_field_reason().await.is_some()
+                    || self.rate_limit.lock().await.check(std::time::Instant::now()).is_err()
+                {
+                    return;
+                }
+                String::new()
+            }
+            // Backspacing is only safe while the cursor is still right after our text
+            Progress::Typed(shown) => {
+                let ours = self.last_typed.lock().await.as_ref().is_some_and(|typed| typed.id == id);
+                if !ours || self.do_not_type_reason().await.is_some() {
+                    self.transcripts.lock().await.set_progress(id, Progress::Interrupted);
+                    return;
                 }
+                shown
+            }
+        };
+
+        let edit = correction::edit(&shown, text);
+        let typed = self.simulate_typing(&self.injector, &Profile::default(), edit.backspaces, edit.insert).await;
+        let progress = match typed {
+            Ok(()) => {
+                *self.last_typed.lock().await = Some(correction::Typed { id: id.to_string(), text: text.to_string() });
+                Progress::Typed(text.to_string())
+            }
+            Err(e) => {
+                *self.last_typed.lock().await = None;
+                if self.cancel.is_cancelled() {
+                    self.notify(typing::CANCELLED).await;
+                } else {
+                    self.report_error(&format!("Typing error: {}", e)).await;
+                }
+                Progress::Interrupted
+            }
+        };
+        self.transcripts.lock().await.set_progress(id, progress);
+    }
+
+    /// Turn the live-typed interim text of `dictation` into its final text
+    async fn finish_live(&self, dictation: &Dictation, shown: &str) -> Option<WsMessage> {
+        let ours = self.last_typed.lock().await.as_ref().is_some_and(|typed| typed.id == dictation.id);
+        if !ours || self.do_not_type_reason().await.is_some() {
+            return self.live_interrupted(dictation).await;
+        }
+
+        let edit = correction::edit(shown, &dictation.text);
+        let typed = self.simulate_typing(&self.injector, &Profile::default(), edit.backspaces, edit.insert).await;
+        *self.last_typed.lock().await = typed.as_ref().ok().map(|_| correction::Typed {
+            id: dictation.id.clone(),
+            text: dictation.text.clone(),
+        });
+        match typed {
+            Ok(()) => self.ack(dictation, Delivery::Typed).await,
+            Err(_) if self.cancel.is_cancelled() => {
+                self.notify(typing::CANCELLED).await;
+                self.ack(dictation, Delivery::Cancelled).await
+            }
+            Err(e) => {
+                self.report_error(&format!("Typing error: {}", e)).await;
+                self.ack_failed(dictation, e).await
+            }
+        }
+    }
+
+    /// The final of a dictation whose live typing was cut short: the
+    /// partial text on screen can't be fixed up, so it's left alone
+    async fn live_interrupted(&self, dictation: &Dictation) -> Option<WsMessage> {
+        self.report_error("Live typing was interrupted; the final text is in history").await;
+        self.ack_failed(dictation, "live typing was interrupted".to_string()).await
+    }
+
+    /// Why the focused window is a do-not-type zone, if it is
+    ///
+    /// With `--target-class` text goes to the target window, so what has
+    /// focus doesn't matter. Where the focused window can't be determined
+    /// (e.g. Wayland) nothing is held.
+    async fn do_not_type_reason(&self) -> Option<String> {
+        let zones = self.config.do_not_type_classes.is_some() || !self.config.do_not_type_workspaces.is_empty();
+        if !zones || self.target_class.is_some() {
+            return None;
+        }
+        let injector = self.injector.clone();
+        let window = tokio::task::spawn_blocking(move || injector.focused_window()).await.ok()??;
+        self.config.do_not_type(&window)
+    }
+
+    /// Why a dictation typed now would go nowhere, if the tool can tell no
+    /// text field has focus (AT-SPI inserts into the focused one)
+    ///
+    /// With `--target-class` the target window gets focus first, so what
+    /// has it now doesn't matter.
+    async fn no_text_field_reason(&self) -> Option<String> {
+        if self.target_class.is_some() {
+            return None;
+        }
+        let injector = match self.profile().await.and_then(|profile| profile.tool) {
+            Some(tool) => self.injector.with_tool(&tool),
+            None => self.injector.clone(),
+        };
+        let focused = tokio::task::spawn_blocking(move || injector.has_text_field()).await.ok()??;
+        (!focused).then(|| "no text field is focused".to_string())
+    }
+
+    /// Show in the TUI where the next dictation would go and with which tool
+    async fn show_focus(&self) {
+        let window = match self.target_class {
+            Some(ref class) => Some(typing::FocusedWindow { class: class.clone(), title: None, workspace: None }),
+            None => {
+                let injector = self.injector.clone();
+                tokio::task::spawn_blocking(move || injector.focused_window()).await.ok().flatten()
+            }
+        };
+        // Zones don't apply to --target-class
+        let held = window.as_ref().filter(|_| self.target_class.is_none()).and_then(|window| self.config.do_not_type(window));
+        let tool = window
+            .as_ref()
+            .and_then(|window| self.config.profile(&window.class))
+            .and_then(|profile| profile.tool.clone())
+            .unwrap_or_else(|| self.injector.tool().to_string());
+        let (class, title) = match window {
+            Some(window) => (Some(window.class), window.title),
+            None => (None, None),
+        };
+        self.state.lock().await.focus = Some(Focus { class, title, tool, held });
+    }
+
+    /// How long ago a message was sent, if that's past `--max-age`
+    ///
+    /// Messages without a timestamp, or from a phone whose clock runs ahead,
+    /// count as fresh.
+    fn stale_age(&self, timestamp: Option<i64>) -> Option<String> {
+        let max_age = self.max_age?;
+        let age_ms = chrono::Utc::now().timestamp_millis() - timestamp?;
+        let age = Duration::from_millis(u64::try_from(age_ms).ok()?);
+        (age > max_age).then(|| describe_age(age))
+    }
+
+    /// Keep a dictation until the user releases it
+    async fn hold(&self, dictation: Dictation, reason: &str) {
+        // Whatever we typed last is no longer where the cursor is
+        *self.last_typed.lock().await = None;
+        let mut held = self.held.lock().await;
+        held.push(dictation);
+        self.state.lock().await.held = held.len();
+        drop(held);
+
+        let how = if self.tui { "Press f" } else { "Type f and Enter" };
+        self.notify(&format!("Held dictation: {}. {}, then focus the right window to release", reason, how)).await;
+    }
+
+    /// Release the held dictations once the user has focused the window to
+    /// type into, with a `ReleaseNow`; the session keeps running meanwhile
+    fn request_release(&self) {
+        let client = self.clone();
+        tokio::spawn(async move {
+            if client.held.lock().await.is_empty() {
+                return;
+            }
+            if client.await_focus_change().await {
+                let _ = client.commands_tx.send(Command::ReleaseNow);
             } else {
-                "just now".to_string()
+                client.report_error("Not released: focus stayed on utterd's terminal").await;
+            }
+        });
+    }
+
+    /// Type the held dictations into the window that's focused now,
+    /// returning Acks that update the phones' "held" status
+    async fn release_held(&self) -> Vec<WsMessage> {
+        if let Some(reason) = self.do_not_type_reason().await {
+            self.report_error(&format!("Not released: {}", reason)).await;
+            return Vec::new();
+        }
+        if let Some(reason) = self.no_text_field_reason().await {
+            self.report_error(&format!("Not released: {}", reason)).await;
+            return Vec::new();
+        }
+        if !self.can_inject().await {
+            self.report_error("Not released: typing unavailable").await;
+            return Vec::new();
+        }
+
+        let held = std::mem::take(&mut *self.held.lock().await);
+        self.state.lock().await.held = 0;
+        let mut acks = Vec::new();
+        for dictation in held {
+            let ack = match self.type_dictation(&dictation).await {
+                Ok(status) => self.ack(&dictation, status).await,
+                Err(e) => self.ack_failed(&dictation, e).await,
             };
+            acks.extend(ack);
+        }
+        acks
+    }
+
+    /// Wait for focus to move from utterd's terminal, where release was
+    /// asked for, to the window to type into; false if it never did
+    ///
+    /// With `--target-class` focus doesn't matter. Where the focused window
+    /// can't be determined (e.g. Wayland) it's a countdown instead.
+    async fn await_focus_change(&self) -> bool {
+        if self.target_class.is_some() {
+            return true;
+        }
+        let focused = || {
+            let injector = self.injector.clone();
+            async move { tokio::task::spawn_blocking(move || injector.focused_window()).await.ok().flatten() }
+        };
+        let Some(terminal) = focused().await else {
+            self.notify(&format!("Releasing in {} seconds: focus the window to type into", PICK_TYPE_DELAY.as_secs())).await;
+            tokio::time::sleep(PICK_TYPE_DELAY).await;
+            return true;
+        };
+
+        self.notify(&format!("Focus the window to type into within {} seconds to release", RELEASE_WAIT.as_secs())).await;
+        let deadline = tokio::time::Instant::now() + RELEASE_WAIT;
+        while tokio::time::Instant::now() < deadline {
+            tokio::time::sleep(RELEASE_POLL).await;
+            if focused().await.is_some_and(|window| window != terminal) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Put the first dictation waiting for an edit in the TUI's editor;
+    /// `reload` replaces what's in the editor even if it's the same one
+    async fn show_editing(&self, reload: bool) {
+        let editing = self.editing.lock().await;
+        let mut state = self.state.lock().await;
+        let revision = state.editing.as_ref().map_or(0, |shown| shown.revision + u64::from(reload));
+        state.editing = editing.front().map(|dictation| state::Editing {
+            id: dictation.id.clone(),
+            sender: dictation.sender.clone(),
+            text: dictation.text.clone(),
+            queued: editing.len() - 1,
+            revision,
+        });
+    }
+
+    /// Take a dictation from the editor with the user's changes, returning
+    /// the Ack for the phone if it was typed
+    ///
+    /// The terminal has focus while editing, so keystrokes typed now would
+    /// land in the status view as commands: only a `--target-class` window
+    /// gets the text right away, otherwise it's held until released.
+    async fn type_edited(&self, id: &str, text: String) -> Option<WsMessage> {
+        if !self.editing.lock().await.iter().any(|d| d.id == id) {
+            return None;
+        }
+        // Kept in the editor, edits included, until it can go somewhere
+        let refusal = match self.target_class {
+            Some(_) if !self.can_inject().await => Some("Not typed: typing unavailable"),
+            _ => None,
+        };
+        if let Some(refusal) = refusal {
+            if let Some(dictation) = self.editing.lock().await.iter_mut().find(|d| d.id == id) {
+                dictation.text = text;
+            }
+            self.show_editing(true).await;
+            self.report_error(refusal).await;
+            return None;
+        }
 
-            // Print message status (two lines)
-            use std::io::Write;
-            print!("\x1b[2A\r\x1b[K{}Last:{} {} {}from {}{}\n\x1b[K↓ {}\n",
-                colors::DIM, colors::RESET,
-                time_ago,
-                colors::DIM, colors::RESET, sender,
-                text);
-            std::io::stdout().flush().unwrap();
+        let mut dictation = {
+            let mut editing = self.editing.lock().await;
+            let position = editing.iter().position(|d| d.id == id)?;
+            editing.remove(position)?
+        };
+        self.show_editing(false).await;
+        if text != dictation.text {
+            self.record_history(&dictation.id, &dictation.sender, &text).await;
+            dictation.text = text;
+            self.show_last_text(&dictation.text).await;
+        }
+        if self.target_class.is_none() {
+            // The phone was told it's held when it went into the editor
+            self.hold(dictation, "edited").await;
+            return None;
+        }
+        match self.type_dictation(&dictation).await {
+            Ok(status) => self.ack(&dictation, status).await,
+            Err(e) => self.ack_failed(&dictation, e).await,
+        }
+    }
+
+    /// Drop a dictation from the editor, returning the Ack for the phone
+    async fn discard_edited(&self, id: &str) -> Option<WsMessage> {
+        let dictation = {
+            let mut editing = self.editing.lock().await;
+            let position = editing.iter().position(|d| d.id == id)?;
+            editing.remove(position)?
+        };
+        self.show_editing(false).await;
+        self.notify("Discarded the dictation (kept in history)").await;
+        self.ack(&dictation, Delivery::Cancelled).await
+    }
+
+    /// The `[[profiles]]` entry for the window the next dictation goes into
+    ///
+    /// That's the `--target-class` window if set, otherwise the focused one.
+    async fn profile(&self) -> Option<Profile> {
+        if self.config.profiles.is_empty() {
+            return None;
+        }
+        let class = match self.target_class {
+            Some(ref class) => class.clone(),
+            None => {
+                let injector = self.injector.clone();
+                tokio::task::spawn_blocking(move || injector.focused_window()).await.ok()??.class
+            }
+        };
+        self.config.profile(&class).cloned()
+    }
+
+    /// The injector with a profile's tool and keystroke delay applied
+    async fn profile_injector(&self, profile: &Profile) -> Injector {
+        let injector = match profile.tool {
+            Some(ref tool) => self.injector.with_tool(tool).with_extra_args(self.config.tool_args(tool)),
+            None => self.injector.clone(),
+        };
+        let Some(delay_ms) = profile.delay_ms else {
+            return injector;
+        };
+        match typing::delay_args(injector.tool(), delay_ms) {
+            Some(delay) => {
+                let args = injector.extra_args().iter().cloned().chain(delay).collect();
+                injector.with_extra_args(args)
+            }
+            None => {
+                self.report_error(&format!("Profile delay ignored: {} has no keystroke delay", injector.tool())).await;
+                injector
+            }
+        }
+    }
+
+    /// Erase `backspaces` characters, then type `text` as `profile` says
+    ///
+    /// Runs on a blocking thread so the TUI's Esc can cancel it mid-stream.
+    /// Text the backend can't type (e.g. emoji via ydotool) is pasted
+    /// through the clipboard instead.
+    async fn simulate_typing(&self, injector: &Injector, profile: &Profile, backspaces: usize, text: &str) -> Result<(), String> {
+        self.cancel.reset();
+        self.state.lock().await.typing = true;
+
+        let fallback = !injector.can_type(text);
+        let paste = profile.clipboard || fallback;
+        let enter = profile.trailing_newline;
+
+        let injector = injector.clone();
+        let tool = injector.tool().to_string();
+        let target_class = self.target_class.clone();
+        let cancel = self.cancel.clone();
+        let text = text.to_string();
+        let result = tokio::task::spawn_blocking(move || {
+            with_target_focus(&injector, target_class.as_deref(), || {
+                injector.backspace(backspaces)?;
+                if paste {
+                    injector.paste(&text)?;
+                } else {
+                    injector.type_text(&text, &cancel)?;
+                }
+                if enter {
+                    injector.press_key(Key::Enter)?;
+                }
+                Ok(())
+            })
+        })
+        .await
+        .unwrap_or_else(|e| Err(format!("Typing task failed: {}", e)));
+
+        self.state.lock().await.typing = false;
+
+        if fallback && !profile.clipboard && result.is_ok() {
+            self.notify(&format!("Pasted via clipboard: {} can't type some characters", tool)).await;
+        }
+        result
+    }
+
+    async fn press_key(&self, key: Key) -> Result<(), String> {
+        let injector = self.injector.clone();
+        let target_class = self.target_class.clone();
+        tokio::task::spawn_blocking(move || {
+            with_target_focus(&injector, target_class.as_deref(), || injector.press_key(key))
+        })
+        .await
+        .unwrap_or_else(|e| Err(format!("Key task failed: {}", e)))
+    }
+
+    /// Report a message refused for security reasons and add it to the audit
+    /// log, with the sender it claimed to be from
+    async fn reject(&self, from: Option<&str>, public_key: Option<&str>, reason: &str) {
+        self.report_error(reason).await;
+        self.state.lock().await.rejected += 1;
+        let rejection = audit::Rejection {
+            timestamp: chrono::Utc::now(),
+            from: from.map(str::to_string),
+            fingerprint: public_key.and_then(|key| pairing::fingerprint(key).ok()),
+            reason: reason.to_string(),
+        };
+        if let Err(e) = audit::AuditLog::new().and_then(|log| log.append(&rejection)) {
+            self.report_error(&format!("Audit log error: {}", e)).await;
+        }
+    }
+
+    /// Keep a copy so a dictation typed into the wrong window can be recovered
+    async fn record_history(&self, id: &str, sender: &str, text: &str) {
+        if let Err(e) = History::new().and_then(|h| h.append(&HistoryEntry {
+            id: Some(id.to_string()),
+            timestamp: chrono::Utc::now(),
+            sender: sender.to_string(),
+            text: text.to_string(),
+        })) {
+            self.report_error(&format!("History error: {}", e)).await;
+        }
+    }
+
+    /// Decrypt an E2E-encrypted payload from `from`, reporting why if it can't be
+    async fn decrypt_content(&self, from: Option<&str>, sealed: Sealed) -> Option<String> {
+        let Sealed { content, encrypted, nonce, ephemeral_public_key, sender_public_key, aad, sender_auth, cipher, recipients, seq } = sealed;
+        // ENFORCE ENCRYPTION: Reject plaintext messages
+        if !encrypted.unwrap_or(false) {
+            self.reject(from, sender_public_key.as_deref(), "Rejected plaintext message").await;
+            return None;
+        }
+
+        // Bound to both device ids, so it can't have been meant for another
+        // desktop or sent the other way
+        let to = get_hostname();
+        let bound = match aad {
+            None => false,
+            Some(1) => true,
+            Some(version) => {
+                let reason = format!("Rejected message with unsupported associated data (aad {})", version);
+                self.reject(from, sender_public_key.as_deref(), &reason).await;
+                return None;
+            }
+        };
+
+        // The message key mixes in the sender's static key, so it only
+        // decrypts if whoever encrypted it holds the key it claims; phones
+        // from before sender-auth derive it from the ephemeral key alone
+        let sender_key = match sender_auth {
+            None => None,
+            Some(1) => match sender_public_key.clone().filter(|key| !key.is_empty()) {
+                Some(key) => Some(key),
+                None => {
+                    self.reject(from, None, "Rejected authenticated message without the sender's public key").await;
+                    return None;
+                }
+            },
+            Some(version) => {
+                let reason = format!("Rejected message with unsupported sender authentication (senderAuth {})", version);
+                self.reject(from, sender_public_key.as_deref(), &reason).await;
+                return None;
+            }
+        };
+
+        let cipher = match cipher.as_deref().map(Cipher::from_name) {
+            None => Cipher::default(),
+            Some(Some(cipher)) => cipher,
+            Some(None) => {
+                let reason = format!("Rejected message encrypted with an unsupported cipher ({})", cipher.unwrap_or_default());
+                self.reject(from, sender_public_key.as_deref(), &reason).await;
+                return None;
+            }
+        };
+
+        // Decrypt encrypted message; a broadcast has no ephemeral key of its
+        // own, only its key slots
+        let broadcast = recipients.is_some();
+        if let (Some(ref enc), Some(nonce_str), Some(eph_key)) =
+            (&self.message_encryption, nonce, ephemeral_public_key.or(broadcast.then(String::new))) {
+
+            let encrypted_msg = EncryptedMessage {
+                ciphertext: content,
+                nonce: nonce_str,
+                ephemeral_public_key: eph_key,
+                cipher,
+                recipients: recipients.unwrap_or_default(),
+            };
+
+            // Phones that got a session key encrypt to it; the rest (or
+            // messages sent before it arrived) to the long-term key. The ECDH
+            // runs on the blocking pool, so a burst of messages doesn't hold
+            // up the TUI and keepalives, and no lock is held meanwhile
+            let enc = Arc::clone(enc);
+            let authenticated = sender_key.is_some();
+            let session_keys = self.session_keys.lock().await.private_keys();
+            let from_id = from.unwrap_or_default().to_string();
+            let decrypted = tokio::task::spawn_blocking(move || {
+                let binding = bound.then_some(Binding { from: &from_id, to: &to, seq });
+                enc.decrypt_any(&encrypted_msg, &session_keys, sender_key.as_deref(), binding.as_ref()).map_err(|e| e.to_string())
+            })
+            .await
+            .unwrap_or_else(|e| Err(e.to_string()));
+
+            // Use sender's public key for authenticity verification
+            if !matches!(decrypted, Ok((Some(_), _))) && sender_public_key.as_deref().unwrap_or_default().is_empty() {
+                self.notify("⚠ Warning: No sender public key provided. Message authenticity cannot be verified.").await;
+            }
+
+            match decrypted {
+                Ok((session, plaintext)) => {
+                    let key = session.map_or("long-term key".to_string(), |index| format!("session key {}", index));
+                    self.debug(&format!(
+                        "Decrypted message from {} with {} ({}{}{}{})",
+                        from.unwrap_or("?"),
+                        key,
+                        cipher.name(),
+                        if bound { ", bound" } else { "" },
+                        if authenticated { ", sender authenticated" } else { "" },
+                        if broadcast { ", broadcast" } else { "" }
+                    ))
+                    .await;
+                    if let Some(index) = session {
+                        if self.session_keys.lock().await.used(index) {
+                            let _ = self.commands_tx.send(Command::AnnounceKeys);
+                        }
+                    }
+                    Some(plaintext)
+                }
+                Err(e) => {
+                    self.reject(from, sender_public_key.as_deref(), &format!("Decryption failed: {}", e)).await;
+                    None
+                }
+            }
+        } else {
+            self.report_error("Crypto not initialized").await;
+            None
+        }
+    }
+
+    /// Decrypt a message that will be acted on, if its sender is trusted;
+    /// fails with the status to report to the phone
+    async fn decrypt_from_paired(&self, from: Option<&str>, sealed: Sealed) -> Result<String, Delivery> {
+        match self.sender_trust(from, sealed.sender_public_key.as_deref()).await {
+            Trust::Trusted => self.decrypt_once(from, sealed).await,
+            // Only dictations wait for the user's answer
+            Trust::Asked | Trust::Refused => Err(Delivery::Unpaired),
+        }
+    }
+
+    /// Whether a sender's messages may be acted on; a device that isn't
+    /// trusted yet gets a trust prompt the first time it sends something
+    async fn sender_trust(&self, from: Option<&str>, public_key: Option<&str>) -> Trust {
+        let pairings = self.pairings.lock().await;
+        if pairings.is_trusted(public_key) {
+            return Trust::Trusted;
+        }
+        let key_changed = from.is_some_and(|from| pairings.pinned_key(from).is_some());
+        drop(pairings);
+
+        let (Some(from), Some(public_key)) = (from, public_key) else {
+            self.reject(from, public_key, "Rejected message from an unpaired device").await;
+            return Trust::Refused;
+        };
+        // Like a changed SSH host key: the app was reinstalled, or something
+        // poses as the phone. Only an explicit pairing replaces a pinned key
+        if key_changed {
+            let reason = format!("Rejected message from {}: its key changed since it was trusted, pair it again", from);
+            self.reject(Some(from), Some(public_key), &reason).await;
+            return Trust::Refused;
+        }
+
+        let asked = self.pending_pair.lock().await.as_ref().map(|pending| pending.bundle.public_key == public_key);
+        if asked == Some(true) {
+            return Trust::Asked;
+        }
+        // One question at a time
+        if asked.is_some() || self.refused.lock().await.contains(public_key) {
+            self.reject(Some(from), Some(public_key), "Rejected message from an unpaired device").await;
+            return Trust::Refused;
+        }
+        let Ok(fingerprint) = pairing::fingerprint(public_key) else {
+            self.reject(Some(from), Some(public_key), "Rejected message from a device with an invalid key").await;
+            return Trust::Refused;
+        };
+
+        let device_name = self
+            .state
+            .lock()
+            .await
+            .phones
+            .iter()
+            .find(|phone| phone.public_key.as_deref() == Some(public_key))
+            .map_or_else(|| from.to_string(), |phone| phone.name.clone());
+        let bundle = pairing::PairingBundle {
+            device_id: from.to_string(),
+            device_name,
+            public_key: public_key.to_string(),
+        };
+        self.prompt_pairing(PendingPair { to: from.to_string(), bundle, requested: false, dictations: Vec::new() }, fingerprint)
+            .await;
+        Trust::Asked
+    }
+
+    /// Ask the user whether to trust a device, showing its key's fingerprint,
+    /// and for a pairing request the emoji the phone shows too
+    async fn prompt_pairing(&self, pending: PendingPair, fingerprint: String) {
+        let device_name = pending.bundle.device_name.clone();
+        let desktop_key = self.key_manager.as_ref().and_then(|km| km.get_public_key_base64().ok());
+        let sas = desktop_key
+            .filter(|_| pending.requested)
+            .and_then(|desktop_key| pairing::short_auth_string(&pending.bundle.public_key, &desktop_key).ok());
+        if !self.tui {
+            let what = match sas {
+                Some(ref sas) => format!("Pairing request from {}, code {} (fingerprint {})", device_name, sas, fingerprint),
+                None if pending.requested => format!("Pairing request from {}, fingerprint {}", device_name, fingerprint),
+                None => format!("{} wants to type here, fingerprint {}", device_name, fingerprint),
+            };
+            self.announce(&format!("{}. Check it matches the phone, then type y to accept or n to reject", what));
+        }
+        self.state.lock().await.pending_pairing = Some(PairingPrompt {
+            device_name,
+            fingerprint,
+            sas,
+            first_contact: !pending.requested,
+        });
+        *self.pending_pair.lock().await = Some(pending);
+    }
+
+    /// Decrypt a message, refusing one that was received before
+    async fn decrypt_once(&self, from: Option<&str>, sealed: Sealed) -> Result<String, Delivery> {
+        let id = sealed.nonce.clone().zip(sealed.ephemeral_key());
+        let sender_public_key = sealed.sender_public_key.clone();
+        let plaintext = self.decrypt_content(from, sealed).await.ok_or(Delivery::DecryptionFailed)?;
+
+        // Only the phone can make a new ciphertext, but the relay can resend
+        // an old one: a second Enter or dictation the user never gave
+        let (nonce, ephemeral_public_key) = id.ok_or(Delivery::DecryptionFailed)?;
+        match self.replay.lock().await.first_time(&nonce, &ephemeral_public_key) {
+            Ok(true) => Ok(plaintext),
+            Ok(false) => {
+                self.reject(from, sender_public_key.as_deref(), "Rejected a message received before (replayed by the relay?)")
+                    .await;
+                Err(Delivery::Replayed)
+            }
+            Err(e) => {
+                self.report_error(&e).await;
+                Ok(plaintext)
+            }
+        }
+    }
+
+    /// The Google sign-in for this profile, its token cache encrypted with a
+    /// key from the keypair
+    fn oauth_manager(&self) -> Result<oauth::OAuthManager, String> {
+        let cache_key = self.key_manager.as_ref().and_then(|km| km.derive_key("oauth").ok());
+        oauth::OAuthManager::selected(self.profile.as_deref(), cache_key)
+    }
+
+    /// Use a new relay JWT, showing when it expires and, after a Google
+    /// sign-in, which account it's for
+    async fn set_jwt(&mut self, jwt: String, account: Option<String>) {
+        let mut state = self.state.lock().await;
+        state.session_expires = auth::decode_jwt_payload(&jwt).ok().map(|payload| payload.exp);
+        if account.is_some() {
+            state.account = account;
+        }
+        drop(state);
+        self.jwt = Some(jwt);
+    }
+
+    /// Where the Google sign-in reports progress: the status line while the
+    /// TUI owns the terminal, else like `announce`
+    fn oauth_progress(&self) -> impl Fn(&str) + Send + Sync + 'static {
+        let (state, tui, to_stderr, speak) = (self.state.clone(), self.tui, self.output == Output::Stdout, self.screen_reader);
+        move |text: &str| {
+            if tui {
+                let (state, text) = (state.clone(), text.to_string());
+                tokio::spawn(async move { state.lock().await.set_notice(&text, false) });
+                return;
+            }
+            if to_stderr {
+                eprintln!("{}", text);
+            } else {
+                println!("{}", text);
+            }
+            if speak {
+                speech::speak(text);
+            }
+        }
+    }
+
+    /// For every paired phone: the current session key, and after a key
+    /// rotation the new long-term key vouched for by the old one (until the
+    /// grace window is over)
+    async fn key_notices(&self) -> Vec<WsMessage> {
+        let (Some(km), Some(enc)) = (&self.key_manager, &self.message_encryption) else {
+            return Vec::new();
+        };
+        let Ok(public_key) = km.get_public_key_base64() else {
+            return Vec::new();
+        };
+        let session_key = self.session_keys.lock().await.public_key();
+        let pairings = self.pairings.lock().await;
+
+        let mut notices = Vec::new();
+        for device in pairings.devices() {
+            let proofs = enc
+                .rotation_proof(&device.public_key)
+                .and_then(|rotation| Ok((rotation, enc.session_key_proof(&device.public_key, &session_key)?)))
+                .map_err(|e| e.to_string());
+            let (rotation, session) = match proofs {
+                Ok(proofs) => proofs,
+                Err(e) => {
+                    self.report_error(&format!("Cannot vouch for keys to {}: {}", device.device_name, e)).await;
+                    continue;
+                }
+            };
+            if let Some(proof) = rotation {
+                notices.push(WsMessage::KeyRotated {
+                    to: device.device_id.clone(),
+                    public_key: public_key.clone(),
+                    proof,
+                });
+            }
+            notices.push(WsMessage::SessionKey {
+                to: device.device_id.clone(),
+                public_key: general_purpose::STANDARD.encode(session_key),
+                proof: session,
+            });
+        }
+        notices
+    }
+
+    /// Apply the user's y/n to the pending pairing request or first contact,
+    /// returning the replies for the phone
+    async fn answer_pairing(&self, accepted: bool) -> Vec<WsMessage> {
+        let Some(PendingPair { to, bundle, requested, dictations }) = self.pending_pair.lock().await.take() else {
+            return Vec::new();
+        };
+        self.state.lock().await.pending_pairing = None;
+
+        let saved = if accepted {
+            self.pairings.lock().await.add(&bundle)
+        } else {
+            Err("rejected".to_string())
+        };
+        match saved {
+            Ok(()) => {
+                self.refused.lock().await.remove(&bundle.public_key);
+                let pairings = self.pairings.lock().await;
+                for phone in self.state.lock().await.phones.iter_mut() {
+                    phone.can_type = pairings.is_trusted(phone.public_key.as_deref());
+                }
+                drop(pairings);
+                self.notify(&format!("✓ Paired with {}", bundle.device_name)).await;
+            }
+            Err(_) if !accepted => {
+                if !requested {
+                    self.refused.lock().await.insert(bundle.public_key.clone());
+                }
+                self.notify(&format!("Pairing with {} rejected", bundle.device_name)).await;
+            }
+            Err(ref e) => self.report_error(&format!("Pairing failed: {}", e)).await,
+        }
+
+        let mut replies = Vec::new();
+        for dictation in dictations {
+            if saved.is_ok() {
+                // The terminal has focus after answering; the user picks where they go
+                self.record_history(&dictation.id, &dictation.sender, &dictation.text).await;
+                self.hold(dictation, &format!("from {}, just trusted", bundle.device_name)).await;
+            } else {
+                replies.extend(self.ack(&dictation, Delivery::Unpaired).await);
+            }
+        }
+        if requested {
+            replies.push(WsMessage::PairResponse { to, accepted: saved.is_ok() });
+        }
+        replies
+    }
+
+    async fn handle_message(&self, msg: WsMessage) -> Option<WsMessage> {
+        match msg {
+            WsMessage::Connected { client_id } => {
+                let mut state = self.state.lock().await;
+                state.client_id = Some(client_id.clone());
+                drop(state);
+
+                let hostname = get_hostname();
+
+                // Get public key if crypto is enabled
+                let public_key = if let Some(ref km) = self.key_manager {
+                    km.get_public_key_base64().ok()
+                } else {
+                    None
+                };
+
+                Some(WsMessage::Register {
+                    client_type: "target".to_string(),
+                    device_id: hostname.clone(),
+                    device_name: hostname,
+                    public_key,
+                    version: Some(format!("utterd v{}", VERSION)),
+                    platform: Some(get_platform_info()),
+                    arch: Some(std::env::consts::ARCH.to_string()),
+                    jwt: self.jwt.clone(),
+                    ciphers: Some(Cipher::SUPPORTED.iter().map(|cipher| cipher.name().to_string()).collect()),
+                })
+            }
+            WsMessage::Registered => {
+                let status = match self.listen {
+                    Some(addr) => format!("Listening on {}", addr),
+                    None => "Connected".to_string(),
+                };
+                self.set_connection(true, status).await;
+                self.state.lock().await.stats.registered();
+                // A new key for every session
+                self.session_keys.lock().await.rotate();
+                self.debug("Registered with the relay; new session key").await;
+                let _ = self.commands_tx.send(Command::AnnounceKeys);
+                self.sync.as_ref().map(|_| WsMessage::GetConfig)
+            }
+            WsMessage::Text { content, from, timestamp, encrypted, nonce, ephemeral_public_key, sender_public_key, aad, sender_auth, cipher, recipients, language, id, seq, .. } => {
+                // The phone resends when an Ack went missing; report the
+                // outcome again rather than typing the text twice
+                if let (Some(to), Some(id)) = (&from, &id) {
+                    let previous = self.deliveries.lock().await.iter().find(|(known, _)| known == id).map(|&(_, status)| status);
+                    if let Some(status) = previous.filter(|status| status.is_final()) {
+                        return Some(WsMessage::Ack { to: to.clone(), id: id.clone(), status, error: None });
+                    }
+                }
+                let ackable = from.is_some() && id.is_some();
+
+                // A test client without keys can't pair either
+                let unencrypted = self.allow_plaintext && !encrypted.unwrap_or(false);
+                let trust = if unencrypted {
+                    Trust::Trusted
+                } else {
+                    self.sender_trust(from.as_deref(), sender_public_key.as_deref()).await
+                };
+                let sender_key = if unencrypted { None } else { sender_public_key.clone() };
+                let plaintext = match trust {
+                    Trust::Refused => Err(Delivery::Unpaired),
+                    Trust::Trusted if unencrypted => Ok(content),
+                    Trust::Trusted | Trust::Asked => {
+                        let sealed = Sealed { content, encrypted, nonce, ephemeral_public_key, sender_public_key, aad, sender_auth, cipher, recipients, seq };
+                        self.decrypt_once(from.as_deref(), sealed).await
+                    }
+                };
+                let plaintext = match plaintext {
+                    Ok(plaintext) => plaintext,
+                    Err(status) => return from.zip(id).map(|(to, id)| WsMessage::Ack { to, id, status, error: None }),
+                };
+
+                let plaintext = if self.normalize {
+                    normalize::normalize(&plaintext, language.as_deref())
+                } else {
+                    plaintext
+                };
+
+                // Get sender name
+                let sender = from.unwrap_or_else(|| "unknown".to_string());
+
+                // Relays that predate message ids don't send one; corrections
+                // then can't reach this message, but history still gets an id
+                let id = id.unwrap_or_else(|| format!("{:016x}", rand::random::<u64>()));
+
+                // First contact: wait for the user to trust the phone
+                if trust == Trust::Asked {
+                    let dictation = Dictation { id, sender, ackable, text: plaintext, language, timestamp };
+                    let ack = self.ack(&dictation, Delivery::Held).await;
+                    if let Some(pending) = self.pending_pair.lock().await.as_mut() {
+                        pending.dictations.push(dictation);
+                    }
+                    return ack;
+                }
+
+                self.record_history(&id, &sender, &plaintext).await;
+
+                // Update state with message info (messages without a timestamp count from now)
+                let mut state = self.state.lock().await;
+                let now = chrono::Utc::now().timestamp_millis();
+                // Messages queued while offline say nothing about how slow delivery is
+                if let Some(delay) = timestamp.map(|sent| now - sent).filter(|delay| (0..=DELIVERY_SAMPLE_LIMIT).contains(delay)) {
+                    state.delivery.record(Duration::from_millis(delay as u64));
+                }
+                state.last_message_timestamp = Some(timestamp.unwrap_or(now));
+                state.last_message_sender = Some(sender.clone());
+                state.last_sender_fingerprint = sender_key.and_then(|key| pairing::fingerprint(&key).ok());
+                state.last_message_text = Some(preview(&plaintext));
+                state.last_message_full = Some(plaintext.clone());
+                state.received += 1;
+                drop(state);
+
+                if !self.tui {
+                    let shown = if self.config.redact { redacted(&plaintext) } else { plaintext.clone() };
+                    self.announce(&format!("Message from {}: {}", sender, shown));
+                }
+
+                let dictation = Dictation {
+                    id,
+                    sender,
+                    ackable,
+                    text: plaintext,
+                    language,
+                    timestamp,
+                };
+
+                // The final of a streamed utterance; with --live most of it is on screen already
+                let streamed = self.transcripts.lock().await.finish(&dictation.id);
+                self.show_interim().await;
+                match streamed {
+                    Some(Progress::Typed(shown)) => return self.finish_live(&dictation, &shown).await,
+                    Some(Progress::Interrupted) => return self.live_interrupted(&dictation).await,
+                    Some(Progress::Buffered) | None => {}
+                }
+
+                if self.output != Output::Type {
+                    return match self.output.write(&dictation.text, timestamp).await {
+                        Ok(()) => self.ack(&dictation, Delivery::Written).await,
+                        Err(e) => {
+                            self.report_error(&format!("Output error: {} (dictation kept in history)", e)).await;
+                            self.ack_failed(&dictation, e.to_string()).await
+                        }
+                    };
+                }
+
+                if !self.can_inject().await {
+                    *self.last_typed.lock().await = None;
+                    let status = self.deliver_without_typing(&dictation.text).await;
+                    return self.ack(&dictation, status).await;
+                }
+
+                // Typing a long-delayed dictation into whatever is focused
+                // now could land it anywhere
+                if let Some(age) = self.stale_age(timestamp) {
+                    if self.stale == Defer::Drop {
+                        self.notify(&format!("Dropped dictation sent {} ago (kept in history)", age)).await;
+                        return self.ack(&dictation, Delivery::Expired).await;
+                    }
+                    let ack = self.ack(&dictation, Delivery::Held).await;
+                    self.hold(dictation, &format!("sent {} ago", age)).await;
+                    return ack;
+                }
+
+                // Checked for do-not-type zones once edited, like a release
+                if self.edit && self.tui {
+                    let ack = self.ack(&dictation, Delivery::Held).await;
+                    self.editing.lock().await.push_back(dictation);
+                    self.show_editing(false).await;
+                    return ack;
+                }
+
+                if let Some(reason) = self.do_not_type_reason().await {
+                    let ack = self.ack(&dictation, Delivery::Held).await;
+                    self.hold(dictation, &reason).await;
+                    return ack;
+                }
+
+                // Inserted with nothing editable focused, it would be lost
+                if let Some(reason) = self.no_text_field_reason().await {
+                    let ack = self.ack(&dictation, Delivery::Held).await;
+                    self.hold(dictation, &reason).await;
+                    return ack;
+                }
+
+                if let Err(limit) = self.rate_limit.lock().await.check(std::time::Instant::now()) {
+                    if self.over_limit == Defer::Drop {
+                        self.set_notice(&format!("More than {} dictations: dropped one (kept in history)", limit), true).await;
+                        return self.ack(&dictation, Delivery::RateLimited).await;
+                    }
+                    let ack = self.ack(&dictation, Delivery::Held).await;
+                    self.hold(dictation, &format!("more than {} dictations", limit)).await;
+                    return ack;
+                }
+                match self.type_dictation(&dictation).await {
+                    Ok(status) => self.ack(&dictation, status).await,
+                    Err(e) => self.ack_failed(&dictation, e).await,
+                }
+            }
+            WsMessage::TextPartial { id, content, from, encrypted, nonce, ephemeral_public_key, sender_public_key, aad, sender_auth, cipher, recipients, language, .. } => {
+                let sealed = Sealed { content, encrypted, nonce, ephemeral_public_key, sender_public_key, aad, sender_auth, cipher, recipients, seq: None };
+                let text = self.decrypt_from_paired(from.as_deref(), sealed).await.ok()?;
+                let text = if self.normalize {
+                    normalize::normalize(&text, language.as_deref())
+                } else {
+                    text
+                };
+                let sender = from.unwrap_or_else(|| "unknown".to_string());
+
+                let now = std::time::Instant::now();
+                let progress = {
+                    let mut transcripts = self.transcripts.lock().await;
+                    transcripts.expire(now);
+                    transcripts.update(&id, &sender, text.clone(), now)
+                };
+                self.show_interim().await;
+                if let Some(progress) = progress.filter(|_| self.live) {
+                    self.type_partial(&id, progress, &text).await;
+                }
+                None
+            }
+            WsMessage::Correction { id, content, from, encrypted, nonce, ephemeral_public_key, sender_public_key, aad, sender_auth, cipher, recipients, language, seq } => {
+                let sealed = Sealed { content, encrypted, nonce, ephemeral_public_key, sender_public_key, aad, sender_auth, cipher, recipients, seq };
+                let corrected = self.decrypt_from_paired(from.as_deref(), sealed).await.ok()?;
+                let corrected = if self.normalize {
+                    normalize::normalize(&corrected, language.as_deref())
+                } else {
+                    corrected
+                };
+
+                if self.output != Output::Type {
+                    self.report_error(&format!("Correction ignored: dictations go to {}", self.output)).await;
+                    return None;
+                }
+
+                // A dictation that's still held is simply replaced
+                if let Some(dictation) = self.held.lock().await.iter_mut().find(|d| d.id == id) {
+                    dictation.text = corrected.clone();
+                    self.record_history(&id, &dictation.sender, &corrected).await;
+                    self.notify("✓ Corrected held dictation").await;
+                    return None;
+                }
+                let editing = self.editing.lock().await.iter_mut().find(|d| d.id == id).map(|dictation| {
+                    dictation.text = corrected.clone();
+                    dictation.sender.clone()
+                });
+                if let Some(sender) = editing {
+                    self.record_history(&id, &sender, &corrected).await;
+                    self.show_editing(true).await;
+                    self.notify("✓ Corrected the dictation being edited").await;
+                    return None;
+                }
+
+                if !self.can_inject().await {
+                    let sender = from.unwrap_or_else(|| "unknown".to_string());
+                    self.record_history(&id, &sender, &corrected).await;
+                    self.show_last_text(&corrected).await;
+                    self.deliver_without_typing(&corrected).await;
+                    return None;
+                }
+
+                if let Some(reason) = self.do_not_type_reason().await {
+                    self.report_error(&format!("Correction not typed: {}", reason)).await;
+                    return None;
+                }
+
+                // Backspacing is only safe while the cursor still sits right
+                // after the text we typed, i.e. for the most recent dictation
+                let last_typed = self.last_typed.lock().await.clone();
+                let Some(last_typed) = last_typed.filter(|t| t.id == id) else {
+                    self.report_error("Correction ignored: only the most recent dictation can be corrected").await;
+                    return None;
+                };
+
+                let edit = correction::edit(&last_typed.text, &corrected);
+                let typed = self.simulate_typing(&self.injector, &Profile::default(), edit.backspaces, edit.insert).await;
+
+                // After a failed or cancelled edit we no longer know what's on screen
+                *self.last_typed.lock().await = typed.as_ref().ok().map(|_| correction::Typed {
+                    id: id.clone(),
+                    text: corrected.clone(),
+                });
+
+                match typed {
+                    Ok(()) => {
+                        let sender = from.unwrap_or_else(|| "unknown".to_string());
+                        self.record_history(&id, &sender, &corrected).await;
+                        self.show_last_text(&corrected).await;
+                        self.state.lock().await.transcript.correct(&id, &corrected);
+                        self.notify("✓ Corrected last dictation").await;
+                    }
+                    Err(_) if self.cancel.is_cancelled() => self.notify(typing::CANCELLED).await,
+                    Err(e) => self.report_error(&format!("Correction failed: {}", e)).await,
+                }
+                None
+            }
+            WsMessage::Key { key, from, encrypted, nonce, ephemeral_public_key, sender_public_key, aad, sender_auth, cipher, timestamp, seq } => {
+                let sealed = Sealed { content: key, encrypted, nonce, ephemeral_public_key, sender_public_key, aad, sender_auth, cipher, recipients: None, seq };
+                let name = self.decrypt_from_paired(from.as_deref(), sealed).await.ok()?;
+                let Some(key) = Key::parse(&name) else {
+                    self.report_error(&format!("Unknown key: {}", name)).await;
+                    return None;
+                };
+                // A late Enter would submit whatever is in the focused window now
+                if let Some(age) = self.stale_age(timestamp) {
+                    self.report_error(&format!("{:?} ignored: sent {} ago", key, age)).await;
+                    return None;
+                }
+                if self.output != Output::Type {
+                    self.report_error(&format!("{:?} ignored: dictations go to {}", key, self.output)).await;
+                    return None;
+                }
+                if !self.can_inject().await {
+                    self.report_error(&format!("Can't press {:?}: typing unavailable", key)).await;
+                    return None;
+                }
+                // An Enter into a password prompt would submit it
+                if let Some(reason) = self.do_not_type_reason().await {
+                    self.report_error(&format!("{:?} not pressed: {}", key, reason)).await;
+                    return None;
+                }
+                // A burst of Enters or Backspaces does damage fast
+                if let Err(limit) = self.rate_limit.lock().await.check(std::time::Instant::now()) {
+                    self.report_error(&format!("{:?} not pressed: more than {} dictations and keys", key, limit)).await;
+                    return None;
+                }
+                if let Err(e) = self.press_key(key).await {
+                    self.report_error(&format!("Key error: {}", e)).await;
+                }
+                None
+            }
+            WsMessage::Clipboard { content, id, from, encrypted, nonce, ephemeral_public_key, sender_public_key, aad, sender_auth, cipher, seq, .. } => {
+                let sealed = Sealed { content, encrypted, nonce, ephemeral_public_key, sender_public_key, aad, sender_auth, cipher, recipients: None, seq };
+                let text = match self.decrypt_from_paired(from.as_deref(), sealed).await {
+                    Ok(text) => text,
+                    Err(status) => return from.zip(id).map(|(to, id)| WsMessage::Ack { to, id, status, error: None }),
+                };
+                // Never typed, shown or kept in history: it may well be a password
+                let copied = tokio::task::spawn_blocking(move || clipboard::copy(&text))
+                    .await
+                    .unwrap_or_else(|e| Err(e.to_string()));
+                let sender = from.clone().unwrap_or_else(|| "phone".to_string());
+                let (status, error) = match copied {
+                    Ok(()) => {
+                        self.notify(&format!("Copied to clipboard from {}", sender)).await;
+                        (Delivery::Clipboard, None)
+                    }
+                    Err(e) => {
+                        self.report_error(&format!("Clipboard from {}: {}", sender, e)).await;
+                        (Delivery::Failed, Some(e))
+                    }
+                };
+                from.zip(id).map(|(to, id)| WsMessage::Ack { to, id, status, error })
+            }
+            WsMessage::PairRequest { content, from, encrypted, nonce, ephemeral_public_key, sender_public_key, aad, sender_auth, cipher } => {
+                let sealed = Sealed { content, encrypted, nonce, ephemeral_public_key, sender_public_key: sender_public_key.clone(), aad, sender_auth, cipher, recipients: None, seq: None };
+                let plaintext = self.decrypt_content(from.as_deref(), sealed).await?;
+                let bundle: pairing::PairingBundle = match serde_json::from_str(&plaintext) {
+                    Ok(bundle) => bundle,
+                    Err(e) => {
+                        self.report_error(&format!("Invalid pairing request: {}", e)).await;
+                        return None;
+                    }
+                };
+
+                // The key the relay registered for the sender must be the one being paired
+                if sender_public_key.as_deref() != Some(bundle.public_key.as_str()) {
+                    let reason = "Pairing request rejected: key doesn't match the sending device";
+                    self.reject(from.as_deref(), sender_public_key.as_deref(), reason).await;
+                    return None;
+                }
+                let fingerprint = match pairing::fingerprint(&bundle.public_key) {
+                    Ok(fingerprint) => fingerprint,
+                    Err(e) => {
+                        self.report_error(&format!("Invalid pairing request: {}", e)).await;
+                        return None;
+                    }
+                };
+
+                // A phone that made first contact and then asked to pair keeps its held dictations
+                let from = from.unwrap_or_else(|| bundle.device_id.clone());
+                let dictations = match self.pending_pair.lock().await.take() {
+                    Some(earlier) if earlier.bundle.public_key == bundle.public_key => earlier.dictations,
+                    _ => Vec::new(),
+                };
+                self.prompt_pairing(PendingPair { to: from, bundle, requested: true, dictations }, fingerprint).await;
+                None
+            }
+            WsMessage::Config { version, blob, nonce, updated_by } => {
+                let sync = self.sync.clone()?;
+                let remote = blob.as_deref().zip(nonce.as_deref()).map(|(blob, nonce)| (version, blob, nonce));
+                let from = updated_by.unwrap_or_else(|| "another desktop".to_string());
+                match sync.reconcile(remote) {
+                    Ok(sync::Outcome::UpToDate) => None,
+                    Ok(sync::Outcome::Push(upload)) => Some(WsMessage::PutConfig {
+                        blob: upload.blob,
+                        nonce: upload.nonce,
+                        base_version: upload.base_version,
+                    }),
+                    Ok(sync::Outcome::Pulled) => {
+                        self.notify(&format!("Config synced from {}, restart utterd to apply", from)).await;
+                        None
+                    }
+                    Ok(sync::Outcome::Conflict(paths)) => {
+                        let saved: Vec<String> = paths.iter().map(|p| p.display().to_string()).collect();
+                        self.set_notice(
+                            &format!("Config changed here and on {}; theirs is in {}, merge and restart to upload", from, saved.join(", ")),
+                            true,
+                        ).await;
+                        None
+                    }
+                    Err(e) => {
+                        self.report_error(&format!("Config sync: {}", e)).await;
+                        None
+                    }
+                }
+            }
+            WsMessage::ConfigSaved { version } => {
+                if let Some(ref sync) = self.sync {
+                    match sync.saved(version) {
+                        Ok(()) => self.notify("✓ Config uploaded for your other desktops").await,
+                        Err(e) => self.report_error(&format!("Config sync: {}", e)).await,
+                    }
+                }
+                None
+            }
+            // Someone else uploaded in between; fetch theirs and reconcile again
+            WsMessage::ConfigConflict { .. } => self.sync.as_ref().map(|_| WsMessage::GetConfig),
+            WsMessage::Devices { devices } => {
+                let pairings = self.pairings.lock().await;
+                let phones = devices
+                    .into_iter()
+                    .filter(|device| device.device_type == "android")
+                    .map(|device| Phone {
+                        can_type: pairings.is_trusted(device.public_key.as_deref()),
+                        fingerprint: device.public_key.as_deref().and_then(|key| pairing::fingerprint(key).ok()),
+                        public_key: device.public_key,
+                        name: device.device_name,
+                        platform: device.platform,
+                    })
+                    .collect();
+                drop(pairings);
+                self.state.lock().await.phones = phones;
+                // A phone that just came online needs the session key
+                let _ = self.commands_tx.send(Command::AnnounceKeys);
+                None
+            }
+            WsMessage::PairingCode { code } => {
+                let mut state = self.state.lock().await;
+                state.pairing_code = state.pairing_code.as_deref().map(|uri| pairing::with_join_code(uri, &code));
+                state.join_code = Some(code);
+                None
+            }
+            WsMessage::Pong => None,
+            WsMessage::Error { message } => {
+                self.report_error(&format!("Relay: {}", message)).await;
+                None
+            }
+            _ => None,
         }
     }
 
-    async fn connect(&self) -> Result<(), String> {
-        // Connect to WebSocket
-        let (ws_stream, _) = connect_async(&self.server_url)
+    /// Run one relay session, returning how the relay ended it
+    async fn connect(&self) -> Result<CloseStatus, ConnectError> {
+        // Connect to WebSocket; strict mode also caps frame sizes
+        let mut ws_config = WebSocketConfig::default();
+        if self.strict {
+            ws_config.max_message_size = Some(strict::MAX_MESSAGE_SIZE);
+            ws_config.max_frame_size = Some(strict::MAX_MESSAGE_SIZE);
+        }
+        // A unix:// relay is reached through its socket, with a placeholder URL
+        #[cfg(unix)]
+        let socket = unix::socket_path(&self.server_url);
+        #[cfg(unix)]
+        let url = socket.map_or(self.server_url.as_str(), |_| unix::WS_URL);
+        #[cfg(not(unix))]
+        let url = self.server_url.as_str();
+        self.debug(&format!("Connecting to {}", self.server_url)).await;
+
+        // The JWT also goes in the upgrade request, so a relay (or a proxy in
+        // front of it) can turn away unauthenticated clients before upgrading
+        let mut request = url
+            .into_client_request()
+            .map_err(|e| ConnectError::new(format!("Invalid server URL: {}", e)))?;
+        if let Some(ref jwt) = self.jwt {
+            let bearer = HeaderValue::from_str(&format!("Bearer {}", jwt))
+                .map_err(|_| ConnectError::new("JWT is not a valid header value"))?;
+            request.headers_mut().insert(AUTHORIZATION, bearer);
+        }
+        self.headers.apply(request.headers_mut());
+
+        #[cfg(unix)]
+        if let Some(path) = socket {
+            let (ws_stream, _) = unix::connect_websocket(path, request, Some(ws_config)).await.map_err(|e| {
+                ConnectError::new(match e {
+                    WsError::Io(ref io) if io.kind() == std::io::ErrorKind::NotFound => format!("No relay socket at {}", path.display()),
+                    WsError::Io(ref io) if io.kind() == std::io::ErrorKind::ConnectionRefused => "Server not running".to_string(),
+                    e => e.to_string().chars().take(60).collect(),
+                })
+            })?;
+            let (write, read) = ws_stream.split();
+            return Ok(self.session(write, read).await);
+        }
+
+        let connector = self.tls.connector().map_err(ConnectError::new)?;
+        let (ws_stream, _) = proxy::connect_websocket(request, Some(ws_config), connector, self.proxy.as_ref())
             .await
             .map_err(|e| {
-                if e.to_string().contains("Connection refused") || e.to_string().contains("111") {
+                // Relay rejected the upgrade while overloaded (429/503 + Retry-After)
+                if let WsError::Http(ref response) = e {
+                    if let Some(retry_after) = reconnect::retry_after_from_response(response) {
+                        return ConnectError {
+                            message: format!("Relay busy (HTTP {})", response.status().as_u16()),
+                            retry_after: Some(retry_after),
+                        };
+                    }
+                }
+
+                // wss:// handshake failures (untrusted certificate, wrong host name)
+                if let WsError::Tls(ref tls) = e {
+                    return ConnectError::new(format!("TLS: {}", tls).chars().take(60).collect::<String>());
+                }
+
+                ConnectError::new(if e.to_string().contains("Connection refused") || e.to_string().contains("111") {
                     "Server not running".to_string()
                 } else if e.to_string().contains("getaddrinfo failed") {
                     "Cannot resolve hostname".to_string()
                 } else if e.to_string().contains("Multiple exceptions") {
                     "Server not reachable".to_string()
                 } else {
-                    let err_str = e.to_string();
-                    if err_str.len() > 60 {
-                        err_str[..60].to_string()
-                    } else {
-                        err_str
-                    }
-                }
+                    // By characters: error text can contain anything
+                    e.to_string().chars().take(60).collect()
+                })
             })?;
 
-        let (mut write, mut read) = ws_stream.split();
+        let (write, read) = ws_stream.split();
+        Ok(self.session(write, read).await)
+    }
 
-        // Spawn background task to update message display every second
-        let client_clone = self.clone();
-        let update_task = tokio::spawn(async move {
-            loop {
-                sleep(Duration::from_secs(1)).await;
-                client_clone.update_message_display().await;
-            }
-        });
+    /// Exchange messages with the relay until it or we end the session
+    async fn session<W, R>(&self, mut write: W, mut read: R) -> CloseStatus
+    where
+        W: SinkExt<Message, Error = WsError> + Unpin,
+        R: Stream<Item = Result<Message, WsError>> + Unpin,
+    {
+        let mut status = CloseStatus::Normal;
+        let mut commands = self.commands.lock().await;
+        let mut session = self.strict.then(strict::Session::default);
+
+        // Keepalive: a half-open connection (NAT dropped it, laptop changed
+        // networks) reads nothing forever, so ping and expect traffic back
+        let ping_every = self.ping_interval.unwrap_or(Duration::from_secs(3600));
+        let mut ping = tokio::time::interval_at(tokio::time::Instant::now() + ping_every, ping_every);
+        ping.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        // Set while a ping is waiting for any frame from the relay
+        let mut silent_since: Option<tokio::time::Instant> = None;
+        // When the ping awaiting its pong was sent, for the round-trip time
+        let mut ping_sent: Option<std::time::Instant> = None;
 
         // Message loop
-        loop {
+        'messages: loop {
+            let deadline = self.reorder.lock().await.next_deadline();
+
             tokio::select! {
+                _ = ping.tick(), if self.ping_interval.is_some() => {
+                    if let Err(e) = write.send(Message::Ping(Vec::new())).await {
+                        self.set_connection(false, format!("Disconnected ({})", e)).await;
+                        break;
+                    }
+                    silent_since.get_or_insert_with(tokio::time::Instant::now);
+                    ping_sent.get_or_insert_with(std::time::Instant::now);
+                }
+                _ = tokio::time::sleep_until(silent_since.unwrap_or_else(tokio::time::Instant::now) + self.ping_timeout), if silent_since.is_some() => {
+                    status = CloseStatus::Unresponsive(self.ping_timeout);
+                    self.set_connection(false, format!("Disconnected: {}", status.describe())).await;
+                    break;
+                }
+                Some(command) = commands.recv() => match command {
+                    Command::Pair(accepted) => {
+                        for reply in self.answer_pairing(accepted).await {
+                            if let Err(e) = send_reply(&mut write, Some(reply)).await {
+                                self.report_error(&format!("Send error: {}", e)).await;
+                                break 'messages;
+                            }
+                        }
+                    }
+                    Command::Release => self.request_release(),
+                    Command::ReleaseNow => {
+                        for ack in self.release_held().await {
+                            if let Err(e) = send_reply(&mut write, Some(ack)).await {
+                                self.report_error(&format!("Send error: {}", e)).await;
+                                break 'messages;
+                            }
+                        }
+                    }
+                    Command::Edited { id, text } => {
+                        if let Err(e) = send_reply(&mut write, self.type_edited(&id, text).await).await {
+                            self.report_error(&format!("Send error: {}", e)).await;
+                            break 'messages;
+                        }
+                    }
+                    Command::Discard(id) => {
+                        if let Err(e) = send_reply(&mut write, self.discard_edited(&id).await).await {
+                            self.report_error(&format!("Send error: {}", e)).await;
+                            break 'messages;
+                        }
+                    }
+                    Command::AnnounceKeys => {
+                        let notices = self.key_notices().await;
+                        self.debug(&format!("Announcing keys: {} notices for paired phones", notices.len())).await;
+                        for notice in notices {
+                            if let Err(e) = send_reply(&mut write, Some(notice)).await {
+                                self.report_error(&format!("Send error: {}", e)).await;
+                                break 'messages;
+                            }
+                        }
+                    }
+                    Command::Reconnect => {}
+                    Command::NetworkChanged => {
+                        status = CloseStatus::NetworkChanged;
+                        self.set_connection(false, format!("Disconnected: {}", status.describe())).await;
+                        break;
+                    }
+                    // A clean close lets the relay tell the phones we're gone,
+                    // instead of them finding out on the next dictation
+                    Command::Shutdown => {
+                        let frame = CloseFrame {
+                            code: CloseCode::Normal,
+                            reason: "utterd shutting down".into(),
+                        };
+                        if write.send(Message::Close(Some(frame))).await.is_ok() {
+                            let _ = tokio::time::timeout(CLOSE_TIMEOUT, async {
+                                while let Some(Ok(msg)) = read.next().await {
+                                    if let Message::Close(_) = msg {
+                                        break;
+                                    }
+                                }
+                            })
+                            .await;
+                        }
+                        status = CloseStatus::Quit;
+                        break;
+                    }
+                },
+                // The message a held one was waiting for didn't come; type the held ones
+                _ = tokio::time::sleep_until(deadline.unwrap_or_else(std::time::Instant::now).into()), if deadline.is_some() => {
+                    let ready = self.reorder.lock().await.expire(std::time::Instant::now());
+                    for ws_msg in ready {
+                        if let Err(e) = send_reply(&mut write, self.handle_message(ws_msg).await).await {
+                            self.report_error(&format!("Send error: {}", e)).await;
+                            break 'messages;
+                        }
+                    }
+                }
                 msg = read.next() => {
+                    // Any frame, not just the pong, shows the connection is alive
+                    silent_since = None;
+                    if let Some(Ok(ref msg)) = msg {
+                        self.state.lock().await.stats.bytes_received += msg.len() as u64;
+                    }
                     match msg {
                         Some(Ok(Message::Text(text))) => {
-                            match serde_json::from_str::<WsMessage>(&text) {
+                            match parse_message(&text, session.as_mut()) {
                                 Ok(ws_msg) => {
-                                    if let Some(response) = self.handle_message(ws_msg).await {
-                                        let json = serde_json::to_string(&response).unwrap();
-                                        if let Err(e) = write.send(Message::Text(json)).await {
-                                            println!("\r\x1b[K{}✗ Send error: {}{}", colors::RED, e, colors::RESET);
-                                            break;
+                                    let ws_msg = match self.reassemble(ws_msg).await {
+                                        Ok(Some(ws_msg)) => ws_msg,
+                                        Ok(None) => continue,
+                                        Err(refusal) => {
+                                            if let Err(e) = send_reply(&mut write, refusal).await {
+                                                self.report_error(&format!("Send error: {}", e)).await;
+                                                break 'messages;
+                                            }
+                                            continue;
+                                        }
+                                    };
+                                    let ready = match ws_msg.sequence() {
+                                        Some((from, seq)) => {
+                                            let from = from.to_string();
+                                            self.reorder.lock().await.push(&from, seq, ws_msg, std::time::Instant::now())
+                                        }
+                                        None => vec![ws_msg],
+                                    };
+                                    for ws_msg in ready {
+                                        if let Err(e) = send_reply(&mut write, self.handle_message(ws_msg).await).await {
+                                            self.report_error(&format!("Send error: {}", e)).await;
+                                            break 'messages;
                                         }
                                     }
                                 }
+                                Err(violation) if self.strict => {
+                                    status = self.hang_up(&mut write, violation).await;
+                                    break;
+                                }
                                 Err(_) => {
-                                    println!("\r\x1b[K{}✗ Invalid JSON received{}", colors::RED, colors::RESET);
+                                    self.report_error("Invalid JSON received").await;
                                 }
                             }
                         }
-                        Some(Ok(Message::Close(_))) => {
-                            // Update status to show disconnected (move up 4 lines to status line)
-                            print!("\x1b[4A\r\x1b[K{}●{} Disconnected\n\n\n\n", colors::RED, colors::RESET);
-                            use std::io::Write;
-                            std::io::stdout().flush().unwrap();
+                        Some(Ok(Message::Pong(_))) => {
+                            if let Some(sent) = ping_sent.take() {
+                                self.state.lock().await.round_trip.record(sent.elapsed());
+                            }
+                        }
+                        Some(Ok(Message::Binary(data))) if self.strict => {
+                            status = self.hang_up(&mut write, format!("unexpected {}-byte binary frame", data.len())).await;
+                            break;
+                        }
+                        Some(Ok(Message::Close(frame))) => {
+                            status = CloseStatus::from_frame(frame.as_ref());
+                            self.set_connection(false, format!("Disconnected: {}", status.describe())).await;
                             break;
                         }
                         Some(Err(e)) => {
-                            // Update status to show disconnected with error
-                            print!("\x1b[4A\r\x1b[K{}●{} Disconnected ({})\n\n\n\n", colors::RED, colors::RESET, e);
-                            use std::io::Write;
-                            std::io::stdout().flush().unwrap();
+                            self.set_connection(false, format!("Disconnected ({})", e)).await;
                             break;
                         }
                         None => {
-                            // Update status to show disconnected
-                            print!("\x1b[4A\r\x1b[K{}●{} Disconnected\n\n\n\n", colors::RED, colors::RESET);
-                            use std::io::Write;
-                            std::io::stdout().flush().unwrap();
+                            self.set_connection(false, "Disconnected".to_string()).await;
                             break;
                         }
                         _ => {}
@@ -512,97 +2836,380 @@ impl UtterClient {
             }
         }
 
-        // Clean up: abort the update task
-        update_task.abort();
+        status
+    }
 
-        Ok(())
+    /// End a session over a `--strict` violation, logging what the relay sent
+    async fn hang_up<S>(&self, write: &mut S, violation: String) -> CloseStatus
+    where
+        S: SinkExt<Message, Error = WsError> + Unpin,
+    {
+        self.report_error(&format!("Strict mode: {}", violation)).await;
+        let _ = write
+            .send(Message::Close(Some(CloseFrame {
+                code: CloseCode::Policy,
+                reason: "protocol violation".into(),
+            })))
+            .await;
+        let status = CloseStatus::ProtocolViolation(violation);
+        self.set_connection(false, format!("Disconnected: {}", status.describe())).await;
+        status
     }
 
-    async fn run(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        if !self.check_dependencies() {
-            eprintln!("\n{}✗ {} not found{}", colors::RED, self.tool, colors::RESET);
-            eprintln!("\n{}Please install {}{}", colors::YELLOW, self.tool, colors::RESET);
-            eprintln!("\n{}Install command:{}", colors::DIM, colors::RESET);
-            eprintln!("  {}sudo apt install {}{}", colors::CYAN, self.tool, colors::RESET);
-            return Ok(());
+    /// Check the typing tool and sign in; returns the relay's HTTP URL, or
+    /// None with `--listen`
+    async fn authenticate(&mut self) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        // Without a usable typing tool keep running, handing dictations to the
+        // clipboard until it's installed
+        let warning = self.injection_problem();
+        if let Some(ref warning) = warning {
+            eprintln!("\n{}⚠ {}: starting in clipboard-only mode{}", colors::YELLOW, warning.problem, colors::RESET);
+            eprintln!("{}{}{}", colors::CYAN, warning.hint, colors::RESET);
+            eprintln!("{}Dictations are copied to the clipboard (and kept in history) until then{}\n", colors::DIM, colors::RESET);
         }
+        self.state.lock().await.warning = warning;
 
-        // Initialize OAuth (runs blocking I/O, so use spawn_blocking)
-        let tokens = tokio::task::spawn_blocking(|| {
-            let oauth_manager = oauth::OAuthManager::new()?;
-            oauth_manager.get_or_authenticate()
-        })
-        .await
-        .map_err(|e| format!("OAuth task failed: {}", e))?
-        .map_err(|e| {
-            eprintln!("{}✗ OAuth failed: {}{}", colors::RED, e, colors::RESET);
+        // Phones connect to us directly; there's no relay to sign in to
+        if self.listen.is_some() {
+            return Ok(None);
+        }
+
+        let http_url = http_url_for(&self.server_url);
+        let (auth_response, account) = self.sign_in(&http_url).await.map_err(|e| {
+            eprintln!("{}✗ {}{}", colors::RED, e, colors::RESET);
             eprintln!("{}Cannot start without authentication.{}\n", colors::RED, colors::RESET);
             e
         })?;
 
-        // Exchange OAuth token for JWT
-        let http_url = self.server_url.replace("ws://", "http://").replace("wss://", "https://");
-        let auth_response = auth::exchange_for_jwt(&http_url, &tokens.id_token).await
-            .map_err(|e| {
-                eprintln!("{}✗ Failed to obtain JWT: {}{}", colors::RED, e, colors::RESET);
+        self.set_jwt(auth_response.jwt, account).await;
+
+        if let Some(ref passphrase) = self.sync_passphrase {
+            let sync = sync::ConfigSync::new(passphrase, &auth_response.user_id).map_err(|e| {
+                eprintln!("{}✗ Config sync: {}{}", colors::RED, e, colors::RESET);
                 e
             })?;
+            self.sync = Some(Arc::new(sync));
+        }
 
-        self.jwt = Some(auth_response.jwt);
+        Ok(Some(http_url))
+    }
+
+    /// Get a relay JWT: with this desktop's own secret under `--anonymous`,
+    /// else with the Google sign-in; also returns the account to show
+    async fn sign_in(&self, http_url: &str) -> Result<(auth::AuthResponse, Option<String>), String> {
+        if self.anonymous {
+            let secret = auth::anonymous_secret(self.profile.as_deref())?;
+            let auth_response = auth::exchange_anonymous(self.tls.http(), http_url, &secret)
+                .await
+                .map_err(|e| e.to_string())?;
+            return Ok((auth_response, Some("anonymous".to_string())));
+        }
+
+        let tokens = self.oauth_manager()?
+            .get_or_authenticate(self.oauth_progress())
+            .await
+            .map_err(|e| format!("OAuth failed: {}", e))?;
+        let auth_response = auth::exchange_for_jwt(self.tls.http(), http_url, &tokens.id_token)
+            .await
+            .map_err(|e| format!("Failed to obtain JWT: {}", e))?;
+        Ok((auth_response, tokens.account()))
+    }
+
+    async fn run(&mut self, http_url: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
+        let destination = match self.output {
+            Output::Type => format!("typing with {}", self.injector.tool()),
+            ref output => format!("writing to {}", output),
+        };
+
+        // The TUI needs a real terminal; screen readers and services get plain
+        // lines, and so does --output stdout, which needs stdout for dictations
+        let quit = Arc::new(Notify::new());
+        let tui = if self.screen_reader || self.output == Output::Stdout || !std::io::stdout().is_terminal() {
+            let relay = match self.listen {
+                Some(addr) => format!("listening on {}", addr),
+                None => format!("relay {}", strip_ws_prefix(&self.server_url)),
+            };
+            let banner = format!("Utter Daemon, {}, device {}, {}", relay, get_hostname(), destination);
+            if self.output == Output::Stdout {
+                eprintln!("{}", banner);
+            } else {
+                println!("{}", banner);
+            }
+            None
+        } else {
+            // Checked when the config was loaded; NO_COLOR asks for no colors too
+            let mut theme = theme::Theme::from_config(&self.config.theme).unwrap_or_default();
+            if std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty()) {
+                theme = theme::Theme::monochrome();
+            }
+            let options = tui::Options {
+                theme,
+                mouse: !self.config.no_mouse,
+                alert: self.config.alert,
+                redact: self.config.redact,
+            };
+            Some(Tui::start(self.state.clone(), self.cancel.clone(), quit.clone(), self.commands_tx.clone(), options)?)
+        };
+        self.tui = tui.is_some();
+        if self.edit && !self.tui {
+            self.announce("⚠ --edit needs the status view: typing dictations without editing");
+        }
+
+        // Without the TUI, pairing prompts are answered and held dictations released on stdin
+        if tui.is_none() && std::io::stdin().is_terminal() {
+            let commands = self.commands_tx.clone();
+            std::thread::spawn(move || {
+                for line in std::io::stdin().lines().map_while(Result::ok) {
+                    let command = match line.trim().to_lowercase().as_str() {
+                        "y" | "yes" => Command::Pair(true),
+                        "n" | "no" => Command::Pair(false),
+                        "f" | "release" => Command::Release,
+                        "r" | "c" | "connect" | "reconnect" => Command::Reconnect,
+                        _ => continue,
+                    };
+                    let _ = commands.send(command);
+                }
+            });
+        }
+
+        // Notice a daemon (ydotoold) stopping or coming back between dictations
+        let health = {
+            let client = self.clone();
+            tokio::spawn(async move {
+                loop {
+                    sleep(HEALTH_CHECK_INTERVAL).await;
+                    client.can_inject().await;
+                }
+            })
+        };
+
+        // Keep the status view's "Next dictation" line current
+        let focus = (self.tui && self.output == Output::Type).then(|| {
+            let client = self.clone();
+            tokio::spawn(async move {
+                loop {
+                    client.show_focus().await;
+                    sleep(FOCUS_INTERVAL).await;
+                }
+            })
+        });
+
+        // Reconnect as soon as a Wi-Fi switch or VPN toggle moves the route
+        // to the relay, rather than once pings time out. Elsewhere than
+        // Linux the pings are all there is.
+        if self.listen.is_none() {
+            let commands = self.commands_tx.clone();
+            let _ = netwatch::watch(move || {
+                let _ = commands.send(Command::NetworkChanged);
+            });
+        }
+
+        let client = self.clone();
+        let mut conn_handle = tokio::spawn(async move {
+            match (client.listen, http_url) {
+                (Some(addr), _) => client.serve_directly(addr).await,
+                (None, Some(http_url)) => client.run_with_display(http_url).await,
+                (None, None) => Err("Not signed in".to_string()),
+            }
+        });
+
+        let finished = tokio::select! {
+            result = &mut conn_handle => Some(result.map_err(|e| format!("Connection task failed: {}", e))?),
+            _ = quit.notified() => None,
+            _ = tokio::signal::ctrl_c() => None,
+            _ = terminated() => None,
+        };
+        let result = match finished {
+            Some(result) => result,
+            None => {
+                // Close the connection properly if the session gets to it in time
+                let _ = self.commands_tx.send(Command::Shutdown);
+                let _ = tokio::time::timeout(CLOSE_TIMEOUT * 2, &mut conn_handle).await;
+                Ok(())
+            }
+        };
+        conn_handle.abort();
+        health.abort();
+        if let Some(focus) = focus {
+            focus.abort();
+        }
+
+        // Restore the terminal before anything is printed to it
+        drop(tui);
+        result.map_err(Into::into)
+    }
 
-        // Print startup banner
-        let hostname = get_hostname();
-        println!("{}{}Utter{} {}Daemon{}",
-            colors::BRIGHT, colors::CYAN, colors::RESET, colors::DIM, colors::RESET);
-        println!("{}{} • {}{}\n",
-            colors::GRAY, strip_ws_prefix(&self.server_url), hostname, colors::RESET);
+    /// Connection loop: connect, wait out the relay's backoff, refresh auth, repeat
+    async fn run_with_display(mut self, mut http_url: String) -> Result<(), String> {
+        // Set when the relay closed the session because our JWT was rejected
+        let mut force_jwt_refresh = false;
+        let mut backoff = reconnect::Backoff::default();
+        let mut relays = reconnect::Relays::new(self.servers.clone());
 
         // Connection loop
         loop {
             // Refresh JWT if expiring soon (< 5 minutes)
-            if let Some(ref current_jwt) = self.jwt {
-                if auth::is_jwt_expiring_soon(current_jwt, 300) {
-                    println!("{}↻ Refreshing JWT...{}", colors::YELLOW, colors::RESET);
-                    match auth::refresh_jwt(&http_url, current_jwt).await {
+            // Whether this connection uses a token refreshed after a rejection
+            let refreshed_after_rejection = std::mem::take(&mut force_jwt_refresh) && self.jwt.is_some();
+            if let Some(current_jwt) = self.jwt.clone() {
+                if refreshed_after_rejection || auth::is_jwt_expiring_soon(&current_jwt, 300) {
+                    self.notify("↻ Refreshing JWT...").await;
+                    match auth::refresh_jwt(self.tls.http(), &http_url, &current_jwt).await.map_err(|e| e.to_string()) {
                         Ok(new_auth_response) => {
-                            self.jwt = Some(new_auth_response.jwt);
-                            println!("{}✓{} JWT refreshed", colors::GREEN, colors::RESET);
+                            self.set_jwt(new_auth_response.jwt, None).await;
+                            self.notify("✓ JWT refreshed").await;
                         }
                         Err(e) => {
-                            eprintln!("{}✗ JWT refresh failed: {}{}", colors::RED, e, colors::RESET);
-                            eprintln!("{}Re-authenticating with Google...{}", colors::YELLOW, colors::RESET);
+                            let with = if self.anonymous { "" } else { " with Google" };
+                            self.report_error(&format!("JWT refresh failed: {}. Re-authenticating{}...", e, with)).await;
 
-                            // Re-authenticate with Google
-                            let new_tokens = tokio::task::spawn_blocking(|| {
-                                let oauth_manager = oauth::OAuthManager::new()?;
-                                oauth_manager.get_or_authenticate()
-                            })
-                            .await
-                            .map_err(|e| format!("OAuth task failed: {}", e))??;
-
-                            let new_auth_response = auth::exchange_for_jwt(&http_url, &new_tokens.id_token).await?;
-                            self.jwt = Some(new_auth_response.jwt);
-                            println!("{}✓{} Re-authenticated and obtained new JWT", colors::GREEN, colors::RESET);
+                            let (new_auth_response, account) = self.sign_in(&http_url).await?;
+                            self.set_jwt(new_auth_response.jwt, account).await;
+                            self.notify("✓ Re-authenticated and obtained new JWT").await;
                         }
                     }
                 }
             }
 
             // Try to connect
-            if let Err(e) = self.connect().await {
-                print!("\r\x1b[K{}✗ {}{}", colors::RED, e, colors::RESET);
+            let started = std::time::Instant::now();
+            let delay = match self.connect().await {
+                Ok(CloseStatus::Quit) => return Ok(()),
+                Ok(status) => {
+                    if status == CloseStatus::AuthExpired {
+                        force_jwt_refresh = true;
+                    }
+                    // A new network deserves a fresh start too
+                    if started.elapsed() >= reconnect::STABLE_SESSION || status == CloseStatus::NetworkChanged {
+                        backoff.reset();
+                        relays.reset();
+                    }
+                    // Another relay may still be up; a rejected token is
+                    // refreshed and retried on the same one, and after a
+                    // network change the same relay is worth another try
+                    let failover = !matches!(status, CloseStatus::AuthExpired | CloseStatus::Kicked | CloseStatus::NetworkChanged);
+                    // Without a token, or with one just refreshed that was
+                    // rejected again, refreshing won't help right away
+                    let refreshing = self.jwt.is_some() && !refreshed_after_rejection;
+                    if failover && relays.advance() {
+                        Duration::ZERO
+                    } else {
+                        match status.reconnect_delay(&mut backoff, refreshing) {
+                            Some(delay) => delay,
+                            // Another instance owns this device now; fighting it would loop forever
+                            None => return Err(format!("{}. Not reconnecting.", status.describe())),
+                        }
+                    }
+                }
+                Err(e) => {
+                    self.set_connection(false, e.message.clone()).await;
+                    if relays.advance() {
+                        Duration::ZERO
+                    } else {
+                        // Honor relay backoff requests, jittered so a fleet of
+                        // clients doesn't come back in the same second
+                        let delay = backoff.next();
+                        e.retry_after.map_or(delay, |retry_after| delay.max(reconnect::with_jitter(retry_after)))
+                    }
+                }
+            };
+
+            // Relays share the JWT secret, so the token carries over
+            if relays.current() != self.server_url {
+                self.server_url = relays.current().to_string();
+                http_url = http_url_for(&self.server_url);
+                self.state.lock().await.server_url = self.server_url.clone();
+            }
+
+            let relay = if relays.len() > 1 {
+                format!(" to {}", strip_ws_prefix(&self.server_url))
+            } else {
+                String::new()
+            };
+            self.notify(&format!("Reconnecting{} in {} seconds", relay, delay.as_millis().div_ceil(1000))).await;
+            if !self.wait_to_reconnect(delay).await {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Direct LAN mode: the session talks to the built-in relay, which phones connect to
+    async fn serve_directly(self, addr: SocketAddr) -> Result<(), String> {
+        let listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .map_err(|e| format!("Cannot listen on {}: {}", addr, e))?;
+
+        // Lets the app list this desktop instead of asking for its address;
+        // withdrawn when dropped
+        let _advertisement = if self.advertise && !addr.ip().is_loopback() {
+            let fingerprint = self
+                .key_manager
+                .as_ref()
+                .and_then(|km| km.get_public_key_base64().ok())
+                .and_then(|key| pairing::fingerprint(&key).ok());
+            let advertised = tokio::task::spawn_blocking(move || mdns::advertise(&get_hostname(), addr.port(), fingerprint.as_deref()))
+                .await
+                .map_err(|e| format!("mDNS task failed: {}", e))?;
+            match advertised {
+                Ok(advertisement) => Some(advertisement),
+                Err(e) => {
+                    self.report_error(&format!("mDNS: {}; enter this desktop's address in the app", e)).await;
+                    None
+                }
             }
+        } else {
+            None
+        };
+
+        let (to_session, from_hub) = futures_channel::mpsc::unbounded();
+        let (to_hub, from_session) = futures_channel::mpsc::unbounded();
+        let hub = tokio::spawn(lan::serve(listener, to_session, from_session));
+        let status = self
+            .session(to_hub.sink_map_err(|_| WsError::ConnectionClosed), from_hub.map(Ok))
+            .await;
+
+        if status == CloseStatus::Quit {
+            // Give the phones a moment to see the close
+            let _ = tokio::time::timeout(CLOSE_TIMEOUT, hub).await;
+            return Ok(());
+        }
+        hub.abort();
+        Err(format!("{}. Not listening anymore.", status.describe()))
+    }
+
+    /// Sleep before the next connection attempt, unless r or c (TUI) or "r"
+    /// (stdin) asks to retry right away; false if utterd is quitting
+    async fn wait_to_reconnect(&self, delay: Duration) -> bool {
+        self.state.lock().await.reconnect_pending = true;
+        let mut commands = self.commands.lock().await;
+        let wake = sleep(delay);
+        tokio::pin!(wake);
 
-            // Reconnect after 5 seconds
-            print!("\r{}Reconnecting in 5s...{}", colors::YELLOW, colors::RESET);
-            use std::io::Write;
-            std::io::stdout().flush().unwrap();
-            sleep(Duration::from_secs(5)).await;
-            print!("\r\x1b[K"); // Clear the line
-            // Move cursor back up to status line so Registered will overwrite it
-            print!("\x1b[4A");
-            std::io::stdout().flush().unwrap();
+        loop {
+            tokio::select! {
+                _ = &mut wake => break,
+                Some(command) = commands.recv() => match command {
+                    Command::Reconnect | Command::NetworkChanged => break,
+                    Command::Shutdown => {
+                        self.state.lock().await.reconnect_pending = false;
+                        return false;
+                    }
+                    // Offline, so the phones keep showing "held" for these
+                    Command::Release => self.request_release(),
+                    Command::ReleaseNow => drop(self.release_held().await),
+                    // No relay to send the answer to; the prompt stays up until reconnected
+                    Command::Pair(_) => {}
+                    // Announced again on the next registration
+                    Command::AnnounceKeys => {}
+                    // The phones keep showing "held" for these
+                    Command::Edited { id, text } => drop(self.type_edited(&id, text).await),
+                    Command::Discard(id) => drop(self.discard_edited(&id).await),
+                },
+            }
         }
+
+        self.state.lock().await.reconnect_pending = false;
+        true
     }
 }
 
@@ -610,36 +3217,551 @@ impl Clone for UtterClient {
     fn clone(&self) -> Self {
         Self {
             server_url: self.server_url.clone(),
-            tool: self.tool.clone(),
+            servers: self.servers.clone(),
+            listen: self.listen,
+            advertise: self.advertise,
+            anonymous: self.anonymous,
+            injector: self.injector.clone(),
             state: self.state.clone(),
             key_manager: self.key_manager.clone(),
             message_encryption: self.message_encryption.clone(),
             jwt: self.jwt.clone(),
+            normalize: self.normalize,
+            screen_reader: self.screen_reader,
+            target_class: self.target_class.clone(),
+            output: self.output.clone(),
+            strict: self.strict,
+            allow_plaintext: self.allow_plaintext,
+            max_age: self.max_age,
+            stale: self.stale,
+            rate_limit: self.rate_limit.clone(),
+            over_limit: self.over_limit,
+            ping_interval: self.ping_interval,
+            ping_timeout: self.ping_timeout,
+            tls: self.tls.clone(),
+            proxy: self.proxy.clone(),
+            headers: self.headers.clone(),
+            config: self.config.clone(),
+            cancel: self.cancel.clone(),
+            tui: self.tui,
+            last_typed: self.last_typed.clone(),
+            sync_passphrase: self.sync_passphrase.clone(),
+            sync: self.sync.clone(),
+            pairings: self.pairings.clone(),
+            pending_pair: self.pending_pair.clone(),
+            refused: self.refused.clone(),
+            commands_tx: self.commands_tx.clone(),
+            commands: self.commands.clone(),
+            held: self.held.clone(),
+            reorder: self.reorder.clone(),
+            deliveries: self.deliveries.clone(),
+            transcripts: self.transcripts.clone(),
+            live: self.live,
+            edit: self.edit,
+            editing: self.editing.clone(),
+            chunks: self.chunks.clone(),
+            replay: self.replay.clone(),
+            session_keys: self.session_keys.clone(),
+            profile: self.profile.clone(),
+        }
+    }
+}
+
+/// Parse a relay message, running the `--strict` checks when given a session
+/// SIGTERM, how `kill` and service managers stop utterd
+#[cfg(unix)]
+async fn terminated() {
+    match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+        Ok(mut signal) => drop(signal.recv().await),
+        Err(_) => std::future::pending().await,
+    }
+}
+
+#[cfg(not(unix))]
+async fn terminated() {
+    std::future::pending().await
+}
+
+fn parse_message(text: &str, session: Option<&mut strict::Session>) -> Result<WsMessage, String> {
+    let Some(session) = session else {
+        return serde_json::from_str(text).map_err(|e| e.to_string());
+    };
+
+    let raw: serde_json::Value = serde_json::from_str(text).map_err(|e| format!("invalid JSON: {}", e))?;
+    let message: WsMessage = serde_json::from_value(raw.clone()).map_err(|e| format!("unrecognized message: {}", e))?;
+    let parsed = serde_json::to_value(&message).map_err(|e| e.to_string())?;
+    session.check(&raw, &parsed)?;
+    Ok(message)
+}
+
+/// Send the reply to a relay message, if there is one
+async fn send_reply<S>(write: &mut S, response: Option<WsMessage>) -> Result<(), WsError>
+where
+    S: SinkExt<Message, Error = WsError> + Unpin,
+{
+    if let Some(response) = response {
+        let json = serde_json::to_string(&response).unwrap();
+        write.send(Message::Text(json)).await?;
+    }
+    Ok(())
+}
+
+/// Use --tool if given, otherwise the best backend for this session
+fn injector_from_args(args: &Args, config: &Config) -> Injector {
+    let injector = match args.tool {
+        Some(ref tool) => Injector::new(tool.clone(), args.display.clone(), args.wayland_display.clone()).with_detected_layout(),
+        None => Injector::detect(args.display.clone(), args.wayland_display.clone()),
+    };
+    let extra_args = config.tool_args(injector.tool());
+    let injector = injector
+        .with_extra_args(extra_args)
+        .with_ydotool_socket(args.ydotool_socket.clone());
+    if args.harden {
+        injector.hardened()
+    } else {
+        injector
+    }
+}
+
+/// Status-line preview of a dictation (truncated on a char boundary, not a byte index)
+/// "45s", "12 min" or "3 h", for dictation ages
+fn describe_age(age: Duration) -> String {
+    match age.as_secs() {
+        secs @ 0..=119 => format!("{}s", secs),
+        secs @ 120..=7199 => format!("{} min", secs / 60),
+        secs => format!("{} h", secs / 3600),
+    }
+}
+
+/// What `redact` shows instead of dictated text: only its length
+fn redacted(text: &str) -> String {
+    format!("‹{} characters›", text.chars().count())
+}
+
+fn preview(text: &str) -> String {
+    if text.chars().count() > 60 {
+        format!("{}...", text.chars().take(60).collect::<String>())
+    } else {
+        text.to_string()
+    }
+}
+
+/// Run `inject`, first focusing the `--target-class` window if one is set
+fn with_target_focus(
+    injector: &Injector,
+    target_class: Option<&str>,
+    inject: impl FnOnce() -> Result<(), String>,
+) -> Result<(), String> {
+    let Some(class) = target_class else {
+        return inject();
+    };
+
+    // Never fall back to typing into whatever has focus; that's the
+    // window the user is working in
+    let previous = injector.focus_class(class)?;
+    let typed = inject();
+    let restored = match previous {
+        Some(ref window) => injector.restore_focus(window),
+        None => Ok(()),
+    };
+    typed.and(restored)
+}
+
+/// The key store, with the key passphrase from UTTER_KEY_PASSPHRASE or,
+/// if the key file is encrypted or --encrypt-key asks for that, a prompt
+fn open_keys(args: &Args) -> Result<KeyManager, Box<dyn std::error::Error>> {
+    let mut key_manager = KeyManager::new(args.profile.as_deref())?;
+    let passphrase = match args.key_passphrase {
+        Some(ref passphrase) => Some(passphrase.clone()),
+        None if key_manager.is_passphrase_protected()? => Some(
+            rpassword::prompt_password("Key passphrase: ")
+                .map_err(|e| format!("The key file is encrypted; set UTTER_KEY_PASSPHRASE ({})", e))?,
+        ),
+        None if args.encrypt_key => Some(
+            new_passphrase("New key passphrase: ").map_err(|e| format!("{}; or set UTTER_KEY_PASSPHRASE", e))?,
+        ),
+        None => None,
+    };
+    if args.tpm && passphrase.is_some() {
+        return Err("--tpm can't be combined with a key passphrase (UTTER_KEY_PASSPHRASE)".into());
+    }
+    key_manager.set_passphrase(passphrase);
+    key_manager.set_tpm(args.tpm);
+    Ok(key_manager)
+}
+
+/// The OAuth token cache key, for subcommands that sign in; None (a
+/// plaintext cache) if the keypair can't be loaded
+fn oauth_cache_key(args: &Args) -> Option<SecretBox<[u8; 32]>> {
+    let mut key_manager = open_keys(args).ok()?;
+    key_manager.get_or_generate_keypair().ok()?;
+    key_manager.derive_key("oauth").ok()
+}
+
+/// `utterd login`: sign in with Google (or refresh the cached sign-in),
+/// and have the daemon use that account
+async fn login(args: &Args, account: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    let oauth_manager = oauth::OAuthManager::new(args.profile.as_deref(), account, oauth_cache_key(args))?;
+    let tokens = oauth_manager.get_or_authenticate(|text: &str| println!("{}", text)).await?;
+    oauth::select_account(args.profile.as_deref(), account)?;
+    println!(
+        "{}✓ Signed in{}{} {}(token valid until {}){}",
+        colors::GREEN,
+        tokens.account().map(|account| format!(" as {}", account)).unwrap_or_default(),
+        colors::RESET,
+        colors::DIM,
+        tokens.expires_at.with_timezone(&chrono::Local).format("%H:%M"),
+        colors::RESET
+    );
+    if let Some(account) = account {
+        println!("  utterd{} now uses the {} account", args.profile.as_deref().map(|p| format!(" --profile {}", p)).unwrap_or_default(), account);
+    }
+    Ok(())
+}
+
+/// `utterd logout`: delete a cached Google sign-in
+fn logout(args: &Args, account: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    let oauth_manager = oauth::OAuthManager::new(args.profile.as_deref(), account, None)?;
+    if oauth_manager.sign_out()? {
+        println!("{}✓ Signed out{}", colors::GREEN, colors::RESET);
+    } else {
+        println!("Not signed in");
+    }
+    // Back to the default account rather than signing in to a forgotten one
+    if account.is_some() && oauth::selected_account(args.profile.as_deref()).as_deref() == account {
+        oauth::select_account(args.profile.as_deref(), None)?;
+    }
+    Ok(())
+}
+
+/// `utterd keys rotate`: replace the keypair, keeping the old key for the grace window
+fn keys_rotate(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+    // A running daemon would go on registering the old key
+    let _lock_file = acquire_singleton_lock(args.lock_file.clone(), args.profile.as_deref())
+        .map_err(|e| format!("Stop utterd before rotating its key: {}", e))?;
+
+    let mut key_manager = open_keys(args)?;
+    key_manager.get_or_generate_keypair()?;
+    let oauth_manager = oauth::OAuthManager::new(args.profile.as_deref(), None, Some(key_manager.derive_key("oauth")?))?;
+    key_manager.rotate()?;
+    // The token cache's key is derived from the keypair
+    if let Err(e) = oauth_manager.reencrypt(key_manager.derive_key("oauth")?) {
+        eprintln!("{}⚠ {}; you'll be asked to sign in again{}", colors::YELLOW, e, colors::RESET);
+    }
+    let public_key = key_manager.get_public_key_base64()?;
+    println!("{}✓ New key {}{}", colors::GREEN, pairing::fingerprint(&public_key)?, colors::RESET);
+    println!(
+        "  The old key still decrypts for {} days. Paired phones are sent the new key each time utterd connects until then.",
+        crypto::keys::ROTATION_GRACE.as_secs() / 86400
+    );
+    Ok(())
+}
+
+/// Ask for a passphrase to encrypt something with, twice
+fn new_passphrase(prompt: &str) -> Result<String, String> {
+    let passphrase = rpassword::prompt_password(prompt).map_err(|e| format!("Cannot ask for a passphrase: {}", e))?;
+    if passphrase.is_empty() {
+        return Err("A passphrase is needed".to_string());
+    }
+    let repeated = rpassword::prompt_password("Repeat it: ").map_err(|e| format!("Cannot ask for a passphrase: {}", e))?;
+    if repeated != passphrase {
+        return Err("The passphrases don't match".to_string());
+    }
+    Ok(passphrase)
+}
+
+/// `utterd keys export`: seal the key and trusted phones into an identity bundle
+fn keys_export(args: &Args, file: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let mut key_manager = open_keys(args)?;
+    if !key_manager.has_keypair()? {
+        return Err("No keypair yet; run utterd once first".into());
+    }
+    key_manager.get_or_generate_keypair()?;
+
+    let passphrase = new_passphrase("Passphrase for the bundle: ")?;
+
+    let identity = identity::Identity::new(key_manager.get_private_key_bytes()?.expose_secret(), pairing::Pairings::load(args.profile.as_deref())?.devices().to_vec());
+    let bundle = identity::seal(&identity, &passphrase)?;
+
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(file)?.write_all(bundle.as_bytes())?;
+
+    println!(
+        "{}✓ Wrote key {} and {} trusted phone(s) to {}{}",
+        colors::GREEN,
+        pairing::fingerprint(&key_manager.get_public_key_base64()?)?,
+        identity.trusted_devices.len(),
+        file.display(),
+        colors::RESET
+    );
+    println!("  Anyone with the file and the passphrase can read your dictations; delete it once imported.");
+    Ok(())
+}
+
+/// `utterd keys show`: fingerprints to check out-of-band against the phones
+fn keys_show(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+    let mut key_manager = open_keys(args)?;
+    if !key_manager.has_keypair()? {
+        return Err("No keypair yet; run utterd once first".into());
+    }
+    key_manager.get_or_generate_keypair()?;
+    println!("This desktop  {}", pairing::fingerprint(&key_manager.get_public_key_base64()?)?);
+
+    let pairings = pairing::Pairings::load(args.profile.as_deref())?;
+    if pairings.devices().is_empty() {
+        println!("{}No trusted phones{}", colors::DIM, colors::RESET);
+    }
+    for device in pairings.devices() {
+        let fingerprint = pairing::fingerprint(&device.public_key).unwrap_or_else(|e| e.to_string());
+        println!(
+            "{}  {}  {}({}, trusted {}){}",
+            device.device_name,
+            fingerprint,
+            colors::DIM,
+            device.device_id,
+            device.paired_at.with_timezone(&chrono::Local).format("%Y-%m-%d"),
+            colors::RESET
+        );
+    }
+    Ok(())
+}
+
+/// `utterd keys import`: take over an identity bundle from another machine
+fn keys_import(args: &Args, file: &Path, force: bool) -> Result<(), Box<dyn std::error::Error>> {
+    // A running daemon would go on using the key it loaded
+    let _lock_file = acquire_singleton_lock(args.lock_file.clone(), args.profile.as_deref())
+        .map_err(|e| format!("Stop utterd before importing a key: {}", e))?;
+
+    let bundle = std::fs::read_to_string(file).map_err(|e| format!("Cannot read {}: {}", file.display(), e))?;
+    let passphrase = rpassword::prompt_password("Passphrase for the bundle: ")?;
+    let identity = identity::open(&bundle, &passphrase)?;
+    let private_key = identity.private_key_bytes()?;
+
+    let mut key_manager = open_keys(args)?;
+    let mut oauth_manager = None;
+    if key_manager.has_keypair()? {
+        key_manager.get_or_generate_keypair()?;
+        oauth_manager = Some(oauth::OAuthManager::new(args.profile.as_deref(), None, Some(key_manager.derive_key("oauth")?))?);
+        if key_manager.get_private_key_bytes()?.expose_secret() != private_key.expose_secret() && !force {
+            return Err(format!(
+                "This desktop already has its own key ({}); --force replaces it, and phones paired with it must pair again",
+                pairing::fingerprint(&key_manager.get_public_key_base64()?)?
+            )
+            .into());
+        }
+    }
+    key_manager.import(private_key.expose_secret())?;
+    // The token cache's key is derived from the keypair
+    if let Some(oauth_manager) = oauth_manager {
+        if let Err(e) = oauth_manager.reencrypt(key_manager.derive_key("oauth")?) {
+            eprintln!("{}⚠ {}; you'll be asked to sign in again{}", colors::YELLOW, e, colors::RESET);
         }
     }
+    let trusted = identity.trusted_devices.len();
+    pairing::Pairings::load(args.profile.as_deref())?.merge(identity.trusted_devices)?;
+
+    println!(
+        "{}✓ Imported key {} and {} trusted phone(s){}",
+        colors::GREEN,
+        pairing::fingerprint(&key_manager.get_public_key_base64()?)?,
+        trusted,
+        colors::RESET
+    );
+    Ok(())
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// Delay before typing a picked entry, so the user can focus the target window
+const PICK_TYPE_DELAY: Duration = Duration::from_secs(3);
+
+/// `utterd history pick`: re-type or copy a previous dictation
+fn history_export(format: history::ExportFormat, since: Option<chrono::DateTime<chrono::Utc>>) -> Result<(), Box<dyn std::error::Error>> {
+    let entries = History::new()?.load()?;
+    let entries: Vec<HistoryEntry> = history::final_versions(&entries)
+        .into_iter()
+        .filter(|e| since.is_none_or(|since| e.timestamp >= since))
+        .collect();
+    print!("{}", history::export(&entries, format)?);
+    Ok(())
+}
+
+async fn history_pick(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+    let mut entries = History::new()?.load()?;
+    if entries.is_empty() {
+        println!("No dictations in history yet");
+        return Ok(());
+    }
+    entries.reverse(); // newest first
+    let redact = Config::load()?.redact;
+
+    let items: Vec<String> = entries
+        .iter()
+        .map(|e| {
+            let text = if redact { redacted(&e.text) } else { e.text.replace('\n', " ") };
+            format!("{}  {}", e.timestamp.with_timezone(&chrono::Local).format("%b %d %H:%M"), text)
+        })
+        .collect();
+
+    let Some((index, action)) = picker::pick(&items)? else {
+        return Ok(());
+    };
+    let text = &entries[index].text;
+
+    match action {
+        picker::PickAction::Copy => {
+            clipboard::copy(text)?;
+            println!("{}✓{} Copied to clipboard", colors::GREEN, colors::RESET);
+        }
+        picker::PickAction::Type => {
+            let injector = injector_from_args(args, &Config::load()?);
+            if !injector.is_available() {
+                return Err(format!("{} is not available", injector.tool()).into());
+            }
+            println!("Typing in {}s, focus the target window...", PICK_TYPE_DELAY.as_secs());
+            sleep(PICK_TYPE_DELAY).await;
+            injector.type_text(text, &CancelToken::default())?;
+        }
+    }
+
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
 
+    // Validate tool argument
+    if let Some(ref tool) = args.tool {
+        if !TOOLS.contains(&tool.as_str()) {
+            eprintln!("{}✗ Invalid tool: {}{}", colors::RED, tool, colors::RESET);
+            eprintln!("{}Valid options: {}{}", colors::YELLOW, TOOLS.join(", "), colors::RESET);
+            std::process::exit(1);
+        }
+    }
+
+    // Without a relay there's nothing to sync through
+    if args.listen.is_some() && args.sync_passphrase.is_some() {
+        eprintln!("{}✗ UTTER_SYNC_PASSPHRASE can't be used with --listen{}", colors::RED, colors::RESET);
+        std::process::exit(1);
+    }
+
+    let config = Config::load().unwrap_or_else(|e| {
+        eprintln!("{}✗ {}{}", colors::RED, e, colors::RESET);
+        std::process::exit(1);
+    });
+
+    // Relays from the command line or environment replace config.toml's;
+    // normalize them (add ws:// if missing)
+    let servers = if !args.server.is_empty() {
+        args.server.clone()
+    } else if !config.servers.is_empty() {
+        config.servers.clone()
+    } else {
+        vec!["ws://localhost:8080".to_string()]
+    };
+    let servers: Vec<String> = servers.iter().map(|server| normalize_server_url(server)).collect();
+    if cfg!(not(unix)) && servers.iter().any(|server| server.starts_with("unix://")) {
+        eprintln!("{}✗ unix:// relays are only supported on Linux and macOS{}", colors::RED, colors::RESET);
+        std::process::exit(1);
+    }
+    // Proxy settings from the environment are looked up for the first one
+    let server_url = servers[0].clone();
+
+    let proxy = match args.proxy.clone() {
+        Some(proxy) => Some(proxy),
+        None => Proxy::from_env(&server_url).unwrap_or_else(|e| {
+            eprintln!("{}✗ Proxy from environment: {}{}", colors::RED, e, colors::RESET);
+            std::process::exit(1);
+        }),
+    };
+
+    let client_cert = args.tls_cert.as_deref().zip(args.tls_key.as_deref());
+    let headers = Headers::new(&config.headers, &args.headers).unwrap_or_else(|e| {
+        eprintln!("{}✗ {}{}", colors::RED, e, colors::RESET);
+        std::process::exit(1);
+    });
+
+    let tls = tls::Tls::new(args.tls_ca.as_deref(), client_cert, args.tls_insecure, proxy.as_ref(), &headers).unwrap_or_else(|e| {
+        eprintln!("{}✗ TLS: {}{}", colors::RED, e, colors::RESET);
+        std::process::exit(1);
+    });
+    if tls.is_insecure() {
+        eprintln!("{}⚠ --tls-insecure: the relay's certificate is not checked{}", colors::YELLOW, colors::RESET);
+    }
+
+    let mut runtime = tokio::runtime::Runtime::new()?;
+
+    // Subcommands run alongside the daemon, so they don't take the lock
+    match args.command {
+        Some(Commands::History { action: HistoryCommand::Pick }) => return runtime.block_on(history_pick(&args)),
+        Some(Commands::History { action: HistoryCommand::Export { format, since } }) => return history_export(format, since),
+        Some(Commands::Probe) => {
+            let oauth_manager = oauth::OAuthManager::selected(args.profile.as_deref(), oauth_cache_key(&args))?;
+            let anonymous_secret = args.anonymous.then(|| auth::anonymous_secret(args.profile.as_deref())).transpose()?;
+            return runtime.block_on(probe::run(&server_url, &tls, proxy.as_ref(), &headers, oauth_manager, anonymous_secret));
+        }
+        Some(Commands::Login { ref account }) => return runtime.block_on(login(&args, account.as_deref())),
+        Some(Commands::Logout { ref account }) => return logout(&args, account.as_deref()),
+        Some(Commands::Keys { action: KeysCommand::Show }) => return keys_show(&args),
+        Some(Commands::Keys { action: KeysCommand::Rotate }) => return keys_rotate(&args),
+        Some(Commands::Keys { action: KeysCommand::Export { ref file } }) => return keys_export(&args, file),
+        Some(Commands::Keys { action: KeysCommand::Import { ref file, force } }) => return keys_import(&args, file, force),
+        None => {}
+    }
+
     // Acquire singleton lock to prevent multiple instances
-    let _lock_file = acquire_singleton_lock(args.lock_file).map_err(|e| {
+    let _lock_file = acquire_singleton_lock(args.lock_file.clone(), args.profile.as_deref()).map_err(|e| {
         eprintln!("{}✗ {}{}", colors::RED, e, colors::RESET);
         std::process::exit(1);
     }).unwrap();
     // Lock is held for the lifetime of _lock_file, which is the entire program
 
-    // Validate tool argument
-    if args.tool != "xdotool" && args.tool != "ydotool" {
-        eprintln!("{}✗ Invalid tool: {}{}", colors::RED, args.tool, colors::RESET);
-        eprintln!("{}Valid options: xdotool, ydotool{}", colors::YELLOW, colors::RESET);
+    // The fork keeps only the calling thread, so the runtime is started again after it
+    #[cfg(unix)]
+    let _pid_file = if args.daemon {
+        drop(runtime);
+        let profile = args.profile.as_deref();
+        let log = match args.log_file {
+            Some(ref path) => Ok(PathBuf::from(path)),
+            None => daemon::log_path(profile),
+        };
+        let pid_file = log
+            .and_then(|log| daemon::detach(&log, &daemon::pid_path(profile)?))
+            .unwrap_or_else(|e| {
+                eprintln!("{}✗ --daemon: {}{}", colors::RED, e, colors::RESET);
+                std::process::exit(1);
+            });
+        runtime = tokio::runtime::Runtime::new()?;
+        Some(pid_file)
+    } else {
+        None
+    };
+    #[cfg(not(unix))]
+    if args.daemon {
+        eprintln!("{}✗ --daemon is only supported on Linux and macOS{}", colors::RED, colors::RESET);
+        std::process::exit(1);
+    }
+
+    if let Err(e) = args.output.prepare() {
+        eprintln!("{}✗ --output: {}{}", colors::RED, e, colors::RESET);
         std::process::exit(1);
     }
 
-    // Normalize server URL (add ws:// if missing)
-    let server_url = normalize_server_url(&args.server);
+    let mut client = UtterClient::new(servers, &args, config, tls, proxy, headers);
+    let http_url = runtime.block_on(client.authenticate())?;
+
+    if args.harden {
+        // Landlock only covers threads created after it is applied, so
+        // restrict the main thread and give the daemon a fresh runtime
+        drop(runtime);
+        let extra: Vec<PathBuf> = args.output.path().map(PathBuf::from).into_iter().collect();
+        match sandbox::restrict_self(&extra) {
+            Ok(()) => eprintln!("{}✓ Hardened: filesystem read-only except utterd's data{}", colors::GREEN, colors::RESET),
+            Err(e) => eprintln!("{}⚠ Hardening incomplete: {}{}", colors::YELLOW, e, colors::RESET),
+        }
+        runtime = tokio::runtime::Runtime::new()?;
+    }
 
-    let mut client = UtterClient::new(server_url, args.tool);
-    client.run().await
+    runtime.block_on(client.run(http_url))
 }
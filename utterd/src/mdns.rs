@@ -0,0 +1,122 @@
+/// DNS-SD service type phones browse for
+const SERVICE_TYPE: &str = "_utter._tcp";
+
+/// A published service, withdrawn when dropped
+pub struct Advertisement {
+    _handle: platform::Handle,
+}
+
+/// Announce `--listen` on the local network, so the app can list this
+/// desktop instead of asking for its address
+///
+/// The instance is named after the device; TXT records carry what the app
+/// needs before connecting: device id, protocol version and the key
+/// fingerprint it will show when pairing.
+pub fn advertise(device_id: &str, port: u16, fingerprint: Option<&str>) -> Result<Advertisement, String> {
+    let txt = txt_records(device_id, fingerprint);
+    platform::publish(device_id, port, &txt).map(|handle| Advertisement { _handle: handle })
+}
+
+fn txt_records(device_id: &str, fingerprint: Option<&str>) -> Vec<String> {
+    let mut txt = vec![
+        format!("id={}", device_id),
+        format!("proto={}", crate::probe::PROTOCOL_VERSION),
+        format!("version={}", crate::VERSION),
+    ];
+    txt.extend(fingerprint.map(|fingerprint| format!("fp={}", fingerprint)));
+    txt
+}
+
+/// Avahi, over D-Bus
+#[cfg(all(unix, not(target_os = "macos")))]
+mod platform {
+    use super::SERVICE_TYPE;
+    use zbus::blocking::Connection;
+    use zbus::zvariant::OwnedObjectPath;
+
+    const AVAHI_NAME: &str = "org.freedesktop.Avahi";
+    const SERVER_IFACE: &str = "org.freedesktop.Avahi.Server";
+    const ENTRY_GROUP_IFACE: &str = "org.freedesktop.Avahi.EntryGroup";
+
+    /// All interfaces and both IPv4 and IPv6
+    const IF_UNSPEC: i32 = -1;
+    const PROTO_UNSPEC: i32 = -1;
+
+    /// Avahi drops the entry group, and with it the service, once its
+    /// creator leaves the bus
+    pub struct Handle {
+        _conn: Connection,
+    }
+
+    pub fn publish(name: &str, port: u16, txt: &[String]) -> Result<Handle, String> {
+        let conn = Connection::system().map_err(|e| format!("Cannot connect to system bus: {}", e))?;
+        let group: OwnedObjectPath = conn
+            .call_method(Some(AVAHI_NAME), "/", Some(SERVER_IFACE), "EntryGroupNew", &())
+            .and_then(|reply| reply.body().deserialize())
+            .map_err(|e| format!("Avahi not available: {}", e))?;
+
+        let txt: Vec<Vec<u8>> = txt.iter().map(|entry| entry.as_bytes().to_vec()).collect();
+        conn.call_method(
+            Some(AVAHI_NAME),
+            group.as_str(),
+            Some(ENTRY_GROUP_IFACE),
+            "AddService",
+            // Empty domain and host: .local and this machine's name
+            &(IF_UNSPEC, PROTO_UNSPEC, 0u32, name, SERVICE_TYPE, "", "", port, txt),
+        )
+        .map_err(|e| format!("Avahi AddService failed: {}", e))?;
+        conn.call_method(Some(AVAHI_NAME), group.as_str(), Some(ENTRY_GROUP_IFACE), "Commit", &())
+            .map_err(|e| format!("Avahi Commit failed: {}", e))?;
+
+        Ok(Handle { _conn: conn })
+    }
+}
+
+/// Bonjour's dns-sd, which publishes for as long as it runs
+#[cfg(target_os = "macos")]
+mod platform {
+    use super::SERVICE_TYPE;
+    use std::process::{Child, Command, Stdio};
+
+    pub struct Handle(Child);
+
+    impl Drop for Handle {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+
+    pub fn publish(name: &str, port: u16, txt: &[String]) -> Result<Handle, String> {
+        Command::new("dns-sd")
+            .args(["-R", name, SERVICE_TYPE, "local", &port.to_string()])
+            .args(txt)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map(Handle)
+            .map_err(|e| format!("Cannot run dns-sd: {}", e))
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    pub struct Handle;
+
+    pub fn publish(_name: &str, _port: u16, _txt: &[String]) -> Result<Handle, String> {
+        Err("not available on Windows".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_txt_records() {
+        let txt = txt_records("desk", Some("AB12 CD34 EF56 7890"));
+        assert_eq!(txt[0], "id=desk");
+        assert!(txt.contains(&"fp=AB12 CD34 EF56 7890".to_string()));
+        assert!(!txt_records("desk", None).iter().any(|entry| entry.starts_with("fp=")));
+    }
+}
@@ -0,0 +1,248 @@
+/// Run `on_change` from a background thread whenever the default route
+/// changes: a Wi-Fi switch, a cable plugged in, a VPN coming up or down
+///
+/// A connection through the old route may hang silently until pings time
+/// out; this lets the caller reconnect right away instead.
+pub fn watch(on_change: impl Fn() + Send + 'static) -> Result<(), String> {
+    #[cfg(target_os = "linux")]
+    return linux::watch(on_change);
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = on_change;
+        Err("route monitoring is only available on Linux".to_string())
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::collections::HashSet;
+    use std::io;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::time::Duration;
+
+    const NLMSG_HDRLEN: usize = 16;
+    /// struct rtmsg
+    const RTMSG_LEN: usize = 12;
+    const RTA_HDRLEN: usize = 4;
+    const RTA_OIF: u16 = 4;
+    const RTA_GATEWAY: u16 = 5;
+    const RTA_TABLE: u16 = 15;
+
+    /// Route changes come in bursts (old route removed, new one added, IPv4
+    /// and IPv6 separately); wait for them to settle before reporting once
+    const SETTLE: Duration = Duration::from_millis(500);
+
+    /// A default route, identified by what makes it a different path
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    pub struct DefaultRoute {
+        family: u8,
+        table: u32,
+        gateway: Vec<u8>,
+        interface: u32,
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub enum Event {
+        Added(DefaultRoute),
+        Removed(DefaultRoute),
+        /// End of the initial dump
+        Done,
+    }
+
+    /// Default-route events in a netlink datagram; other routes are skipped
+    pub fn parse(buf: &[u8]) -> Vec<Event> {
+        let mut events = Vec::new();
+        let mut rest = buf;
+
+        while rest.len() >= NLMSG_HDRLEN {
+            let len = u32::from_ne_bytes(rest[0..4].try_into().unwrap()) as usize;
+            let kind = u16::from_ne_bytes(rest[4..6].try_into().unwrap());
+            if len < NLMSG_HDRLEN || len > rest.len() {
+                break;
+            }
+            let body = &rest[NLMSG_HDRLEN..len];
+            rest = &rest[align(len).min(rest.len())..];
+
+            match kind {
+                k if k == libc::NLMSG_DONE as u16 => events.push(Event::Done),
+                libc::RTM_NEWROUTE | libc::RTM_DELROUTE => {
+                    let Some(route) = default_route(body) else {
+                        continue;
+                    };
+                    events.push(if kind == libc::RTM_NEWROUTE {
+                        Event::Added(route)
+                    } else {
+                        Event::Removed(route)
+                    });
+                }
+                _ => {}
+            }
+        }
+        events
+    }
+
+    fn align(len: usize) -> usize {
+        (len + 3) & !3
+    }
+
+    /// The route in an rtmsg body, if it's a default route (prefix length 0)
+    fn default_route(body: &[u8]) -> Option<DefaultRoute> {
+        if body.len() < RTMSG_LEN || body[1] != 0 {
+            return None;
+        }
+        let mut route = DefaultRoute {
+            family: body[0],
+            table: body[4] as u32,
+            gateway: Vec::new(),
+            interface: 0,
+        };
+
+        let mut attrs = &body[RTMSG_LEN..];
+        while attrs.len() >= RTA_HDRLEN {
+            let len = u16::from_ne_bytes([attrs[0], attrs[1]]) as usize;
+            let kind = u16::from_ne_bytes([attrs[2], attrs[3]]);
+            if len < RTA_HDRLEN || len > attrs.len() {
+                break;
+            }
+            let value = &attrs[RTA_HDRLEN..len];
+            match kind {
+                RTA_GATEWAY => route.gateway = value.to_vec(),
+                RTA_OIF if value.len() == 4 => route.interface = u32::from_ne_bytes(value.try_into().unwrap()),
+                // Tables above 255 only fit here
+                RTA_TABLE if value.len() == 4 => route.table = u32::from_ne_bytes(value.try_into().unwrap()),
+                _ => {}
+            }
+            attrs = &attrs[align(len).min(attrs.len())..];
+        }
+        Some(route)
+    }
+
+    fn last_error(what: &str) -> String {
+        format!("{}: {}", what, io::Error::last_os_error())
+    }
+
+    pub fn watch(on_change: impl Fn() + Send + 'static) -> Result<(), String> {
+        // SAFETY: plain socket(2); the descriptor is owned from here on
+        let fd = unsafe { libc::socket(libc::AF_NETLINK, libc::SOCK_RAW | libc::SOCK_CLOEXEC, libc::NETLINK_ROUTE) };
+        if fd < 0 {
+            return Err(last_error("netlink socket"));
+        }
+        let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        // SAFETY: sockaddr_nl is plain data, all zeroes is a valid value
+        let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        addr.nl_groups = (libc::RTMGRP_IPV4_ROUTE | libc::RTMGRP_IPV6_ROUTE) as u32;
+        let bound = unsafe {
+            libc::bind(
+                socket.as_raw_fd(),
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if bound < 0 {
+            return Err(last_error("netlink bind"));
+        }
+
+        // Ask for the current routes, so refreshes of a route we already
+        // know (router advertisements) aren't taken for a change
+        const REQUEST_LEN: usize = NLMSG_HDRLEN + RTMSG_LEN;
+        let mut request = [0u8; REQUEST_LEN];
+        request[0..4].copy_from_slice(&(REQUEST_LEN as u32).to_ne_bytes());
+        request[4..6].copy_from_slice(&libc::RTM_GETROUTE.to_ne_bytes());
+        request[6..8].copy_from_slice(&((libc::NLM_F_REQUEST | libc::NLM_F_DUMP) as u16).to_ne_bytes());
+        request[NLMSG_HDRLEN] = libc::AF_UNSPEC as u8;
+        let sent = unsafe { libc::send(socket.as_raw_fd(), request.as_ptr().cast(), request.len(), 0) };
+        if sent < 0 {
+            return Err(last_error("netlink route dump"));
+        }
+
+        std::thread::spawn(move || {
+            let mut routes: HashSet<DefaultRoute> = HashSet::new();
+            let mut dumped = false;
+            let mut buf = vec![0u8; 32 * 1024];
+
+            loop {
+                let mut changed = false;
+                let mut flags = 0;
+                loop {
+                    let n = unsafe { libc::recv(socket.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len(), flags) };
+                    if n < 0 {
+                        match io::Error::last_os_error().raw_os_error() {
+                            Some(libc::EINTR) => continue,
+                            // Burst drained
+                            Some(libc::EAGAIN) => break,
+                            // Buffer overrun: changes were lost, so assume one happened
+                            Some(libc::ENOBUFS) => changed |= dumped,
+                            _ => return,
+                        }
+                    } else {
+                        for event in parse(&buf[..n as usize]) {
+                            changed |= match event {
+                                Event::Added(route) => routes.insert(route) && dumped,
+                                Event::Removed(route) => routes.remove(&route) && dumped,
+                                Event::Done => {
+                                    dumped = true;
+                                    false
+                                }
+                            };
+                        }
+                    }
+
+                    if changed && flags == 0 {
+                        std::thread::sleep(SETTLE);
+                        flags = libc::MSG_DONTWAIT;
+                    }
+                }
+
+                if changed {
+                    on_change();
+                }
+            }
+        });
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn route_message(kind: u16, dst_len: u8, gateway: [u8; 4]) -> Vec<u8> {
+            let mut attr = Vec::new();
+            attr.extend_from_slice(&8u16.to_ne_bytes());
+            attr.extend_from_slice(&RTA_GATEWAY.to_ne_bytes());
+            attr.extend_from_slice(&gateway);
+
+            let mut rtmsg = vec![libc::AF_INET as u8, dst_len, 0, 0, 254, 0, 0, 1, 0, 0, 0, 0];
+            rtmsg.extend(attr);
+
+            let mut message = Vec::new();
+            message.extend_from_slice(&((NLMSG_HDRLEN + rtmsg.len()) as u32).to_ne_bytes());
+            message.extend_from_slice(&kind.to_ne_bytes());
+            message.extend_from_slice(&[0; 10]);
+            message.extend(rtmsg);
+            message
+        }
+
+        #[test]
+        fn test_parse_default_routes() {
+            let mut datagram = route_message(libc::RTM_NEWROUTE, 0, [192, 168, 1, 1]);
+            // A subnet route is not a change of path
+            datagram.extend(route_message(libc::RTM_NEWROUTE, 24, [0, 0, 0, 0]));
+            datagram.extend(route_message(libc::RTM_DELROUTE, 0, [10, 0, 0, 1]));
+
+            let events = parse(&datagram);
+            assert_eq!(events.len(), 2);
+            let Event::Added(ref route) = events[0] else {
+                panic!("expected an added route, got {:?}", events[0]);
+            };
+            assert_eq!(route.gateway, vec![192, 168, 1, 1]);
+            assert_eq!(route.table, 254);
+            assert!(matches!(events[1], Event::Removed(ref route) if route.gateway == vec![10, 0, 0, 1]));
+
+            // Truncated input stops parsing instead of panicking
+            assert!(parse(&datagram[..20]).is_empty());
+        }
+    }
+}
@@ -0,0 +1,515 @@
+//! Spoken-number and date normalization
+//!
+//! Speech recognizers often return numbers as words ("twenty four",
+//! "vierundzwanzig"). This rewrites runs of number words as numerals and
+//! formats spoken dates the way each locale writes them, using the
+//! message's language tag to pick a locale pack.
+
+/// Word tables for one language
+struct LocalePack {
+    /// Words with a plain additive value (units, teens, tens)
+    units: &'static [(&'static str, u64)],
+    /// Multipliers ("hundred", "thousand", ...)
+    scales: &'static [(&'static str, u64)],
+    /// Filler words allowed between number words ("and", "y", "et")
+    connectors: &'static [&'static str],
+    /// Ordinal words used for days of the month
+    ordinals: &'static [(&'static str, u64)],
+    /// Lowercase month names, January first
+    months: &'static [&'static str],
+    /// Words linking a day to its month ("of", "de")
+    date_links: &'static [&'static str],
+    /// Render a day of the month next to a month name
+    format_day: fn(day: u64, spoken_ordinal: bool) -> String,
+}
+
+const EN: LocalePack = LocalePack {
+    units: &[
+        ("zero", 0), ("one", 1), ("two", 2), ("three", 3), ("four", 4), ("five", 5),
+        ("six", 6), ("seven", 7), ("eight", 8), ("nine", 9), ("ten", 10),
+        ("eleven", 11), ("twelve", 12), ("thirteen", 13), ("fourteen", 14),
+        ("fifteen", 15), ("sixteen", 16), ("seventeen", 17), ("eighteen", 18),
+        ("nineteen", 19), ("twenty", 20), ("thirty", 30), ("forty", 40),
+        ("fifty", 50), ("sixty", 60), ("seventy", 70), ("eighty", 80), ("ninety", 90),
+    ],
+    scales: &[("hundred", 100), ("thousand", 1_000), ("million", 1_000_000)],
+    connectors: &["and"],
+    ordinals: &[
+        ("first", 1), ("second", 2), ("third", 3), ("fourth", 4), ("fifth", 5),
+        ("sixth", 6), ("seventh", 7), ("eighth", 8), ("ninth", 9), ("tenth", 10),
+        ("eleventh", 11), ("twelfth", 12), ("thirteenth", 13), ("fourteenth", 14),
+        ("fifteenth", 15), ("sixteenth", 16), ("seventeenth", 17), ("eighteenth", 18),
+        ("nineteenth", 19), ("twentieth", 20), ("thirtieth", 30),
+    ],
+    months: &[
+        "january", "february", "march", "april", "may", "june", "july",
+        "august", "september", "october", "november", "december",
+    ],
+    date_links: &["of"],
+    format_day: |day, spoken_ordinal| {
+        if !spoken_ordinal {
+            return day.to_string();
+        }
+        let suffix = match (day % 10, day % 100) {
+            (_, 11..=13) => "th",
+            (1, _) => "st",
+            (2, _) => "nd",
+            (3, _) => "rd",
+            _ => "th",
+        };
+        format!("{}{}", day, suffix)
+    },
+};
+
+// German numerals are written as one compound word; `parse_de` handles those
+const DE: LocalePack = LocalePack {
+    units: &[],
+    scales: &[("hundert", 100), ("tausend", 1_000), ("million", 1_000_000), ("millionen", 1_000_000)],
+    connectors: &[],
+    ordinals: &[],
+    months: &[
+        "januar", "februar", "märz", "april", "mai", "juni", "juli",
+        "august", "september", "oktober", "november", "dezember",
+    ],
+    date_links: &["im"],
+    format_day: |day, _| format!("{}.", day),
+};
+
+const FR: LocalePack = LocalePack {
+    units: &[
+        ("zéro", 0), ("un", 1), ("une", 1), ("deux", 2), ("trois", 3), ("quatre", 4),
+        ("cinq", 5), ("six", 6), ("sept", 7), ("huit", 8), ("neuf", 9), ("dix", 10),
+        ("onze", 11), ("douze", 12), ("treize", 13), ("quatorze", 14), ("quinze", 15),
+        ("seize", 16), ("vingt", 20), ("trente", 30), ("quarante", 40),
+        ("cinquante", 50), ("soixante", 60),
+        // Kept whole by `number_parts` so "quatre-vingt" isn't read as 4 + 20
+        ("quatre-vingt", 80), ("quatre-vingts", 80),
+    ],
+    scales: &[("cent", 100), ("cents", 100), ("mille", 1_000), ("million", 1_000_000), ("millions", 1_000_000)],
+    connectors: &["et"],
+    ordinals: &[("premier", 1), ("première", 1)],
+    months: &[
+        "janvier", "février", "mars", "avril", "mai", "juin", "juillet",
+        "août", "septembre", "octobre", "novembre", "décembre",
+    ],
+    date_links: &[],
+    format_day: |day, _| if day == 1 { "1er".to_string() } else { day.to_string() },
+};
+
+const ES: LocalePack = LocalePack {
+    units: &[
+        ("cero", 0), ("uno", 1), ("una", 1), ("un", 1), ("dos", 2), ("tres", 3),
+        ("cuatro", 4), ("cinco", 5), ("seis", 6), ("siete", 7), ("ocho", 8),
+        ("nueve", 9), ("diez", 10), ("once", 11), ("doce", 12), ("trece", 13),
+        ("catorce", 14), ("quince", 15), ("dieciséis", 16), ("diecisiete", 17),
+        ("dieciocho", 18), ("diecinueve", 19), ("veinte", 20), ("veintiuno", 21),
+        ("veintidós", 22), ("veintitrés", 23), ("veinticuatro", 24), ("veinticinco", 25),
+        ("veintiséis", 26), ("veintisiete", 27), ("veintiocho", 28), ("veintinueve", 29),
+        ("treinta", 30), ("cuarenta", 40), ("cincuenta", 50), ("sesenta", 60),
+        ("setenta", 70), ("ochenta", 80), ("noventa", 90),
+        // Hundreds are single words in Spanish
+        ("cien", 100), ("ciento", 100), ("doscientos", 200), ("trescientos", 300),
+        ("cuatrocientos", 400), ("quinientos", 500), ("seiscientos", 600),
+        ("setecientos", 700), ("ochocientos", 800), ("novecientos", 900),
+    ],
+    scales: &[("mil", 1_000), ("millón", 1_000_000), ("millones", 1_000_000)],
+    connectors: &["y"],
+    ordinals: &[("primero", 1)],
+    months: &[
+        "enero", "febrero", "marzo", "abril", "mayo", "junio", "julio",
+        "agosto", "septiembre", "octubre", "noviembre", "diciembre",
+    ],
+    date_links: &["de"],
+    format_day: |day, _| day.to_string(),
+};
+
+// Hindi 21-99 are irregular words; only the regular forms are covered here
+const HI: LocalePack = LocalePack {
+    units: &[
+        ("शून्य", 0), ("एक", 1), ("दो", 2), ("तीन", 3), ("चार", 4), ("पांच", 5),
+        ("पाँच", 5), ("छह", 6), ("सात", 7), ("आठ", 8), ("नौ", 9), ("दस", 10),
+        ("ग्यारह", 11), ("बारह", 12), ("तेरह", 13), ("चौदह", 14), ("पंद्रह", 15),
+        ("सोलह", 16), ("सत्रह", 17), ("अठारह", 18), ("उन्नीस", 19), ("बीस", 20),
+        ("तीस", 30), ("चालीस", 40), ("पचास", 50), ("साठ", 60), ("सत्तर", 70),
+        ("अस्सी", 80), ("नब्बे", 90),
+    ],
+    scales: &[("सौ", 100), ("हज़ार", 1_000), ("हजार", 1_000), ("लाख", 100_000), ("करोड़", 10_000_000)],
+    connectors: &[],
+    ordinals: &[],
+    months: &[
+        "जनवरी", "फ़रवरी", "मार्च", "अप्रैल", "मई", "जून", "जुलाई",
+        "अगस्त", "सितंबर", "अक्टूबर", "नवंबर", "दिसंबर",
+    ],
+    date_links: &[],
+    format_day: |day, _| day.to_string(),
+};
+
+/// Pick a locale pack from a BCP 47 tag such as "de-DE" (English by default)
+fn pack_for(language: Option<&str>) -> (&'static str, &'static LocalePack) {
+    let primary = language
+        .and_then(|tag| tag.split(['-', '_']).next())
+        .map(|l| l.to_lowercase());
+
+    match primary.as_deref() {
+        Some("de") => ("de", &DE),
+        Some("fr") => ("fr", &FR),
+        Some("es") => ("es", &ES),
+        Some("hi") => ("hi", &HI),
+        _ => ("en", &EN),
+    }
+}
+
+/// Parse a German compound numeral ("dreihundertvierundzwanzig")
+fn parse_de(word: &str) -> Option<u64> {
+    if word.is_empty() {
+        return None;
+    }
+
+    for (scale, value) in [("tausend", 1_000), ("hundert", 100)] {
+        if let Some(idx) = word.find(scale) {
+            let (left, right) = (&word[..idx], &word[idx + scale.len()..]);
+            let multiplier = if left.is_empty() { 1 } else { parse_de(left)? };
+            let rest = if right.is_empty() { 0 } else { parse_de(right)? };
+            return Some(multiplier * value + rest);
+        }
+    }
+
+    // "vierundzwanzig" = 4 + 20
+    if let Some((unit, tens)) = word.split_once("und") {
+        if !unit.is_empty() && !tens.is_empty() {
+            return Some(parse_de(unit)? + parse_de(tens)?);
+        }
+    }
+
+    let value = match word {
+        "null" => 0,
+        "eins" | "ein" | "eine" | "einen" | "einem" | "einer" => 1,
+        "zwei" => 2, "drei" => 3, "vier" => 4, "fünf" => 5, "sechs" => 6,
+        "sieben" => 7, "acht" => 8, "neun" => 9, "zehn" => 10, "elf" => 11,
+        "zwölf" => 12, "sechzehn" => 16, "siebzehn" => 17,
+        "zwanzig" => 20, "dreißig" => 30, "sechzig" => 60, "siebzig" => 70,
+        _ => {
+            // Regular teens and tens: dreizehn, vierzig, ...
+            if let Some(stem) = word.strip_suffix("zehn") {
+                return parse_de(stem).filter(|v| (3..=9).contains(v)).map(|v| v + 10);
+            }
+            if let Some(stem) = word.strip_suffix("zig") {
+                return parse_de(stem).filter(|v| (4..=9).contains(v)).map(|v| v * 10);
+            }
+            return None;
+        }
+    };
+    Some(value)
+}
+
+/// Parse a German ordinal ("dritter", "einundzwanzigsten")
+fn parse_de_ordinal(word: &str) -> Option<u64> {
+    let stem = word.trim_end_matches(['r', 'n', 's', 'm']);
+    if let Some(value) = stem.strip_suffix("ste").and_then(parse_de).filter(|v| *v >= 20) {
+        return Some(value);
+    }
+    // "sechste" is sechs + te, not sech + ste
+    let stem = stem.strip_suffix("te")?;
+    match stem {
+        "ers" => Some(1),
+        "drit" => Some(3),
+        "sieb" => Some(7),
+        "ach" => Some(8),
+        _ => parse_de(stem).filter(|v| *v < 20),
+    }
+}
+
+/// What a single (lowercased, punctuation-free) word means numerically
+#[derive(Clone, Copy, PartialEq)]
+enum NumberWord {
+    Unit(u64),
+    Scale(u64),
+    Connector,
+    Ordinal(u64),
+}
+
+fn classify(lang: &str, pack: &LocalePack, word: &str) -> Option<NumberWord> {
+    if let Some((_, v)) = pack.units.iter().find(|(w, _)| *w == word) {
+        return Some(NumberWord::Unit(*v));
+    }
+    if let Some((_, v)) = pack.scales.iter().find(|(w, _)| *w == word) {
+        return Some(NumberWord::Scale(*v));
+    }
+    if pack.connectors.contains(&word) {
+        return Some(NumberWord::Connector);
+    }
+    if let Some((_, v)) = pack.ordinals.iter().find(|(w, _)| *w == word) {
+        return Some(NumberWord::Ordinal(*v));
+    }
+    if lang == "de" {
+        if let Some(v) = parse_de(word) {
+            return Some(NumberWord::Unit(v));
+        }
+        if let Some(v) = parse_de_ordinal(word) {
+            return Some(NumberWord::Ordinal(v));
+        }
+    }
+    None
+}
+
+/// A word plus the text that surrounds it, so output keeps the original spacing
+struct Token<'a> {
+    /// Leading punctuation and the whitespace before the word
+    prefix: &'a str,
+    word: &'a str,
+    suffix: &'a str,
+}
+
+fn tokenize(text: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut start = 0;
+    for (idx, _) in text.match_indices(char::is_whitespace).chain([(text.len(), "")]) {
+        if idx < start {
+            continue;
+        }
+        let chunk = &text[start..idx];
+        let word_start = chunk.find(|c: char| c.is_alphanumeric()).unwrap_or(chunk.len());
+        let word_end = chunk.rfind(|c: char| c.is_alphanumeric())
+            .map(|i| i + chunk[i..].chars().next().map(char::len_utf8).unwrap_or(1))
+            .unwrap_or(word_start);
+        tokens.push(Token {
+            prefix: &chunk[..word_start],
+            word: &chunk[word_start..word_end],
+            suffix: &chunk[word_end..],
+        });
+        // Keep the whitespace character with the next token's prefix
+        start = idx;
+    }
+    tokens
+}
+
+/// Split a hyphenated word into number parts ("twenty-three", "dix-sept")
+///
+/// French "quatre-vingt" stays joined since it multiplies rather than adds.
+fn number_parts(lang: &str, pack: &LocalePack, word: &str) -> Option<Vec<NumberWord>> {
+    let lower = word.to_lowercase();
+    if let Some(n) = classify(lang, pack, &lower) {
+        return Some(vec![n]);
+    }
+    if !lower.contains('-') {
+        return None;
+    }
+
+    let mut parts = Vec::new();
+    let mut rest = lower.as_str();
+    while !rest.is_empty() {
+        if lang == "fr" && rest.starts_with("quatre-vingt") {
+            let len = if rest.starts_with("quatre-vingts") { 13 } else { 12 };
+            parts.push(NumberWord::Unit(80));
+            rest = rest[len..].trim_start_matches('-');
+            continue;
+        }
+        let (part, tail) = rest.split_once('-').unwrap_or((rest, ""));
+        parts.push(classify(lang, pack, part)?);
+        rest = tail;
+    }
+    Some(parts)
+}
+
+/// Largest value that may directly follow `prev` in the same numeral
+///
+/// "twenty" takes a unit (twenty three), "hundred" takes anything below
+/// 100, and French 60/80 take teens (soixante-dix, quatre-vingt-dix).
+fn place(lang: &str, prev: u64) -> u64 {
+    match prev {
+        10 => 10,
+        p if p >= 100 && p % 100 == 0 => 100,
+        60 | 80 if lang == "fr" => 20,
+        p if p >= 20 && p % 10 == 0 => 10,
+        _ => 0,
+    }
+}
+
+/// Whether a run of number words forms a single numeral
+///
+/// Keeps lists like "one and two" or "three four" from being summed.
+fn valid_sequence(lang: &str, seq: &[NumberWord]) -> bool {
+    let mut prev: Option<NumberWord> = None;
+    let mut after_connector = false;
+    for &word in seq {
+        if word == NumberWord::Connector {
+            let allowed = match prev {
+                Some(NumberWord::Scale(_)) => true,
+                Some(NumberWord::Unit(p)) => p >= 20 && p % 10 == 0,
+                _ => false,
+            };
+            if !allowed || after_connector {
+                return false;
+            }
+            after_connector = true;
+            continue;
+        }
+
+        let follows = match (prev, word) {
+            (None, _) => true,
+            (Some(NumberWord::Scale(p)), NumberWord::Scale(s)) => p < s,
+            (Some(NumberWord::Unit(_)), NumberWord::Scale(_)) => true,
+            (Some(NumberWord::Scale(p)), NumberWord::Unit(v) | NumberWord::Ordinal(v)) => v < p,
+            (Some(NumberWord::Unit(p)), NumberWord::Unit(v) | NumberWord::Ordinal(v)) => v < place(lang, p),
+            _ => false,
+        };
+        if !follows {
+            return false;
+        }
+        prev = Some(word);
+        after_connector = false;
+    }
+    true
+}
+
+/// Fold a run of number words into a value, and whether it was spoken as an ordinal
+fn evaluate(parts: &[NumberWord]) -> (u64, bool) {
+    let mut total = 0;
+    let mut current = 0;
+    let mut ordinal = false;
+    for part in parts {
+        match *part {
+            NumberWord::Unit(v) => current += v,
+            NumberWord::Ordinal(v) => {
+                current += v;
+                ordinal = true;
+            }
+            NumberWord::Scale(100) => current = current.max(1) * 100,
+            NumberWord::Scale(v) => {
+                total += current.max(1) * v;
+                current = 0;
+            }
+            NumberWord::Connector => {}
+        }
+    }
+    (total + current, ordinal)
+}
+
+/// Rewrite spoken numbers and dates in `text` using the locale for `language`
+pub fn normalize(text: &str, language: Option<&str>) -> String {
+    let (lang, pack) = pack_for(language);
+    let tokens = tokenize(text);
+    let month_at = |i: usize| {
+        tokens
+            .get(i)
+            .map(|t| pack.months.contains(&t.word.to_lowercase().as_str()))
+            .unwrap_or(false)
+    };
+    let capitalized_at = |i: usize| tokens.get(i).and_then(|t| t.word.chars().next()).is_some_and(char::is_uppercase);
+    let year_at = |i: usize| tokens.get(i).is_some_and(|t| t.word.len() == 4 && t.word.bytes().all(|b| b.is_ascii_digit()));
+
+    let mut out = String::with_capacity(text.len());
+    let mut i = 0;
+    while i < tokens.len() {
+        // Collect the longest run of number words starting here
+        let mut parts = Vec::new();
+        let mut end = i;
+        while end < tokens.len() {
+            // Punctuation inside a run ("three, four") ends it
+            if end > i && !tokens[end - 1].suffix.is_empty() {
+                break;
+            }
+            let Some(next) = number_parts(lang, pack, tokens[end].word) else {
+                break;
+            };
+            let mut candidate = parts.clone();
+            candidate.extend(next);
+            if !valid_sequence(lang, &candidate) {
+                break;
+            }
+            parts = candidate;
+            end += 1;
+        }
+        // A trailing connector belongs to the sentence, not the number
+        while end > i && parts.last() == Some(&NumberWord::Connector) {
+            parts.pop();
+            end -= 1;
+        }
+
+        let starts_with_number = matches!(parts.first(), Some(NumberWord::Unit(_) | NumberWord::Ordinal(_)));
+        if end == i || !starts_with_number {
+            let token = &tokens[i];
+            out.push_str(token.prefix);
+            out.push_str(token.word);
+            out.push_str(token.suffix);
+            i += 1;
+            continue;
+        }
+
+        let (value, ordinal) = evaluate(&parts);
+        let link = tokens
+            .get(end)
+            .map(|t| pack.date_links.contains(&t.word.to_lowercase().as_str()))
+            .unwrap_or(false);
+        // "may" and "march" are verbs too: a month before the number only
+        // makes a date when it's capitalized or a link or year follows
+        let after_month = i > 0 && month_at(i - 1) && (capitalized_at(i - 1) || link || year_at(end));
+        let next_to_month = month_at(end) || (link && month_at(end + 1)) || after_month;
+        let is_day = next_to_month && (1..=31).contains(&value);
+
+        // Small cardinals read better as words ("one of them"); dates and
+        // longer runs become numerals
+        let single_small = end - i == 1 && value < 10 && !ordinal;
+        let rendered = if is_day {
+            Some((pack.format_day)(value, ordinal))
+        } else if ordinal || single_small {
+            None
+        } else {
+            Some(value.to_string())
+        };
+
+        match rendered {
+            Some(numeral) => {
+                out.push_str(tokens[i].prefix);
+                out.push_str(&numeral);
+                out.push_str(tokens[end - 1].suffix);
+            }
+            None => {
+                for token in &tokens[i..end] {
+                    out.push_str(token.prefix);
+                    out.push_str(token.word);
+                    out.push_str(token.suffix);
+                }
+            }
+        }
+        i = end;
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_english_numbers_and_dates() {
+        assert_eq!(normalize("I need twenty three apples", None), "I need 23 apples");
+        assert_eq!(normalize("one of them", Some("en-US")), "one of them");
+        assert_eq!(normalize("two thousand twenty four", None), "2024");
+        assert_eq!(normalize("March twenty-first, please", None), "March 21st, please");
+        assert_eq!(normalize("the third of may", None), "the 3rd of may");
+        assert_eq!(normalize("it may one day", None), "it may one day");
+        assert_eq!(normalize("we march two miles", None), "we march two miles");
+        assert_eq!(normalize("may second 2025", None), "may 2nd 2025");
+        assert_eq!(normalize("bread and butter", None), "bread and butter");
+        assert_eq!(normalize("one and two or three four", None), "one and two or three four");
+        assert_eq!(normalize("one hundred and five", None), "105");
+    }
+
+    #[test]
+    fn test_german() {
+        assert_eq!(normalize("Ich habe vierundzwanzig Äpfel", Some("de-DE")), "Ich habe 24 Äpfel");
+        assert_eq!(normalize("am dritten März", Some("de")), "am 3. März");
+        assert_eq!(normalize("dreihundertzwölf", Some("de")), "312");
+    }
+
+    #[test]
+    fn test_french_spanish_hindi() {
+        assert_eq!(normalize("quatre-vingt-dix-sept euros", Some("fr-FR")), "97 euros");
+        assert_eq!(normalize("le premier mai", Some("fr")), "le 1er mai");
+        assert_eq!(normalize("treinta y dos personas", Some("es-MX")), "32 personas");
+        assert_eq!(normalize("tres de marzo", Some("es")), "3 de marzo");
+        assert_eq!(normalize("दो सौ लोग", Some("hi-IN")), "200 लोग");
+    }
+}
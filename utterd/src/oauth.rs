@@ -1,16 +1,33 @@
+use aes_gcm::aead::{Aead, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::Engine as _;
 use chrono::{DateTime, Utc};
+use rand::RngCore;
+use crate::crypto::keys::secret;
+use secrecy::{ExposeSecret, SecretBox};
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::PathBuf;
-use std::sync::mpsc;
 use std::thread;
-use tiny_http::{Response, Server};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
 
 const AUTH_URL: &str = "https://accounts.google.com/o/oauth2/v2/auth";
 const TOKEN_URL: &str = "https://oauth2.googleapis.com/token";
 const REDIRECT_URI: &str = "http://localhost:3000/oauth/callback";
 const SCOPES: &str = "openid email profile";
 
+/// How long the browser sign-in waits for Google to redirect back
+const SIGN_IN_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Longest loopback request head read, so a stray client can't make it grow
+const MAX_REQUEST_HEAD: usize = 8 * 1024;
+
+/// Start of an encrypted token file, followed by the AES-GCM nonce and the
+/// encrypted JSON
+const ENCRYPTED: &[u8] = b"utterd-oauth-v1\n";
+
 // OAuth credentials for Utter desktop application
 // NOTE: It is safe and normal to embed these in native/desktop applications.
 // Google's OAuth security model for native apps does not rely on keeping CLIENT_SECRET
@@ -34,6 +51,30 @@ pub struct OAuthTokens {
     pub expires_at: DateTime<Utc>,
 }
 
+/// What utterd reads from the ID token: who signed in
+#[derive(Debug, Deserialize)]
+struct IdTokenClaims {
+    email: Option<String>,
+    name: Option<String>,
+    sub: Option<String>,
+    nonce: Option<String>,
+}
+
+impl OAuthTokens {
+    /// The signed-in Google account (its email, else name or id), from the
+    /// ID token's claims; the relay checks the signature, this only displays
+    pub fn account(&self) -> Option<String> {
+        let claims = self.claims()?;
+        claims.email.or(claims.name).or(claims.sub)
+    }
+
+    fn claims(&self) -> Option<IdTokenClaims> {
+        let payload = self.id_token.split('.').nth(1)?;
+        let json = base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(payload.trim_end_matches('=')).ok()?;
+        serde_json::from_slice(&json).ok()
+    }
+}
+
 #[derive(Debug, Deserialize)]
 struct TokenResponse {
     id_token: String,
@@ -49,29 +90,74 @@ struct RefreshTokenResponse {
     expires_in: i64,
 }
 
+/// Signs in with Google and caches the tokens in oauth.json
+///
+/// The file is encrypted with a key derived from the device keypair, so the
+/// tokens are as well protected as the key (keyring, passphrase or TPM).
+/// Each `--profile` signs in separately, and can keep further named
+/// accounts (`login --account`) in oauth-<account>.json, one of them
+/// selected for the daemon.
 pub struct OAuthManager {
     token_path: PathBuf,
+    /// None keeps the file in plaintext, when there's no keypair to derive from
+    cache_key: Option<SecretBox<[u8; 32]>>,
 }
 
 impl OAuthManager {
-    pub fn new() -> Result<Self, String> {
-        let config_dir = dirs::config_dir()
-            .ok_or("Cannot determine config directory")?
-            .join("utterd");
+    /// The sign-in of `account`, or the profile's default one
+    pub fn new(profile: Option<&str>, account: Option<&str>, cache_key: Option<SecretBox<[u8; 32]>>) -> Result<Self, String> {
+        let config_dir = crate::config::identity_dir(profile)
+            .ok_or("Cannot determine config directory")?;
 
         if !config_dir.exists() {
             fs::create_dir_all(&config_dir)
                 .map_err(|e| format!("Failed to create config directory: {}", e))?;
         }
 
-        let token_path = config_dir.join("oauth.json");
+        let token_path = config_dir.join(match account {
+            Some(account) => format!("oauth-{}.json", account),
+            None => "oauth.json".to_string(),
+        });
 
         Ok(Self {
             token_path,
+            cache_key,
         })
     }
 
-    pub fn get_or_authenticate(&self) -> Result<OAuthTokens, String> {
+    /// The sign-in of the account the profile last logged in with
+    pub fn selected(profile: Option<&str>, cache_key: Option<SecretBox<[u8; 32]>>) -> Result<Self, String> {
+        Self::new(profile, selected_account(profile).as_deref(), cache_key)
+    }
+
+    /// Encrypt the cached tokens of every account with `cache_key` from now
+    /// on, e.g. after the keypair it's derived from was replaced
+    pub fn reencrypt(&self, cache_key: SecretBox<[u8; 32]>) -> Result<(), String> {
+        let Some(dir) = self.token_path.parent() else {
+            return Ok(());
+        };
+        let entries = fs::read_dir(dir).map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?;
+        let token_files = entries.filter_map(Result::ok).map(|entry| entry.path()).filter(|path| {
+            let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+            name == "oauth.json" || (name.starts_with("oauth-") && name.ends_with(".json"))
+        });
+
+        for token_path in token_files {
+            let key = |key: &SecretBox<[u8; 32]>| secret(key.expose_secret());
+            let cached = Self { token_path: token_path.clone(), cache_key: self.cache_key.as_ref().map(key) };
+            let tokens = cached.load_tokens()?;
+            let rekeyed = Self { token_path, cache_key: Some(key(&cache_key)) };
+            rekeyed.save_tokens(&tokens)?;
+        }
+        Ok(())
+    }
+
+    /// Cached tokens, refreshed if they're about to expire, or a new sign-in
+    /// in the browser; `progress` shows what's happening (e.g. in the TUI)
+    ///
+    /// Everything here is async, so a refresh or a pending browser sign-in
+    /// doesn't hold up the rest of the runtime.
+    pub async fn get_or_authenticate(&self, progress: impl Fn(&str) + Sync) -> Result<OAuthTokens, String> {
         // Try to load existing tokens
         if self.token_path.exists() {
             match self.load_tokens() {
@@ -83,126 +169,66 @@ impl OAuthManager {
                         return Ok(tokens);
                     } else if let Some(ref refresh_token) = tokens.refresh_token {
                         // Try to refresh
-                        match self.refresh_token(refresh_token) {
+                        match self.refresh_token(refresh_token).await {
                             Ok(new_tokens) => {
                                 self.save_tokens(&new_tokens)?;
                                 return Ok(new_tokens);
                             }
                             Err(_) => {
-                                eprintln!("⚠ Token refresh failed. Re-authenticating...");
+                                progress("⚠ Token refresh failed. Re-authenticating...");
                             }
                         }
                     }
                 }
                 Err(_) => {
-                    eprintln!("⚠ Failed to load tokens. Re-authenticating...");
+                    progress("⚠ Failed to load tokens. Re-authenticating...");
                 }
             }
         }
 
         // Perform new OAuth flow
-        println!();
-        let tokens = self.browser_auth_flow()?;
+        let tokens = self.browser_auth_flow(&progress).await?;
         self.save_tokens(&tokens)?;
 
         Ok(tokens)
     }
 
-    fn browser_auth_flow(&self) -> Result<OAuthTokens, String> {
-        let (tx, rx) = mpsc::channel();
-
+    async fn browser_auth_flow(&self, progress: &(dyn Fn(&str) + Sync)) -> Result<OAuthTokens, String> {
         // Start local HTTP server
-        let server = Server::http("127.0.0.1:3000")
+        let listener = TcpListener::bind("127.0.0.1:3000")
+            .await
             .map_err(|e| format!("Failed to start local server: {}", e))?;
 
+        // Only a callback carrying `state` answers this request; `nonce`
+        // comes back in the ID token, tying it to this sign-in too
+        let (state, nonce) = (random_token(), random_token());
+
         // Generate authorization URL
         let auth_url = format!(
-            "{}?client_id={}&redirect_uri={}&response_type=code&scope={}&access_type=offline&prompt=consent",
+            "{}?client_id={}&redirect_uri={}&response_type=code&scope={}&access_type=offline&prompt=consent&state={}&nonce={}",
             AUTH_URL,
             urlencoding::encode(CLIENT_ID),
             urlencoding::encode(REDIRECT_URI),
-            urlencoding::encode(SCOPES)
+            urlencoding::encode(SCOPES),
+            state,
+            nonce
         );
 
-        println!("📱 Sign in with Google:");
-        println!();
-        println!("   Visit: \x1b[36m{}\x1b[0m", auth_url);
-        println!();
-        println!("Waiting for authorization...");
-        println!();
-
-        // Handle callback in separate thread
-        thread::spawn(move || {
-            for request in server.incoming_requests() {
-                let url = request.url().to_string();
-
-                if url.starts_with("/oauth/callback") {
-                    // Parse query string
-                    if let Some(query) = url.split('?').nth(1) {
-                        let params: Vec<(&str, &str)> = query
-                            .split('&')
-                            .filter_map(|pair| {
-                                let parts: Vec<&str> = pair.split('=').collect();
-                                if parts.len() == 2 {
-                                    Some((parts[0], parts[1]))
-                                } else {
-                                    None
-                                }
-                            })
-                            .collect();
-
-                        let code = params.iter().find(|(k, _)| *k == "code").map(|(_, v)| *v);
-
-                        if let Some(code_encoded) = code {
-                            // URL-decode the authorization code
-                            let code = match urlencoding::decode(code_encoded) {
-                                Ok(decoded) => decoded.to_string(),
-                                Err(_) => {
-                                    let html = "<h1>Error: Failed to decode authorization code</h1>";
-                                    let response = Response::from_string(html)
-                                        .with_header(tiny_http::Header::from_bytes(&b"Content-Type"[..], &b"text/html"[..]).unwrap());
-                                    let _ = request.respond(response);
-                                    let _ = tx.send(Err("Failed to decode authorization code".to_string()));
-                                    break;
-                                }
-                            };
-
-                            // Send success response to browser
-                            let html = r#"
-                                <html>
-                                    <body style="font-family: sans-serif; text-align: center; padding: 50px;">
-                                        <h1>✓ Authentication Successful!</h1>
-                                        <p>You can close this window and return to the terminal.</p>
-                                    </body>
-                                </html>
-                            "#;
-                            let response = Response::from_string(html)
-                                .with_header(tiny_http::Header::from_bytes(&b"Content-Type"[..], &b"text/html"[..]).unwrap());
-                            let _ = request.respond(response);
-
-                            // Send code to main thread
-                            let _ = tx.send(Ok(code));
-                        } else {
-                            let html = "<h1>Error: No authorization code received</h1>";
-                            let response = Response::from_string(html)
-                                .with_header(tiny_http::Header::from_bytes(&b"Content-Type"[..], &b"text/html"[..]).unwrap());
-                            let _ = request.respond(response);
-
-                            let _ = tx.send(Err("No authorization code received".to_string()));
-                        }
-                        break;
-                    }
-                }
-            }
-        });
+        // The URL stays visible for when the browser is on another machine
+        if open_browser(&auth_url) {
+            progress(&format!("📱 Sign in with Google in the browser that opened, or visit {}", auth_url));
+        } else {
+            progress(&format!("📱 Sign in with Google: visit {}", auth_url));
+        }
 
         // Wait for callback with timeout
-        let code = rx
-            .recv_timeout(std::time::Duration::from_secs(300))
+        let code = tokio::time::timeout(SIGN_IN_TIMEOUT, wait_for_callback(&listener, &state))
+            .await
             .map_err(|_| "OAuth flow timed out".to_string())??;
+        drop(listener);
 
         // Exchange code for tokens
-        let client = reqwest::blocking::Client::new();
+        let client = reqwest::Client::new();
         let params = [
             ("client_id", CLIENT_ID),
             ("client_secret", CLIENT_SECRET),
@@ -215,22 +241,28 @@ impl OAuthManager {
             .post(TOKEN_URL)
             .form(&params)
             .send()
+            .await
             .map_err(|e| format!("Token exchange failed: {}", e))?
             .json::<TokenResponse>()
+            .await
             .map_err(|e| format!("Failed to parse token response: {}", e))?;
 
         let expires_at = Utc::now() + chrono::Duration::seconds(response.expires_in);
 
-        Ok(OAuthTokens {
+        let tokens = OAuthTokens {
             id_token: response.id_token,
             access_token: response.access_token,
             refresh_token: response.refresh_token,
             expires_at,
-        })
+        };
+        if tokens.claims().and_then(|claims| claims.nonce).as_deref() != Some(nonce.as_str()) {
+            return Err("ID token was not issued for this sign-in (nonce mismatch)".to_string());
+        }
+        Ok(tokens)
     }
 
-    fn refresh_token(&self, refresh_token: &str) -> Result<OAuthTokens, String> {
-        let client = reqwest::blocking::Client::new();
+    async fn refresh_token(&self, refresh_token: &str) -> Result<OAuthTokens, String> {
+        let client = reqwest::Client::new();
 
         let params = [
             ("client_id", CLIENT_ID),
@@ -243,8 +275,10 @@ impl OAuthManager {
             .post(TOKEN_URL)
             .form(&params)
             .send()
+            .await
             .map_err(|e| format!("Token refresh failed: {}", e))?
             .json::<RefreshTokenResponse>()
+            .await
             .map_err(|e| format!("Failed to parse refresh response: {}", e))?;
 
         let expires_at = Utc::now() + chrono::Duration::seconds(response.expires_in);
@@ -257,19 +291,54 @@ impl OAuthManager {
         })
     }
 
+    /// Read the cached tokens; a plaintext file from an earlier version is
+    /// encrypted on the way
     fn load_tokens(&self) -> Result<OAuthTokens, String> {
-        let json = fs::read_to_string(&self.token_path)
+        let contents = fs::read(&self.token_path)
             .map_err(|e| format!("Failed to read token file: {}", e))?;
 
-        serde_json::from_str(&json)
+        let Some(sealed) = contents.strip_prefix(ENCRYPTED) else {
+            let tokens = serde_json::from_slice(&contents)
+                .map_err(|e| format!("Failed to parse token file: {}", e))?;
+            if self.cache_key.is_some() {
+                self.save_tokens(&tokens)?;
+            }
+            return Ok(tokens);
+        };
+
+        let key = self.cache_key.as_ref().ok_or("Token file is encrypted, but there's no keypair to decrypt it")?;
+        if sealed.len() < 12 {
+            return Err("Token file is truncated".to_string());
+        }
+        let (nonce, ciphertext) = sealed.split_at(12);
+        let cipher = Aes256Gcm::new_from_slice(key.expose_secret()).map_err(|e| e.to_string())?;
+        #[allow(deprecated)]
+        let json = cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| "Cannot decrypt token file (keypair replaced?)".to_string())?;
+
+        serde_json::from_slice(&json)
             .map_err(|e| format!("Failed to parse token file: {}", e))
     }
 
     fn save_tokens(&self, tokens: &OAuthTokens) -> Result<(), String> {
-        let json = serde_json::to_string_pretty(tokens)
+        let json = serde_json::to_vec_pretty(tokens)
             .map_err(|e| format!("Failed to serialize tokens: {}", e))?;
 
-        fs::write(&self.token_path, json)
+        let contents = match self.cache_key {
+            Some(ref key) => {
+                let mut nonce = [0u8; 12];
+                OsRng.fill_bytes(&mut nonce);
+                let cipher = Aes256Gcm::new_from_slice(key.expose_secret()).map_err(|e| e.to_string())?;
+                let ciphertext = cipher
+                    .encrypt(&Nonce::from(nonce), json.as_ref())
+                    .map_err(|e| format!("Token encryption failed: {}", e))?;
+                [ENCRYPTED, &nonce, &ciphertext].concat()
+            }
+            None => json,
+        };
+
+        fs::write(&self.token_path, contents)
             .map_err(|e| format!("Failed to write token file: {}", e))?;
 
         // Set restrictive permissions on Unix
@@ -283,14 +352,254 @@ impl OAuthManager {
         Ok(())
     }
 
-    #[allow(dead_code)]
-    pub fn sign_out(&self) -> Result<(), String> {
-        if self.token_path.exists() {
-            fs::remove_file(&self.token_path)
-                .map_err(|e| format!("Failed to remove token file: {}", e))?;
-            println!("✓ Signed out");
+    /// Delete the cached tokens; false if there were none
+    pub fn sign_out(&self) -> Result<bool, String> {
+        if !self.token_path.exists() {
+            return Ok(false);
         }
+        fs::remove_file(&self.token_path)
+            .map_err(|e| format!("Failed to remove token file: {}", e))?;
+        Ok(true)
+    }
+}
 
-        Ok(())
+/// The account `login --account` last chose for this profile; None for the
+/// default sign-in
+pub fn selected_account(profile: Option<&str>) -> Option<String> {
+    let path = crate::config::identity_dir(profile)?.join("account");
+    let account = fs::read_to_string(path).ok()?;
+    crate::config::parse_profile(account.trim()).ok()
+}
+
+/// Make the daemon of this profile sign in as `account` (None: the default)
+pub fn select_account(profile: Option<&str>, account: Option<&str>) -> Result<(), String> {
+    let path = crate::config::identity_dir(profile).ok_or("Cannot determine config directory")?.join("account");
+    match account {
+        Some(account) => fs::write(&path, format!("{}\n", account)),
+        None if path.exists() => fs::remove_file(&path),
+        None => Ok(()),
+    }
+    .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+const STATE_MISMATCH: &str = "Sign-in response doesn't match this sign-in (state mismatch)";
+
+/// 32 random bytes, URL-safe base64, for the `state` and `nonce` parameters
+fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Answer loopback requests until the one carrying this sign-in's code (or
+/// its refusal) arrives
+async fn wait_for_callback(listener: &TcpListener, state: &str) -> Result<String, String> {
+    loop {
+        let Ok((mut stream, _)) = listener.accept().await else {
+            continue;
+        };
+        let Some(url) = read_request_path(&mut stream).await else {
+            continue;
+        };
+        if !url.starts_with("/oauth/callback") {
+            let _ = respond(&mut stream, "404 Not Found", "").await;
+            continue;
+        }
+
+        let result = callback_code(&url, state);
+        let html = match result {
+            Ok(_) => r#"
+                        <html>
+                            <body style="font-family: sans-serif; text-align: center; padding: 50px;">
+                                <h1>✓ Authentication Successful!</h1>
+                                <p>You can close this window and return to the terminal.</p>
+                            </body>
+                        </html>
+                    "#
+            .to_string(),
+            Err(ref e) => format!("<h1>Error: {}</h1>", e),
+        };
+        let status = if result.is_ok() { "200 OK" } else { "400 Bad Request" };
+        let _ = respond(&mut stream, status, &html).await;
+
+        // Some other page's redirect (or a forged one) rather than the
+        // answer to this sign-in: refuse it and keep waiting
+        if !result.as_ref().is_err_and(|e| e == STATE_MISMATCH) {
+            return result;
+        }
+    }
+}
+
+/// The path of a loopback GET request; None for anything else
+async fn read_request_path(stream: &mut tokio::net::TcpStream) -> Option<String> {
+    let mut head = Vec::new();
+    let mut buffer = [0u8; 1024];
+    while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+        let read = tokio::time::timeout(Duration::from_secs(10), stream.read(&mut buffer)).await.ok()?.ok()?;
+        if read == 0 || head.len() + read > MAX_REQUEST_HEAD {
+            return None;
+        }
+        head.extend_from_slice(&buffer[..read]);
+    }
+    request_path(&String::from_utf8_lossy(&head)).map(str::to_string)
+}
+
+fn request_path(head: &str) -> Option<&str> {
+    let mut request_line = head.lines().next()?.split(' ');
+    match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some(path)) => Some(path),
+        _ => None,
+    }
+}
+
+async fn respond(stream: &mut tokio::net::TcpStream, status: &str, html: &str) -> std::io::Result<()> {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        html.len(),
+        html
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// The authorization code from the loopback callback's URL, if its `state`
+/// is the one this sign-in sent
+fn callback_code(url: &str, expected_state: &str) -> Result<String, String> {
+    let query = url.split_once('?').map_or("", |(_, query)| query);
+    let param = |name: &str| {
+        query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| urlencoding::decode(value).map(|value| value.into_owned()))
+    };
+
+    match param("state") {
+        Some(Ok(state)) if state == expected_state => {}
+        _ => return Err(STATE_MISMATCH.to_string()),
+    }
+    if let Some(Ok(error)) = param("error") {
+        return Err(format!("Sign-in refused: {}", error));
+    }
+    match param("code") {
+        Some(Ok(code)) => Ok(code),
+        Some(Err(_)) => Err("Failed to decode authorization code".to_string()),
+        None => Err("No authorization code received".to_string()),
+    }
+}
+
+/// Show `url` in the default browser; false when there's none to show it in
+/// (no display, e.g. over SSH) or it couldn't be started
+fn open_browser(url: &str) -> bool {
+    #[cfg(target_os = "macos")]
+    let mut command = std::process::Command::new("open");
+    #[cfg(windows)]
+    let mut command = {
+        let mut command = std::process::Command::new("rundll32");
+        command.arg("url.dll,FileProtocolHandler");
+        command
+    };
+    #[cfg(not(any(target_os = "macos", windows)))]
+    let mut command = {
+        if std::env::var_os("DISPLAY").is_none() && std::env::var_os("WAYLAND_DISPLAY").is_none() {
+            return false;
+        }
+        std::process::Command::new("xdg-open")
+    };
+
+    let child = command
+        .arg(url)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn();
+    match child {
+        Ok(mut child) => {
+            // Reap it whenever the launcher exits; some wait for the browser
+            thread::spawn(move || child.wait());
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_file_encryption() {
+        let dir = std::env::temp_dir().join(format!("utterd-oauth-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let token_path = dir.join("oauth.json");
+        let tokens = OAuthTokens {
+            id_token: "id".to_string(),
+            access_token: "access".to_string(),
+            refresh_token: Some("refresh-secret".to_string()),
+            expires_at: Utc::now(),
+        };
+
+        // A plaintext file is encrypted the first time it's read with a key
+        let plain = OAuthManager { token_path: token_path.clone(), cache_key: None };
+        plain.save_tokens(&tokens).unwrap();
+        let manager = OAuthManager { token_path: token_path.clone(), cache_key: Some(secret(&[1; 32])) };
+        assert_eq!(manager.load_tokens().unwrap().refresh_token, tokens.refresh_token);
+        let contents = fs::read(&token_path).unwrap();
+        assert!(contents.starts_with(ENCRYPTED));
+        assert!(!String::from_utf8_lossy(&contents).contains("refresh-secret"));
+
+        assert!(plain.load_tokens().is_err());
+
+        // Re-encrypting covers the other accounts' sign-ins too
+        let work_path = dir.join("oauth-work.json");
+        let work = OAuthManager { token_path: work_path.clone(), cache_key: Some(secret(&[1; 32])) };
+        work.save_tokens(&tokens).unwrap();
+        manager.reencrypt(secret(&[2; 32])).unwrap();
+        assert!(manager.load_tokens().is_err());
+        for token_path in [token_path, work_path] {
+            let rekeyed = OAuthManager { token_path, cache_key: Some(secret(&[2; 32])) };
+            assert_eq!(rekeyed.load_tokens().unwrap().id_token, "id");
+        }
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_callback_code() {
+        let url = |query: &str| format!("/oauth/callback?{}", query);
+        assert_eq!(callback_code(&url("state=abc&code=4%2F0A"), "abc").unwrap(), "4/0A");
+        assert_eq!(callback_code(&url("code=4%2F0A&state=abc"), "abc").unwrap(), "4/0A");
+
+        // A code without our state is refused whatever else it carries
+        for query in ["code=4%2F0A", "state=xyz&code=4%2F0A", "state=&code=4%2F0A", "state=abcd&code=x"] {
+            assert_eq!(callback_code(&url(query), "abc").unwrap_err(), STATE_MISMATCH);
+        }
+        assert!(callback_code("/oauth/callback", "abc").is_err());
+        assert!(callback_code(&url("state=abc&error=access_denied"), "abc").unwrap_err().contains("access_denied"));
+        assert!(callback_code(&url("state=abc"), "abc").unwrap_err().contains("No authorization code"));
+    }
+
+    #[test]
+    fn test_request_path() {
+        let head = "GET /oauth/callback?state=abc&code=x HTTP/1.1\r\nHost: localhost:3000\r\n\r\n";
+        assert_eq!(request_path(head), Some("/oauth/callback?state=abc&code=x"));
+        assert_eq!(request_path("POST /oauth/callback HTTP/1.1\r\n\r\n"), None);
+        assert_eq!(request_path(""), None);
+    }
+
+    #[test]
+    fn test_account() {
+        let id_token = |claims: &str| format!("e30.{}.sig", base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(claims));
+        let tokens = |id_token: String| OAuthTokens {
+            id_token,
+            access_token: String::new(),
+            refresh_token: None,
+            expires_at: Utc::now(),
+        };
+        let signed_in = tokens(id_token(r#"{"sub": "1234", "email": "ada@example.com", "name": "Ada"}"#));
+        assert_eq!(signed_in.account().as_deref(), Some("ada@example.com"));
+        assert_eq!(tokens(id_token(r#"{"sub": "1234"}"#)).account().as_deref(), Some("1234"));
+        assert_eq!(tokens("id".to_string()).account(), None);
     }
 }
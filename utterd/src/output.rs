@@ -0,0 +1,184 @@
+use chrono::{DateTime, Local};
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Where received dictations go
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Output {
+    /// Type into the focused window
+    #[default]
+    Type,
+    /// One line per dictation on stdout
+    Stdout,
+    /// One line per dictation into a named pipe, created if missing
+    Fifo(PathBuf),
+    /// Append each dictation to a notes file with its time
+    File(PathBuf),
+}
+
+impl FromStr for Output {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "type" => Ok(Output::Type),
+            "stdout" => Ok(Output::Stdout),
+            _ => match s.split_once(':') {
+                Some(("fifo", path)) if !path.is_empty() => Ok(Output::Fifo(expand_home(path))),
+                Some(("file", path)) if !path.is_empty() => Ok(Output::File(expand_home(path))),
+                _ => Err(format!("unknown output {:?} (expected type, stdout, fifo:PATH or file:PATH)", s)),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Output {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Output::Type => write!(f, "type"),
+            Output::Stdout => write!(f, "stdout"),
+            Output::Fifo(path) => write!(f, "fifo {}", path.display()),
+            Output::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// Expand a leading `~/`, which the shell leaves alone after `fifo:` and `file:`
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// A dictation as a single line, so readers can split on newlines
+fn line(text: &str) -> String {
+    format!("{}\n", text.replace(['\r', '\n'], " "))
+}
+
+/// A notes entry: local time of dictation, then the text as dictated
+fn entry(text: &str, timestamp: Option<i64>) -> String {
+    let time = timestamp
+        .and_then(DateTime::from_timestamp_millis)
+        .map(|t| t.with_timezone(&Local))
+        .unwrap_or_else(Local::now);
+    format!("[{}] {}\n", time.format("%Y-%m-%d %H:%M"), text.trim_end())
+}
+
+impl Output {
+    /// Create the named pipe or notes file if needed; run once at startup
+    pub fn prepare(&self) -> Result<(), String> {
+        match self {
+            Output::Fifo(path) => prepare_fifo(path),
+            Output::File(path) => {
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)
+                        .map_err(|e| format!("Cannot create {}: {}", parent.display(), e))?;
+                }
+                open_notes(path).map(drop)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// File the output writes to, which `--harden` must leave writable
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Output::Fifo(path) | Output::File(path) => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Write a dictation made at `timestamp` (ms since epoch); `Type` is handled by the caller
+    pub async fn write(&self, text: &str, timestamp: Option<i64>) -> Result<(), String> {
+        match self {
+            Output::Type => Ok(()),
+            Output::Stdout => {
+                let mut stdout = std::io::stdout().lock();
+                stdout
+                    .write_all(line(text).as_bytes())
+                    .and_then(|()| stdout.flush())
+                    .map_err(|e| format!("Cannot write to stdout: {}", e))
+            }
+            Output::Fifo(path) => write_fifo(path, &line(text)).await,
+            Output::File(path) => open_notes(path)?
+                .write_all(entry(text, timestamp).as_bytes())
+                .map_err(|e| format!("Cannot write to {}: {}", path.display(), e)),
+        }
+    }
+}
+
+/// Open the notes file for appending, creating it owner-only like the history
+fn open_notes(path: &Path) -> Result<std::fs::File, String> {
+    let mut options = OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path).map_err(|e| format!("Cannot open {}: {}", path.display(), e))
+}
+
+#[cfg(unix)]
+fn prepare_fifo(path: &Path) -> Result<(), String> {
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::FileTypeExt;
+
+    match std::fs::metadata(path) {
+        Ok(metadata) if metadata.file_type().is_fifo() => Ok(()),
+        Ok(_) => Err(format!("{} exists and is not a named pipe", path.display())),
+        Err(_) => {
+            let c_path = std::ffi::CString::new(path.as_os_str().as_bytes())
+                .map_err(|_| format!("Invalid pipe path {}", path.display()))?;
+            // SAFETY: `c_path` is NUL-terminated and outlives the call
+            if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } != 0 {
+                return Err(format!("Cannot create {}: {}", path.display(), std::io::Error::last_os_error()));
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn prepare_fifo(_path: &Path) -> Result<(), String> {
+    Err("fifo output needs a Unix system".to_string())
+}
+
+/// Write to the pipe without waiting for a reader to show up
+#[cfg(unix)]
+async fn write_fifo(path: &Path, line: &str) -> Result<(), String> {
+    use tokio::io::AsyncWriteExt;
+
+    let mut sender = tokio::net::unix::pipe::OpenOptions::new()
+        .open_sender(path)
+        .map_err(|e| match e.raw_os_error() {
+            Some(libc::ENXIO) => format!("Nothing is reading {}", path.display()),
+            _ => format!("Cannot open {}: {}", path.display(), e),
+        })?;
+    sender
+        .write_all(line.as_bytes())
+        .await
+        .map_err(|e| format!("Cannot write to {}: {}", path.display(), e))
+}
+
+#[cfg(not(unix))]
+async fn write_fifo(_path: &Path, _line: &str) -> Result<(), String> {
+    Err("fifo output needs a Unix system".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_output() {
+        assert_eq!("stdout".parse(), Ok(Output::Stdout));
+        assert_eq!("fifo:/tmp/utter".parse(), Ok(Output::Fifo(PathBuf::from("/tmp/utter"))));
+        assert!("fifo:".parse::<Output>().is_err());
+        assert_eq!("file:notes.md".parse(), Ok(Output::File(PathBuf::from("notes.md"))));
+        assert!("speaker".parse::<Output>().is_err());
+        assert_eq!(line("first\nsecond\r\n"), "first second  \n");
+        assert!(entry("Buy milk\n", Some(0)).ends_with("] Buy milk\n"));
+    }
+}
@@ -0,0 +1,261 @@
+use base64::{engine::general_purpose, Engine as _};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::PathBuf;
+
+/// What the phone sends (encrypted to this desktop) to start pairing
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PairingBundle {
+    pub device_id: String,
+    pub device_name: String,
+    /// The phone's X25519 public key, base64
+    pub public_key: String,
+}
+
+/// A phone the user confirmed on this desktop
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PairedDevice {
+    pub device_id: String,
+    pub device_name: String,
+    pub public_key: String,
+    pub paired_at: DateTime<Utc>,
+}
+
+/// Devices the user trusted, with their pinned public keys, in
+/// ~/.config/utterd/trusted_devices.json (owner-only), or the `--profile`'s
+/// directory under it
+///
+/// Only pinned keys may type. A device is pinned when the user confirms its
+/// pairing request, or its first message (trust on first use).
+pub struct Pairings {
+    path: PathBuf,
+    devices: Vec<PairedDevice>,
+}
+
+impl Pairings {
+    pub fn load(profile: Option<&str>) -> Result<Self, String> {
+        let dir = crate::config::identity_dir(profile).ok_or("Cannot determine config directory")?;
+        let path = dir.join("trusted_devices.json");
+
+        // Earlier versions kept the same list as paired.json
+        let old = dir.join("paired.json");
+        if !path.exists() && old.exists() {
+            fs::rename(&old, &path).map_err(|e| format!("Failed to move {} to {}: {}", old.display(), path.display(), e))?;
+        }
+
+        let devices = if path.exists() {
+            let contents = fs::read_to_string(&path)
+                .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+            serde_json::from_str(&contents).map_err(|e| format!("Invalid {}: {}", path.display(), e))?
+        } else {
+            Vec::new()
+        };
+
+        Ok(Self { path, devices })
+    }
+
+    /// Whether messages from `public_key` may be typed; that the sender holds
+    /// it is checked when they're decrypted, if they set `senderAuth`
+    pub fn is_trusted(&self, public_key: Option<&str>) -> bool {
+        public_key.is_some_and(|key| self.devices.iter().any(|device| device.public_key == key))
+    }
+
+    pub fn devices(&self) -> &[PairedDevice] {
+        &self.devices
+    }
+
+    /// The key pinned for `device_id`, to notice a device whose key changed
+    pub fn pinned_key(&self, device_id: &str) -> Option<&str> {
+        self.devices
+            .iter()
+            .find(|device| device.device_id == device_id)
+            .map(|device| device.public_key.as_str())
+    }
+
+    /// Remember `bundle`, replacing an earlier pairing of the same device
+    pub fn add(&mut self, bundle: &PairingBundle) -> Result<(), String> {
+        self.devices.retain(|device| device.device_id != bundle.device_id);
+        self.devices.push(PairedDevice {
+            device_id: bundle.device_id.clone(),
+            device_name: bundle.device_name.clone(),
+            public_key: bundle.public_key.clone(),
+            paired_at: Utc::now(),
+        });
+        self.save()
+    }
+
+    /// Take devices trusted on another machine (`utterd keys import`),
+    /// replacing earlier pairings of the same devices
+    pub fn merge(&mut self, devices: Vec<PairedDevice>) -> Result<(), String> {
+        for device in devices {
+            self.devices.retain(|known| known.device_id != device.device_id);
+            self.devices.push(device);
+        }
+        self.save()
+    }
+
+    fn save(&self) -> Result<(), String> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("Failed to create config directory: {}", e))?;
+        }
+
+        let json = serde_json::to_string_pretty(&self.devices).map_err(|e| e.to_string())?;
+        fs::write(&self.path, json).map_err(|e| format!("Failed to write {}: {}", self.path.display(), e))?;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&self.path, fs::Permissions::from_mode(0o600))
+                .map_err(|e| format!("Failed to set pairing file permissions: {}", e))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Short, human-comparable digest of a base64 public key, e.g. "1A2B 3C4D 5E6F 7A8B"
+///
+/// The phone shows the same digest of its own key, so the user can check
+/// the relay didn't swap it.
+pub fn fingerprint(public_key: &str) -> Result<String, String> {
+    let key = general_purpose::STANDARD
+        .decode(public_key)
+        .map_err(|e| format!("Invalid public key: {}", e))?;
+    if key.len() != 32 {
+        return Err("Invalid public key length".to_string());
+    }
+
+    let digest = Sha256::digest(&key);
+    Ok(digest[..8]
+        .chunks(2)
+        .map(|pair| format!("{:02X}{:02X}", pair[0], pair[1]))
+        .collect::<Vec<_>>()
+        .join(" "))
+}
+
+/// What the pairing QR code holds: this desktop's device id, name and public
+/// key, and where to reach it on the LAN with `--listen`
+///
+/// `utter://pair?id=…&name=…&key=…[&lan=ws://…][&code=…]`, values
+/// URL-encoded. The app takes the key from here instead of from the relay.
+/// `code` (`with_join_code`) is the relay's one-time code for joining an
+/// anonymous account.
+pub fn pairing_uri(device_id: &str, device_name: &str, public_key: &str, lan: Option<&str>) -> String {
+    let mut uri = format!(
+        "utter://pair?id={}&name={}&key={}",
+        urlencoding::encode(device_id),
+        urlencoding::encode(device_name),
+        urlencoding::encode(public_key)
+    );
+    if let Some(lan) = lan {
+        uri.push_str(&format!("&lan={}", urlencoding::encode(lan)));
+    }
+    uri
+}
+
+/// `uri` from `pairing_uri` carrying the relay's pairing code `code` instead
+/// of any earlier one
+pub fn with_join_code(uri: &str, code: &str) -> String {
+    let uri = uri.split("&code=").next().unwrap_or(uri);
+    format!("{}&code={}", uri, urlencoding::encode(code))
+}
+
+/// Emoji for the short authentication string, indexed by 6 bits of it
+const SAS_EMOJI: [(&str, &str); 64] = [
+    ("🐶", "Dog"), ("🐱", "Cat"), ("🦁", "Lion"), ("🐎", "Horse"), ("🦄", "Unicorn"), ("🐷", "Pig"), ("🐘", "Elephant"), ("🐰", "Rabbit"),
+    ("🐼", "Panda"), ("🐓", "Rooster"), ("🐧", "Penguin"), ("🐢", "Turtle"), ("🐟", "Fish"), ("🐙", "Octopus"), ("🦋", "Butterfly"), ("🌷", "Flower"),
+    ("🌳", "Tree"), ("🌵", "Cactus"), ("🍄", "Mushroom"), ("🌏", "Globe"), ("🌙", "Moon"), ("☁️", "Cloud"), ("🔥", "Fire"), ("🍌", "Banana"),
+    ("🍎", "Apple"), ("🍓", "Strawberry"), ("🌽", "Corn"), ("🍕", "Pizza"), ("🎂", "Cake"), ("❤️", "Heart"), ("😀", "Smiley"), ("🤖", "Robot"),
+    ("🎩", "Hat"), ("👓", "Glasses"), ("🔧", "Spanner"), ("🎅", "Santa"), ("👍", "Thumbs up"), ("☂️", "Umbrella"), ("⌛", "Hourglass"), ("⏰", "Clock"),
+    ("🎁", "Gift"), ("💡", "Light bulb"), ("📕", "Book"), ("✏️", "Pencil"), ("📎", "Paperclip"), ("✂️", "Scissors"), ("🔒", "Lock"), ("🔑", "Key"),
+    ("🔨", "Hammer"), ("☎️", "Telephone"), ("🏁", "Flag"), ("🚂", "Train"), ("🚲", "Bicycle"), ("✈️", "Aeroplane"), ("🚀", "Rocket"), ("🏆", "Trophy"),
+    ("⚽", "Ball"), ("🎸", "Guitar"), ("🎺", "Trumpet"), ("🔔", "Bell"), ("⚓", "Anchor"), ("🎧", "Headphones"), ("📁", "Folder"), ("📌", "Pin"),
+];
+
+/// Emoji in a short authentication string (42 bits)
+const SAS_LENGTH: usize = 7;
+
+/// Short authentication string for pairing `phone_key` with `desktop_key`,
+/// e.g. "🐶 Dog · 🔑 Key · …"
+///
+/// Derived from both keys as each side sees them, so the phone shows the
+/// same emoji only if the relay swapped neither: SHA-256 of
+/// "utter-sas-v1", the phone's key and the desktop's key (raw bytes), whose
+/// first 42 bits pick 7 emoji 6 bits at a time.
+pub fn short_auth_string(phone_key: &str, desktop_key: &str) -> Result<String, String> {
+    let decode = |key: &str| {
+        general_purpose::STANDARD
+            .decode(key)
+            .ok()
+            .filter(|key| key.len() == 32)
+            .ok_or_else(|| "Invalid public key".to_string())
+    };
+    let mut hasher = Sha256::new();
+    hasher.update(b"utter-sas-v1");
+    hasher.update(decode(phone_key)?);
+    hasher.update(decode(desktop_key)?);
+    let digest = hasher.finalize();
+
+    let bits = digest[..6].iter().fold(0u64, |bits, byte| bits << 8 | u64::from(*byte));
+    Ok((0..SAS_LENGTH)
+        .map(|i| SAS_EMOJI[(bits >> (42 - 6 * i)) as usize & 63])
+        .map(|(emoji, name)| format!("{} {}", emoji, name))
+        .collect::<Vec<_>>()
+        .join(" · "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fingerprint_and_trust() {
+        let key = general_purpose::STANDARD.encode([7u8; 32]);
+        let print = fingerprint(&key).unwrap();
+        assert_eq!(print.len(), 19);
+        assert_eq!(print, fingerprint(&key).unwrap());
+        assert!(fingerprint("c2hvcnQ=").is_err());
+
+        let desktop = general_purpose::STANDARD.encode([9u8; 32]);
+        let sas = short_auth_string(&key, &desktop).unwrap();
+        assert_eq!(sas.split(" · ").count(), SAS_LENGTH);
+        assert_eq!(sas, short_auth_string(&key, &desktop).unwrap());
+        assert_ne!(sas, short_auth_string(&desktop, &key).unwrap());
+        assert!(short_auth_string(&key, "c2hvcnQ=").is_err());
+        assert_eq!(
+            pairing_uri("desk", "My Desk", "ab+c/d=", Some("ws://192.168.1.5:8080")),
+            "utter://pair?id=desk&name=My%20Desk&key=ab%2Bc%2Fd%3D&lan=ws%3A%2F%2F192.168.1.5%3A8080"
+        );
+        let uri = pairing_uri("desk", "desk", "key", None);
+        assert_eq!(with_join_code(&uri, "7KQ2XM9D"), "utter://pair?id=desk&name=desk&key=key&code=7KQ2XM9D");
+        assert_eq!(with_join_code(&with_join_code(&uri, "7KQ2XM9D"), "ABCD2345"), with_join_code(&uri, "ABCD2345"));
+        let names: std::collections::HashSet<_> = SAS_EMOJI.iter().map(|(_, name)| name).collect();
+        assert_eq!(names.len(), SAS_EMOJI.len());
+
+        let mut pairings = Pairings {
+            path: std::env::temp_dir().join(format!("utterd-paired-test-{}.json", std::process::id())),
+            devices: Vec::new(),
+        };
+        // Nothing is trusted until the user says so
+        assert!(!pairings.is_trusted(Some(&key)));
+        assert!(!pairings.is_trusted(None));
+
+        pairings
+            .add(&PairingBundle {
+                device_id: "pixel".to_string(),
+                device_name: "Pixel 8".to_string(),
+                public_key: key.clone(),
+            })
+            .unwrap();
+        assert!(pairings.is_trusted(Some(&key)));
+        assert!(!pairings.is_trusted(Some("other")));
+        assert!(!pairings.is_trusted(None));
+        assert_eq!(pairings.pinned_key("pixel"), Some(key.as_str()));
+        assert_eq!(pairings.pinned_key("tablet"), None);
+
+        fs::remove_file(&pairings.path).unwrap();
+    }
+}
@@ -0,0 +1,161 @@
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{List, ListItem, ListState, Paragraph};
+
+/// What to do with the picked entry
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PickAction {
+    Type,
+    Copy,
+}
+
+/// Score `candidate` as a case-insensitive subsequence match of `query`
+///
+/// Consecutive matches and matches at word starts score higher. Every
+/// occurrence of the first query character is tried as a starting point,
+/// so "note" prefers "notes" over the stray "n" in "meeting". Returns None
+/// when some query character doesn't appear in order.
+pub fn fuzzy_score(query: &str, candidate: &str) -> Option<i64> {
+    let query: Vec<char> = query.chars().flat_map(char::to_lowercase).collect();
+    let candidate: Vec<char> = candidate.chars().flat_map(char::to_lowercase).collect();
+
+    let Some(&first) = query.first() else {
+        return Some(0);
+    };
+
+    (0..candidate.len())
+        .filter(|&start| candidate[start] == first)
+        .filter_map(|start| score_from(&query, &candidate, start))
+        .max()
+}
+
+/// Greedy subsequence score with the first query character pinned at `start`
+fn score_from(query: &[char], candidate: &[char], start: usize) -> Option<i64> {
+    let mut remaining = query.iter().peekable();
+    let mut score = 0;
+    let mut prev_match: Option<usize> = None;
+
+    for (i, &c) in candidate.iter().enumerate().skip(start) {
+        let Some(&&q) = remaining.peek() else {
+            break;
+        };
+        if q == c {
+            score += 1;
+            if prev_match.is_some_and(|p| p + 1 == i) {
+                score += 5;
+            }
+            if i == 0 || !candidate[i - 1].is_alphanumeric() {
+                score += 3;
+            }
+            prev_match = Some(i);
+            remaining.next();
+        }
+    }
+
+    remaining.peek().is_none().then_some(score)
+}
+
+/// Indices of `items` matching `query`, best first (ties keep input order)
+fn filter(items: &[String], query: &str) -> Vec<usize> {
+    let mut scored: Vec<(usize, i64)> = items
+        .iter()
+        .enumerate()
+        .filter_map(|(i, item)| fuzzy_score(query, item).map(|s| (i, s)))
+        .collect();
+    scored.sort_by_key(|&(i, score)| (-score, i));
+    scored.into_iter().map(|(i, _)| i).collect()
+}
+
+/// Full-screen fuzzy finder over `items`
+///
+/// Returns the index of the chosen item and whether to type or copy it,
+/// or None if the user cancelled.
+pub fn pick(items: &[String]) -> Result<Option<(usize, PickAction)>, String> {
+    let mut terminal = ratatui::try_init().map_err(|e| format!("Cannot start terminal UI: {}", e))?;
+    let result = run(&mut terminal, items);
+    ratatui::restore();
+    result
+}
+
+fn run(terminal: &mut ratatui::DefaultTerminal, items: &[String]) -> Result<Option<(usize, PickAction)>, String> {
+    let mut query = String::new();
+    let mut matches = filter(items, &query);
+    let mut list_state = ListState::default().with_selected(Some(0));
+
+    loop {
+        terminal
+            .draw(|frame| {
+                let [input_area, list_area, help_area] = Layout::vertical([
+                    Constraint::Length(1),
+                    Constraint::Min(0),
+                    Constraint::Length(1),
+                ])
+                .areas(frame.area());
+
+                frame.render_widget(Paragraph::new(format!("> {}", query)), input_area);
+
+                let list_items: Vec<ListItem> = matches
+                    .iter()
+                    .map(|&i| ListItem::new(Line::from(items[i].as_str())))
+                    .collect();
+                let list = List::new(list_items)
+                    .highlight_style(Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD))
+                    .highlight_symbol("▶ ");
+                frame.render_stateful_widget(list, list_area, &mut list_state);
+
+                frame.render_widget(
+                    Paragraph::new(format!("{}/{} · Enter type · Tab copy · Esc cancel", matches.len(), items.len()))
+                        .style(Style::default().add_modifier(Modifier::DIM)),
+                    help_area,
+                );
+            })
+            .map_err(|e| format!("Failed to draw: {}", e))?;
+
+        let Event::Key(key) = event::read().map_err(|e| format!("Failed to read input: {}", e))? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+
+        let selected = list_state.selected().and_then(|s| matches.get(s).copied());
+        match key.code {
+            KeyCode::Esc => return Ok(None),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Ok(None),
+            KeyCode::Enter => return Ok(selected.map(|i| (i, PickAction::Type))),
+            KeyCode::Tab => return Ok(selected.map(|i| (i, PickAction::Copy))),
+            KeyCode::Up => list_state.select_previous(),
+            KeyCode::Down => list_state.select_next(),
+            KeyCode::Backspace => {
+                query.pop();
+                matches = filter(items, &query);
+                list_state.select(Some(0));
+            }
+            KeyCode::Char(c) => {
+                query.push(c);
+                matches = filter(items, &query);
+                list_state.select(Some(0));
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fuzzy_score() {
+        assert!(fuzzy_score("mtg", "meeting notes").is_some());
+        assert!(fuzzy_score("xyz", "meeting notes").is_none());
+        // Contiguous and word-start matches beat scattered ones
+        assert!(fuzzy_score("note", "meeting notes") > fuzzy_score("note", "no time to eat"));
+
+        let items = vec!["buy milk".to_string(), "meeting notes".to_string()];
+        assert_eq!(filter(&items, "mn"), vec![1]);
+        assert_eq!(filter(&items, ""), vec![0, 1]);
+    }
+}
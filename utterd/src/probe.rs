@@ -0,0 +1,213 @@
+use crate::headers::Headers;
+use crate::proxy::{self, Proxy};
+use crate::tls::Tls;
+use crate::{auth, get_hostname, oauth, VERSION};
+use futures_util::{SinkExt, Stream, StreamExt};
+use serde_json::{json, Value};
+use std::time::Duration;
+use tokio::time::timeout;
+use tokio_tungstenite::tungstenite::{client::IntoClientRequest, Error as WsError, Message};
+
+/// Relay protocol revision this daemon speaks
+pub const PROTOCOL_VERSION: u64 = 1;
+
+/// Relay features the daemon relies on
+const DAEMON_FEATURES: &[&str] = &["jwt-auth", "e2e-relay", "capabilities", "message-ids", "corrections", "keys", "pairing", "config-sync", "sequence-numbers", "acks", "clipboard", "partials", "chunks", "session-keys", "bound-aad", "cipher-negotiation", "broadcast", "sender-auth"];
+
+/// How long to wait for each relay reply
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// `utterd probe`: register with the relay and print what each side supports
+///
+/// Registers as "<hostname>-probe" so a running daemon on this machine
+/// keeps its own registration.
+pub async fn run(
+    server_url: &str,
+    tls: &Tls,
+    proxy: Option<&Proxy>,
+    headers: &Headers,
+    oauth_manager: oauth::OAuthManager,
+    anonymous_secret: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let http_url = server_url.replace("ws://", "http://").replace("wss://", "https://");
+    let jwt = match anonymous_secret {
+        Some(secret) => auth::exchange_anonymous(tls.http(), &http_url, &secret).await?.jwt,
+        None => {
+            let tokens = oauth_manager.get_or_authenticate(|text: &str| println!("{}", text)).await?;
+            auth::exchange_for_jwt(tls.http(), &http_url, &tokens.id_token).await?.jwt
+        }
+    };
+
+    let cannot_connect = |e: WsError| format!("Cannot connect to {}: {}", server_url, e);
+    #[cfg(unix)]
+    if let Some(path) = crate::unix::socket_path(server_url) {
+        let mut request = crate::unix::WS_URL.into_client_request()?;
+        headers.apply(request.headers_mut());
+        let (ws_stream, _) = crate::unix::connect_websocket(path, request, None)
+            .await
+            .map_err(cannot_connect)?;
+        let (write, read) = ws_stream.split();
+        let (capabilities, devices) = query(write, read, jwt).await?;
+        print_report(server_url, capabilities.as_ref(), devices.as_ref());
+        return Ok(());
+    }
+
+    let mut request = server_url.into_client_request()?;
+    headers.apply(request.headers_mut());
+    let (ws_stream, _) = proxy::connect_websocket(request, None, tls.connector()?, proxy)
+        .await
+        .map_err(cannot_connect)?;
+    let (write, read) = ws_stream.split();
+    let (capabilities, devices) = query(write, read, jwt).await?;
+    print_report(server_url, capabilities.as_ref(), devices.as_ref());
+    Ok(())
+}
+
+/// Register and ask for the relay's capabilities and device list
+async fn query<W, R>(mut write: W, mut read: R, jwt: String) -> Result<(Option<Value>, Option<Value>), String>
+where
+    W: SinkExt<Message, Error = WsError> + Unpin,
+    R: Stream<Item = Result<Message, WsError>> + Unpin,
+{
+    expect(&mut read, "connected").await?;
+
+    let hostname = get_hostname();
+    send(&mut write, json!({
+        "type": "register",
+        "clientType": "target",
+        "deviceId": format!("{}-probe", hostname),
+        "deviceName": format!("{} (probe)", hostname),
+        "version": format!("utterd v{}", VERSION),
+        "jwt": jwt,
+    })).await?;
+    expect(&mut read, "registered").await?;
+
+    send(&mut write, json!({ "type": "get_capabilities" })).await?;
+    // Relays that predate this message just log it as unknown and stay silent
+    let capabilities = expect(&mut read, "capabilities").await.ok();
+
+    send(&mut write, json!({ "type": "get_devices" })).await?;
+    let devices = expect(&mut read, "devices").await.ok();
+
+    let _ = write.send(Message::Close(None)).await;
+    Ok((capabilities, devices))
+}
+
+async fn send<S>(write: &mut S, message: Value) -> Result<(), String>
+where
+    S: SinkExt<Message, Error = WsError> + Unpin,
+{
+    write
+        .send(Message::Text(message.to_string()))
+        .await
+        .map_err(|e| format!("Send error: {}", e))
+}
+
+/// Wait for a message of type `kind`, skipping anything else the relay sends
+async fn expect<S>(read: &mut S, kind: &str) -> Result<Value, String>
+where
+    S: Stream<Item = Result<Message, WsError>> + Unpin,
+{
+    let wait = async {
+        while let Some(message) = read.next().await {
+            let Message::Text(text) = message.map_err(|e| e.to_string())? else {
+                continue;
+            };
+            let Ok(value) = serde_json::from_str::<Value>(&text) else {
+                continue;
+            };
+
+            match value["type"].as_str() {
+                Some(t) if t == kind => return Ok(value),
+                Some("error") => {
+                    return Err(value["message"].as_str().unwrap_or("relay error").to_string());
+                }
+                _ => {}
+            }
+        }
+        Err("Relay closed the connection".to_string())
+    };
+
+    timeout(REPLY_TIMEOUT, wait)
+        .await
+        .map_err(|_| format!("No {} reply from relay", kind))?
+}
+
+fn print_report(server_url: &str, capabilities: Option<&Value>, devices: Option<&Value>) {
+    println!("Relay {}\n", server_url);
+
+    let Some(capabilities) = capabilities else {
+        println!("✗ Relay did not answer get_capabilities; it predates capability probing.");
+        println!("  Update the relay server to compare versions.");
+        return;
+    };
+
+    let relay_version = capabilities["version"].as_str().unwrap_or("?");
+    let relay_protocol = capabilities["protocol"].as_u64();
+    let relay_features: Vec<&str> = capabilities["features"]
+        .as_array()
+        .map(|f| f.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default();
+
+    let mut features: Vec<&str> = DAEMON_FEATURES.to_vec();
+    for feature in &relay_features {
+        if !features.contains(feature) {
+            features.push(feature);
+        }
+    }
+
+    let mark = |supported: bool| if supported { "✓" } else { "-" };
+
+    println!("{:<16} daemon     relay", "");
+    println!("{:<16} {:<10} {}", "version", VERSION, relay_version);
+    println!(
+        "{:<16} {:<10} {}",
+        "protocol",
+        PROTOCOL_VERSION,
+        relay_protocol.map(|p| p.to_string()).unwrap_or_else(|| "?".to_string())
+    );
+    for feature in &features {
+        println!(
+            "{:<16} {:<10} {}",
+            feature,
+            mark(DAEMON_FEATURES.contains(feature)),
+            mark(relay_features.contains(feature))
+        );
+    }
+    if let Some(max) = capabilities["maxMessageLength"].as_u64() {
+        println!("{:<27} {}", "max message", max);
+    }
+
+    println!();
+    let missing: Vec<&&str> = DAEMON_FEATURES
+        .iter()
+        .filter(|f| !relay_features.contains(f))
+        .collect();
+    if relay_protocol != Some(PROTOCOL_VERSION) {
+        println!("✗ Protocol mismatch: daemon speaks {}, relay {}", PROTOCOL_VERSION, relay_protocol.unwrap_or(0));
+    } else if !missing.is_empty() {
+        let missing: Vec<&str> = missing.into_iter().copied().collect();
+        println!("✗ Relay lacks: {}", missing.join(", "));
+    } else {
+        println!("✓ Daemon and relay are compatible");
+    }
+
+    let Some(devices) = devices.and_then(|d| d["devices"].as_array()) else {
+        return;
+    };
+
+    println!("\nDevices on this account");
+    for device in devices {
+        let id = device["deviceId"].as_str().unwrap_or("?");
+        if id.ends_with("-probe") {
+            continue;
+        }
+        println!(
+            "  {:<24} {:<10} {:<24} {}",
+            device["deviceName"].as_str().unwrap_or(id),
+            device["deviceType"].as_str().unwrap_or("?"),
+            device["version"].as_str().unwrap_or("(no version)"),
+            device["status"].as_str().unwrap_or("?"),
+        );
+    }
+}
@@ -0,0 +1,69 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Caps on how fast dictations and keys get typed (`--max-per-second`,
+/// `--max-per-minute`)
+///
+/// A paired phone that misbehaves, or whoever controls it, could otherwise
+/// type into the focused window as fast as the relay forwards. Both caps
+/// are sliding windows; only what was let through counts.
+pub struct RateLimit {
+    per_second: Option<usize>,
+    per_minute: Option<usize>,
+    /// When recent items were let through, oldest first
+    recent: VecDeque<Instant>,
+}
+
+const SECOND: Duration = Duration::from_secs(1);
+const MINUTE: Duration = Duration::from_secs(60);
+
+impl RateLimit {
+    /// 0 leaves that window uncapped
+    pub fn new(per_second: usize, per_minute: usize) -> Self {
+        Self {
+            per_second: (per_second > 0).then_some(per_second),
+            per_minute: (per_minute > 0).then_some(per_minute),
+            recent: VecDeque::new(),
+        }
+    }
+
+    /// Count an item arriving at `now`, or say which cap it would exceed
+    pub fn check(&mut self, now: Instant) -> Result<(), String> {
+        while self.recent.front().is_some_and(|&at| now.duration_since(at) >= MINUTE) {
+            self.recent.pop_front();
+        }
+
+        let in_last_second = self.recent.iter().rev().take_while(|&&at| now.duration_since(at) < SECOND).count();
+        if let Some(cap) = self.per_second.filter(|&cap| in_last_second >= cap) {
+            return Err(format!("{} per second", cap));
+        }
+        if let Some(cap) = self.per_minute.filter(|&cap| self.recent.len() >= cap) {
+            return Err(format!("{} per minute", cap));
+        }
+
+        self.recent.push_back(now);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limit() {
+        let start = Instant::now();
+        let mut limit = RateLimit::new(2, 3);
+        assert!(limit.check(start).is_ok());
+        assert!(limit.check(start).is_ok());
+        assert_eq!(limit.check(start), Err("2 per second".to_string()));
+
+        // A second later the per-second window has room, the minute doesn't for long
+        assert!(limit.check(start + SECOND).is_ok());
+        assert_eq!(limit.check(start + SECOND * 2), Err("3 per minute".to_string()));
+        assert!(limit.check(start + MINUTE).is_ok());
+
+        let mut unlimited = RateLimit::new(0, 0);
+        assert!((0..100).all(|_| unlimited.check(start).is_ok()));
+    }
+}
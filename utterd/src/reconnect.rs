@@ -0,0 +1,350 @@
+use rand::Rng;
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::http::Response;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+
+/// First reconnect delay; each failed attempt doubles it
+const BACKOFF_START: Duration = Duration::from_secs(1);
+
+/// Longest wait between attempts while the relay stays down
+const BACKOFF_CAP: Duration = Duration::from_secs(60);
+
+/// A session that lasted this long was healthy, so the backoff starts over
+pub const STABLE_SESSION: Duration = Duration::from_secs(30);
+
+/// Minimum cooldown once the relay has told us it is overloaded
+pub const OVERLOAD_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// Upper bound on any relay-requested cooldown
+const MAX_COOLDOWN: Duration = Duration::from_secs(600);
+
+/// Application close code the relay uses for "too many requests" (HTTP 429 equivalent)
+pub const CLOSE_RATE_LIMITED: u16 = 4029;
+
+/// Application close code for an expired or revoked JWT (HTTP 401 equivalent)
+pub const CLOSE_AUTH_EXPIRED: u16 = 4401;
+
+/// Application close code when another session registered the same device (HTTP 409 equivalent)
+pub const CLOSE_REPLACED: u16 = 4409;
+
+/// How the relay ended a session, derived from its close frame
+#[derive(Debug, PartialEq)]
+pub enum CloseStatus {
+    /// Clean close, or the connection simply dropped
+    Normal,
+    /// Relay is restarting or going away (1001, 1012)
+    ShuttingDown,
+    /// Relay is shedding load and asked us to stay away (1013, 4029)
+    Overloaded(Duration),
+    /// JWT rejected mid-session; refresh before reconnecting
+    AuthExpired,
+    /// Another utterd signed in as this device; reconnecting would just kick it back
+    Kicked,
+    /// We hung up on a relay that broke the protocol (`--strict`)
+    ProtocolViolation(String),
+    /// A ping went unanswered this long: the connection is half-open
+    Unresponsive(Duration),
+    /// We hung up because the network changed under the connection
+    NetworkChanged,
+    /// We hung up because utterd is quitting
+    Quit,
+    /// Anything else, kept verbatim for the status line
+    Other(u16, String),
+}
+
+impl CloseStatus {
+    pub fn from_frame(frame: Option<&CloseFrame>) -> Self {
+        let Some(frame) = frame else {
+            return CloseStatus::Normal;
+        };
+
+        if let Some(cooldown) = retry_after_from_close(frame) {
+            return CloseStatus::Overloaded(cooldown);
+        }
+
+        match u16::from(frame.code) {
+            1000 => CloseStatus::Normal,
+            1001 | 1012 => CloseStatus::ShuttingDown,
+            CLOSE_AUTH_EXPIRED => CloseStatus::AuthExpired,
+            CLOSE_REPLACED => CloseStatus::Kicked,
+            // Policy violations are how relays without app codes reject a bad token
+            1008 if frame.reason.to_lowercase().contains("auth")
+                || frame.reason.to_lowercase().contains("jwt") => CloseStatus::AuthExpired,
+            code => CloseStatus::Other(code, frame.reason.to_string()),
+        }
+    }
+
+    /// Short user-facing explanation for the status line
+    pub fn describe(&self) -> String {
+        match self {
+            CloseStatus::Normal => "connection closed".to_string(),
+            CloseStatus::ShuttingDown => "server shutting down".to_string(),
+            CloseStatus::Overloaded(cooldown) => format!("relay overloaded, waiting {}s", cooldown.as_secs()),
+            CloseStatus::AuthExpired => "auth expired".to_string(),
+            CloseStatus::Kicked => "kicked: signed in elsewhere".to_string(),
+            CloseStatus::ProtocolViolation(violation) => format!("protocol violation: {}", violation),
+            CloseStatus::Unresponsive(timeout) => format!("no reply to ping in {}s", timeout.as_secs()),
+            CloseStatus::NetworkChanged => "network changed".to_string(),
+            CloseStatus::Quit => "quitting".to_string(),
+            CloseStatus::Other(code, reason) if reason.is_empty() => format!("closed with code {}", code),
+            CloseStatus::Other(code, reason) => format!("{} ({})", reason, code),
+        }
+    }
+
+    /// Delay before reconnecting, or None if we should not reconnect at all
+    ///
+    /// `refreshing` tells whether the next attempt gets a token the relay
+    /// hasn't rejected yet; otherwise an expired auth backs off like any
+    /// other failure instead of reconnecting in a tight loop.
+    pub fn reconnect_delay(&self, backoff: &mut Backoff, refreshing: bool) -> Option<Duration> {
+        match self {
+            CloseStatus::Kicked | CloseStatus::Quit => None,
+            // Token gets refreshed first, so there is nothing to wait for
+            CloseStatus::AuthExpired if refreshing => Some(Duration::ZERO),
+            // The relay is fine; only our route to it changed
+            CloseStatus::NetworkChanged => Some(Duration::ZERO),
+            CloseStatus::Overloaded(cooldown) => Some(backoff.next().max(with_jitter(*cooldown))),
+            CloseStatus::AuthExpired
+            | CloseStatus::ShuttingDown
+            | CloseStatus::Normal
+            | CloseStatus::ProtocolViolation(_)
+            | CloseStatus::Unresponsive(_)
+            | CloseStatus::Other(..) => {
+                Some(backoff.next())
+            }
+        }
+    }
+}
+
+/// Exponential reconnect backoff: about 1s, 2s, 4s... up to a minute
+///
+/// Quick blips reconnect almost at once, while a relay that stays down
+/// isn't hammered. Each delay is half fixed and half random, so clients
+/// that lost the relay together don't all come back in the same second.
+#[derive(Debug, Default)]
+pub struct Backoff {
+    attempts: u32,
+}
+
+impl Backoff {
+    /// Delay before the next attempt
+    pub fn next(&mut self) -> Duration {
+        let ceiling = BACKOFF_START.saturating_mul(1 << self.attempts.min(16)).min(BACKOFF_CAP);
+        self.attempts = self.attempts.saturating_add(1);
+
+        let half = ceiling / 2;
+        half + Duration::from_millis(rand::thread_rng().gen_range(0..=half.as_millis() as u64))
+    }
+
+    /// Start over after a healthy session
+    pub fn reset(&mut self) {
+        self.attempts = 0;
+    }
+}
+
+/// The relays given with `--server`, tried in turn
+///
+/// utterd stays on a relay while it works. When an attempt fails or a
+/// session ends, the next relay is tried right away; only once all of them
+/// have failed in a row is it time to back off.
+#[derive(Debug)]
+pub struct Relays {
+    urls: Vec<String>,
+    current: usize,
+    /// Relays that failed in a row
+    misses: usize,
+}
+
+impl Relays {
+    pub fn new(urls: Vec<String>) -> Self {
+        assert!(!urls.is_empty(), "at least one relay");
+        Self {
+            urls,
+            current: 0,
+            misses: 0,
+        }
+    }
+
+    pub fn current(&self) -> &str {
+        &self.urls[self.current]
+    }
+
+    pub fn len(&self) -> usize {
+        self.urls.len()
+    }
+
+    /// Move on from a relay that failed or dropped us; false once the whole
+    /// list has failed in a row, meaning wait before starting over
+    pub fn advance(&mut self) -> bool {
+        self.current = (self.current + 1) % self.urls.len();
+        self.misses += 1;
+        if self.misses < self.urls.len() {
+            return true;
+        }
+        self.misses = 0;
+        false
+    }
+
+    /// A healthy session: every relay is worth trying again
+    pub fn reset(&mut self) {
+        self.misses = 0;
+    }
+}
+
+/// Why a connection attempt failed
+#[derive(Debug)]
+pub struct ConnectError {
+    pub message: String,
+    /// Cooldown requested by the relay, if it asked us to back off
+    pub retry_after: Option<Duration>,
+}
+
+impl ConnectError {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            retry_after: None,
+        }
+    }
+}
+
+/// Extract a backoff request from a close frame
+///
+/// Codes 1013 (Try Again Later) and 4029 (rate limited) mean the relay is
+/// shedding load. The reason may carry a hint such as "retry-after=60" or
+/// just "60"; without one we fall back to `OVERLOAD_COOLDOWN`.
+fn retry_after_from_close(frame: &CloseFrame) -> Option<Duration> {
+    let overloaded = matches!(frame.code, CloseCode::Again)
+        || u16::from(frame.code) == CLOSE_RATE_LIMITED;
+
+    if !overloaded {
+        return None;
+    }
+
+    let hint = parse_retry_after(&frame.reason).unwrap_or(OVERLOAD_COOLDOWN);
+    Some(hint.max(OVERLOAD_COOLDOWN))
+}
+
+/// Extract a backoff request from a rejected handshake (429/503 + Retry-After)
+pub fn retry_after_from_response<T>(response: &Response<T>) -> Option<Duration> {
+    let status = response.status().as_u16();
+    if status != 429 && status != 503 {
+        return None;
+    }
+
+    let hint = response
+        .headers()
+        .get("retry-after")
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_retry_after)
+        .unwrap_or(OVERLOAD_COOLDOWN);
+    Some(hint.max(OVERLOAD_COOLDOWN))
+}
+
+/// Parse "60", "retry-after=60" or "Retry-After: 60" into a duration (seconds)
+fn parse_retry_after(text: &str) -> Option<Duration> {
+    let value = text
+        .rsplit(['=', ':'])
+        .next()
+        .unwrap_or(text)
+        .trim();
+
+    value
+        .parse::<u64>()
+        .ok()
+        .map(|secs| Duration::from_secs(secs).min(MAX_COOLDOWN))
+}
+
+/// Add up to 25% random jitter so many clients don't reconnect in lockstep
+pub fn with_jitter(delay: Duration) -> Duration {
+    let max_jitter = delay.as_millis() as u64 / 4;
+    if max_jitter == 0 {
+        return delay;
+    }
+    delay + Duration::from_millis(rand::thread_rng().gen_range(0..=max_jitter))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+
+    fn frame(code: u16, reason: &'static str) -> CloseFrame<'static> {
+        CloseFrame {
+            code: CloseCode::from(code),
+            reason: Cow::Borrowed(reason),
+        }
+    }
+
+    #[test]
+    fn test_retry_after_from_close() {
+        assert_eq!(retry_after_from_close(&frame(1000, "")), None);
+        assert_eq!(retry_after_from_close(&frame(1013, "")), Some(OVERLOAD_COOLDOWN));
+        assert_eq!(
+            retry_after_from_close(&frame(1013, "retry-after=120")),
+            Some(Duration::from_secs(120))
+        );
+        // Hints shorter than the overload floor are stretched, huge ones capped
+        assert_eq!(retry_after_from_close(&frame(4029, "5")), Some(OVERLOAD_COOLDOWN));
+        assert_eq!(retry_after_from_close(&frame(4029, "99999")), Some(MAX_COOLDOWN));
+    }
+
+    #[test]
+    fn test_backoff() {
+        let mut backoff = Backoff::default();
+        let delays: Vec<Duration> = (0..10).map(|_| backoff.next()).collect();
+        assert!(delays[0] <= BACKOFF_START);
+        assert!(delays[3] >= Duration::from_secs(4));
+        assert!(delays.iter().all(|&delay| delay <= BACKOFF_CAP));
+        assert!(delays[9] >= BACKOFF_CAP / 2);
+
+        // A relay-requested cooldown is never cut short
+        let overloaded = CloseStatus::Overloaded(Duration::from_secs(120));
+        backoff.reset();
+        assert!(overloaded.reconnect_delay(&mut backoff, false).unwrap() >= Duration::from_secs(120));
+        assert!(backoff.next() <= Duration::from_secs(2));
+
+        // A rejected token is retried at once only if there's a new one to try
+        backoff.reset();
+        assert_eq!(CloseStatus::AuthExpired.reconnect_delay(&mut backoff, true), Some(Duration::ZERO));
+        assert_eq!(CloseStatus::AuthExpired.reconnect_delay(&mut backoff, true), Some(Duration::ZERO));
+        assert!(CloseStatus::AuthExpired.reconnect_delay(&mut backoff, false).unwrap() > Duration::ZERO);
+        assert!(backoff.next() >= Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_relays() {
+        let mut relays = Relays::new(vec!["ws://a".to_string(), "ws://b".to_string(), "ws://c".to_string()]);
+        assert!(relays.advance());
+        assert_eq!(relays.current(), "ws://b");
+        assert!(relays.advance());
+        // All three failed: back off, then start over where the round began
+        assert!(!relays.advance());
+        assert_eq!(relays.current(), "ws://a");
+
+        assert!(relays.advance());
+        relays.reset();
+        assert!(relays.advance() && relays.advance());
+        assert_eq!(relays.current(), "ws://a");
+
+        // A single relay always waits
+        let mut relays = Relays::new(vec!["ws://a".to_string()]);
+        assert!(!relays.advance());
+        assert!(!relays.advance());
+    }
+
+    #[test]
+    fn test_close_status_from_frame() {
+        assert_eq!(CloseStatus::from_frame(None), CloseStatus::Normal);
+        assert_eq!(CloseStatus::from_frame(Some(&frame(1001, "Server shutting down"))), CloseStatus::ShuttingDown);
+        assert_eq!(CloseStatus::from_frame(Some(&frame(4401, ""))), CloseStatus::AuthExpired);
+        assert_eq!(CloseStatus::from_frame(Some(&frame(1008, "JWT expired"))), CloseStatus::AuthExpired);
+        assert_eq!(CloseStatus::from_frame(Some(&frame(4409, ""))), CloseStatus::Kicked);
+        assert_eq!(CloseStatus::from_frame(Some(&frame(4409, ""))).reconnect_delay(&mut Backoff::default(), true), None);
+        assert_eq!(
+            CloseStatus::from_frame(Some(&frame(4000, "bye"))),
+            CloseStatus::Other(4000, "bye".to_string())
+        );
+    }
+}
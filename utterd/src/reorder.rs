@@ -0,0 +1,122 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+/// Sequence numbers this far behind are a restarted sender, not a late message
+const RESTART_GAP: u64 = 64;
+
+/// Puts each sender's messages back in sequence-number order
+///
+/// A message that arrives ahead of a gap (e.g. the phone's live message
+/// overtaking one it queued while offline) is held for up to `window` for
+/// the missing ones. After that the gap is skipped, so a lost message
+/// delays the rest by at most one window.
+pub struct Reorderer<T> {
+    window: Duration,
+    senders: HashMap<String, Sequence<T>>,
+}
+
+struct Sequence<T> {
+    /// Next sequence number to release
+    next: u64,
+    /// Held messages with their arrival time
+    held: BTreeMap<u64, (Instant, T)>,
+}
+
+impl<T> Sequence<T> {
+    /// Release held messages from `next` on until the next gap
+    fn drain(&mut self, out: &mut Vec<T>) {
+        while let Some((_, item)) = self.held.remove(&self.next) {
+            out.push(item);
+            self.next += 1;
+        }
+    }
+}
+
+impl<T> Reorderer<T> {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            senders: HashMap::new(),
+        }
+    }
+
+    /// Accept message `seq` from `sender`; returns the messages now ready, in order
+    pub fn push(&mut self, sender: &str, seq: u64, item: T, now: Instant) -> Vec<T> {
+        let sequence = self.senders.entry(sender.to_string()).or_insert_with(|| Sequence {
+            next: seq,
+            held: BTreeMap::new(),
+        });
+
+        let mut ready = Vec::new();
+        if seq < sequence.next {
+            if sequence.next - seq > RESTART_GAP {
+                // Sender restarted its numbering: flush what's held and start over
+                ready.extend(std::mem::take(&mut sequence.held).into_values().map(|(_, item)| item));
+                sequence.next = seq;
+            } else {
+                // Too late to reorder; typing it now beats dropping it
+                ready.push(item);
+                return ready;
+            }
+        }
+
+        sequence.held.insert(seq, (now, item));
+        sequence.drain(&mut ready);
+        ready
+    }
+
+    /// Release messages that have waited out the window, skipping the gaps before them
+    pub fn expire(&mut self, now: Instant) -> Vec<T> {
+        let mut ready = Vec::new();
+        for sequence in self.senders.values_mut() {
+            while let Some((&first, &(arrived, _))) = sequence.held.iter().next() {
+                if now.duration_since(arrived) < self.window {
+                    break;
+                }
+                sequence.next = first;
+                sequence.drain(&mut ready);
+            }
+        }
+        ready
+    }
+
+    /// When the oldest held message times out, if any are held
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.senders
+            .values()
+            .flat_map(|sequence| sequence.held.values().map(|&(arrived, _)| arrived + self.window))
+            .min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reorder() {
+        let window = Duration::from_millis(500);
+        let start = Instant::now();
+        let mut reorderer = Reorderer::new(window);
+
+        assert_eq!(reorderer.push("phone", 1, "one", start), vec!["one"]);
+        // 3 overtakes 2 and is held until 2 shows up
+        assert!(reorderer.push("phone", 3, "three", start).is_empty());
+        assert_eq!(reorderer.next_deadline(), Some(start + window));
+        assert_eq!(reorderer.push("phone", 2, "two", start), vec!["two", "three"]);
+
+        // A gap that never fills is skipped once the window passes
+        assert!(reorderer.push("phone", 5, "five", start).is_empty());
+        assert!(reorderer.expire(start + window / 2).is_empty());
+        assert_eq!(reorderer.expire(start + window), vec!["five"]);
+        // The missing message turning up afterwards is still typed
+        assert_eq!(reorderer.push("phone", 4, "four", start + window), vec!["four"]);
+
+        // Senders are independent, and a restart resets the numbering
+        assert_eq!(reorderer.push("tablet", 9, "nine", start), vec!["nine"]);
+        assert!(reorderer.push("phone", 1000, "thousand", start).is_empty());
+        assert_eq!(reorderer.expire(start + window), vec!["thousand"]);
+        assert_eq!(reorderer.push("phone", 1, "restarted", start), vec!["restarted"]);
+        assert_eq!(reorderer.next_deadline(), None);
+    }
+}
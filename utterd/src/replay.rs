@@ -0,0 +1,136 @@
+use sha2::{Digest, Sha256};
+use std::collections::{HashSet, VecDeque};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+/// Messages remembered; a phone sends nowhere near this many before the
+/// oldest are past any use to a replaying relay
+const REMEMBERED: usize = 10_000;
+
+type Digest16 = [u8; 16];
+
+/// Encrypted messages already accepted, so a relay can't deliver one twice
+///
+/// A message is identified by its nonce and ephemeral key, which the phone
+/// picks afresh for every encryption; the relay can't forge new ones without
+/// the key. Digests are kept in ~/.local/share/utterd/seen (owner-only) so a
+/// restart doesn't let old messages back in.
+#[derive(Default)]
+pub struct ReplayGuard {
+    /// None: remember for this run only
+    path: Option<PathBuf>,
+    order: VecDeque<Digest16>,
+    seen: HashSet<Digest16>,
+}
+
+impl ReplayGuard {
+    pub fn load() -> Result<Self, String> {
+        let data_dir = dirs::data_dir()
+            .ok_or("Cannot determine data directory")?
+            .join("utterd");
+        fs::create_dir_all(&data_dir).map_err(|e| format!("Failed to create data directory: {}", e))?;
+        Self::open(data_dir.join("seen"))
+    }
+
+    fn open(path: PathBuf) -> Result<Self, String> {
+        let mut guard = Self::default();
+        if path.exists() {
+            let contents = fs::read_to_string(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+            for digest in contents.lines().filter_map(decode) {
+                guard.remember(digest);
+            }
+        }
+        guard.path = Some(path);
+        Ok(guard)
+    }
+
+    /// Record a decrypted message; false if it was accepted before
+    pub fn first_time(&mut self, nonce: &str, ephemeral_public_key: &str) -> Result<bool, String> {
+        let mut hasher = Sha256::new();
+        hasher.update(nonce.as_bytes());
+        hasher.update([0]);
+        hasher.update(ephemeral_public_key.as_bytes());
+        let mut digest = Digest16::default();
+        digest.copy_from_slice(&hasher.finalize()[..16]);
+
+        if self.seen.contains(&digest) {
+            return Ok(false);
+        }
+        self.remember(digest);
+        self.save(digest)?;
+        Ok(true)
+    }
+
+    fn remember(&mut self, digest: Digest16) {
+        if self.order.len() >= REMEMBERED {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        if self.seen.insert(digest) {
+            self.order.push_back(digest);
+        }
+    }
+
+    /// Append `digest`, compacting the file once it holds twice what's remembered
+    fn save(&self, digest: Digest16) -> Result<(), String> {
+        let Some(ref path) = self.path else {
+            return Ok(());
+        };
+        let error = |e: std::io::Error| format!("Failed to write {}: {}", path.display(), e);
+
+        let mut file = OpenOptions::new().create(true).append(true).open(path).map_err(error)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(path, fs::Permissions::from_mode(0o600)).map_err(error)?;
+        }
+        writeln!(file, "{}", encode(&digest)).map_err(error)?;
+
+        let lines = file.metadata().map_err(error)?.len() as usize / 33;
+        if lines > REMEMBERED * 2 {
+            let contents: String = self.order.iter().map(|digest| encode(digest) + "\n").collect();
+            fs::write(path, contents).map_err(error)?;
+        }
+        Ok(())
+    }
+}
+
+fn encode(digest: &Digest16) -> String {
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode(line: &str) -> Option<Digest16> {
+    let mut digest = Digest16::default();
+    if line.len() != 32 || !line.is_ascii() {
+        return None;
+    }
+    for (byte, pair) in digest.iter_mut().zip(line.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(digest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replay_guard() {
+        let path = std::env::temp_dir().join(format!("utterd-seen-test-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut guard = ReplayGuard::open(path.clone()).unwrap();
+        assert_eq!(guard.first_time("nonce1", "key1"), Ok(true));
+        assert_eq!(guard.first_time("nonce1", "key1"), Ok(false));
+        assert_eq!(guard.first_time("nonce1", "key2"), Ok(true));
+
+        // Still refused after a restart
+        let mut reloaded = ReplayGuard::open(path.clone()).unwrap();
+        assert_eq!(reloaded.first_time("nonce1", "key1"), Ok(false));
+        assert_eq!(reloaded.first_time("nonce2", "key1"), Ok(true));
+
+        let _ = fs::remove_file(&path);
+    }
+}
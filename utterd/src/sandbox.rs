@@ -0,0 +1,259 @@
+//! Optional hardening for `--harden`
+//!
+//! Limits what a malicious relay could achieve through utterd:
+//! - typing subprocesses get a minimal environment and (on Linux) a seccomp
+//!   filter that refuses IPv4/IPv6 sockets, so they can't reach the network
+//! - after startup, Landlock makes the filesystem read-only for utterd and
+//!   everything it runs, apart from its data directory and temp/runtime
+//!   directories; the config directory (keys, OAuth tokens, hooks) can no
+//!   longer be changed
+
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Variables typing and clipboard tools need to find the session
+const ENV_ALLOWLIST: &[&str] = &[
+    "PATH",
+    "HOME",
+    "USER",
+    "LANG",
+    "LC_ALL",
+    "LC_CTYPE",
+    "TMPDIR",
+    "DISPLAY",
+    "XAUTHORITY",
+    "WAYLAND_DISPLAY",
+    "XDG_RUNTIME_DIR",
+    "XDG_SESSION_TYPE",
+    "DBUS_SESSION_BUS_ADDRESS",
+    "YDOTOOL_SOCKET",
+    "SYSTEMROOT",
+];
+
+/// Restrict a subprocess before it is spawned
+pub fn restrict_command(cmd: &mut Command) {
+    cmd.env_clear();
+    for name in ENV_ALLOWLIST {
+        if let Some(value) = std::env::var_os(name) {
+            cmd.env(name, value);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    linux::deny_network(cmd);
+}
+
+/// Make the filesystem read-only for this thread and threads and processes
+/// it starts later, except for utterd's own writable directories and
+/// `extra` (e.g. the `--output` pipe)
+///
+/// Landlock can't restrict threads that already exist, so call this from
+/// the main thread before the daemon's runtime starts. Returns a
+/// description of what couldn't be applied, for the user to see.
+pub fn restrict_self(extra: &[PathBuf]) -> Result<(), String> {
+    #[cfg(target_os = "linux")]
+    return linux::restrict_filesystem(extra);
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = extra;
+        Err("filesystem restrictions are only available on Linux".to_string())
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use seccompiler::{
+        BpfProgram, SeccompAction, SeccompCmpArgLen, SeccompCmpOp, SeccompCondition, SeccompFilter, SeccompRule,
+    };
+    use std::collections::BTreeMap;
+    use std::ffi::CString;
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::process::CommandExt;
+    use std::path::{Path, PathBuf};
+    use std::process::Command;
+    use std::sync::OnceLock;
+
+    /// Filter making socket(AF_INET/AF_INET6, ...) fail with EACCES; Unix
+    /// sockets (X11, ydotoold, keyd, D-Bus) keep working
+    fn network_filter() -> Option<&'static BpfProgram> {
+        static FILTER: OnceLock<Option<BpfProgram>> = OnceLock::new();
+        FILTER
+            .get_or_init(|| {
+                let rules = [libc::AF_INET, libc::AF_INET6]
+                    .into_iter()
+                    .map(|family| {
+                        SeccompCondition::new(0, SeccompCmpArgLen::Dword, SeccompCmpOp::Eq, family as u64)
+                            .and_then(|condition| SeccompRule::new(vec![condition]))
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+                    .ok()?;
+
+                let filter = SeccompFilter::new(
+                    BTreeMap::from([(libc::SYS_socket, rules)]),
+                    SeccompAction::Allow,
+                    SeccompAction::Errno(libc::EACCES as u32),
+                    // Unsupported architectures just go without the filter
+                    std::env::consts::ARCH.try_into().ok()?,
+                )
+                .ok()?;
+                filter.try_into().ok()
+            })
+            .as_ref()
+    }
+
+    pub fn deny_network(cmd: &mut Command) {
+        let Some(filter) = network_filter() else {
+            return;
+        };
+        let filter = filter.clone();
+        // SAFETY: apply_filter only makes the prctl and seccomp syscalls,
+        // which are safe to call between fork and exec
+        unsafe {
+            cmd.pre_exec(move || {
+                seccompiler::apply_filter(&filter).map_err(|_| io::Error::from(io::ErrorKind::PermissionDenied))
+            });
+        }
+    }
+
+    // Landlock ABI (linux/landlock.h); libc has the syscall numbers only
+    const CREATE_RULESET_VERSION: u32 = 1 << 0;
+    const RULE_PATH_BENEATH: libc::c_int = 1;
+
+    const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+    const ACCESS_FS_REMOVE_DIR: u64 = 1 << 4;
+    const ACCESS_FS_REMOVE_FILE: u64 = 1 << 5;
+    const ACCESS_FS_MAKE_CHAR: u64 = 1 << 6;
+    const ACCESS_FS_MAKE_DIR: u64 = 1 << 7;
+    const ACCESS_FS_MAKE_REG: u64 = 1 << 8;
+    const ACCESS_FS_MAKE_SOCK: u64 = 1 << 9;
+    const ACCESS_FS_MAKE_FIFO: u64 = 1 << 10;
+    const ACCESS_FS_MAKE_BLOCK: u64 = 1 << 11;
+    const ACCESS_FS_MAKE_SYM: u64 = 1 << 12;
+    /// ABI 2: renames and links across directories
+    const ACCESS_FS_REFER: u64 = 1 << 13;
+    /// ABI 3
+    const ACCESS_FS_TRUNCATE: u64 = 1 << 14;
+
+    #[repr(C)]
+    struct RulesetAttr {
+        handled_access_fs: u64,
+    }
+
+    #[repr(C, packed)]
+    struct PathBeneathAttr {
+        allowed_access: u64,
+        parent_fd: i32,
+    }
+
+    /// Directories that stay writable: history and sync state, the lock
+    /// file, temp files (clipboard tools) and the runtime dir (sockets)
+    fn writable_dirs() -> Vec<PathBuf> {
+        let mut dirs = vec![std::env::temp_dir(), PathBuf::from("/tmp"), PathBuf::from("/dev")];
+        dirs.extend(dirs::data_dir().map(|dir| dir.join("utterd")));
+        dirs.extend(dirs::home_dir().map(|dir| dir.join(".utterd")));
+        dirs.extend(std::env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from));
+        dirs
+    }
+
+    pub fn restrict_filesystem(extra: &[PathBuf]) -> Result<(), String> {
+        // SAFETY: a null attr with the VERSION flag only queries the ABI
+        let abi = unsafe {
+            libc::syscall(libc::SYS_landlock_create_ruleset, std::ptr::null::<RulesetAttr>(), 0, CREATE_RULESET_VERSION)
+        };
+        if abi < 1 {
+            return Err("Landlock is not supported or disabled in this kernel".to_string());
+        }
+
+        let mut handled = ACCESS_FS_WRITE_FILE
+            | ACCESS_FS_REMOVE_DIR
+            | ACCESS_FS_REMOVE_FILE
+            | ACCESS_FS_MAKE_CHAR
+            | ACCESS_FS_MAKE_DIR
+            | ACCESS_FS_MAKE_REG
+            | ACCESS_FS_MAKE_SOCK
+            | ACCESS_FS_MAKE_FIFO
+            | ACCESS_FS_MAKE_BLOCK
+            | ACCESS_FS_MAKE_SYM;
+        if abi >= 2 {
+            handled |= ACCESS_FS_REFER;
+        }
+        if abi >= 3 {
+            handled |= ACCESS_FS_TRUNCATE;
+        }
+
+        let attr = RulesetAttr { handled_access_fs: handled };
+        // SAFETY: attr is a valid landlock_ruleset_attr prefix of the given size
+        let ruleset = unsafe {
+            libc::syscall(libc::SYS_landlock_create_ruleset, &attr, std::mem::size_of::<RulesetAttr>(), 0)
+        } as libc::c_int;
+        if ruleset < 0 {
+            return Err(format!("Cannot create Landlock ruleset: {}", io::Error::last_os_error()));
+        }
+
+        let result = add_rules(ruleset, handled, extra).and_then(|()| {
+            // SAFETY: plain prctl/syscall with a ruleset fd we own
+            unsafe {
+                if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+                    return Err(format!("Cannot set no_new_privs: {}", io::Error::last_os_error()));
+                }
+                if libc::syscall(libc::SYS_landlock_restrict_self, ruleset, 0) != 0 {
+                    return Err(format!("Cannot apply Landlock ruleset: {}", io::Error::last_os_error()));
+                }
+            }
+            Ok(())
+        });
+
+        // SAFETY: closing the fd we created above
+        unsafe { libc::close(ruleset) };
+        result
+    }
+
+    fn add_rules(ruleset: libc::c_int, handled: u64, extra: &[PathBuf]) -> Result<(), String> {
+        for dir in writable_dirs().iter().chain(extra) {
+            let Ok(path) = CString::new(dir.as_os_str().as_bytes()) else {
+                continue;
+            };
+            // Rules on a single file may only grant file rights
+            let allowed = if Path::is_dir(dir) {
+                handled
+            } else {
+                handled & (ACCESS_FS_WRITE_FILE | ACCESS_FS_TRUNCATE)
+            };
+            // SAFETY: path is NUL-terminated; O_PATH opens without reading
+            let fd = unsafe { libc::open(path.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) };
+            if fd < 0 {
+                continue; // Missing directories need no rule
+            }
+
+            let rule = PathBeneathAttr { allowed_access: allowed, parent_fd: fd };
+            // SAFETY: rule is a valid landlock_path_beneath_attr; fd is open
+            let added = unsafe { libc::syscall(libc::SYS_landlock_add_rule, ruleset, RULE_PATH_BENEATH, &rule, 0) };
+            let error = io::Error::last_os_error();
+            // SAFETY: closing the fd opened above
+            unsafe { libc::close(fd) };
+            if added != 0 {
+                return Err(format!("Cannot allow writes to {}: {}", dir.display(), error));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_restrict_command_env() {
+        std::env::set_var("UTTERD_TEST_SECRET", "hunter2");
+        let mut cmd = Command::new("true");
+        restrict_command(&mut cmd);
+
+        let names: Vec<_> = cmd.get_envs().map(|(name, _)| name.to_string_lossy().to_string()).collect();
+        assert!(!names.contains(&"UTTERD_TEST_SECRET".to_string()));
+        assert!(names.contains(&"PATH".to_string()));
+        assert!(cmd.status().unwrap().success());
+    }
+}
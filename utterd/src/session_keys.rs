@@ -0,0 +1,89 @@
+use rand::rngs::OsRng;
+use std::collections::VecDeque;
+use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::Zeroizing;
+
+/// Messages decrypted with a session key before it's replaced
+const ROTATE_AFTER: u32 = 50;
+
+/// Replaced session keys still accepted, for messages already on their way
+const KEPT: usize = 2;
+
+/// Short-lived keys the phones encrypt to instead of the long-term key
+///
+/// A fresh key is made for every relay session and after every 50 messages,
+/// and announced to the paired phones vouched for by the long-term key.
+/// Session keys only ever live in memory and are dropped soon after they're
+/// replaced, so a stolen long-term key can't decrypt traffic the relay
+/// recorded earlier (forward secrecy).
+pub struct SessionKeys {
+    /// Newest first
+    keys: VecDeque<StaticSecret>,
+    used: u32,
+}
+
+impl Default for SessionKeys {
+    fn default() -> Self {
+        Self {
+            keys: VecDeque::from([StaticSecret::random_from_rng(OsRng)]),
+            used: 0,
+        }
+    }
+}
+
+impl SessionKeys {
+    /// The key phones should encrypt to now
+    pub fn public_key(&self) -> [u8; 32] {
+        self.keys.front().map_or([0; 32], |key| *PublicKey::from(key).as_bytes())
+    }
+
+    /// Private keys still accepted, newest first; wiped when dropped
+    pub fn private_keys(&self) -> Zeroizing<Vec<[u8; 32]>> {
+        Zeroizing::new(self.keys.iter().map(StaticSecret::to_bytes).collect())
+    }
+
+    /// Start using a new key, forgetting the oldest beyond those kept
+    pub fn rotate(&mut self) {
+        self.keys.push_front(StaticSecret::random_from_rng(OsRng));
+        self.keys.truncate(KEPT + 1);
+        self.used = 0;
+    }
+
+    /// Count a message decrypted with the key at `index` of `private_keys`;
+    /// true if that used up the current key and a new one must be announced
+    pub fn used(&mut self, index: usize) -> bool {
+        if index != 0 {
+            return false;
+        }
+        self.used += 1;
+        if self.used < ROTATE_AFTER {
+            return false;
+        }
+        self.rotate();
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_keys() {
+        let mut keys = SessionKeys::default();
+        let first = keys.private_keys()[0];
+        for _ in 1..ROTATE_AFTER {
+            assert!(!keys.used(0));
+        }
+        assert!(keys.used(0));
+        assert_eq!(keys.private_keys()[1], first);
+
+        // Late messages to an older key don't count toward the current one
+        assert!(!keys.used(1));
+        for _ in 0..KEPT {
+            keys.rotate();
+        }
+        assert_eq!(keys.private_keys().len(), KEPT + 1);
+        assert!(!keys.private_keys().contains(&first));
+    }
+}
@@ -0,0 +1,15 @@
+use std::process::{Command, Stdio};
+
+/// Speak text through speech-dispatcher, for screen-reader users
+///
+/// `spd-say` queues the utterance with the user's configured voice and
+/// returns immediately. Failures are ignored since the same text is always
+/// printed as well.
+pub fn speak(text: &str) {
+    let _ = Command::new("spd-say")
+        .arg("--")
+        .arg(text)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+}
@@ -0,0 +1,502 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// A transient message under the connection status
+#[derive(Clone)]
+pub struct Notice {
+    pub text: String,
+    pub is_error: bool,
+}
+
+/// Where the next dictation would be typed, checked while the TUI is up
+#[derive(Clone)]
+pub struct Focus {
+    /// Focused window's class, or the `--target-class`; None where the
+    /// focused window can't be known (Wayland)
+    pub class: Option<String>,
+    pub title: Option<String>,
+    /// Typing tool for that window, a profile's if one matches
+    pub tool: String,
+    /// The do-not-type zone it would be held for instead
+    pub held: Option<String>,
+}
+
+/// Why dictations can't be typed right now, and how to fix it
+#[derive(Clone)]
+pub struct Warning {
+    pub problem: String,
+    pub hint: String,
+}
+
+/// A phone waiting for the user to confirm pairing
+#[derive(Clone)]
+pub struct PairingPrompt {
+    pub device_name: String,
+    pub fingerprint: String,
+    /// Emoji derived from both keys that the phone shows too, for a pairing
+    /// request
+    pub sas: Option<String>,
+    /// It didn't ask to pair, it sent a message: trust on first use
+    pub first_contact: bool,
+}
+
+/// A phone connected to the same account, from the relay's device list
+#[derive(Clone)]
+pub struct Phone {
+    pub name: String,
+    /// e.g. "Android 14", if the app reported it
+    pub platform: Option<String>,
+    pub public_key: Option<String>,
+    pub fingerprint: Option<String>,
+    /// Trusted (its key is pinned): its dictations get typed
+    pub can_type: bool,
+}
+
+/// Latency samples kept for the min/avg/max
+const LATENCY_SAMPLES: usize = 50;
+
+/// Recent round trips or delivery delays, for telling a slow network from a
+/// slow phone when dictation feels sluggish
+#[derive(Clone, Default)]
+pub struct Latency {
+    samples: VecDeque<Duration>,
+}
+
+impl Latency {
+    pub fn record(&mut self, sample: Duration) {
+        if self.samples.len() >= LATENCY_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    /// The most recent sample
+    pub fn latest(&self) -> Option<Duration> {
+        self.samples.back().copied()
+    }
+
+    /// Min, average and max of the recent samples
+    pub fn summary(&self) -> Option<(Duration, Duration, Duration)> {
+        let min = *self.samples.iter().min()?;
+        let max = *self.samples.iter().max()?;
+        let avg = self.samples.iter().sum::<Duration>() / self.samples.len() as u32;
+        Some((min, avg, max))
+    }
+}
+
+/// Counts for the status view's statistics line; s in the TUI resets them,
+/// though not the uptime
+#[derive(Clone)]
+pub struct Stats {
+    pub started: Instant,
+    /// When the counters were last reset, if they were
+    pub reset_at: Option<chrono::DateTime<chrono::Local>>,
+    /// Registrations after the first: relay reconnects
+    pub reconnects: u32,
+    pub bytes_received: u64,
+    pub messages_typed: u32,
+    pub characters_typed: u64,
+    registered: bool,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            reset_at: None,
+            reconnects: 0,
+            bytes_received: 0,
+            messages_typed: 0,
+            characters_typed: 0,
+            registered: false,
+        }
+    }
+}
+
+impl Stats {
+    /// Count a registration with the relay, all but the first being reconnects
+    pub fn registered(&mut self) {
+        if self.registered {
+            self.reconnects += 1;
+        }
+        self.registered = true;
+    }
+
+    pub fn typed(&mut self, text: &str) {
+        self.messages_typed += 1;
+        self.characters_typed += text.chars().count() as u64;
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self {
+            started: self.started,
+            reset_at: Some(chrono::Local::now()),
+            registered: self.registered,
+            ..Self::default()
+        };
+    }
+}
+
+/// A dictation typed this session
+#[derive(Clone)]
+pub struct TranscriptEntry {
+    pub id: String,
+    pub time: chrono::DateTime<chrono::Local>,
+    pub text: String,
+}
+
+/// Everything typed this session, for the TUI's transcript view (t) and
+/// its export (e)
+#[derive(Clone, Default)]
+pub struct Transcript {
+    pub entries: Vec<TranscriptEntry>,
+}
+
+impl Transcript {
+    pub fn push(&mut self, id: &str, text: &str) {
+        self.entries.push(TranscriptEntry { id: id.to_string(), time: chrono::Local::now(), text: text.to_string() });
+    }
+
+    /// Replace a dictation's text with its correction
+    pub fn correct(&mut self, id: &str, text: &str) {
+        if let Some(entry) = self.entries.iter_mut().rev().find(|entry| entry.id == id) {
+            entry.text = text.to_string();
+        }
+    }
+
+    /// Write the transcript to a new file in the documents directory (owner-only)
+    pub fn export(&self) -> Result<std::path::PathBuf, String> {
+        let dir = dirs::document_dir().or_else(dirs::home_dir).ok_or("Cannot determine documents directory")?;
+        let path = dir.join(format!("utterd-transcript-{}.txt", chrono::Local::now().format("%Y%m%d-%H%M%S")));
+        let error = |e: std::io::Error| format!("Failed to write {}: {}", path.display(), e);
+        std::fs::write(&path, self.to_text()).map_err(error)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).map_err(error)?;
+        }
+        Ok(path)
+    }
+
+    /// Plain text for the export: one timestamped line per dictation
+    pub fn to_text(&self) -> String {
+        self.entries
+            .iter()
+            .map(|entry| format!("[{}] {}\n", entry.time.format("%Y-%m-%d %H:%M:%S"), entry.text))
+            .collect()
+    }
+}
+
+/// Log entries kept for the TUI's log pane
+const LOG_ENTRIES: usize = 200;
+
+/// How much a log entry matters; the log pane shows those up to the
+/// verbosity picked with v
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error,
+    Info,
+    /// Connection and crypto details: which key and cipher a message used, ...
+    Debug,
+}
+
+impl LogLevel {
+    /// The verbosity after this one, cycling back to errors only
+    pub fn next(self) -> Self {
+        match self {
+            LogLevel::Error => LogLevel::Info,
+            LogLevel::Info => LogLevel::Debug,
+            LogLevel::Debug => LogLevel::Error,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LogLevel::Error => "errors",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct LogEntry {
+    pub time: chrono::DateTime<chrono::Local>,
+    pub level: LogLevel,
+    pub text: String,
+}
+
+/// What utterd would print if the TUI didn't own the terminal: every notice,
+/// error and connection change, plus debug details, newest last
+#[derive(Clone, Default)]
+pub struct Log {
+    entries: VecDeque<LogEntry>,
+}
+
+impl Log {
+    pub fn push(&mut self, level: LogLevel, text: impl Into<String>) {
+        if self.entries.len() >= LOG_ENTRIES {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(LogEntry { time: chrono::Local::now(), level, text: text.into() });
+    }
+
+    /// Entries at `verbosity` or more important
+    pub fn count(&self, verbosity: LogLevel) -> usize {
+        self.entries.iter().filter(|entry| entry.level <= verbosity).count()
+    }
+
+    /// `count` entries at `verbosity` or more important, oldest first: the
+    /// newest, or those before the newest `skip` when scrolled back (as far
+    /// as the oldest)
+    pub fn recent(&self, verbosity: LogLevel, count: usize, skip: usize) -> Vec<&LogEntry> {
+        let skip = skip.min(self.count(verbosity).saturating_sub(count));
+        let mut recent: Vec<_> = self.entries.iter().rev().filter(|entry| entry.level <= verbosity).skip(skip).take(count).collect();
+        recent.reverse();
+        recent
+    }
+}
+
+/// What the user asked for from the TUI or stdin
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Answer to the pending pairing prompt
+    Pair(bool),
+    /// Type the dictations held back by a do-not-type zone, once focus
+    /// leaves utterd's terminal
+    Release,
+    /// Focus left the terminal after a `Release`: type them now
+    ReleaseNow,
+    /// Skip the rest of the wait before the next connection attempt
+    Reconnect,
+    /// The default route changed (Wi-Fi switch, VPN toggle): the
+    /// connection may be dead without knowing it
+    NetworkChanged,
+    /// utterd is quitting: close the connection cleanly
+    Shutdown,
+    /// Tell the paired phones the current session key (and a rotated
+    /// long-term key)
+    AnnounceKeys,
+    /// Type a dictation held for editing (`--edit`) as the user left it
+    Edited { id: String, text: String },
+    /// Drop a dictation held for editing without typing it
+    Discard(String),
+}
+
+/// The dictation waiting in the TUI's editor (`--edit`)
+#[derive(Clone)]
+pub struct Editing {
+    pub id: String,
+    pub sender: String,
+    pub text: String,
+    /// Dictations waiting behind this one
+    pub queued: usize,
+    /// Bumped when the text is replaced under the editor (a correction from
+    /// the phone, an edit that couldn't be typed), so it reloads
+    pub revision: u64,
+}
+
+/// Everything the display shows, shared between the connection task and the TUI
+#[derive(Clone)]
+pub struct AppState {
+    pub client_id: Option<String>,
+    /// Relay in use; changes when failing over to another `--server`
+    pub server_url: String,
+    pub device: String,
+    /// Typing backend in use, as shown in the header
+    pub tool_status: String,
+    pub connected: bool,
+    /// "Connected", "Disconnected: server shutting down", ...
+    pub connection: String,
+    /// Waiting out the backoff before the next connection attempt
+    pub reconnect_pending: bool,
+    /// True while a dictation is being injected (Esc cancels it)
+    pub typing: bool,
+    pub notice: Option<Notice>,
+    /// Set while the typing tool is unusable and dictations go to the clipboard
+    pub warning: Option<Warning>,
+    pub pending_pairing: Option<PairingPrompt>,
+    /// Phones online on the account, while connected
+    pub phones: Vec<Phone>,
+    /// Dictations held back because a do-not-type window was focused
+    pub held: usize,
+    pub focus: Option<Focus>,
+    pub editing: Option<Editing>,
+    pub last_message_timestamp: Option<i64>,
+    pub last_message_sender: Option<String>,
+    pub last_message_text: Option<String>,
+    /// All of the last message's text, for copying it with y
+    pub last_message_full: Option<String>,
+    /// Dictations received this run, so the TUI can tell a new one came
+    pub received: u64,
+    /// Latest interim result of a dictation the phone is still streaming
+    pub interim: Option<String>,
+    /// What the pairing QR code holds (`pairing::pairing_uri`), if the
+    /// desktop has a key
+    pub pairing_code: Option<String>,
+    /// One-time code from the relay a phone joins this desktop's anonymous
+    /// account with (`--anonymous`), also in `pairing_code`
+    pub join_code: Option<String>,
+    /// Fingerprint of this desktop's public key, to compare with the phones
+    pub fingerprint: Option<String>,
+    /// A keypair is loaded, so encrypted messages can be read
+    pub encryption: bool,
+    /// Fingerprint of the key the last typed message was encrypted by
+    pub last_sender_fingerprint: Option<String>,
+    /// WebSocket ping round trips to the relay, while connected
+    pub round_trip: Latency,
+    /// From the phone sending a dictation to it arriving here, by the
+    /// phone's clock
+    pub delivery: Latency,
+    /// How long the typing tool took to type a dictation
+    pub type_time: Latency,
+    pub stats: Stats,
+    pub transcript: Transcript,
+    /// Unencrypted messages are accepted (`--allow-plaintext`)
+    pub insecure: bool,
+    /// Messages refused this run for security reasons (see `audit`)
+    pub rejected: usize,
+    /// Google account this desktop is registered under
+    pub account: Option<String>,
+    /// When the relay's JWT expires (Unix seconds); it's renewed on the
+    /// next connection after that
+    pub session_expires: Option<u64>,
+    pub log: Log,
+}
+
+impl AppState {
+    pub fn new(server_url: String, device: String, tool_status: String) -> Self {
+        Self {
+            client_id: None,
+            server_url,
+            device,
+            tool_status,
+            connected: false,
+            connection: "Connecting...".to_string(),
+            reconnect_pending: false,
+            typing: false,
+            notice: None,
+            warning: None,
+            pending_pairing: None,
+            phones: Vec::new(),
+            held: 0,
+            last_message_timestamp: None,
+            last_message_sender: None,
+            last_sender_fingerprint: None,
+            last_message_text: None,
+            last_message_full: None,
+            received: 0,
+            focus: None,
+            editing: None,
+            interim: None,
+            pairing_code: None,
+            join_code: None,
+            fingerprint: None,
+            encryption: false,
+            round_trip: Latency::default(),
+            delivery: Latency::default(),
+            type_time: Latency::default(),
+            stats: Stats::default(),
+            transcript: Transcript::default(),
+            insecure: false,
+            rejected: 0,
+            account: None,
+            session_expires: None,
+            log: Log::default(),
+        }
+    }
+
+    /// Show a notice under the connection status, logging it too
+    pub fn set_notice(&mut self, text: &str, is_error: bool) {
+        self.notice = Some(Notice {
+            text: text.to_string(),
+            is_error,
+        });
+        self.log.push(if is_error { LogLevel::Error } else { LogLevel::Info }, text);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latency() {
+        let mut latency = Latency::default();
+        assert!(latency.summary().is_none());
+        for ms in [40, 10, 70] {
+            latency.record(Duration::from_millis(ms));
+        }
+        assert_eq!(latency.latest(), Some(Duration::from_millis(70)));
+        assert_eq!(
+            latency.summary(),
+            Some((Duration::from_millis(10), Duration::from_millis(40), Duration::from_millis(70)))
+        );
+
+        // Only the most recent samples count
+        for _ in 0..LATENCY_SAMPLES {
+            latency.record(Duration::from_millis(5));
+        }
+        assert_eq!(latency.summary().map(|(_, _, max)| max), Some(Duration::from_millis(5)));
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut stats = Stats::default();
+        stats.registered();
+        stats.typed("héllo");
+        assert_eq!((stats.reconnects, stats.messages_typed, stats.characters_typed), (0, 1, 5));
+
+        stats.registered();
+        stats.bytes_received += 512;
+        stats.reset();
+        assert_eq!((stats.reconnects, stats.bytes_received, stats.messages_typed), (0, 0, 0));
+        assert!(stats.reset_at.is_some());
+
+        // Still registered, so the next registration is a reconnect
+        stats.registered();
+        assert_eq!(stats.reconnects, 1);
+    }
+
+    #[test]
+    fn test_transcript() {
+        let mut transcript = Transcript::default();
+        transcript.push("a1", "Agenda for Monday");
+        transcript.push("b2", "first item budgte");
+        transcript.correct("b2", "first item budget");
+        transcript.correct("zz", "unknown");
+
+        let text = transcript.to_text();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with('[') && lines[0].ends_with("] Agenda for Monday"));
+        assert!(lines[1].ends_with("] first item budget"));
+    }
+
+    #[test]
+    fn test_log() {
+        let mut log = Log::default();
+        log.push(LogLevel::Info, "Connected");
+        log.push(LogLevel::Debug, "Decrypted with aes-256-gcm");
+        log.push(LogLevel::Error, "Relay: JWT expired");
+
+        let texts = |verbosity| log.recent(verbosity, 10, 0).iter().map(|entry| entry.text.clone()).collect::<Vec<_>>();
+        assert_eq!(texts(LogLevel::Error), ["Relay: JWT expired"]);
+        assert_eq!(texts(LogLevel::Info), ["Connected", "Relay: JWT expired"]);
+        assert_eq!(texts(LogLevel::Debug).len(), 3);
+        assert_eq!(log.recent(LogLevel::Debug, 1, 0)[0].text, "Relay: JWT expired");
+        assert_eq!(log.recent(LogLevel::Debug, 1, 2)[0].text, "Connected");
+        assert_eq!(log.recent(LogLevel::Debug, 2, 5)[0].text, "Connected");
+        assert_eq!(log.count(LogLevel::Info), 2);
+
+        for i in 0..LOG_ENTRIES {
+            log.push(LogLevel::Info, format!("line {}", i));
+        }
+        assert_eq!(log.recent(LogLevel::Debug, LOG_ENTRIES * 2, 0).len(), LOG_ENTRIES);
+        assert_eq!(LogLevel::Debug.next(), LogLevel::Error);
+    }
+}
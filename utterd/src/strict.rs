@@ -0,0 +1,152 @@
+use crate::crypto::Cipher;
+use base64::{engine::general_purpose, Engine as _};
+use serde_json::{Map, Value};
+
+/// Largest message accepted with `--strict`: a maximum-size config blob
+/// plus its envelope
+pub const MAX_MESSAGE_SIZE: usize = 96 * 1024;
+
+/// Fields every relay message may carry that utterd doesn't use
+const COMMON_FIELDS: &[&str] = &["type", "timestamp"];
+
+/// Fields the relay sends that utterd doesn't model, per message type
+fn ignored_fields(kind: &str) -> &'static [&'static str] {
+    match kind {
+        "connected" => &["message"],
+        "registered" => &["clientId", "deviceId", "clientType", "userId"],
+        "config" => &["updatedAt"],
+        "pairing_code" => &["expiresIn"],
+        _ => &[],
+    }
+}
+
+/// Base64 fields and the decoded length they must have, if fixed (the
+/// nonce's depends on the cipher)
+const BASE64_FIELDS: &[(&str, Option<usize>)] = &[
+    ("ephemeralPublicKey", Some(32)),
+    ("senderPublicKey", Some(32)),
+    ("blob", None),
+];
+
+#[derive(Debug, Default, PartialEq)]
+enum Phase {
+    #[default]
+    AwaitingConnected,
+    AwaitingRegistered,
+    Registered,
+}
+
+/// Protocol checks for relays the user doesn't operate (`--strict`)
+///
+/// Lenient parsing ignores what it doesn't understand; here every anomaly
+/// is reported so the caller can drop the connection. One per session.
+#[derive(Default)]
+pub struct Session {
+    phase: Phase,
+}
+
+impl Session {
+    /// Check a relay message, given as received (`raw`) and as utterd
+    /// understood it (`parsed`, the `WsMessage` serialized back)
+    pub fn check(&mut self, raw: &Value, parsed: &Value) -> Result<(), String> {
+        let (Some(raw), Some(parsed)) = (raw.as_object(), parsed.as_object()) else {
+            return Err("message is not a JSON object".to_string());
+        };
+        let kind = raw.get("type").and_then(Value::as_str).unwrap_or_default();
+
+        if let Some(field) = raw.keys().find(|field| {
+            !parsed.contains_key(*field)
+                && !COMMON_FIELDS.contains(&field.as_str())
+                && !ignored_fields(kind).contains(&field.as_str())
+        }) {
+            return Err(format!("unexpected field `{}` in `{}` message", field, kind));
+        }
+
+        check_base64(kind, raw)?;
+        self.check_order(kind)
+    }
+
+    fn check_order(&mut self, kind: &str) -> Result<(), String> {
+        self.phase = match (&self.phase, kind) {
+            // The relay reports errors (e.g. a rejected JWT) at any point
+            (_, "error") => return Ok(()),
+            (Phase::AwaitingConnected, "connected") => Phase::AwaitingRegistered,
+            (Phase::AwaitingRegistered, "registered") => Phase::Registered,
+            (Phase::Registered, "connected" | "registered") => {
+                return Err(format!("`{}` repeated mid-session", kind));
+            }
+            (Phase::Registered, _) => Phase::Registered,
+            (phase, _) => return Err(format!("`{}` message while {:?}", kind, phase)),
+        };
+        Ok(())
+    }
+}
+
+fn check_base64(kind: &str, raw: &Map<String, Value>) -> Result<(), String> {
+    let mut fields: Vec<(&str, Option<usize>)> = BASE64_FIELDS.to_vec();
+    let cipher = match raw.get("cipher") {
+        None => Cipher::default(),
+        Some(name) => name
+            .as_str()
+            .and_then(Cipher::from_name)
+            .ok_or_else(|| format!("unsupported `cipher` in `{}` message", kind))?,
+    };
+    fields.push(("nonce", Some(cipher.nonce_length())));
+    // Encrypted payloads are base64 ciphertext too
+    if raw.get("encrypted") == Some(&Value::Bool(true)) {
+        fields.push((if kind == "key" { "key" } else { "content" }, None));
+    }
+
+    for (field, length) in fields {
+        let Some(value) = raw.get(field) else {
+            continue;
+        };
+        let bytes = value
+            .as_str()
+            .and_then(|s| general_purpose::STANDARD.decode(s).ok())
+            .ok_or_else(|| format!("`{}` in `{}` message is not valid base64", field, kind))?;
+        if length.is_some_and(|length| bytes.len() != length) {
+            return Err(format!("`{}` in `{}` message decodes to {} bytes", field, kind, bytes.len()));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_strict_session() {
+        let mut session = Session::default();
+        let text = json!({"type": "text", "content": "aGk=", "encrypted": true, "nonce": "AAAAAAAAAAAAAAAA"});
+
+        // Nothing but connected/registered before registration
+        assert!(session.check(&text, &text).is_err());
+        let connected = json!({"type": "connected", "clientId": "c1", "message": "hi", "timestamp": 1});
+        assert!(session.check(&connected, &json!({"type": "connected", "clientId": "c1"})).is_ok());
+        assert!(session.check(&json!({"type": "registered", "userId": "u"}), &json!({"type": "registered"})).is_ok());
+        assert!(session.check(&text, &text).is_ok());
+        assert!(session.check(&connected, &connected).is_err());
+
+        // Fields utterd didn't parse, bad base64 and wrong key lengths
+        let parsed = text.clone();
+        let mut extra = text.clone();
+        extra["exec"] = json!("rm -rf ~");
+        assert!(session.check(&extra, &parsed).unwrap_err().contains("`exec`"));
+        let bad = json!({"type": "text", "content": "not base64!", "encrypted": true});
+        assert!(session.check(&bad, &bad).is_err());
+        let short = json!({"type": "key", "key": "aGk=", "encrypted": true, "ephemeralPublicKey": "aGk="});
+        assert!(session.check(&short, &short).unwrap_err().contains("2 bytes"));
+
+        // XChaCha20-Poly1305 nonces are 24 bytes
+        let mut xchacha = text.clone();
+        xchacha["cipher"] = json!("xchacha20-poly1305");
+        assert!(session.check(&xchacha, &xchacha).unwrap_err().contains("12 bytes"));
+        xchacha["nonce"] = json!(general_purpose::STANDARD.encode([0u8; 24]));
+        assert!(session.check(&xchacha, &xchacha).is_ok());
+        xchacha["cipher"] = json!("rot13");
+        assert!(session.check(&xchacha, &xchacha).unwrap_err().contains("unsupported"));
+    }
+}
@@ -0,0 +1,269 @@
+//! End-to-end encrypted sync of the config directory between desktops
+//!
+//! The relay keeps one opaque blob per account plus a version number that
+//! it bumps on every upload. The key is stretched from a passphrase the
+//! user enters on each desktop (Argon2id, salted with the account id), so
+//! the relay never sees the contents.
+//!
+//! Each desktop remembers the version and content hash it last synced. On
+//! connect it compares both sides: a local edit is uploaded, a remote one
+//! written to disk, and when both changed the remote copy is saved next to
+//! the local file as `<name>.remote` for the user to merge. The merged
+//! local file is then uploaded on the next connect.
+
+use aes_gcm::aead::{Aead, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Nonce};
+use argon2::Argon2;
+use base64::{engine::general_purpose, Engine as _};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+/// Files in the config directory that are synced, when present
+const SYNCED_FILES: &[&str] = &["config.toml"];
+
+/// Upload limit enforced by the relay (base64 of the encrypted bundle)
+pub const MAX_BLOB_LENGTH: usize = 64 * 1024;
+
+/// File name → contents
+type Bundle = BTreeMap<String, String>;
+
+/// What this desktop last agreed on with the relay
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SyncState {
+    version: u64,
+    hash: String,
+}
+
+/// An encrypted upload for the relay
+pub struct Upload {
+    pub blob: String,
+    pub nonce: String,
+    /// Version the upload is based on; the relay refuses it if it has moved on
+    pub base_version: u64,
+}
+
+/// Result of comparing the local files with the relay's copy
+pub enum Outcome {
+    UpToDate,
+    Push(Upload),
+    /// Remote changes were written to disk
+    Pulled,
+    /// Both sides changed; the remote copies were saved with these paths
+    Conflict(Vec<PathBuf>),
+}
+
+pub struct ConfigSync {
+    key: [u8; 32],
+    config_dir: PathBuf,
+    state_path: PathBuf,
+}
+
+impl ConfigSync {
+    pub fn new(passphrase: &str, user_id: &str) -> Result<Self, String> {
+        let config_dir = crate::config::dir().ok_or("Cannot determine config directory")?;
+        let data_dir = dirs::data_dir()
+            .ok_or("Cannot determine data directory")?
+            .join("utterd");
+        fs::create_dir_all(&data_dir)
+            .map_err(|e| format!("Failed to create data directory: {}", e))?;
+
+        Ok(Self {
+            key: derive_key(passphrase, user_id)?,
+            config_dir,
+            state_path: data_dir.join("sync.json"),
+        })
+    }
+
+    /// Decide what to do given the relay's copy (None if it has none)
+    pub fn reconcile(&self, remote: Option<(u64, &str, &str)>) -> Result<Outcome, String> {
+        let local = self.read_local()?;
+        let local_hash = hash(&local);
+        let state = self.load_state();
+
+        let Some((version, blob, nonce)) = remote else {
+            // Nothing uploaded yet (or the relay lost it on restart)
+            if local.is_empty() {
+                return Ok(Outcome::UpToDate);
+            }
+            return Ok(Outcome::Push(self.seal(&local, 0)?));
+        };
+
+        // A desktop that never synced and has no files has nothing to keep
+        let local_changed = local_hash != state.hash && !(state.hash.is_empty() && local.is_empty());
+        if version == state.version {
+            return Ok(if local_changed {
+                Outcome::Push(self.seal(&local, version)?)
+            } else {
+                Outcome::UpToDate
+            });
+        }
+
+        let remote = self.open(blob, nonce)?;
+        let remote_hash = hash(&remote);
+        if remote_hash == local_hash {
+            self.save_state(&SyncState { version, hash: remote_hash })?;
+            return Ok(Outcome::UpToDate);
+        }
+
+        if !local_changed {
+            self.write_files(&remote, "")?;
+            self.save_state(&SyncState { version, hash: remote_hash })?;
+            return Ok(Outcome::Pulled);
+        }
+
+        // Keep the old hash so the user's merge counts as a local change
+        let paths = self.write_files(&remote, ".remote")?;
+        self.save_state(&SyncState { version, hash: state.hash })?;
+        Ok(Outcome::Conflict(paths))
+    }
+
+    /// Record that the relay accepted our upload as `version`
+    pub fn saved(&self, version: u64) -> Result<(), String> {
+        let hash = hash(&self.read_local()?);
+        self.save_state(&SyncState { version, hash })
+    }
+
+    fn read_local(&self) -> Result<Bundle, String> {
+        let mut bundle = Bundle::new();
+        for name in SYNCED_FILES {
+            let path = self.config_dir.join(name);
+            if path.exists() {
+                let contents = fs::read_to_string(&path)
+                    .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+                bundle.insert(name.to_string(), contents);
+            }
+        }
+        Ok(bundle)
+    }
+
+    /// Write the bundle's files with `suffix` appended to each name
+    fn write_files(&self, bundle: &Bundle, suffix: &str) -> Result<Vec<PathBuf>, String> {
+        fs::create_dir_all(&self.config_dir)
+            .map_err(|e| format!("Failed to create config directory: {}", e))?;
+
+        let mut paths = Vec::new();
+        // Only write names we know; the bundle came from another machine
+        for (name, contents) in bundle.iter().filter(|(name, _)| SYNCED_FILES.contains(&name.as_str())) {
+            let path = self.config_dir.join(format!("{}{}", name, suffix));
+            fs::write(&path, contents).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+            paths.push(path);
+        }
+        Ok(paths)
+    }
+
+    fn seal(&self, bundle: &Bundle, base_version: u64) -> Result<Upload, String> {
+        let plaintext = serde_json::to_vec(bundle).map_err(|e| e.to_string())?;
+
+        let mut nonce = [0u8; 12];
+        OsRng.fill_bytes(&mut nonce);
+        let cipher = Aes256Gcm::new_from_slice(&self.key).map_err(|e| e.to_string())?;
+        let ciphertext = cipher
+            .encrypt(&Nonce::from(nonce), plaintext.as_ref())
+            .map_err(|e| format!("Config encryption failed: {}", e))?;
+
+        let blob = general_purpose::STANDARD.encode(ciphertext);
+        if blob.len() > MAX_BLOB_LENGTH {
+            return Err(format!("Config too large to sync ({} of {} bytes)", blob.len(), MAX_BLOB_LENGTH));
+        }
+
+        Ok(Upload {
+            blob,
+            nonce: general_purpose::STANDARD.encode(nonce),
+            base_version,
+        })
+    }
+
+    fn open(&self, blob: &str, nonce: &str) -> Result<Bundle, String> {
+        let ciphertext = general_purpose::STANDARD.decode(blob).map_err(|e| format!("Invalid config blob: {}", e))?;
+        let nonce: [u8; 12] = general_purpose::STANDARD
+            .decode(nonce)
+            .map_err(|e| format!("Invalid config nonce: {}", e))?
+            .try_into()
+            .map_err(|_| "Invalid config nonce length".to_string())?;
+
+        let cipher = Aes256Gcm::new_from_slice(&self.key).map_err(|e| e.to_string())?;
+        let plaintext = cipher
+            .decrypt(&Nonce::from(nonce), ciphertext.as_ref())
+            .map_err(|_| "Cannot decrypt synced config (different sync passphrase on another desktop?)".to_string())?;
+
+        serde_json::from_slice(&plaintext).map_err(|e| format!("Invalid synced config: {}", e))
+    }
+
+    fn load_state(&self) -> SyncState {
+        fs::read_to_string(&self.state_path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    fn save_state(&self, state: &SyncState) -> Result<(), String> {
+        let json = serde_json::to_string(state).map_err(|e| e.to_string())?;
+        fs::write(&self.state_path, json).map_err(|e| format!("Failed to write sync state: {}", e))
+    }
+}
+
+/// Stretch the passphrase into an AES-256 key, salted with the account id
+/// so equal passphrases on different accounts give different keys
+fn derive_key(passphrase: &str, user_id: &str) -> Result<[u8; 32], String> {
+    let salt = Sha256::digest(format!("utter-config-sync:{}", user_id));
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+        .map_err(|e| format!("Key derivation failed: {}", e))?;
+    Ok(key)
+}
+
+fn hash(bundle: &Bundle) -> String {
+    let json = serde_json::to_vec(bundle).unwrap_or_default();
+    Sha256::digest(json).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sync_in(dir: &std::path::Path, passphrase: &str) -> ConfigSync {
+        ConfigSync {
+            key: derive_key(passphrase, "user@example.com").unwrap(),
+            config_dir: dir.join("config"),
+            state_path: dir.join("sync.json"),
+        }
+    }
+
+    #[test]
+    fn test_reconcile() {
+        let dir = std::env::temp_dir().join(format!("utterd-sync-test-{}", std::process::id()));
+        let a = sync_in(&dir.join("a"), "secret");
+        let b = sync_in(&dir.join("b"), "secret");
+        fs::create_dir_all(&a.config_dir).unwrap();
+        fs::create_dir_all(&b.config_dir).unwrap();
+
+        // A uploads its config; B pulls it
+        fs::write(a.config_dir.join("config.toml"), "normalize = 1\n").unwrap();
+        let Outcome::Push(upload) = a.reconcile(None).unwrap() else { panic!("expected push") };
+        a.saved(1).unwrap();
+        assert!(matches!(b.reconcile(Some((1, &upload.blob, &upload.nonce))).unwrap(), Outcome::Pulled));
+        assert_eq!(fs::read_to_string(b.config_dir.join("config.toml")).unwrap(), "normalize = 1\n");
+        assert!(matches!(b.reconcile(Some((1, &upload.blob, &upload.nonce))).unwrap(), Outcome::UpToDate));
+
+        // Both edit: B keeps its file and gets the remote copy beside it
+        fs::write(a.config_dir.join("config.toml"), "from_a = 1\n").unwrap();
+        let Outcome::Push(upload) = a.reconcile(Some((1, "", ""))).unwrap() else { panic!("expected push") };
+        assert_eq!(upload.base_version, 1);
+        fs::write(b.config_dir.join("config.toml"), "from_b = 1\n").unwrap();
+        assert!(matches!(b.reconcile(Some((2, &upload.blob, &upload.nonce))).unwrap(), Outcome::Conflict(_)));
+        assert_eq!(fs::read_to_string(b.config_dir.join("config.toml.remote")).unwrap(), "from_a = 1\n");
+        // The merged file is uploaded next time
+        assert!(matches!(b.reconcile(Some((2, &upload.blob, &upload.nonce))).unwrap(), Outcome::Push(_)));
+
+        // A different passphrase can't read it
+        let c = sync_in(&dir.join("c"), "other");
+        assert!(c.reconcile(Some((2, &upload.blob, &upload.nonce))).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,87 @@
+//! Colors of the TUI, from the `[theme]` section of config.toml
+
+use crate::config::ThemeConfig;
+use ratatui::style::{Color, Modifier, Style};
+use std::str::FromStr;
+
+/// Styles the TUI draws with, named for what they mean rather than their
+/// color
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Theme {
+    /// Titles and questions for the user
+    pub accent: Style,
+    /// Connected; phones that can type
+    pub ok: Style,
+    pub warning: Style,
+    pub error: Style,
+    /// Secondary details: the header line, timestamps, unpaired phones
+    pub muted: Style,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            accent: Style::default().fg(Color::Cyan),
+            ok: Style::default().fg(Color::Green),
+            warning: Style::default().fg(Color::Yellow),
+            error: Style::default().fg(Color::Red),
+            muted: Style::default().fg(Color::DarkGray),
+        }
+    }
+}
+
+impl Theme {
+    /// No colors at all, for light terminals, high-contrast setups and
+    /// NO_COLOR: emphasis through bold, reverse video and dimming only
+    pub fn monochrome() -> Self {
+        Self {
+            accent: Style::default().add_modifier(Modifier::BOLD),
+            ok: Style::default(),
+            warning: Style::default().add_modifier(Modifier::BOLD),
+            error: Style::default().add_modifier(Modifier::BOLD | Modifier::REVERSED),
+            muted: Style::default().add_modifier(Modifier::DIM),
+        }
+    }
+
+    /// The default colors with those `config` overrides; every color is
+    /// checked even when `monochrome` makes them moot
+    pub fn from_config(config: &ThemeConfig) -> Result<Self, String> {
+        let mut theme = Self::default();
+        for (style, name, value) in [
+            (&mut theme.accent, "accent", &config.accent),
+            (&mut theme.ok, "ok", &config.ok),
+            (&mut theme.warning, "warning", &config.warning),
+            (&mut theme.error, "error", &config.error),
+            (&mut theme.muted, "muted", &config.muted),
+        ] {
+            if let Some(value) = value {
+                let color = Color::from_str(value).map_err(|_| {
+                    format!("theme.{}: unknown color {:?} (a name such as \"light-blue\", \"#rrggbb\" or 0-255)", name, value)
+                })?;
+                *style = Style::default().fg(color);
+            }
+        }
+        Ok(if config.monochrome { Self::monochrome() } else { theme })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_config() {
+        let parse = |s: &str| Theme::from_config(&toml::from_str::<ThemeConfig>(s).unwrap());
+
+        assert_eq!(parse("").unwrap(), Theme::default());
+        let theme = parse("accent = \"light-blue\"\nerror = \"#ff5555\"\nmuted = \"244\"").unwrap();
+        assert_eq!(theme.accent.fg, Some(Color::LightBlue));
+        assert_eq!(theme.error.fg, Some(Color::Rgb(0xff, 0x55, 0x55)));
+        assert_eq!(theme.muted.fg, Some(Color::Indexed(244)));
+        assert_eq!(theme.ok, Theme::default().ok);
+
+        assert_eq!(parse("monochrome = true\nok = \"green\"").unwrap(), Theme::monochrome());
+        assert!(Theme::monochrome().error.fg.is_none());
+        assert!(parse("warning = \"orangeish\"").unwrap_err().contains("theme.warning"));
+    }
+}
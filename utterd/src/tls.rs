@@ -0,0 +1,184 @@
+use crate::headers::Headers;
+use crate::proxy::Proxy;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use tokio_tungstenite::Connector;
+
+/// How to verify the relay's certificate (`--tls-ca`, `--tls-insecure`)
+/// and which one to present to it (`--tls-cert`, `--tls-key`)
+///
+/// Applies to both the WebSocket and the HTTPS calls for the JWT, which go
+/// to the same host.
+#[derive(Clone, Default)]
+pub struct Tls {
+    /// The system roots plus `--tls-ca`, the client certificate and whether
+    /// checks are skipped; None when none of those were given
+    config: Option<Arc<ClientConfig>>,
+    /// Skip certificate and host name checks entirely
+    insecure: bool,
+    /// For the JWT exchange
+    http: reqwest::Client,
+}
+
+impl Tls {
+    /// Read the files now: they may no longer be readable once hardened
+    ///
+    /// The JWT exchange goes through `proxy` like the WebSocket does.
+    pub fn new(
+        ca: Option<&Path>,
+        client_cert: Option<(&Path, &Path)>,
+        insecure: bool,
+        proxy: Option<&Proxy>,
+        headers: &Headers,
+    ) -> Result<Self, String> {
+        let read = |path: &Path| fs::read(path).map_err(|e| format!("Cannot read {}: {}", path.display(), e));
+        let ca = ca.map(read).transpose()?;
+        let identity = client_cert
+            .map(|(cert, key)| Ok::<_, String>((read(cert)?, read(key)?)))
+            .transpose()?;
+
+        // Fail at startup rather than on every connection attempt
+        let config = if ca.is_some() || identity.is_some() || insecure {
+            let identity = identity.as_ref().map(|(cert, key)| (cert.as_slice(), key.as_slice()));
+            Some(Arc::new(client_config(ca.as_deref(), identity, insecure)?))
+        } else {
+            None
+        };
+        let mut http = reqwest::Client::builder();
+        if let Some(ref config) = config {
+            http = http.use_preconfigured_tls(ClientConfig::clone(config));
+        }
+        http = http.default_headers(headers.for_http()?);
+        http = match proxy {
+            Some(proxy) => http.proxy(proxy.for_http()?),
+            // The environment was already weighed when picking the proxy
+            None => http.no_proxy(),
+        };
+        let http = http.build().map_err(|e| format!("TLS setup failed: {}", e))?;
+
+        Ok(Self { config, insecure, http })
+    }
+
+    pub fn is_insecure(&self) -> bool {
+        self.insecure
+    }
+
+    /// Connector for the WebSocket, or None to use the system root store
+    pub fn connector(&self) -> Result<Option<Connector>, String> {
+        Ok(self.config.clone().map(Connector::Rustls))
+    }
+
+    /// HTTP client with the same trust and client certificate as the WebSocket
+    pub fn http(&self) -> &reqwest::Client {
+        &self.http
+    }
+}
+
+/// rustls client settings: the system's roots and `ca`, or no checks at all
+/// when `insecure`, presenting `identity` (certificate chain, key) if given
+fn client_config(ca: Option<&[u8]>, identity: Option<(&[u8], &[u8])>, insecure: bool) -> Result<ClientConfig, String> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| format!("TLS setup failed: {}", e))?;
+    let builder = if insecure {
+        builder.dangerous().with_custom_certificate_verifier(Arc::new(AcceptAnyCertificate(provider)))
+    } else {
+        let mut roots = RootCertStore::empty();
+        // Certificates the system store has that rustls can't parse are skipped
+        roots.add_parsable_certificates(rustls_native_certs::load_native_certs().certs);
+        if let Some(pem) = ca {
+            for cert in read_certificates(pem).map_err(|e| format!("Not a PEM certificate: {}", e))? {
+                roots.add(cert).map_err(|e| format!("Invalid CA: {}", e))?;
+            }
+        }
+        builder.with_root_certificates(roots)
+    };
+
+    let Some((cert, key)) = identity else {
+        return Ok(builder.with_no_client_auth());
+    };
+    let chain = read_certificates(cert).map_err(identity_error)?;
+    let key = PrivateKeyDer::from_pem_slice(key).map_err(|e| identity_error(e.to_string()))?;
+    builder.with_client_auth_cert(chain, key).map_err(|e| identity_error(e.to_string()))
+}
+
+/// The certificates in a PEM file, at least one
+fn read_certificates(pem: &[u8]) -> Result<Vec<CertificateDer<'static>>, String> {
+    let certs = CertificateDer::pem_slice_iter(pem).collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())?;
+    if certs.is_empty() {
+        return Err("no BEGIN CERTIFICATE block".to_string());
+    }
+    Ok(certs)
+}
+
+fn identity_error(e: String) -> String {
+    format!("Invalid client certificate or key ({}); both must be unencrypted PEM files", e)
+}
+
+/// `--tls-insecure`: takes any certificate for any host name, only checking
+/// that the handshake is signed by the key in it
+#[derive(Debug)]
+struct AcceptAnyCertificate(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tls_options() {
+        // Defaults leave tokio-tungstenite to its own connector
+        assert!(Tls::default().connector().unwrap().is_none());
+        assert!(Tls::new(None, None, true, None, &Headers::default()).unwrap().connector().unwrap().is_some());
+
+        let path = std::env::temp_dir().join(format!("utterd-test-ca-{}.pem", std::process::id()));
+        fs::write(&path, "not a certificate").unwrap();
+        let result = Tls::new(Some(&path), None, false, None, &Headers::default());
+        assert!(Tls::new(None, Some((&path, &path)), false, None, &Headers::default()).is_err());
+        fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+
+        assert!(Tls::new(Some(Path::new("/nonexistent/ca.pem")), None, false, None, &Headers::default()).is_err());
+    }
+}
@@ -0,0 +1,111 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// An utterance with no interim result or final for this long was abandoned
+/// (the phone lost its connection, recognition was cancelled)
+const ABANDONED: Duration = Duration::from_secs(30);
+
+/// Finished utterance ids remembered to ignore partials that arrive late
+const RECENT_FINISHED: usize = 64;
+
+/// Where a streamed utterance stands
+#[derive(Debug, Clone, PartialEq)]
+pub enum Progress {
+    /// Nothing typed yet; interim results wait for the final
+    Buffered,
+    /// Interim results typed as they came (`--live`); this is on screen
+    Typed(String),
+    /// Live typing stopped partway (focus moved, another dictation came in),
+    /// so what's on screen can't be fixed up anymore
+    Interrupted,
+}
+
+struct Utterance {
+    sender: String,
+    text: String,
+    progress: Progress,
+    updated: Instant,
+}
+
+/// Assembles `text_partial` streams: the latest interim text of each
+/// utterance still being dictated, until its final `text` arrives
+#[derive(Default)]
+pub struct Transcripts {
+    utterances: HashMap<String, Utterance>,
+    finished: VecDeque<String>,
+}
+
+impl Transcripts {
+    /// Take interim `text` for utterance `id`, returning how far it got;
+    /// None if its final already arrived
+    pub fn update(&mut self, id: &str, sender: &str, text: String, now: Instant) -> Option<Progress> {
+        if self.finished.iter().any(|finished| finished == id) {
+            return None;
+        }
+        let utterance = self.utterances.entry(id.to_string()).or_insert_with(|| Utterance {
+            sender: sender.to_string(),
+            text: String::new(),
+            progress: Progress::Buffered,
+            updated: now,
+        });
+        utterance.text = text;
+        utterance.updated = now;
+        Some(utterance.progress.clone())
+    }
+
+    pub fn set_progress(&mut self, id: &str, progress: Progress) {
+        if let Some(utterance) = self.utterances.get_mut(id) {
+            utterance.progress = progress;
+        }
+    }
+
+    /// The final for `id` arrived: forget the utterance, returning how far
+    /// live typing got if it was streamed
+    pub fn finish(&mut self, id: &str) -> Option<Progress> {
+        if self.finished.len() >= RECENT_FINISHED {
+            self.finished.pop_front();
+        }
+        self.finished.push_back(id.to_string());
+        self.utterances.remove(id).map(|utterance| utterance.progress)
+    }
+
+    /// Drop utterances that stopped streaming without a final
+    pub fn expire(&mut self, now: Instant) {
+        self.utterances.retain(|_, utterance| now.duration_since(utterance.updated) < ABANDONED);
+    }
+
+    /// Sender and text of the utterance that changed last, for the display
+    pub fn latest(&self) -> Option<(&str, &str)> {
+        self.utterances
+            .values()
+            .max_by_key(|utterance| utterance.updated)
+            .map(|utterance| (utterance.sender.as_str(), utterance.text.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transcripts() {
+        let start = Instant::now();
+        let mut transcripts = Transcripts::default();
+        assert_eq!(transcripts.update("u1", "pixel", "meet".to_string(), start), Some(Progress::Buffered));
+        transcripts.set_progress("u1", Progress::Typed("meet".to_string()));
+        assert_eq!(
+            transcripts.update("u1", "pixel", "meet at nine".to_string(), start),
+            Some(Progress::Typed("meet".to_string()))
+        );
+        assert_eq!(transcripts.latest(), Some(("pixel", "meet at nine")));
+
+        // A partial that lost the race with its final is ignored
+        assert_eq!(transcripts.finish("u1"), Some(Progress::Typed("meet".to_string())));
+        assert_eq!(transcripts.update("u1", "pixel", "meet at".to_string(), start), None);
+        assert_eq!(transcripts.finish("u2"), None);
+
+        transcripts.update("u3", "pixel", "hello".to_string(), start);
+        transcripts.expire(start + ABANDONED);
+        assert_eq!(transcripts.latest(), None);
+    }
+}
@@ -0,0 +1,851 @@
+use crate::clipboard;
+use crate::config::Alert;
+use crate::state::{AppState, Command, Editing, Latency, LogLevel};
+use crate::{redacted, strip_ws_prefix};
+use crate::theme::Theme;
+use crate::typing::CancelToken;
+use ratatui::crossterm::event::{
+    self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, MouseEventKind,
+};
+use ratatui::crossterm::execute;
+use ratatui::crossterm::style::Print;
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Paragraph, Wrap};
+use qrcode::render::unicode;
+use qrcode::QrCode;
+use ratatui::{DefaultTerminal, Frame};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::{Mutex, Notify};
+
+/// Redraw interval, which also bounds how long a keypress waits
+const TICK: Duration = Duration::from_millis(250);
+
+/// Warn this long before the relay's JWT expires
+const SESSION_WARNING: Duration = Duration::from_secs(10 * 60);
+
+/// Height of the log pane, its title line included
+const LOG_PANE_HEIGHT: u16 = 10;
+
+/// Log lines one step of the mouse wheel scrolls
+const SCROLL_LINES: usize = 3;
+
+/// Most lines the `--edit` editor takes, its title included
+const EDITOR_MAX_HEIGHT: usize = 12;
+
+/// How long the screen stays inverted for `Alert::Flash`
+const FLASH: Duration = Duration::from_millis(300);
+
+/// The inline editor for a dictation held by `--edit`
+struct Editor {
+    /// The dictation and revision it was loaded from
+    loaded: (String, u64),
+    text: Vec<char>,
+    cursor: usize,
+    /// Sent off with Enter or Esc; keys go to the status view until the
+    /// next dictation loads
+    done: bool,
+}
+
+impl Editor {
+    fn new(editing: &Editing) -> Self {
+        let text: Vec<char> = editing.text.chars().collect();
+        Self {
+            loaded: (editing.id.clone(), editing.revision),
+            cursor: text.len(),
+            text,
+            done: false,
+        }
+    }
+
+    fn is_for(&self, editing: &Editing) -> bool {
+        self.loaded.0 == editing.id && self.loaded.1 == editing.revision
+    }
+
+    fn text(&self) -> String {
+        self.text.iter().collect()
+    }
+
+    /// Apply a key that edits the text or moves the cursor
+    fn key(&mut self, key: KeyEvent) {
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Char('a') if control => self.cursor = 0,
+            KeyCode::Char('e') if control => self.cursor = self.text.len(),
+            KeyCode::Char('u') if control => {
+                self.text.drain(..self.cursor);
+                self.cursor = 0;
+            }
+            KeyCode::Char(c) if !control && !key.modifiers.contains(KeyModifiers::ALT) => {
+                self.text.insert(self.cursor, c);
+                self.cursor += 1;
+            }
+            KeyCode::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.text.remove(self.cursor);
+            }
+            KeyCode::Delete if self.cursor < self.text.len() => {
+                self.text.remove(self.cursor);
+            }
+            KeyCode::Left => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Right => self.cursor = (self.cursor + 1).min(self.text.len()),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = self.text.len(),
+            _ => {}
+        }
+    }
+}
+
+/// The status view's settings from config.toml
+pub struct Options {
+    pub theme: Theme,
+    /// Capture the mouse, for scrolling the log with the wheel
+    pub mouse: bool,
+    pub alert: Option<Alert>,
+    /// Start with dictated text hidden (h toggles it)
+    pub redact: bool,
+}
+
+/// Alternate-screen display, drawn from `AppState` on its own thread
+///
+/// Esc cancels the dictation being typed; q or Ctrl+C (which raw mode turns
+/// into a plain keypress) wakes `quit`; y/n answer a pairing prompt, f
+/// releases held dictations and r (or c) skips the reconnect wait, sent on
+/// `commands`; y (with no prompt up) copies the last message to the
+/// clipboard, h hides or shows dictated text, s resets the statistics, t
+/// shows this session's transcript (e exports it), p the pairing QR code, l
+/// the log pane and v cycles the log's verbosity. With mouse capture the
+/// wheel scrolls the log back, and `alert` signals each dictation that
+/// arrives. With `--edit` an editor takes the keys while a dictation waits
+/// in it: Enter holds the text (or types it into `--target-class`), Esc
+/// discards it. Dropping the handle restores the terminal.
+pub struct Tui {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    mouse: bool,
+}
+
+impl Tui {
+    pub fn start(
+        state: Arc<Mutex<AppState>>,
+        cancel: CancelToken,
+        quit: Arc<Notify>,
+        commands: UnboundedSender<Command>,
+        options: Options,
+    ) -> Result<Self, String> {
+        let terminal = ratatui::try_init().map_err(|e| format!("Cannot start terminal UI: {}", e))?;
+        let mouse = options.mouse;
+        // Without capture the terminal keeps selecting text with the mouse
+        if mouse {
+            if let Err(e) = execute!(std::io::stdout(), EnableMouseCapture) {
+                ratatui::restore();
+                return Err(format!("Cannot capture the mouse: {}", e));
+            }
+        }
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let thread = std::thread::spawn({
+            let stop = stop.clone();
+            move || run(terminal, state, cancel, quit, commands, options, stop)
+        });
+
+        Ok(Self {
+            stop,
+            thread: Some(thread),
+            mouse,
+        })
+    }
+}
+
+impl Drop for Tui {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        if self.mouse {
+            let _ = execute!(std::io::stdout(), DisableMouseCapture);
+        }
+        ratatui::restore();
+    }
+}
+
+fn run(
+    mut terminal: DefaultTerminal,
+    state: Arc<Mutex<AppState>>,
+    cancel: CancelToken,
+    quit: Arc<Notify>,
+    commands: UnboundedSender<Command>,
+    Options { theme, alert, mut redact, .. }: Options,
+    stop: Arc<AtomicBool>,
+) {
+    // Showing the pairing QR code instead of the status
+    let mut show_qr = false;
+    // The log pane below the status, and how much it shows
+    let mut show_log = false;
+    let mut verbosity = LogLevel::Info;
+    // Log lines scrolled back from the newest
+    let mut scroll = 0;
+    // The full-screen transcript instead of the status, and the dictations
+    // scrolled back from the newest
+    let mut show_transcript = false;
+    let mut transcript_scroll = 0;
+    // The dictation being edited (`--edit`)
+    let mut editor: Option<Editor> = None;
+    // Dictations already alerted about, and until when the screen flashes
+    let mut received = 0;
+    let mut flash_until = None;
+    while !stop.load(Ordering::SeqCst) {
+        let snapshot = state.blocking_lock().clone();
+        if snapshot.received != received {
+            received = snapshot.received;
+            match alert {
+                Some(Alert::Bell) => {
+                    let _ = execute!(std::io::stdout(), Print('\x07'));
+                }
+                Some(Alert::Flash) => flash_until = Some(Instant::now() + FLASH),
+                None => {}
+            }
+        }
+        match snapshot.editing {
+            Some(ref editing) if !editor.as_ref().is_some_and(|editor| editor.is_for(editing)) => {
+                editor = Some(Editor::new(editing));
+            }
+            Some(_) => {}
+            None => editor = None,
+        }
+        let flash = flash_until.is_some_and(|until| Instant::now() < until);
+        let drawn = terminal.draw(|frame| {
+            match snapshot.pairing_code {
+                _ if show_transcript => draw_transcript(frame, &theme, &snapshot, transcript_scroll, redact),
+                Some(ref code) if show_qr => draw_qr(frame, &theme, code, snapshot.join_code.as_deref()),
+                _ => draw(frame, &theme, &snapshot, show_log.then_some((verbosity, scroll)), redact, editor.as_ref()),
+            }
+            if flash {
+                let area = frame.area();
+                frame.buffer_mut().set_style(area, Style::default().add_modifier(Modifier::REVERSED));
+            }
+        });
+        if drawn.is_err() {
+            break;
+        }
+
+        // Short enough a wait to end the flash on time
+        let wait = if flash { FLASH.min(TICK) } else { TICK };
+        if !event::poll(wait).unwrap_or(false) {
+            continue;
+        }
+        let key = match event::read() {
+            Ok(Event::Key(key)) => key,
+            Ok(Event::Mouse(mouse)) if show_transcript => {
+                let oldest = snapshot.transcript.entries.len().saturating_sub(1);
+                match mouse.kind {
+                    MouseEventKind::ScrollUp => transcript_scroll = (transcript_scroll + 1).min(oldest),
+                    MouseEventKind::ScrollDown => transcript_scroll = transcript_scroll.saturating_sub(1),
+                    _ => {}
+                }
+                continue;
+            }
+            Ok(Event::Mouse(mouse)) if show_log => {
+                let oldest = snapshot.log.count(verbosity).saturating_sub(LOG_PANE_HEIGHT as usize - 1);
+                match mouse.kind {
+                    MouseEventKind::ScrollUp => scroll = (scroll + SCROLL_LINES).min(oldest),
+                    MouseEventKind::ScrollDown => scroll = scroll.saturating_sub(SCROLL_LINES),
+                    _ => {}
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+
+        // The editor takes every key but quitting and cancelling typing
+        if let Some(edit) = editor.as_mut().filter(|edit| !edit.done && !show_transcript && !show_qr) {
+            let id = edit.loaded.0.clone();
+            match key.code {
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => quit.notify_one(),
+                KeyCode::Esc if snapshot.typing => cancel.cancel(),
+                KeyCode::Enter => {
+                    edit.done = true;
+                    let _ = commands.send(Command::Edited { id, text: edit.text() });
+                }
+                KeyCode::Esc => {
+                    edit.done = true;
+                    let _ = commands.send(Command::Discard(id));
+                }
+                _ => edit.key(key),
+            }
+            continue;
+        }
+
+        match key.code {
+            KeyCode::Esc if snapshot.typing => cancel.cancel(),
+            KeyCode::Esc if show_transcript => show_transcript = false,
+            KeyCode::Esc if show_qr => show_qr = false,
+            KeyCode::Char('t') => {
+                show_transcript = !show_transcript;
+                transcript_scroll = 0;
+            }
+            KeyCode::Up if show_transcript => {
+                transcript_scroll = (transcript_scroll + 1).min(snapshot.transcript.entries.len().saturating_sub(1));
+            }
+            KeyCode::Down if show_transcript => transcript_scroll = transcript_scroll.saturating_sub(1),
+            KeyCode::Char('e') if show_transcript => {
+                let exported = snapshot.transcript.export();
+                let mut state = state.blocking_lock();
+                match exported {
+                    Ok(path) => state.set_notice(&format!("Transcript saved to {}", path.display()), false),
+                    Err(e) => state.set_notice(&format!("Exporting the transcript: {}", e), true),
+                }
+            }
+            KeyCode::Char('p') if snapshot.pairing_code.is_some() => show_qr = !show_qr,
+            KeyCode::Char('s') => state.blocking_lock().stats.reset(),
+            KeyCode::Char('h') => redact = !redact,
+            KeyCode::Char('l') => {
+                show_log = !show_log;
+                scroll = 0;
+            }
+            KeyCode::Char('v') => {
+                verbosity = verbosity.next();
+                show_log = true;
+                scroll = 0;
+            }
+            KeyCode::Char('y') if snapshot.pending_pairing.is_some() => {
+                let _ = commands.send(Command::Pair(true));
+            }
+            KeyCode::Char('n') if snapshot.pending_pairing.is_some() => {
+                let _ = commands.send(Command::Pair(false));
+            }
+            // For when the text landed in the wrong window
+            KeyCode::Char('y') => {
+                if let Some(ref text) = snapshot.last_message_full {
+                    let copied = clipboard::copy(text);
+                    let mut state = state.blocking_lock();
+                    match copied {
+                        Ok(()) => state.set_notice("Copied the last message to the clipboard", false),
+                        Err(e) => state.set_notice(&format!("Copying the last message: {}", e), true),
+                    }
+                }
+            }
+            KeyCode::Char('f') if snapshot.held > 0 => {
+                let _ = commands.send(Command::Release);
+            }
+            KeyCode::Char('r') if snapshot.reconnect_pending => {
+                let _ = commands.send(Command::Reconnect);
+            }
+            KeyCode::Char('q') => quit.notify_one(),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => quit.notify_one(),
+            KeyCode::Char('c') if snapshot.reconnect_pending => {
+                let _ = commands.send(Command::Reconnect);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The status screen, with the log pane at `log` verbosity and scrolled
+/// back that many lines if it's open
+fn draw(
+    frame: &mut Frame,
+    theme: &Theme,
+    state: &AppState,
+    log: Option<(LogLevel, usize)>,
+    redact: bool,
+    editor: Option<&Editor>,
+) {
+    let log_height = if log.is_some() { LOG_PANE_HEIGHT } else { 0 };
+    // The text wrapped to the width, plus the title line, capped
+    let width = frame.area().width.max(1) as usize;
+    let editor_height = editor.map_or(0, |editor| ((editor.text.len() + 1).div_ceil(width) + 1).min(EDITOR_MAX_HEIGHT) as u16);
+    let [main_area, editor_area, log_area, help_area] = Layout::vertical([
+        Constraint::Min(0),
+        Constraint::Length(editor_height),
+        Constraint::Length(log_height),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    let dim = Style::default().add_modifier(Modifier::DIM);
+    let gray = theme.muted;
+
+    let mut lines = vec![
+        Line::from(vec![
+            Span::styled("Utter", theme.accent.add_modifier(Modifier::BOLD)),
+            Span::styled(" Daemon", dim),
+        ]),
+        Line::styled(
+            format!("{} • {} • {}", strip_ws_prefix(&state.server_url), state.device, state.tool_status),
+            gray,
+        ),
+    ];
+    let mut spans = vec![Span::styled("Encryption ", gray)];
+    if state.encryption {
+        spans.push(Span::styled("on", theme.ok));
+    } else {
+        spans.push(Span::styled("off (no keypair)", theme.error));
+    }
+    if let Some(ref fingerprint) = state.fingerprint {
+        spans.extend([Span::styled(" · key ", gray), Span::raw(fingerprint.clone())]);
+    }
+    if let Some(ref fingerprint) = state.last_sender_fingerprint {
+        spans.extend([Span::styled(" · last sender ", gray), Span::raw(fingerprint.clone())]);
+    }
+    lines.push(Line::from(spans));
+    if let Some(ref account) = state.account {
+        let mut spans = vec![Span::styled("Account ", gray), Span::raw(account.clone())];
+        // Renewed on the next connection, which may need a new Google sign-in
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        match state.session_expires.map(|expires| expires.saturating_sub(now)) {
+            Some(0) => spans.push(Span::styled(" · ⚠ sign-in expired", theme.warning)),
+            Some(left) if left < SESSION_WARNING.as_secs() => spans.push(Span::styled(
+                format!(" · ⚠ sign-in expires in {} min", left.div_ceil(60)),
+                theme.warning,
+            )),
+            _ => {}
+        }
+        lines.push(Line::from(spans));
+    }
+    lines.extend([
+        Line::default(),
+        Line::from(vec![
+            Span::styled("● ", if state.connected { theme.ok } else { theme.error }),
+            Span::raw(state.connection.clone()),
+        ]),
+        Line::default(),
+    ]);
+
+    if state.insecure {
+        lines.push(Line::styled(
+            "⚠ INSECURE: unencrypted messages from any sender are typed (--allow-plaintext)",
+            theme.error.add_modifier(Modifier::BOLD),
+        ));
+        lines.push(Line::default());
+    }
+
+    if state.connected {
+        if state.phones.is_empty() {
+            lines.push(Line::from(vec![Span::styled("Phones: ", dim), Span::raw("none online")]));
+        } else {
+            lines.push(Line::styled("Phones:", dim));
+        }
+        for phone in &state.phones {
+            let mut spans = vec![
+                Span::styled(
+                    if phone.can_type { "  ✓ " } else { "  ✗ " },
+                    if phone.can_type { theme.ok } else { theme.muted },
+                ),
+                Span::raw(phone.name.clone()),
+            ];
+            if let Some(ref platform) = phone.platform {
+                spans.push(Span::styled(format!(" · {}", platform), gray));
+            }
+            if let Some(ref fingerprint) = phone.fingerprint {
+                spans.push(Span::styled(format!(" · {}", fingerprint), gray));
+            }
+            if !phone.can_type {
+                spans.push(Span::styled(" · not paired", dim));
+            }
+            lines.push(Line::from(spans));
+        }
+        let mut latency = Vec::new();
+        for (name, samples) in [("relay", &state.round_trip), ("delivery", &state.delivery), ("typing", &state.type_time)] {
+            if let Some(text) = latency_text(samples) {
+                latency.push(format!("{} {}", name, text));
+            }
+        }
+        if !latency.is_empty() {
+            lines.push(Line::from(vec![Span::styled("Latency: ", dim), Span::raw(latency.join(" · "))]));
+        }
+        lines.push(Line::default());
+    }
+
+    let stats = &state.stats;
+    let mut spans = vec![
+        Span::styled("Up ", dim),
+        Span::raw(uptime_text(stats.started.elapsed())),
+        Span::styled(" · ", dim),
+        Span::raw(format!(
+            "{} reconnects · {} received · {} typed ({} characters)",
+            stats.reconnects,
+            bytes_text(stats.bytes_received),
+            stats.messages_typed,
+            stats.characters_typed
+        )),
+    ];
+    if let Some(reset_at) = stats.reset_at {
+        spans.push(Span::styled(reset_at.format(" since %H:%M").to_string(), dim));
+    }
+    lines.push(Line::from(spans));
+    lines.push(Line::default());
+
+    if let Some(ref prompt) = state.pending_pairing {
+        let question = if prompt.first_contact {
+            format!("{} wants to type here. Trust it?", prompt.device_name)
+        } else {
+            format!("Pair with {}?", prompt.device_name)
+        };
+        lines.push(Line::styled(
+            question,
+            theme.accent.add_modifier(Modifier::BOLD),
+        ));
+        if let Some(ref sas) = prompt.sas {
+            lines.push(Line::from(vec![
+                Span::styled("  Code ", dim),
+                Span::styled(sas.clone(), Style::default().add_modifier(Modifier::BOLD)),
+            ]));
+            lines.push(Line::from(vec![
+                Span::styled("  Fingerprint ", dim),
+                Span::raw(prompt.fingerprint.clone()),
+                Span::styled(" (check the phone shows the same code) · y accept · n reject", dim),
+            ]));
+        } else {
+            lines.push(Line::from(vec![
+                Span::styled("  Fingerprint ", dim),
+                Span::styled(prompt.fingerprint.clone(), Style::default().add_modifier(Modifier::BOLD)),
+                Span::styled(" (check it matches the phone) · y accept · n reject", dim),
+            ]));
+        }
+        lines.push(Line::default());
+    }
+
+    if state.held > 0 {
+        let count = match state.held {
+            1 => "1 dictation".to_string(),
+            n => format!("{} dictations", n),
+        };
+        lines.push(Line::styled(
+            format!("⏸ {} held from a do-not-type window", count),
+            theme.warning.add_modifier(Modifier::BOLD),
+        ));
+        lines.push(Line::styled("  Press f, then focus where they should go to release", dim));
+        lines.push(Line::default());
+    }
+
+    if state.rejected > 0 {
+        let count = match state.rejected {
+            1 => "1 message".to_string(),
+            n => format!("{} messages", n),
+        };
+        lines.push(Line::styled(
+            format!("⚠ {} rejected since start", count),
+            theme.warning.add_modifier(Modifier::BOLD),
+        ));
+        lines.push(Line::styled("  Senders and reasons are in ~/.local/share/utterd/rejected.jsonl", dim));
+        lines.push(Line::default());
+    }
+
+    if let Some(ref warning) = state.warning {
+        lines.push(Line::styled(
+            format!("⚠ {}: clipboard-only mode", warning.problem),
+            theme.error.add_modifier(Modifier::BOLD),
+        ));
+        lines.push(Line::styled(format!("  {}", warning.hint), theme.warning));
+        lines.push(Line::default());
+    }
+
+    match (state.last_message_timestamp, &state.last_message_sender, &state.last_message_text) {
+        (Some(timestamp), Some(sender), Some(text)) => {
+            lines.push(Line::from(vec![
+                Span::styled("Last: ", dim),
+                Span::raw(time_ago(timestamp)),
+                Span::styled(" from ", dim),
+                Span::raw(sender.clone()),
+            ]));
+            match state.last_message_full {
+                Some(ref full) if redact => lines.push(Line::styled(format!("↓ {}", redacted(full)), dim)),
+                _ => lines.push(Line::from(format!("↓ {}", text))),
+            }
+        }
+        _ => {
+            lines.push(Line::from(vec![Span::styled("Last: ", dim), Span::raw("-")]));
+            lines.push(Line::from("↓"));
+        }
+    }
+
+    if let Some(ref focus) = state.focus {
+        let mut spans = vec![Span::styled("Next: ", dim)];
+        match focus.class {
+            Some(ref class) => spans.push(Span::styled(class.clone(), Style::default().add_modifier(Modifier::BOLD))),
+            None => spans.push(Span::raw("the focused window")),
+        }
+        if let Some(ref title) = focus.title {
+            spans.push(Span::styled(format!(" “{}”", title), gray));
+        }
+        spans.push(Span::styled(format!(" · {}", focus.tool), dim));
+        if let Some(ref held) = focus.held {
+            spans.push(Span::styled(format!(" · held: {}", held), theme.warning));
+        }
+        lines.push(Line::from(spans));
+    }
+
+    if let Some(ref interim) = state.interim {
+        let interim = if redact { "dictating".to_string() } else { interim.clone() };
+        lines.push(Line::styled(format!("… {}", interim), dim.add_modifier(Modifier::ITALIC)));
+    }
+
+    if state.typing {
+        lines.push(Line::styled("⌨ Typing... (Esc to cancel)", theme.warning));
+    }
+
+    if let Some(ref notice) = state.notice {
+        lines.push(Line::default());
+        if notice.is_error {
+            lines.push(Line::styled(format!("✗ {}", notice.text), theme.error));
+        } else {
+            lines.push(Line::styled(notice.text.clone(), theme.warning));
+        }
+    }
+
+    frame.render_widget(Paragraph::new(lines), main_area);
+    if let (Some(editor), Some(editing)) = (editor, &state.editing) {
+        draw_editor(frame, theme, editor, editing, editor_area);
+    }
+    if let Some((verbosity, scroll)) = log {
+        draw_log(frame, theme, state, verbosity, scroll, log_area);
+    }
+    let help = match (state.reconnect_pending, state.pairing_code.is_some()) {
+        (true, _) => "r reconnect now · l log",
+        (false, true) => "p pairing code · l log",
+        (false, false) => "l log",
+    };
+    let copy = match (state.last_message_full.is_some(), redact) {
+        (true, false) => " · y copy last · h hide text",
+        (true, true) => " · y copy last · h show text",
+        (false, _) => "",
+    };
+    let transcript = if state.transcript.entries.is_empty() { "" } else { " · t transcript" };
+    let help = format!("{}{}{} · Esc cancel typing · q quit", help, copy, transcript);
+    let help = match editor {
+        Some(editor) if !editor.done => "Enter done · Esc discard · ←→ Home End move · Ctrl+U clear · Ctrl+C quit".to_string(),
+        _ => help,
+    };
+    frame.render_widget(Paragraph::new(help).style(dim), help_area);
+}
+
+/// The dictation being edited, with a block cursor
+fn draw_editor(frame: &mut Frame, theme: &Theme, editor: &Editor, editing: &Editing, area: ratatui::layout::Rect) {
+    let dim = Style::default().add_modifier(Modifier::DIM);
+    let waiting = match editing.queued {
+        0 => String::new(),
+        n => format!(" · {} more waiting", n),
+    };
+    let block = Block::default()
+        .borders(Borders::TOP)
+        .border_style(theme.accent)
+        .title(Span::styled(format!(" Edit before typing · from {}{} ", editing.sender, waiting), theme.accent));
+
+    let text = if editor.done { dim } else { Style::default() };
+    let (before, after) = editor.text.split_at(editor.cursor);
+    let mut spans = vec![Span::styled(before.iter().collect::<String>(), text)];
+    if !editor.done {
+        let under = after.first().map_or(" ".to_string(), char::to_string);
+        spans.push(Span::styled(under, Style::default().add_modifier(Modifier::REVERSED)));
+    }
+    let after = if editor.done { after } else { after.get(1..).unwrap_or_default() };
+    spans.push(Span::styled(after.iter().collect::<String>(), text));
+    frame.render_widget(Paragraph::new(Line::from(spans)).wrap(Wrap { trim: false }).block(block), area);
+}
+
+/// The log entries that fit, the newest unless scrolled back; errors stand
+/// out and debug details are dimmed
+fn draw_log(frame: &mut Frame, theme: &Theme, state: &AppState, verbosity: LogLevel, scroll: usize, area: ratatui::layout::Rect) {
+    let dim = Style::default().add_modifier(Modifier::DIM);
+    let scrolled = if scroll > 0 { " · scrolled back" } else { "" };
+    let block = Block::default()
+        .borders(Borders::TOP)
+        .border_style(dim)
+        .title(Span::styled(format!(" Log: {}{} · v more/less ", verbosity.name(), scrolled), dim));
+    let entries = state.log.recent(verbosity, block.inner(area).height as usize, scroll);
+    let lines: Vec<Line> = entries
+        .into_iter()
+        .map(|entry| {
+            let style = match entry.level {
+                LogLevel::Error => theme.error,
+                LogLevel::Info => Style::default(),
+                LogLevel::Debug => dim,
+            };
+            Line::from(vec![
+                Span::styled(entry.time.format("%H:%M:%S ").to_string(), theme.muted),
+                Span::styled(entry.text.clone(), style),
+            ])
+        })
+        .collect();
+    frame.render_widget(Paragraph::new(lines).block(block), area);
+}
+
+/// Everything typed this session, the newest at the bottom unless scrolled
+/// back `scroll` dictations, with the latest notice (e.g. where it was
+/// exported to) underneath
+fn draw_transcript(frame: &mut Frame, theme: &Theme, state: &AppState, scroll: usize, redact: bool) {
+    let dim = Style::default().add_modifier(Modifier::DIM);
+    let [main_area, notice_area] = Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+    let entries = &state.transcript.entries;
+    let block = Block::default().borders(Borders::TOP).border_style(dim).title(Span::styled(
+        format!(" Transcript: {} typed · e export · ↑↓ scroll · t back ", entries.len()),
+        theme.accent,
+    ));
+    let inner = block.inner(main_area);
+
+    // Whole dictations only, as many as fit when wrapped
+    let width = inner.width.max(1) as usize;
+    let mut height = 0;
+    let mut shown = Vec::new();
+    for entry in entries.iter().rev().skip(scroll) {
+        let text = if redact { redacted(&entry.text) } else { entry.text.clone() };
+        // After the "HH:MM:SS " prefix
+        let lines = (text.chars().count() + 9).div_ceil(width);
+        if height + lines > inner.height as usize && !shown.is_empty() {
+            break;
+        }
+        height += lines;
+        shown.push(Line::from(vec![
+            Span::styled(entry.time.format("%H:%M:%S ").to_string(), theme.muted),
+            Span::raw(text),
+        ]));
+    }
+    shown.reverse();
+    if entries.is_empty() {
+        shown.push(Line::styled("Nothing typed yet", dim));
+    }
+    frame.render_widget(Paragraph::new(shown).wrap(Wrap { trim: false }).block(block), main_area);
+
+    if let Some(ref notice) = state.notice {
+        let style = if notice.is_error { theme.error } else { theme.warning };
+        frame.render_widget(Paragraph::new(notice.text.clone()).style(style), notice_area);
+    }
+}
+
+/// The pairing QR code, for the phone to scan, and the relay's code for
+/// joining an anonymous account if there is one
+///
+/// Dark modules are drawn black on white whatever the terminal's colors, so
+/// scanners see the contrast they expect.
+fn draw_qr(frame: &mut Frame, theme: &Theme, code: &str, join_code: Option<&str>) {
+    let [main_area, help_area] = Layout::vertical([Constraint::Min(0), Constraint::Length(1)])
+        .areas(frame.area());
+    let dim = Style::default().add_modifier(Modifier::DIM);
+
+    let mut lines = vec![
+        Line::styled("Pair a phone", theme.accent.add_modifier(Modifier::BOLD)),
+        Line::styled("Scan this with the Utter app, then confirm the code it shows", dim),
+        Line::default(),
+    ];
+    let qr = QrCode::new(code.as_bytes()).map(|qr| qr.render::<unicode::Dense1x2>().quiet_zone(true).build());
+    match qr {
+        Ok(qr) => {
+            let fits = qr.lines().count() + lines.len() <= main_area.height as usize
+                && qr.lines().all(|line| line.chars().count() <= main_area.width as usize);
+            if fits {
+                let style = Style::default().fg(Color::Black).bg(Color::White);
+                lines.extend(qr.lines().map(|line| Line::styled(line.to_string(), style)));
+            } else {
+                lines.push(Line::styled("Make the terminal larger to show the QR code", theme.warning));
+            }
+        }
+        Err(e) => lines.push(Line::styled(format!("✗ Cannot make a QR code: {}", e), theme.error)),
+    }
+
+    if let Some(join_code) = join_code {
+        lines.push(Line::default());
+        lines.push(Line::from(vec![
+            Span::styled("Or enter the code ", dim),
+            Span::styled(join_code.to_string(), Style::default().add_modifier(Modifier::BOLD)),
+            Span::styled(" in the app (one-time; renewed every 10 minutes)", dim),
+        ]));
+    }
+
+    frame.render_widget(Paragraph::new(lines), main_area);
+    frame.render_widget(Paragraph::new("p or Esc back · q quit").style(dim), help_area);
+}
+
+/// Average with the range, e.g. "42 ms (30–80)"
+/// The latest sample with the recent average and range, e.g. "45 ms (avg 42, 30–80)"
+fn latency_text(latency: &Latency) -> Option<String> {
+    let (min, avg, max) = latency.summary()?;
+    let latest = latency.latest()?;
+    Some(format!("{} ms (avg {}, {}–{})", latest.as_millis(), avg.as_millis(), min.as_millis(), max.as_millis()))
+}
+
+/// How long utterd has run, e.g. "2h 05m"
+fn uptime_text(uptime: Duration) -> String {
+    let secs = uptime.as_secs();
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m", secs / 60),
+        _ => format!("{}h {:02}m", secs / 3600, secs / 60 % 60),
+    }
+}
+
+/// A byte count, e.g. "12.4 KiB"
+fn bytes_text(bytes: u64) -> String {
+    match bytes {
+        0..=1023 => format!("{} B", bytes),
+        1024..=1048575 => format!("{:.1} KiB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MiB", bytes as f64 / 1048576.0),
+    }
+}
+
+/// Age of a millisecond Unix timestamp, e.g. "5s ago"
+fn time_ago(timestamp: i64) -> String {
+    let msg_time = UNIX_EPOCH + Duration::from_millis(timestamp as u64);
+
+    match SystemTime::now().duration_since(msg_time) {
+        Ok(elapsed) => {
+            let secs = elapsed.as_secs();
+            if secs < 60 {
+                format!("{}s ago", secs)
+            } else if secs < 3600 {
+                format!("{}m ago", secs / 60)
+            } else {
+                format!("{}h ago", secs / 3600)
+            }
+        }
+        Err(_) => "just now".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_editor() {
+        let editing = Editing {
+            id: "a1".to_string(),
+            sender: "pixel".to_string(),
+            text: "helo world".to_string(),
+            queued: 0,
+            revision: 0,
+        };
+        let mut editor = Editor::new(&editing);
+        let press = |editor: &mut Editor, code| editor.key(KeyEvent::from(code));
+
+        for _ in 0..7 {
+            press(&mut editor, KeyCode::Left);
+        }
+        press(&mut editor, KeyCode::Char('l'));
+        assert_eq!(editor.text(), "hello world");
+        press(&mut editor, KeyCode::End);
+        press(&mut editor, KeyCode::Backspace);
+        press(&mut editor, KeyCode::Backspace);
+        press(&mut editor, KeyCode::Home);
+        press(&mut editor, KeyCode::Delete);
+        assert_eq!(editor.text(), "ello wor");
+
+        editor.key(KeyEvent::new(KeyCode::Char('u'), KeyModifiers::CONTROL));
+        assert_eq!(editor.text(), "ello wor");
+        press(&mut editor, KeyCode::Right);
+        editor.key(KeyEvent::new(KeyCode::Char('u'), KeyModifiers::CONTROL));
+        assert_eq!(editor.text(), "llo wor");
+
+        assert!(editor.is_for(&editing));
+        assert!(!editor.is_for(&Editing { revision: 1, ..editing }));
+    }
+}
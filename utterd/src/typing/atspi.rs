@@ -0,0 +1,206 @@
+use zbus::blocking::Connection;
+use zbus::zvariant::{OwnedObjectPath, OwnedValue};
+
+// AT-SPI2 D-Bus names (see at-spi2-core's xml/*.xml)
+const A11Y_BUS_NAME: &str = "org.a11y.Bus";
+const A11Y_BUS_PATH: &str = "/org/a11y/bus";
+const REGISTRY_NAME: &str = "org.a11y.atspi.Registry";
+const ROOT_PATH: &str = "/org/a11y/atspi/accessible/root";
+const ACCESSIBLE_IFACE: &str = "org.a11y.atspi.Accessible";
+const TEXT_IFACE: &str = "org.a11y.atspi.Text";
+const EDITABLE_TEXT_IFACE: &str = "org.a11y.atspi.EditableText";
+
+// AtspiStateType bit positions
+const STATE_ACTIVE: u32 = 1;
+const STATE_EDITABLE: u32 = 7;
+const STATE_FOCUSED: u32 = 12;
+
+/// Stop searching huge trees (e.g. browser DOMs) after this many nodes
+const MAX_NODES: usize = 5000;
+
+/// An accessible object: owning bus name + object path
+type Accessible = (String, OwnedObjectPath);
+
+/// Open a connection to the accessibility bus
+///
+/// The a11y bus is separate from the session bus; its address is handed
+/// out by org.a11y.Bus on the session bus.
+fn connect() -> Result<Connection, String> {
+    let session = Connection::session()
+        .map_err(|e| format!("Cannot connect to session bus: {}", e))?;
+
+    let address: String = session
+        .call_method(Some(A11Y_BUS_NAME), A11Y_BUS_PATH, Some(A11Y_BUS_NAME), "GetAddress", &())
+        .and_then(|reply| reply.body().deserialize())
+        .map_err(|e| format!("Accessibility bus not available: {}", e))?;
+
+    zbus::blocking::connection::Builder::address(address.as_str())
+        .and_then(|builder| builder.build())
+        .map_err(|e| format!("Cannot connect to accessibility bus: {}", e))
+}
+
+fn children(conn: &Connection, (name, path): &Accessible) -> Vec<Accessible> {
+    conn.call_method(Some(name.as_str()), path, Some(ACCESSIBLE_IFACE), "GetChildren", &())
+        .and_then(|reply| reply.body().deserialize())
+        .unwrap_or_default()
+}
+
+fn has_state(conn: &Connection, (name, path): &Accessible, state: u32) -> bool {
+    let states: Vec<u32> = conn
+        .call_method(Some(name.as_str()), path, Some(ACCESSIBLE_IFACE), "GetState", &())
+        .and_then(|reply| reply.body().deserialize())
+        .unwrap_or_default();
+
+    // State set is a 64-bit bitfield split across two u32 words
+    states
+        .get((state / 32) as usize)
+        .map(|word| word & (1 << (state % 32)) != 0)
+        .unwrap_or(false)
+}
+
+/// Find the focused, editable widget in the active window
+fn find_focused_editable(conn: &Connection) -> Result<Option<Accessible>, String> {
+    let root = (REGISTRY_NAME.to_string(), OwnedObjectPath::try_from(ROOT_PATH).unwrap());
+
+    for app in children(conn, &root) {
+        // Only descend into the window that currently has focus
+        for window in children(conn, &app) {
+            if !has_state(conn, &window, STATE_ACTIVE) {
+                continue;
+            }
+
+            let mut stack = vec![window];
+            let mut visited = 0;
+            while let Some(node) = stack.pop() {
+                visited += 1;
+                if visited > MAX_NODES {
+                    return Err("Accessibility tree too large to search".to_string());
+                }
+
+                if has_state(conn, &node, STATE_FOCUSED) {
+                    return Ok(has_state(conn, &node, STATE_EDITABLE).then_some(node));
+                }
+                stack.extend(children(conn, &node));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Check that the accessibility bus is reachable
+pub fn is_available() -> bool {
+    connect().is_ok()
+}
+
+/// Whether an editable text field currently has keyboard focus
+pub fn has_focused_text_field() -> Result<bool, String> {
+    let conn = connect()?;
+    Ok(find_focused_editable(&conn)?.is_some())
+}
+
+/// Read the caret position of a text widget (in characters)
+fn caret_offset(conn: &Connection, (name, path): &Accessible) -> Result<i32, String> {
+    let caret: OwnedValue = conn
+        .call_method(
+            Some(name.as_str()),
+            path,
+            Some("org.freedesktop.DBus.Properties"),
+            "Get",
+            &(TEXT_IFACE, "CaretOffset"),
+        )
+        .and_then(|reply| reply.body().deserialize())
+        .map_err(|e| format!("Cannot read caret position: {}", e))?;
+    Ok(i32::try_from(caret).unwrap_or(-1))
+}
+
+/// Insert text at the caret of the focused text field
+///
+/// Unlike synthesized keypresses this is layout-independent and can't
+/// trigger shortcuts, but it needs the target toolkit to expose AT-SPI
+/// (GTK, Qt, Chromium and Firefox do when accessibility is enabled).
+pub fn insert_text(text: &str) -> Result<(), String> {
+    let conn = connect()?;
+    let target = find_focused_editable(&conn)?
+        .ok_or("No focused text field")?;
+    let caret = caret_offset(&conn, &target)?;
+    let (name, path) = target;
+
+    // Length is in characters, not bytes
+    let length = text.chars().count() as i32;
+    let inserted: bool = conn
+        .call_method(
+            Some(name.as_str()),
+            &path,
+            Some(EDITABLE_TEXT_IFACE),
+            "InsertText",
+            &(caret, text, length),
+        )
+        .and_then(|reply| reply.body().deserialize())
+        .map_err(|e| format!("InsertText failed: {}", e))?;
+
+    if !inserted {
+        return Err("Focused widget refused the insertion".to_string());
+    }
+
+    Ok(())
+}
+
+/// Delete `count` characters before the caret, like pressing Backspace
+pub fn delete_before_caret(count: usize) -> Result<(), String> {
+    let conn = connect()?;
+    let target = find_focused_editable(&conn)?
+        .ok_or("No focused text field")?;
+    let caret = caret_offset(&conn, &target)?;
+    if caret < 0 {
+        return Err("Focused widget has no caret".to_string());
+    }
+    let (name, path) = target;
+
+    let start = caret.saturating_sub(count as i32).max(0);
+    let deleted: bool = conn
+        .call_method(
+            Some(name.as_str()),
+            &path,
+            Some(EDITABLE_TEXT_IFACE),
+            "DeleteText",
+            &(start, caret),
+        )
+        .and_then(|reply| reply.body().deserialize())
+        .map_err(|e| format!("DeleteText failed: {}", e))?;
+
+    if !deleted {
+        return Err("Focused widget refused the deletion".to_string());
+    }
+
+    Ok(())
+}
+
+/// Move the caret `delta` characters, standing in for Left/Right arrows
+pub fn move_caret(delta: i32) -> Result<(), String> {
+    let conn = connect()?;
+    let target = find_focused_editable(&conn)?
+        .ok_or("No focused text field")?;
+    let caret = caret_offset(&conn, &target)?;
+    if caret < 0 {
+        return Err("Focused widget has no caret".to_string());
+    }
+    let (name, path) = target;
+
+    let moved: bool = conn
+        .call_method(
+            Some(name.as_str()),
+            &path,
+            Some(TEXT_IFACE),
+            "SetCaretOffset",
+            &((caret + delta).max(0),),
+        )
+        .and_then(|reply| reply.body().deserialize())
+        .map_err(|e| format!("SetCaretOffset failed: {}", e))?;
+
+    if !moved {
+        return Err("Focused widget refused to move the caret".to_string());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,71 @@
+/// Active XKB keyboard layout(s), e.g. "fr" or "us,ru" with variants
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layout {
+    pub layouts: Vec<String>,
+    pub variants: Vec<String>,
+}
+
+impl Layout {
+    /// Parse `setxkbmap -query` or `localectl status` output
+    pub fn parse(output: &str) -> Option<Self> {
+        let mut layouts = Vec::new();
+        let mut variants = Vec::new();
+
+        for line in output.lines() {
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let list = || value.split(',').map(|v| v.trim().to_string()).collect::<Vec<_>>();
+            match key.trim().to_lowercase().as_str() {
+                "layout" | "x11 layout" => layouts = list(),
+                "variant" | "x11 variant" => variants = list(),
+                _ => {}
+            }
+        }
+
+        (!layouts.is_empty()).then_some(Self { layouts, variants })
+    }
+
+    /// From XKB_DEFAULT_LAYOUT / XKB_DEFAULT_VARIANT, as set for many Wayland compositors
+    pub fn from_env() -> Option<Self> {
+        let layout = std::env::var("XKB_DEFAULT_LAYOUT").ok().filter(|l| !l.is_empty())?;
+        let variant = std::env::var("XKB_DEFAULT_VARIANT").unwrap_or_default();
+        Self::parse(&format!("layout: {}\nvariant: {}", layout, variant))
+    }
+
+    /// Whether keycodes produce what a US QWERTY keyboard would
+    ///
+    /// Keycode-based tools (ydotool, keyd) assume this; on AZERTY, Dvorak
+    /// or Cyrillic layouts they type the wrong characters.
+    pub fn is_us_qwerty(&self) -> bool {
+        self.layouts == ["us"] && self.variants.iter().all(|v| v.is_empty())
+    }
+
+    /// More than one layout configured; xdotool only maps keysyms through
+    /// the first group, so typing breaks when another one is active
+    pub fn has_multiple_groups(&self) -> bool {
+        self.layouts.len() > 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let query = "rules:      evdev\nmodel:      pc105\nlayout:     fr\nvariant:    \n";
+        let layout = Layout::parse(query).unwrap();
+        assert_eq!(layout.layouts, ["fr"]);
+        assert!(!layout.is_us_qwerty());
+
+        let localectl = "   System Locale: LANG=en_US.UTF-8\n       X11 Layout: us,ru\n      X11 Variant: dvorak,\n";
+        let layout = Layout::parse(localectl).unwrap();
+        assert_eq!(layout.layouts, ["us", "ru"]);
+        assert!(layout.has_multiple_groups());
+
+        assert!(Layout::parse("layout: us\n").unwrap().is_us_qwerty());
+        assert!(!Layout::parse("layout: us\nvariant: dvorak\n").unwrap().is_us_qwerty());
+        assert_eq!(Layout::parse("nothing here"), None);
+    }
+}
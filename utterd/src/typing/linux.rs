@@ -0,0 +1,384 @@
+use super::{atspi, portal, run_cancellable, CancelToken, FocusedWindow, Injector, Key, Layout, CANCELLED};
+use std::process::Stdio;
+
+pub const COMMAND_TOOLS: &[&str] = &["xdotool", "ydotool", "keyd"];
+
+pub const TOOLS: &[&str] = &["xdotool", "ydotool", "keyd", "atspi", "portal"];
+
+fn is_x11_session(injector: &Injector) -> bool {
+    match std::env::var("XDG_SESSION_TYPE").unwrap_or_default().as_str() {
+        "x11" => true,
+        "wayland" => false,
+        _ => injector.effective_wayland_display().is_none() && injector.effective_display().is_some(),
+    }
+}
+
+/// Order backends by what the session can actually use
+///
+/// XDG_SESSION_TYPE is authoritative when set; otherwise fall back to which
+/// display sockets we can see. xdotool only reaches XWayland windows under a
+/// Wayland compositor, so it goes last there. ydotool and keyd assume US
+/// QWERTY keycodes, so other layouts prefer AT-SPI insertion or the portal.
+/// Inside a Flatpak sandbox only the D-Bus backends can reach anything.
+pub fn preferred_tools(injector: &Injector) -> Vec<&'static str> {
+    if std::path::Path::new("/.flatpak-info").exists() {
+        return vec!["portal", "atspi"];
+    }
+
+    let us_keycodes = injector.layout().is_none_or(Layout::is_us_qwerty);
+    let multiple_groups = injector.layout().is_some_and(Layout::has_multiple_groups);
+
+    match (is_x11_session(injector), us_keycodes) {
+        // xdotool resolves keysyms through the keymap, but only the first group
+        (true, _) if multiple_groups => vec!["atspi", "xdotool", "ydotool", "keyd", "portal"],
+        (true, true) => vec!["xdotool", "ydotool", "keyd", "atspi", "portal"],
+        (true, false) => vec!["xdotool", "atspi", "ydotool", "keyd", "portal"],
+        (false, true) => vec!["ydotool", "keyd", "portal", "atspi", "xdotool"],
+        (false, false) => vec!["portal", "atspi", "ydotool", "keyd", "xdotool"],
+    }
+}
+
+/// Read the layout from setxkbmap on X11, or the compositor's XKB
+/// environment / localectl on Wayland (XWayland's keymap may not match)
+pub fn keyboard_layout(injector: &Injector) -> Option<Layout> {
+    let query = |program: &str, args: &[&str]| {
+        let output = injector.command(program).args(args).output().ok()?;
+        output
+            .status
+            .success()
+            .then(|| String::from_utf8_lossy(&output.stdout).to_string())
+            .and_then(|out| Layout::parse(&out))
+    };
+
+    if is_x11_session(injector) {
+        query("setxkbmap", &["-query"]).or_else(|| query("localectl", &["status"]))
+    } else {
+        Layout::from_env().or_else(|| query("localectl", &["status"]))
+    }
+}
+
+pub fn is_available(injector: &Injector) -> bool {
+    match injector.tool() {
+        "atspi" => return atspi::is_available(),
+        "portal" => return portal::is_available(),
+        _ => {}
+    }
+
+    injector
+        .command(injector.tool())
+        .arg("--version")
+        .output()
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
+
+/// ydotool only talks to ydotoold; without it every call fails (or hangs)
+pub fn backend_problem(injector: &Injector) -> Option<(String, String)> {
+    if injector.tool() != "ydotool" {
+        return None;
+    }
+
+    let sockets = injector.ydotool_sockets();
+    if sockets.iter().any(|socket| ydotoold_listening(socket)) {
+        return None;
+    }
+
+    let tried: Vec<String> = sockets.iter().map(|s| s.display().to_string()).collect();
+    Some((
+        "ydotoold not running".to_string(),
+        format!(
+            "Start it (systemctl --user enable --now ydotool, or run ydotoold), or pass --ydotool-socket; tried {}",
+            tried.join(", ")
+        ),
+    ))
+}
+
+/// ydotoold listens on a datagram socket; connecting fails if the file is
+/// missing or left over from a daemon that exited
+fn ydotoold_listening(socket: &std::path::Path) -> bool {
+    std::os::unix::net::UnixDatagram::unbound()
+        .and_then(|client| client.connect(socket))
+        .is_ok()
+}
+
+/// Whether the backend can type `ch` directly
+///
+/// ydotool and keyd send US-layout keycodes, so only ASCII is reliable, and
+/// on any other layout only space and Enter land where expected. xdotool
+/// borrows a spare keycode for characters missing from the layout, which
+/// many apps ignore for emoji and CJK, and the portal's keysyms hit the same
+/// limit. AT-SPI inserts text, so anything goes.
+pub fn can_type(injector: &Injector, ch: char) -> bool {
+    match injector.tool() {
+        "atspi" => true,
+        "ydotool" | "keyd" if injector.layout().is_none_or(Layout::is_us_qwerty) => ch.is_ascii(),
+        "ydotool" | "keyd" => ch == ' ' || ch == '\n',
+        _ => !is_emoji_or_cjk(ch),
+    }
+}
+
+pub fn paste(injector: &Injector) -> Result<(), String> {
+    let result = match injector.tool() {
+        "atspi" => return Err("AT-SPI inserts text directly; nothing to paste".to_string()),
+        "portal" => return portal::paste(),
+        // Left Ctrl (29) + V (47)
+        "ydotool" => injector.command("ydotool").args(["key", "29:1", "47:1", "47:0", "29:0"]).status(),
+        "keyd" => injector.command("keyd").args(["do", "C-v"]).status(),
+        _ => injector.command("xdotool").args(["key", "--clearmodifiers", "ctrl+v"]).status(),
+    };
+
+    result.map_err(|e| format!("Paste error: {}", e))?;
+    Ok(())
+}
+
+pub fn type_text(injector: &Injector, text: &str, cancel: &CancelToken) -> Result<(), String> {
+    // AT-SPI inserts text directly rather than faking keypresses, all at once
+    if injector.tool() == "atspi" {
+        if cancel.is_cancelled() {
+            return Err(CANCELLED.to_string());
+        }
+        return atspi::insert_text(text);
+    }
+    if injector.tool() == "portal" {
+        return portal::type_text(text, cancel);
+    }
+
+    let mut cmd = if injector.tool() == "ydotool" {
+        let mut cmd = injector.command("ydotool");
+        cmd.arg("type").args(injector.extra_args()).arg(text);
+        cmd
+    } else if injector.tool() == "keyd" {
+        // Route through keyd's virtual keyboard so injected keys get the
+        // same remapping as physical ones
+        let mut cmd = injector.command("keyd");
+        cmd.arg("input").args(injector.extra_args()).arg(text);
+        cmd
+    } else {
+        let mut cmd = injector.command("xdotool");
+        cmd.arg("type").args(injector.extra_args()).arg("--").arg(text);
+        cmd
+    };
+
+    // Keep the tool's chatter off the TUI
+    cmd.stdout(Stdio::null()).stderr(Stdio::null());
+    run_cancellable(cmd, cancel)?;
+    Ok(())
+}
+
+pub fn backspace(injector: &Injector, count: usize) -> Result<(), String> {
+    if injector.tool() == "atspi" {
+        return atspi::delete_before_caret(count);
+    }
+    if injector.tool() == "portal" {
+        return portal::backspace(count);
+    }
+
+    let count_arg = count.to_string();
+    let result = if injector.tool() == "ydotool" {
+        // Linux input keycode 14 is Backspace; each press is down then up
+        let presses: Vec<&str> = ["14:1", "14:0"].repeat(count);
+        injector.command("ydotool").arg("key").args(presses).status()
+    } else if injector.tool() == "keyd" {
+        let macro_keys = vec!["backspace"; count].join(" ");
+        injector.command("keyd").arg("do").arg(macro_keys).status()
+    } else {
+        injector.command("xdotool")
+            .args(["key", "--repeat", &count_arg, "BackSpace"])
+            .status()
+    };
+
+    result.map_err(|e| format!("Backspace error: {}", e))?;
+    Ok(())
+}
+
+pub fn press_key(injector: &Injector, key: Key) -> Result<(), String> {
+    // AT-SPI has no key events; emulate what it can through the text interfaces
+    if injector.tool() == "atspi" {
+        return match key {
+            Key::Enter => atspi::insert_text("\n"),
+            Key::Tab => atspi::insert_text("\t"),
+            Key::Backspace => atspi::delete_before_caret(1),
+            Key::Left => atspi::move_caret(-1),
+            Key::Right => atspi::move_caret(1),
+            Key::Escape | Key::Up | Key::Down => Err(format!("atspi can't send {:?}", key)),
+        };
+    }
+    if injector.tool() == "portal" {
+        return portal::press_key(key);
+    }
+
+    let status = if injector.tool() == "ydotool" {
+        // Linux input event codes (linux/input-event-codes.h)
+        let code = match key {
+            Key::Enter => 28,
+            Key::Tab => 15,
+            Key::Backspace => 14,
+            Key::Escape => 1,
+            Key::Left => 105,
+            Key::Right => 106,
+            Key::Up => 103,
+            Key::Down => 108,
+        };
+        injector.command("ydotool").arg("key").arg(format!("{}:1", code)).arg(format!("{}:0", code)).status()
+    } else if injector.tool() == "keyd" {
+        let name = match key {
+            Key::Enter => "enter",
+            Key::Tab => "tab",
+            Key::Backspace => "backspace",
+            Key::Escape => "esc",
+            Key::Left => "left",
+            Key::Right => "right",
+            Key::Up => "up",
+            Key::Down => "down",
+        };
+        injector.command("keyd").arg("do").arg(name).status()
+    } else {
+        let keysym = match key {
+            Key::Enter => "Return",
+            Key::Tab => "Tab",
+            Key::Backspace => "BackSpace",
+            Key::Escape => "Escape",
+            Key::Left => "Left",
+            Key::Right => "Right",
+            Key::Up => "Up",
+            Key::Down => "Down",
+        };
+        injector.command("xdotool").args(["key", "--clearmodifiers", keysym]).status()
+    };
+
+    let status = status.map_err(|e| format!("Key error: {}", e))?;
+    if !status.success() {
+        return Err(format!("{} failed to press {:?}", injector.tool(), key));
+    }
+    Ok(())
+}
+
+// Window focus goes through xdotool regardless of the typing tool; Wayland
+// compositors don't let clients raise other windows
+
+pub fn active_window(injector: &Injector) -> Option<String> {
+    let output = injector.command("xdotool").arg("getactivewindow").output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let id = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (!id.is_empty()).then_some(id)
+}
+
+pub fn has_class(injector: &Injector, class: &str) -> bool {
+    injector
+        .command("xdotool")
+        .args(["search", "--limit", "1", "--class", class])
+        .stdout(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+pub fn activate_class(injector: &Injector, class: &str) -> Result<(), String> {
+    let status = injector
+        .command("xdotool")
+        .args(["search", "--limit", "1", "--class", class, "windowactivate", "--sync"])
+        .status()
+        .map_err(|e| format!("Focusing windows needs xdotool (X11): {}", e))?;
+
+    if !status.success() {
+        return Err(format!("No window with class {}", class));
+    }
+    Ok(())
+}
+
+pub fn activate_window(injector: &Injector, window: &str) -> Result<(), String> {
+    let status = injector
+        .command("xdotool")
+        .args(["windowactivate", "--sync", window])
+        .status()
+        .map_err(|e| format!("Failed to restore focus: {}", e))?;
+
+    if !status.success() {
+        return Err(format!("Failed to restore focus to window {}", window));
+    }
+    Ok(())
+}
+
+/// Class and title of the active window and the current desktop, via
+/// xdotool (X11 only)
+pub fn focused_window(injector: &Injector) -> Option<FocusedWindow> {
+    let output = injector
+        .command("xdotool")
+        .args(["getactivewindow", "getwindowclassname", "getwindowname"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let output = String::from_utf8_lossy(&output.stdout);
+    let (class, title) = output.split_once('\n').unwrap_or((&output, ""));
+    let (class, title) = (class.trim().to_string(), title.trim());
+    if class.is_empty() {
+        return None;
+    }
+    let title = (!title.is_empty()).then(|| title.to_string());
+
+    let workspace = injector
+        .command("xdotool")
+        .arg("get_desktop")
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8_lossy(&output.stdout).trim().parse().ok());
+    Some(FocusedWindow { class, title, workspace })
+}
+
+pub fn has_text_field(injector: &Injector) -> Option<bool> {
+    text_field_known(injector.tool(), atspi::has_focused_text_field)
+}
+
+/// What `focused` says of the focused text field, for AT-SPI, which inserts
+/// into it; other tools type into whatever has focus, so can't tell, nor can
+/// AT-SPI without an accessibility bus
+fn text_field_known(tool: &str, focused: impl FnOnce() -> Result<bool, String>) -> Option<bool> {
+    (tool == "atspi").then(focused)?.ok()
+}
+
+/// Emoji and other astral-plane characters, plus CJK and fullwidth forms:
+/// the ones keysym-based typing tends to drop
+fn is_emoji_or_cjk(ch: char) -> bool {
+    matches!(ch as u32,
+        0x10000.. // emoji and everything else outside the BMP
+        | 0x2E80..=0x9FFF // CJK radicals, kana, unified ideographs
+        | 0xAC00..=0xD7AF // Hangul syllables
+        | 0xF900..=0xFAFF // CJK compatibility ideographs
+        | 0xFF00..=0xFFEF // fullwidth forms
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_can_type() {
+        let injector = |tool: &str| Injector::new(tool.to_string(), None, None);
+
+        assert!(can_type(&injector("ydotool"), 'a'));
+        assert!(!can_type(&injector("ydotool"), 'é'));
+        assert!(can_type(&injector("xdotool"), 'é'));
+        assert!(!can_type(&injector("xdotool"), '😀'));
+        assert!(!can_type(&injector("xdotool"), '漢'));
+        assert!(can_type(&injector("atspi"), '😀'));
+
+        let mut azerty = injector("ydotool");
+        azerty.layout = Layout::parse("layout: fr");
+        assert!(!can_type(&azerty, 'a'));
+        assert!(can_type(&azerty, ' '));
+    }
+
+    #[test]
+    fn test_text_field_known() {
+        assert_eq!(text_field_known("atspi", || Ok(true)), Some(true));
+        assert_eq!(text_field_known("atspi", || Ok(false)), Some(false));
+        // No accessibility bus: don't hold everything for want of an answer
+        assert_eq!(text_field_known("atspi", || Err("no bus".to_string())), None);
+        assert_eq!(text_field_known("xdotool", || panic!("only AT-SPI is asked")), None);
+    }
+}
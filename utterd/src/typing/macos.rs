@@ -0,0 +1,172 @@
+use super::{run_cancellable, CancelToken, FocusedWindow, Injector, Key, Layout};
+use std::io::Read;
+use std::process::{Command, Stdio};
+
+pub const TOOLS: &[&str] = &["osascript"];
+
+/// osascript runs a fixed script; there are no typing options to pass
+pub const COMMAND_TOOLS: &[&str] = &[];
+
+// Text is passed as an argument rather than spliced into the script, so
+// quotes and backslashes in dictation need no AppleScript escaping
+const KEYSTROKE_SCRIPT: [&str; 3] = [
+    "on run argv",
+    "tell application \"System Events\" to keystroke (item 1 of argv)",
+    "end run",
+];
+
+pub fn preferred_tools(_injector: &Injector) -> Vec<&'static str> {
+    TOOLS.to_vec()
+}
+
+/// keystroke goes through the active input source, so the layout doesn't matter
+pub fn keyboard_layout(_injector: &Injector) -> Option<Layout> {
+    None
+}
+
+pub fn backend_problem(_injector: &Injector) -> Option<(String, String)> {
+    None
+}
+
+/// Check that osascript can run at all
+pub fn is_available(_injector: &Injector) -> bool {
+    Command::new("osascript")
+        .args(["-e", "return"])
+        .output()
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
+
+/// System Events keystroke handles the BMP but mangles emoji
+pub fn can_type(_injector: &Injector, ch: char) -> bool {
+    (ch as u32) < 0x10000
+}
+
+pub fn paste(_injector: &Injector) -> Result<(), String> {
+    let status = Command::new("osascript")
+        .args(["-e", "tell application \"System Events\" to keystroke \"v\" using command down"])
+        .status()
+        .map_err(|e| format!("Paste error: {}", e))?;
+
+    if !status.success() {
+        return Err("osascript failed to paste".to_string());
+    }
+    Ok(())
+}
+
+/// Type text into the frontmost app via System Events
+///
+/// The terminal running utterd needs the Accessibility permission
+/// (System Settings → Privacy & Security → Accessibility).
+pub fn type_text(_injector: &Injector, text: &str, cancel: &CancelToken) -> Result<(), String> {
+    let mut cmd = Command::new("osascript");
+    for line in KEYSTROKE_SCRIPT {
+        cmd.arg("-e").arg(line);
+    }
+    cmd.arg(text).stdout(Stdio::null()).stderr(Stdio::piped());
+
+    let (mut child, status) = run_cancellable(cmd, cancel)?;
+
+    if !status.success() {
+        let mut stderr = String::new();
+        if let Some(mut pipe) = child.stderr.take() {
+            let _ = pipe.read_to_string(&mut stderr);
+        }
+        return Err(format!(
+            "osascript failed (grant Accessibility permission?): {}",
+            stderr.trim()
+        ));
+    }
+
+    Ok(())
+}
+
+pub fn backspace(_injector: &Injector, count: usize) -> Result<(), String> {
+    // Key code 51 is Delete (Backspace)
+    key_code(51, count).map_err(|e| format!("Backspace error: {}", e))
+}
+
+pub fn press_key(_injector: &Injector, key: Key) -> Result<(), String> {
+    // Virtual key codes from HIToolbox/Events.h
+    let code = match key {
+        Key::Enter => 36,
+        Key::Tab => 48,
+        Key::Backspace => 51,
+        Key::Escape => 53,
+        Key::Left => 123,
+        Key::Right => 124,
+        Key::Down => 125,
+        Key::Up => 126,
+    };
+    key_code(code, 1).map_err(|e| format!("Key error: {}", e))
+}
+
+/// Press the key with virtual key code `code`, `count` times
+fn key_code(code: u16, count: usize) -> Result<(), String> {
+    let status = Command::new("osascript")
+        .args([
+            "-e", "on run argv",
+            "-e", "repeat (item 2 of argv as integer) times",
+            "-e", "tell application \"System Events\" to key code (item 1 of argv as integer)",
+            "-e", "end repeat",
+            "-e", "end run",
+        ])
+        .arg(code.to_string())
+        .arg(count.to_string())
+        .status()
+        .map_err(|e| e.to_string())?;
+
+    if !status.success() {
+        return Err(format!("osascript failed to send key code {}", code));
+    }
+    Ok(())
+}
+
+// macOS has no window classes; the "class" is the application name
+
+pub fn active_window(_injector: &Injector) -> Option<String> {
+    let output = Command::new("osascript")
+        .args(["-e", "tell application \"System Events\" to get name of first application process whose frontmost is true"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let name = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (!name.is_empty()).then_some(name)
+}
+
+pub fn focused_window(injector: &Injector) -> Option<FocusedWindow> {
+    active_window(injector).map(|class| FocusedWindow { class, title: None, workspace: None })
+}
+
+pub fn has_text_field(_injector: &Injector) -> Option<bool> {
+    None
+}
+
+pub fn has_class(_injector: &Injector, class: &str) -> bool {
+    Command::new("osascript")
+        .args(["-e", "on run argv", "-e", "return application (item 1 of argv) is running", "-e", "end run", class])
+        .output()
+        .is_ok_and(|output| output.status.success() && String::from_utf8_lossy(&output.stdout).trim() == "true")
+}
+
+pub fn activate_class(_injector: &Injector, class: &str) -> Result<(), String> {
+    activate_app(class)
+}
+
+pub fn activate_window(_injector: &Injector, window: &str) -> Result<(), String> {
+    activate_app(window)
+}
+
+fn activate_app(name: &str) -> Result<(), String> {
+    let status = Command::new("osascript")
+        .args(["-e", "on run argv", "-e", "tell application (item 1 of argv) to activate", "-e", "end run", name])
+        .status()
+        .map_err(|e| format!("Failed to run osascript: {}", e))?;
+
+    if !status.success() {
+        return Err(format!("Failed to activate {}", name));
+    }
+    Ok(())
+}
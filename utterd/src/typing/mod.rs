@@ -0,0 +1,376 @@
+//! Text injection, with the backend chosen per platform at compile time
+//!
+//! Each platform module provides the same items:
+//! - `TOOLS`: names accepted by `--tool`
+//! - `COMMAND_TOOLS`: the subset run as external commands, which accept
+//!   extra arguments from the config file
+//! - `preferred_tools(&Injector) -> Vec<&str>`: candidates for the current
+//!   session, best first, used when `--tool` is not given
+//! - `keyboard_layout(&Injector) -> Option<Layout>`: the session's layout,
+//!   where keycode-based tools care about it
+//! - `is_available(&Injector) -> bool`
+//! - `backend_problem(&Injector) -> Option<(String, String)>`: a problem
+//!   with a helper the tool depends on (e.g. a daemon that isn't running),
+//!   and how to fix it
+//! - `type_text(&Injector, &str, &CancelToken) -> Result<(), String>`, which
+//!   must stop as soon as it can once the token is cancelled
+//! - `can_type(&Injector, char) -> bool`: whether a character can be typed
+//!   directly; messages with any that can't are pasted instead
+//! - `paste(&Injector) -> Result<(), String>`: press the paste shortcut
+//! - `backspace(&Injector, usize) -> Result<(), String>`: erase characters
+//!   before the cursor, for corrections
+//! - `press_key(&Injector, Key) -> Result<(), String>`: a non-text key sent
+//!   by the phone
+//! - `active_window`, `has_class`, `activate_class`, `activate_window`:
+//!   window focus for `--target-class`, with window ids as opaque strings
+//! - `focused_window(&Injector) -> Option<FocusedWindow>`: class and
+//!   workspace of the focused window, for do-not-type zones
+//! - `has_text_field(&Injector) -> Option<bool>`: whether an editable text
+//!   field has keyboard focus, where the tool can tell
+
+#[cfg(all(unix, not(target_os = "macos")))]
+mod atspi;
+mod layout;
+#[cfg(all(unix, not(target_os = "macos")))]
+mod portal;
+
+#[cfg(target_os = "macos")]
+mod macos;
+#[cfg(target_os = "macos")]
+use macos as platform;
+
+#[cfg(windows)]
+mod windows;
+#[cfg(windows)]
+use windows as platform;
+
+#[cfg(all(unix, not(target_os = "macos")))]
+mod linux;
+#[cfg(all(unix, not(target_os = "macos")))]
+use linux as platform;
+
+use std::path::PathBuf;
+use std::process::{Child, Command, ExitStatus};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub use layout::Layout;
+pub use platform::{COMMAND_TOOLS, TOOLS};
+
+/// Error returned when typing was stopped by the user
+pub const CANCELLED: &str = "Typing cancelled";
+
+/// Shared flag for aborting an injection that is already running
+#[derive(Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Re-arm before starting the next injection
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Non-text keys the phone can send (`{"type":"key","key":"Enter"}`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Key {
+    Enter,
+    Tab,
+    Backspace,
+    Escape,
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+impl Key {
+    /// Parse a protocol key name, case-insensitively (DOM names like
+    /// "ArrowLeft" are accepted too)
+    pub fn parse(name: &str) -> Option<Self> {
+        Some(match name.to_ascii_lowercase().as_str() {
+            "enter" | "return" => Self::Enter,
+            "tab" => Self::Tab,
+            "backspace" => Self::Backspace,
+            "escape" | "esc" => Self::Escape,
+            "left" | "arrowleft" => Self::Left,
+            "right" | "arrowright" => Self::Right,
+            "up" | "arrowup" => Self::Up,
+            "down" | "arrowdown" => Self::Down,
+            _ => return None,
+        })
+    }
+}
+
+/// Type-command options for a pause between keystrokes, for tools that have one
+pub fn delay_args(tool: &str, delay_ms: u64) -> Option<Vec<String>> {
+    let option = match tool {
+        "xdotool" => "--delay",
+        "ydotool" => "--key-delay",
+        _ => return None,
+    };
+    Some(vec![option.to_string(), delay_ms.to_string()])
+}
+
+/// What typing would go into right now
+#[derive(Debug, Clone, PartialEq)]
+pub struct FocusedWindow {
+    /// Window class (X11), application name (macOS) or window class name (Windows)
+    pub class: String,
+    /// Window title, where the platform reports one (not on macOS)
+    pub title: Option<String>,
+    /// Virtual desktop number, where the window manager reports one
+    pub workspace: Option<u32>,
+}
+
+/// The selected typing tool plus the session it should type into
+#[derive(Clone)]
+pub struct Injector {
+    tool: String,
+    display: Option<String>,
+    wayland_display: Option<String>,
+    layout: Option<Layout>,
+    extra_args: Vec<String>,
+    /// Minimal environment and no network for tool subprocesses (`--harden`)
+    hardened: bool,
+    /// ydotoold socket, overriding YDOTOOL_SOCKET
+    ydotool_socket: Option<PathBuf>,
+}
+
+impl Injector {
+    pub fn new(tool: String, display: Option<String>, wayland_display: Option<String>) -> Self {
+        Self {
+            tool,
+            display,
+            wayland_display,
+            layout: None,
+            extra_args: Vec::new(),
+            hardened: false,
+            ydotool_socket: None,
+        }
+    }
+
+    /// The same session and settings with another tool (extra args are per tool, so they're dropped)
+    pub fn with_tool(&self, tool: &str) -> Self {
+        Self {
+            tool: tool.to_string(),
+            extra_args: Vec::new(),
+            ..self.clone()
+        }
+    }
+
+    pub fn with_ydotool_socket(mut self, socket: Option<PathBuf>) -> Self {
+        self.ydotool_socket = socket;
+        self
+    }
+
+    /// Socket paths ydotool may use, explicit setting first
+    pub fn ydotool_sockets(&self) -> Vec<PathBuf> {
+        if let Some(ref socket) = self.ydotool_socket {
+            return vec![socket.clone()];
+        }
+        if let Some(socket) = std::env::var_os("YDOTOOL_SOCKET").filter(|s| !s.is_empty()) {
+            return vec![PathBuf::from(socket)];
+        }
+        // Newer ydotool defaults to the runtime dir, 1.0.x to /tmp
+        let mut sockets: Vec<PathBuf> = std::env::var_os("XDG_RUNTIME_DIR")
+            .map(|dir| PathBuf::from(dir).join(".ydotool_socket"))
+            .into_iter()
+            .collect();
+        sockets.push(PathBuf::from("/tmp/.ydotool_socket"));
+        sockets
+    }
+
+    pub fn hardened(mut self) -> Self {
+        self.hardened = true;
+        self
+    }
+
+    pub fn is_hardened(&self) -> bool {
+        self.hardened
+    }
+
+    /// Options inserted into the tool's type command, from `tool_args` in the config
+    pub fn with_extra_args(mut self, args: Vec<String>) -> Self {
+        self.extra_args = args;
+        self
+    }
+
+    pub fn extra_args(&self) -> &[String] {
+        &self.extra_args
+    }
+
+    /// Look up the target session's keyboard layout
+    pub fn with_detected_layout(mut self) -> Self {
+        self.layout = platform::keyboard_layout(&self);
+        self
+    }
+
+    /// Pick the first available tool suited to the current session
+    ///
+    /// Falls back to the top candidate when none is installed, so the
+    /// startup check can name the tool the user should install.
+    pub fn detect(display: Option<String>, wayland_display: Option<String>) -> Self {
+        let probe = Self::new(String::new(), display, wayland_display).with_detected_layout();
+        let candidates = platform::preferred_tools(&probe);
+
+        let tool = candidates
+            .iter()
+            .find(|tool| Self { tool: tool.to_string(), ..probe.clone() }.is_available())
+            .unwrap_or(&candidates[0]);
+
+        Self { tool: tool.to_string(), ..probe }
+    }
+
+    pub fn tool(&self) -> &str {
+        &self.tool
+    }
+
+    pub fn layout(&self) -> Option<&Layout> {
+        self.layout.as_ref()
+    }
+
+    /// Check that the selected tool can be used right now
+    pub fn is_available(&self) -> bool {
+        platform::is_available(self)
+    }
+
+    /// See `backend_problem` in the module docs
+    pub fn backend_problem(&self) -> Option<(String, String)> {
+        platform::backend_problem(self)
+    }
+
+    /// Type (or insert) text into the focused window
+    pub fn type_text(&self, text: &str, cancel: &CancelToken) -> Result<(), String> {
+        platform::type_text(self, text, cancel)
+    }
+
+    /// Whether every character of `text` can be typed directly
+    pub fn can_type(&self, text: &str) -> bool {
+        text.chars().all(|ch| platform::can_type(self, ch))
+    }
+
+    /// Insert text through the clipboard, for characters the backend can't type
+    ///
+    /// Overwrites the clipboard. Apps that don't paste with Ctrl+V (terminals)
+    /// won't receive the text.
+    pub fn paste(&self, text: &str) -> Result<(), String> {
+        crate::clipboard::copy_via(text, |program| self.command(program))?;
+        // Give the clipboard owner a moment to take the selection
+        std::thread::sleep(Duration::from_millis(50));
+        platform::paste(self)
+    }
+
+    /// Erase `count` characters before the cursor
+    pub fn backspace(&self, count: usize) -> Result<(), String> {
+        if count == 0 {
+            return Ok(());
+        }
+        platform::backspace(self, count)
+    }
+
+    pub fn press_key(&self, key: Key) -> Result<(), String> {
+        platform::press_key(self, key)
+    }
+
+    /// Whether a window of `class` is open
+    pub fn has_class(&self, class: &str) -> bool {
+        platform::has_class(self, class)
+    }
+
+    /// Focus the first window of `class`, returning the previously active window
+    pub fn focus_class(&self, class: &str) -> Result<Option<String>, String> {
+        let previous = platform::active_window(self);
+        platform::activate_class(self, class)?;
+        Ok(previous)
+    }
+
+    /// The focused window, or None if it can't be determined (e.g. on Wayland)
+    pub fn focused_window(&self) -> Option<FocusedWindow> {
+        platform::focused_window(self)
+    }
+
+    /// Whether a text field has keyboard focus, or None if the tool can't
+    /// tell (only AT-SPI, which inserts into it, can)
+    pub fn has_text_field(&self) -> Option<bool> {
+        platform::has_text_field(self)
+    }
+
+    /// Give focus back to a window returned by `focus_class`
+    pub fn restore_focus(&self, window: &str) -> Result<(), String> {
+        platform::activate_window(self, window)
+    }
+
+    /// Build a command for an external typing tool, pointed at the target session
+    ///
+    /// When utterd runs in a container or under a different user, its own
+    /// DISPLAY/WAYLAND_DISPLAY don't describe the host session. Two setups work:
+    /// - X11: mount /tmp/.X11-unix (plus an Xauthority) and pass `--display :0`
+    /// - Headless: mount /dev/uinput and the ydotoold socket and use
+    ///   `--tool ydotool`, which needs no display at all
+    pub fn command(&self, program: &str) -> Command {
+        let mut cmd = Command::new(program);
+        if self.hardened {
+            crate::sandbox::restrict_command(&mut cmd);
+        }
+        if let Some(ref display) = self.display {
+            cmd.env("DISPLAY", display);
+        }
+        if let Some(ref wayland_display) = self.wayland_display {
+            cmd.env("WAYLAND_DISPLAY", wayland_display);
+        }
+        if let Some(ref socket) = self.ydotool_socket {
+            cmd.env("YDOTOOL_SOCKET", socket);
+        }
+        cmd
+    }
+
+    /// Display the typing tool will use (override first, then inherited environment)
+    pub fn effective_display(&self) -> Option<String> {
+        self.display
+            .clone()
+            .or_else(|| std::env::var("DISPLAY").ok())
+            .filter(|d| !d.is_empty())
+    }
+
+    /// Wayland display the typing tool will use (override first, then inherited environment)
+    pub fn effective_wayland_display(&self) -> Option<String> {
+        self.wayland_display
+            .clone()
+            .or_else(|| std::env::var("WAYLAND_DISPLAY").ok())
+            .filter(|d| !d.is_empty())
+    }
+}
+
+/// Run an external typing tool, killing it if `cancel` fires part-way through
+///
+/// Returns the child once it has exited so callers can inspect its status
+/// and any piped output.
+fn run_cancellable(mut cmd: Command, cancel: &CancelToken) -> Result<(Child, ExitStatus), String> {
+    let mut child = cmd.spawn().map_err(|e| format!("Typing error: {}", e))?;
+
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => return Ok((child, status)),
+            Ok(None) => {}
+            Err(e) => return Err(format!("Typing error: {}", e)),
+        }
+
+        if cancel.is_cancelled() {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(CANCELLED.to_string());
+        }
+
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
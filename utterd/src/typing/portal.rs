@@ -0,0 +1,279 @@
+use super::{CancelToken, Key, CANCELLED};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use zbus::blocking::{Connection, MessageIterator};
+use zbus::zvariant::{ObjectPath, OwnedObjectPath, OwnedValue, Value};
+
+// xdg-desktop-portal D-Bus names (see org.freedesktop.portal.RemoteDesktop.xml)
+const PORTAL_NAME: &str = "org.freedesktop.portal.Desktop";
+const PORTAL_PATH: &str = "/org/freedesktop/portal/desktop";
+const REMOTE_DESKTOP_IFACE: &str = "org.freedesktop.portal.RemoteDesktop";
+const REQUEST_IFACE: &str = "org.freedesktop.portal.Request";
+
+/// Device type bit for keyboards
+const DEVICE_KEYBOARD: u32 = 1;
+
+/// Keep the grant until the user revokes it, so the dialog only shows once
+const PERSIST_PERMANENT: u32 = 2;
+
+// X11 keysyms (xkbcommon-keysyms.h)
+const KEYSYM_RETURN: i32 = 0xff0d;
+const KEYSYM_TAB: i32 = 0xff09;
+const KEYSYM_BACKSPACE: i32 = 0xff08;
+const KEYSYM_ESCAPE: i32 = 0xff1b;
+const KEYSYM_LEFT: i32 = 0xff51;
+const KEYSYM_UP: i32 = 0xff52;
+const KEYSYM_RIGHT: i32 = 0xff53;
+const KEYSYM_DOWN: i32 = 0xff54;
+const KEYSYM_CONTROL_L: i32 = 0xffe3;
+
+/// A started RemoteDesktop session
+struct Session {
+    conn: Connection,
+    handle: OwnedObjectPath,
+}
+
+/// Starting a session may pop up a permission dialog, so it's done once and
+/// reused until a call on it fails (the user revoked it, or the portal restarted)
+static SESSION: Mutex<Option<Session>> = Mutex::new(None);
+
+/// Check that a portal offering keyboard input is on the session bus
+pub fn is_available() -> bool {
+    let Ok(conn) = Connection::session() else {
+        return false;
+    };
+
+    conn.call_method(
+        Some(PORTAL_NAME),
+        PORTAL_PATH,
+        Some("org.freedesktop.DBus.Properties"),
+        "Get",
+        &(REMOTE_DESKTOP_IFACE, "AvailableDeviceTypes"),
+    )
+    .and_then(|reply| reply.body().deserialize::<OwnedValue>())
+    .ok()
+    .and_then(|types| u32::try_from(types).ok())
+    .is_some_and(|types| types & DEVICE_KEYBOARD != 0)
+}
+
+/// Where the restore token from the last grant is kept
+fn token_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("utterd").join("portal-token"))
+}
+
+/// Call a portal method that answers through a Request object
+///
+/// The reply only carries the request path; the results arrive later in its
+/// Response signal. The path is predictable from our unique name and the
+/// handle_token, so subscribe before calling to not miss a quick answer.
+fn request(
+    conn: &Connection,
+    method: &str,
+    token: &str,
+    body: &(impl serde::Serialize + zbus::zvariant::DynamicType),
+) -> Result<HashMap<String, OwnedValue>, String> {
+    let sender = conn
+        .unique_name()
+        .ok_or("Not connected to the session bus")?
+        .trim_start_matches(':')
+        .replace('.', "_");
+    let path = format!("{}/request/{}/{}", PORTAL_PATH, sender, token);
+
+    let rule = zbus::MatchRule::builder()
+        .msg_type(zbus::message::Type::Signal)
+        .interface(REQUEST_IFACE)
+        .and_then(|rule| rule.member("Response"))
+        .and_then(|rule| rule.path(path.as_str()))
+        .map_err(|e| format!("Invalid portal request path: {}", e))?
+        .build();
+    let mut responses = MessageIterator::for_match_rule(rule, conn, Some(1))
+        .map_err(|e| format!("Cannot watch portal responses: {}", e))?;
+
+    conn.call_method(Some(PORTAL_NAME), PORTAL_PATH, Some(REMOTE_DESKTOP_IFACE), method, body)
+        .map_err(|e| format!("RemoteDesktop {} failed: {}", method, e))?;
+
+    let response = responses
+        .next()
+        .ok_or("Portal went away")?
+        .map_err(|e| format!("Portal response error: {}", e))?;
+    let (code, results): (u32, HashMap<String, OwnedValue>) = response
+        .body()
+        .deserialize()
+        .map_err(|e| format!("Unexpected portal response: {}", e))?;
+
+    match code {
+        0 => Ok(results),
+        1 => Err("Remote control request was denied".to_string()),
+        _ => Err(format!("RemoteDesktop {} was cancelled", method)),
+    }
+}
+
+/// Create, configure and start a keyboard-only RemoteDesktop session
+///
+/// A restore token from an earlier grant skips the dialog; the portal hands
+/// back a fresh one every time, which replaces it.
+fn start_session() -> Result<Session, String> {
+    let conn = Connection::session().map_err(|e| format!("Cannot connect to session bus: {}", e))?;
+    let token = |step: &str| format!("utterd_{}_{}", step, std::process::id());
+
+    let created = request(
+        &conn,
+        "CreateSession",
+        &token("create"),
+        &(HashMap::from([
+            ("handle_token", Value::from(token("create"))),
+            ("session_handle_token", Value::from(token("session"))),
+        ]),),
+    )?;
+    let handle = created
+        .get("session_handle")
+        .and_then(|handle| handle.try_clone().ok())
+        .and_then(|handle| String::try_from(handle).ok())
+        .and_then(|handle| OwnedObjectPath::try_from(handle).ok())
+        .ok_or("Portal returned no session")?;
+
+    let mut select = HashMap::from([
+        ("handle_token", Value::from(token("select"))),
+        ("types", Value::from(DEVICE_KEYBOARD)),
+        ("persist_mode", Value::from(PERSIST_PERMANENT)),
+    ]);
+    let restore_token = token_path().and_then(|path| std::fs::read_to_string(path).ok());
+    if let Some(restore_token) = restore_token.as_deref().map(str::trim).filter(|t| !t.is_empty()) {
+        select.insert("restore_token", Value::from(restore_token.to_string()));
+    }
+    request(&conn, "SelectDevices", &token("select"), &(&handle, select))?;
+
+    let started = request(
+        &conn,
+        "Start",
+        &token("start"),
+        &(&handle, "", HashMap::from([("handle_token", Value::from(token("start")))])),
+    )?;
+
+    let devices = started
+        .get("devices")
+        .and_then(|devices| u32::try_from(devices).ok())
+        .unwrap_or(0);
+    if devices & DEVICE_KEYBOARD == 0 {
+        return Err("Keyboard control was not granted".to_string());
+    }
+
+    if let (Some(path), Some(new_token)) = (
+        token_path(),
+        started.get("restore_token").and_then(|t| <&str>::try_from(t).ok()),
+    ) {
+        // Losing the token only means asking again next time
+        let _ = std::fs::write(path, new_token);
+    }
+
+    Ok(Session { conn, handle })
+}
+
+/// Run `f` against the shared session, starting one if needed
+///
+/// A failure drops the session so the next call starts over.
+fn with_session(f: impl FnOnce(&Session) -> Result<(), String>) -> Result<(), String> {
+    let mut session = SESSION.lock().unwrap_or_else(|e| e.into_inner());
+    if session.is_none() {
+        *session = Some(start_session()?);
+    }
+
+    let result = f(session.as_ref().expect("session was just started"));
+    if result.is_err() {
+        *session = None;
+    }
+    result
+}
+
+fn notify_keysym(session: &Session, keysym: i32, pressed: bool) -> Result<(), String> {
+    session
+        .conn
+        .call_method(
+            Some(PORTAL_NAME),
+            PORTAL_PATH,
+            Some(REMOTE_DESKTOP_IFACE),
+            "NotifyKeyboardKeysym",
+            &(
+                ObjectPath::from(&session.handle),
+                HashMap::<&str, Value>::new(),
+                keysym,
+                pressed as u32,
+            ),
+        )
+        .map_err(|e| format!("Portal refused key event: {}", e))?;
+    Ok(())
+}
+
+fn tap(session: &Session, keysym: i32) -> Result<(), String> {
+    notify_keysym(session, keysym, true)?;
+    notify_keysym(session, keysym, false)
+}
+
+/// Keysym for a character: Latin-1 maps directly, the rest of Unicode sits
+/// at 0x01000000 + codepoint
+fn char_keysym(ch: char) -> i32 {
+    match ch {
+        '\n' => KEYSYM_RETURN,
+        '\t' => KEYSYM_TAB,
+        ' '..='~' | '\u{a0}'..='\u{ff}' => ch as i32,
+        _ => 0x0100_0000 + ch as i32,
+    }
+}
+
+/// Type text as keysym presses
+///
+/// The compositor maps keysyms to keycodes itself, so the keyboard layout
+/// doesn't matter.
+pub fn type_text(text: &str, cancel: &CancelToken) -> Result<(), String> {
+    with_session(|session| {
+        for ch in text.chars().filter(|&ch| ch != '\r') {
+            if cancel.is_cancelled() {
+                return Err(CANCELLED.to_string());
+            }
+            tap(session, char_keysym(ch))?;
+        }
+        Ok(())
+    })
+}
+
+pub fn backspace(count: usize) -> Result<(), String> {
+    with_session(|session| (0..count).try_for_each(|_| tap(session, KEYSYM_BACKSPACE)))
+}
+
+pub fn press_key(key: Key) -> Result<(), String> {
+    let keysym = match key {
+        Key::Enter => KEYSYM_RETURN,
+        Key::Tab => KEYSYM_TAB,
+        Key::Backspace => KEYSYM_BACKSPACE,
+        Key::Escape => KEYSYM_ESCAPE,
+        Key::Left => KEYSYM_LEFT,
+        Key::Right => KEYSYM_RIGHT,
+        Key::Up => KEYSYM_UP,
+        Key::Down => KEYSYM_DOWN,
+    };
+    with_session(|session| tap(session, keysym))
+}
+
+pub fn paste() -> Result<(), String> {
+    with_session(|session| {
+        notify_keysym(session, KEYSYM_CONTROL_L, true)?;
+        let pasted = tap(session, char_keysym('v'));
+        notify_keysym(session, KEYSYM_CONTROL_L, false)?;
+        pasted
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_char_keysym() {
+        assert_eq!(char_keysym('a'), 0x61);
+        assert_eq!(char_keysym('é'), 0xe9);
+        assert_eq!(char_keysym('\n'), KEYSYM_RETURN);
+        assert_eq!(char_keysym('€'), 0x0100_20ac);
+        assert_eq!(char_keysym('😀'), 0x0101_f600);
+    }
+}
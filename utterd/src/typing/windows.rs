@@ -0,0 +1,200 @@
+use super::{CancelToken, FocusedWindow, Injector, Key, Layout, CANCELLED};
+use std::mem::size_of;
+use std::ptr::null;
+use windows_sys::Win32::Foundation::HWND;
+use windows_sys::Win32::UI::Input::KeyboardAndMouse::{
+    SendInput, INPUT, INPUT_0, INPUT_KEYBOARD, KEYBDINPUT, KEYEVENTF_KEYUP, KEYEVENTF_UNICODE,
+    VIRTUAL_KEY, VK_BACK, VK_CONTROL, VK_DOWN, VK_ESCAPE, VK_LEFT, VK_RETURN, VK_RIGHT, VK_TAB, VK_UP,
+};
+use windows_sys::Win32::UI::WindowsAndMessaging::{FindWindowW, GetClassNameW, GetForegroundWindow, GetWindowTextW, SetForegroundWindow};
+
+pub const TOOLS: &[&str] = &["sendinput"];
+
+pub const COMMAND_TOOLS: &[&str] = &[];
+
+pub fn preferred_tools(_injector: &Injector) -> Vec<&'static str> {
+    TOOLS.to_vec()
+}
+
+/// Unicode key events bypass the layout entirely
+pub fn keyboard_layout(_injector: &Injector) -> Option<Layout> {
+    None
+}
+
+pub fn backend_problem(_injector: &Injector) -> Option<(String, String)> {
+    None
+}
+
+/// SendInput is part of user32 and always present
+pub fn is_available(_injector: &Injector) -> bool {
+    true
+}
+
+fn key_input(vk: VIRTUAL_KEY, scan: u16, flags: u32) -> INPUT {
+    INPUT {
+        r#type: INPUT_KEYBOARD,
+        Anonymous: INPUT_0 {
+            ki: KEYBDINPUT {
+                wVk: vk,
+                wScan: scan,
+                dwFlags: flags,
+                time: 0,
+                dwExtraInfo: 0,
+            },
+        },
+    }
+}
+
+/// Type text with synthesized Unicode key events
+///
+/// KEYEVENTF_UNICODE sends UTF-16 code units directly, so the active
+/// keyboard layout doesn't matter. Newlines become Return presses since
+/// most apps ignore a Unicode '\n'. Each character is sent as its own batch
+/// so a cancel takes effect mid-text.
+pub fn type_text(_injector: &Injector, text: &str, cancel: &CancelToken) -> Result<(), String> {
+    for ch in text.chars() {
+        if cancel.is_cancelled() {
+            return Err(CANCELLED.to_string());
+        }
+        send_char(ch)?;
+    }
+    Ok(())
+}
+
+/// Unicode key events carry any UTF-16 text
+pub fn can_type(_injector: &Injector, _ch: char) -> bool {
+    true
+}
+
+pub fn paste(_injector: &Injector) -> Result<(), String> {
+    const VK_V: VIRTUAL_KEY = 0x56;
+    send(&[
+        key_input(VK_CONTROL, 0, 0),
+        key_input(VK_V, 0, 0),
+        key_input(VK_V, 0, KEYEVENTF_KEYUP),
+        key_input(VK_CONTROL, 0, KEYEVENTF_KEYUP),
+    ])
+}
+
+pub fn backspace(_injector: &Injector, count: usize) -> Result<(), String> {
+    let mut inputs = Vec::with_capacity(count * 2);
+    for _ in 0..count {
+        inputs.push(key_input(VK_BACK, 0, 0));
+        inputs.push(key_input(VK_BACK, 0, KEYEVENTF_KEYUP));
+    }
+    send(&inputs)
+}
+
+pub fn press_key(_injector: &Injector, key: Key) -> Result<(), String> {
+    let vk = match key {
+        Key::Enter => VK_RETURN,
+        Key::Tab => VK_TAB,
+        Key::Backspace => VK_BACK,
+        Key::Escape => VK_ESCAPE,
+        Key::Left => VK_LEFT,
+        Key::Right => VK_RIGHT,
+        Key::Up => VK_UP,
+        Key::Down => VK_DOWN,
+    };
+    send(&[key_input(vk, 0, 0), key_input(vk, 0, KEYEVENTF_KEYUP)])
+}
+
+fn send_char(ch: char) -> Result<(), String> {
+    let mut inputs = Vec::with_capacity(4);
+    match ch {
+        '\r' => {}
+        '\n' => {
+            inputs.push(key_input(VK_RETURN, 0, 0));
+            inputs.push(key_input(VK_RETURN, 0, KEYEVENTF_KEYUP));
+        }
+        _ => {
+            let mut units = [0u16; 2];
+            for unit in ch.encode_utf16(&mut units) {
+                inputs.push(key_input(0, *unit, KEYEVENTF_UNICODE));
+                inputs.push(key_input(0, *unit, KEYEVENTF_UNICODE | KEYEVENTF_KEYUP));
+            }
+        }
+    }
+
+    send(&inputs)
+}
+
+fn send(inputs: &[INPUT]) -> Result<(), String> {
+    if inputs.is_empty() {
+        return Ok(());
+    }
+
+    // SAFETY: `inputs` is a valid, initialized slice of INPUT for the duration of the call
+    let sent = unsafe { SendInput(inputs.len() as u32, inputs.as_ptr(), size_of::<INPUT>() as i32) };
+    if sent as usize != inputs.len() {
+        return Err(format!(
+            "SendInput injected {} of {} events (blocked by UIPI or a secure desktop?)",
+            sent,
+            inputs.len()
+        ));
+    }
+
+    Ok(())
+}
+
+// Window ids are HWNDs printed as integers
+
+pub fn active_window(_injector: &Injector) -> Option<String> {
+    // SAFETY: no arguments; returns null when no window has focus
+    let hwnd = unsafe { GetForegroundWindow() };
+    (!hwnd.is_null()).then(|| (hwnd as usize).to_string())
+}
+
+pub fn focused_window(_injector: &Injector) -> Option<FocusedWindow> {
+    // SAFETY: no arguments; returns null when no window has focus
+    let hwnd = unsafe { GetForegroundWindow() };
+    if hwnd.is_null() {
+        return None;
+    }
+    let mut name = [0u16; 256];
+    // SAFETY: the buffer length passed matches `name`
+    let len = unsafe { GetClassNameW(hwnd, name.as_mut_ptr(), name.len() as i32) };
+    let mut title = [0u16; 256];
+    // SAFETY: the buffer length passed matches `title`
+    let title_len = unsafe { GetWindowTextW(hwnd, title.as_mut_ptr(), title.len() as i32) };
+    (len > 0).then(|| FocusedWindow {
+        class: String::from_utf16_lossy(&name[..len as usize]),
+        title: (title_len > 0).then(|| String::from_utf16_lossy(&title[..title_len as usize])),
+        workspace: None,
+    })
+}
+
+pub fn has_text_field(_injector: &Injector) -> Option<bool> {
+    None
+}
+
+pub fn has_class(_injector: &Injector, class: &str) -> bool {
+    find_class(class).is_some()
+}
+
+pub fn activate_class(_injector: &Injector, class: &str) -> Result<(), String> {
+    let hwnd = find_class(class).ok_or_else(|| format!("No window with class {}", class))?;
+    set_foreground(hwnd)
+}
+
+fn find_class(class: &str) -> Option<HWND> {
+    let wide: Vec<u16> = class.encode_utf16().chain(Some(0)).collect();
+    // SAFETY: `wide` is NUL-terminated and outlives the call
+    let hwnd = unsafe { FindWindowW(wide.as_ptr(), null()) };
+    (!hwnd.is_null()).then_some(hwnd)
+}
+
+pub fn activate_window(_injector: &Injector, window: &str) -> Result<(), String> {
+    let hwnd = window
+        .parse::<usize>()
+        .map_err(|_| format!("Invalid window id {}", window))? as HWND;
+    set_foreground(hwnd)
+}
+
+fn set_foreground(hwnd: HWND) -> Result<(), String> {
+    // SAFETY: a stale HWND just makes the call fail
+    if unsafe { SetForegroundWindow(hwnd) } == 0 {
+        return Err("Windows refused to change the foreground window".to_string());
+    }
+    Ok(())
+}
@@ -0,0 +1,102 @@
+use serde_json::Value;
+use std::path::Path;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+use tokio_tungstenite::tungstenite::handshake::client::{Request, Response};
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use tokio_tungstenite::tungstenite::Error as WsError;
+use tokio_tungstenite::WebSocketStream;
+
+/// URL for the upgrade request: the socket decides where it goes, so the
+/// host only fills the Host header
+pub const WS_URL: &str = "ws://localhost/";
+
+/// The socket in a `unix:///run/utter/relay.sock` relay URL
+pub fn socket_path(server_url: &str) -> Option<&Path> {
+    server_url.strip_prefix("unix://").map(Path::new)
+}
+
+/// Open the relay WebSocket over the Unix socket at `path`
+pub async fn connect_websocket(
+    path: &Path,
+    request: Request,
+    config: Option<WebSocketConfig>,
+) -> Result<(WebSocketStream<UnixStream>, Response), WsError> {
+    let stream = UnixStream::connect(path).await.map_err(WsError::Io)?;
+    tokio_tungstenite::client_async_with_config(request, stream, config).await
+}
+
+/// POST `body` to the relay's HTTP `endpoint` (e.g. "/auth") over the socket,
+/// returning whether it succeeded and the JSON reply
+///
+/// reqwest can't reach a Unix socket, and the relay's two JSON endpoints
+/// don't need more than one-shot HTTP/1.1.
+pub async fn post_json(path: &Path, endpoint: &str, body: &Value) -> Result<(bool, Value), String> {
+    let mut stream = UnixStream::connect(path)
+        .await
+        .map_err(|e| format!("Cannot connect to {}: {}", path.display(), e))?;
+
+    let body = body.to_string();
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        endpoint,
+        body.len(),
+        body
+    );
+    stream.write_all(request.as_bytes()).await.map_err(|e| format!("Send error: {}", e))?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.map_err(|e| format!("Read error: {}", e))?;
+    parse_response(&response)
+}
+
+fn parse_response(response: &[u8]) -> Result<(bool, Value), String> {
+    let text = std::str::from_utf8(response).map_err(|_| "HTTP response is not UTF-8")?;
+    let (head, body) = text.split_once("\r\n\r\n").ok_or("Truncated HTTP response")?;
+    let status: u16 = head
+        .split(' ')
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or("Malformed HTTP status line")?;
+
+    let chunked = head.lines().any(|line| {
+        let line = line.to_ascii_lowercase();
+        line.starts_with("transfer-encoding:") && line.contains("chunked")
+    });
+    let body = if chunked { dechunk(body)? } else { body.to_string() };
+
+    let reply = serde_json::from_str(&body).map_err(|e| format!("Invalid JSON reply: {}", e))?;
+    Ok(((200..300).contains(&status), reply))
+}
+
+fn dechunk(mut body: &str) -> Result<String, String> {
+    let mut joined = String::new();
+    loop {
+        let (size, rest) = body.split_once("\r\n").ok_or("Truncated chunked body")?;
+        let size = usize::from_str_radix(size.split(';').next().unwrap_or_default().trim(), 16)
+            .map_err(|_| "Malformed chunk size")?;
+        if size == 0 {
+            return Ok(joined);
+        }
+        joined.push_str(rest.get(..size).ok_or("Truncated chunked body")?);
+        body = rest[size..].strip_prefix("\r\n").ok_or("Malformed chunked body")?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_response() {
+        let plain = b"HTTP/1.1 200 OK\r\nContent-Length: 13\r\n\r\n{\"jwt\":\"abc\"}";
+        assert_eq!(parse_response(plain).unwrap(), (true, serde_json::json!({ "jwt": "abc" })));
+
+        let chunked = b"HTTP/1.1 401 Unauthorized\r\nTransfer-Encoding: chunked\r\n\r\n5\r\n{\"err\r\n9\r\nor\":\"no\"}\r\n0\r\n\r\n";
+        assert_eq!(parse_response(chunked).unwrap(), (false, serde_json::json!({ "error": "no" })));
+
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\n").is_err());
+        assert_eq!(socket_path("unix:///run/utter/relay.sock"), Some(Path::new("/run/utter/relay.sock")));
+        assert_eq!(socket_path("ws://localhost:8080"), None);
+    }
+}